@@ -0,0 +1,50 @@
+// benches/dispatch.rs
+//
+// Compares the classic opcode-matching VM loop with the pre-decoded one on a
+// recursion-heavy and a loop-heavy program. Run with `cargo bench`.
+
+use std::process::Command;
+
+const PROGRAMS: [(&str, &str); 2] = [
+    (
+        "recursion (fib 27)",
+        concat!(include_str!("../tests/common/fib.c"), "int main() { return fib(27) & 255; }\n"),
+    ),
+    (
+        "loops (1500 x 1500)",
+        "int main() { int i, j, s; s = 0; i = 0;\n  while (i < 1500) { j = 0; while (j < 1500) { s = s + (i ^ j) % 13; j++; } i++; }\n  return s & 255; }\n",
+    ),
+];
+
+const RUNS: usize = 5;
+
+// runs the program once and returns the VM's cycles and cycles per second from --stats
+fn measure(path: &std::path::Path, dispatch: &str) -> (u64, f64) {
+    let out = Command::new(env!("CARGO_BIN_EXE_c4_rust_mleiha"))
+        .args(["--stats", dispatch])
+        .arg(path)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let line = stderr.lines().find(|l| l.contains("cycles in")).expect("no --stats line");
+    let cycles = line.split(' ').next().unwrap().parse().unwrap();
+    let rate: f64 = line.split('(').nth(1).unwrap().split('M').next().unwrap().parse().unwrap();
+    (cycles, rate)
+}
+
+fn main() {
+    println!("{:<22} {:>12} {:>16} {:>16} {:>8}", "program", "cycles", "classic Mc/s", "decoded Mc/s", "speedup");
+    for (i, (name, source)) in PROGRAMS.iter().enumerate() {
+        let path = std::env::temp_dir().join(format!("c4_bench_{}_{}.c", i, std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        // best of a few runs, to keep noise from other processes out
+        let best = |dispatch: &str| {
+            (0..RUNS).map(|_| measure(&path, dispatch)).fold((0, 0.0), |(_, best), (c, r)| (c, f64::max(best, r)))
+        };
+        let (cycles, classic) = best("--dispatch=classic");
+        let (decoded_cycles, decoded) = best("--dispatch=decoded");
+        assert_eq!(cycles, decoded_cycles, "both loops must execute the same instructions");
+        println!("{:<22} {:>12} {:>16.1} {:>16.1} {:>7.2}x", name, cycles, classic, decoded, decoded / classic);
+        std::fs::remove_file(&path).ok();
+    }
+}
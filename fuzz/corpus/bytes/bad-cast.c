@@ -0,0 +1 @@
+int main() { int x; x = (int 3; return x; }
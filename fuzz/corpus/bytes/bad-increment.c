@@ -0,0 +1 @@
+int main() { ++3; return 0; }
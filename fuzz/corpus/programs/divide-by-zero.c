@@ -0,0 +1 @@
+int main() { int a; int b; a = 7; b = 0; printf("%d\n", a % 3); return a / b; }
@@ -0,0 +1 @@
+int main() { int m; m = 1 << 63; printf("%d %d %d\n", m - 1, m * -1, m / -1); return m % -1 + (1 << 70); }
@@ -0,0 +1 @@
+int main() { int *p; p = 0; p = p - 100; printf("%d\n", *p); *p = 1; return 0; }
@@ -0,0 +1,488 @@
+// fuzz/main.rs
+//
+// Fuzz targets for the compiler and the VM, runnable offline without
+// cargo-fuzz or any other dependency:
+//
+//   cargo test --test fuzz                          every target, 100 runs each
+//   cargo test --release --test fuzz -- programs --runs 5000 --seed 7
+//
+// `bytes` feeds random bytes and mutated versions of tests/programs/ to the
+// lexer, parser and code generators, compiling with -s so that mutated loops
+// can't run forever. `programs` generates programs from the c4 grammar, some
+// of which divide by zero or dereference wild pointers, and runs them on the
+// VM plain, with -O2 and with --check. `bytecode` skips the compiler: it
+// runs random instructions with odd operands, such as frames of -1 cells,
+// ADJs past the top of memory and extensions to 0 or 65 bits, on both
+// dispatch loops in this process. Either way the compiler must report
+// errors and the VM faults the way they report anything else, and never
+// panic or crash.
+//
+// Every input in fuzz/corpus/<target>/ is replayed first. An input that
+// fails is written to fuzz/artifacts/<target>/ so it can be replayed with
+// `-- <target> --input <file>` and, once fixed, added to the corpus.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use c4_rust_mleiha::dispatch::decode;
+use c4_rust_mleiha::fs::MemoryFs;
+use c4_rust_mleiha::stdio::Capture;
+use c4_rust_mleiha::vm::VM;
+
+const ROOT: &str = env!("CARGO_MANIFEST_DIR");
+
+// a program taking longer than this is reported as a hang
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// xorshift64*, so a seed always gives the same inputs
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    // a number in 0..n
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+// ---- the bytes target ----
+
+// pieces of C worth splicing into an input
+const TOKENS: [&str; 40] = [
+    "int ", "char ", "*", "(", ")", "{", "}", "[", "]", ";", ",", "=", "==", "+", "-", "/", "%", "<<", ">>", "&&",
+    "||", "?", ":", "'", "\"", "\\", "0x", "0", "9999999999", "1e", ".", "//", "/*", "*/", "#", "if ", "while ",
+    "return ", "sizeof(", "enum {",
+];
+
+fn seeds() -> Vec<Vec<u8>> {
+    let mut found: Vec<Vec<u8>> = files(&Path::new(ROOT).join("tests/programs"))
+        .into_iter()
+        .filter(|p| p.extension().is_some_and(|e| e == "c"))
+        .map(|p| std::fs::read(p).unwrap())
+        .collect();
+    found.push(Vec::new());
+    found
+}
+
+// random bytes, or a seed program with a handful of bytes and tokens changed
+fn bytes_input(rng: &mut Rng, seeds: &[Vec<u8>]) -> Vec<u8> {
+    if rng.chance(20) {
+        return (0..rng.below(200)).map(|_| rng.next() as u8).collect();
+    }
+    let mut input = rng.pick(seeds).clone();
+    for _ in 0..1 + rng.below(8) {
+        let at = rng.below(input.len() + 1);
+        match rng.below(5) {
+            0 if at < input.len() => input[at] = rng.next() as u8,
+            1 if at < input.len() => {
+                let end = (at + rng.below(20)).min(input.len());
+                input.drain(at..end);
+            }
+            2 => {
+                let token = rng.pick(&TOKENS).as_bytes();
+                input.splice(at..at, token.iter().copied());
+            }
+            3 => input.truncate(at),
+            _ => input.insert(at.min(input.len()), *rng.pick(b"(){}[];,*&'\"\n\\")),
+        }
+    }
+    input
+}
+
+// ---- the programs target ----
+
+// builds a random program in the subset c4 compiles. Loops count up to a
+// bound with a variable nothing else assigns and calls only go to functions
+// defined earlier, so every program ends.
+struct Gen<'a> {
+    rng: &'a mut Rng,
+    out: String,
+    funcs: Vec<(String, usize)>, // defined so far, with their parameter counts
+    ints: Vec<String>,           // int variables in scope
+    ptrs: Vec<String>,           // char * variables in scope
+    depth: usize,
+    loops: usize,
+}
+
+impl Gen<'_> {
+    fn int(&mut self) -> String {
+        match self.rng.below(6) {
+            0 => self.rng.pick(&["0", "1", "-1", "255", "0x7fffffff", "2147483647", "-2147483647"]).to_string(),
+            1 => format!("'{}'", *self.rng.pick(&["a", "Z", "0", "\\n", "\\0"])),
+            _ => self.rng.below(100).to_string(),
+        }
+    }
+
+    fn expr(&mut self, depth: usize) -> String {
+        if depth == 0 || self.rng.chance(25) {
+            return match self.rng.below(4) {
+                0 if !self.ints.is_empty() => self.rng.pick(&self.ints).clone(),
+                1 if !self.ptrs.is_empty() => {
+                    let p = self.rng.pick(&self.ptrs).clone();
+                    format!("{}[{}]", p, self.rng.below(4))
+                }
+                _ => self.int(),
+            };
+        }
+        let d = depth - 1;
+        match self.rng.below(12) {
+            0..=2 => {
+                let op = *self.rng.pick(&["+", "-", "*", "/", "%", "<<", ">>", "&", "|", "^", "<", ">", "<=", ">=", "==", "!=", "&&", "||"]);
+                format!("({} {} {})", self.expr(d), op, self.expr(d))
+            }
+            3 => format!("{}({})", self.rng.pick(&["-", "!", "~"]), self.expr(d)),
+            4 => format!("({} ? {} : {})", self.expr(d), self.expr(d), self.expr(d)),
+            5 if !self.funcs.is_empty() => {
+                let (name, params) = self.rng.pick(&self.funcs).clone();
+                let args: Vec<String> = (0..params).map(|_| self.expr(d)).collect();
+                format!("{}({})", name, args.join(", "))
+            }
+            6 => format!("\"{}\"[{}]", self.rng.pick(&["", "abc", "hello, world"]), self.rng.below(5)),
+            7 => self.rng.pick(&["sizeof(int)", "sizeof(char)", "sizeof(char *)"]).to_string(),
+            8 => format!("(char)({})", self.expr(d)),
+            // reads through a wild pointer, which the VM has to catch
+            9 if self.rng.chance(30) => format!("*(int *)({})", self.expr(d)),
+            _ if !self.ints.is_empty() && self.rng.chance(50) => {
+                let v = self.rng.pick(&self.ints).clone();
+                format!("({} = {})", v, self.expr(d))
+            }
+            _ => self.int(),
+        }
+    }
+
+    fn line(&mut self, s: &str) {
+        self.out.push_str(&"  ".repeat(self.depth + 1));
+        self.out.push_str(s);
+        self.out.push('\n');
+    }
+
+    fn stmt(&mut self) {
+        match self.rng.below(8) {
+            0 | 1 if !self.ints.is_empty() => {
+                let v = self.rng.pick(&self.ints).clone();
+                let e = self.expr(3);
+                self.line(&format!("{} = {};", v, e));
+            }
+            2 | 3 => {
+                let e = self.expr(3);
+                self.line(&format!("printf(\"%d\\n\", {});", e));
+            }
+            4 if self.depth < 3 => {
+                let c = self.expr(2);
+                self.line(&format!("if ({}) {{", c));
+                self.block();
+                if self.rng.chance(50) {
+                    self.line("} else {");
+                    self.block();
+                }
+                self.line("}");
+            }
+            5 if self.depth < 3 => {
+                // the counter is declared by the function, so only the loop changes it
+                let i = format!("i{}", self.loops);
+                self.loops += 1;
+                self.line(&format!("{} = 0;", i));
+                let bound = self.rng.below(10);
+                self.line(&format!("while ({} < {}) {{", i, bound));
+                self.block();
+                self.line(&format!("  {}++;", i));
+                self.line("}");
+            }
+            6 if !self.ptrs.is_empty() => {
+                let p = self.rng.pick(&self.ptrs).clone();
+                let (at, e) = (self.rng.below(4), self.expr(2));
+                self.line(&format!("{}[{}] = {};", p, at, e));
+            }
+            _ => {
+                let e = self.expr(3);
+                self.line(&format!("{};", e));
+            }
+        }
+    }
+
+    fn block(&mut self) {
+        self.depth += 1;
+        for _ in 0..1 + self.rng.below(3) {
+            self.stmt();
+        }
+        self.depth -= 1;
+    }
+
+    // a function's text; the counters its loops use are declared once known
+    fn function(&mut self, name: &str, params: usize) {
+        let outer = std::mem::take(&mut self.out);
+        let globals = self.ints.clone();
+        let ps: Vec<String> = (0..params).map(|i| format!("a{}", i)).collect();
+        self.ints.extend(ps.iter().cloned());
+        self.ints.extend(["x".to_string(), "y".to_string()]);
+        self.ptrs = vec!["p".to_string()];
+        self.loops = 0;
+        self.line("x = 0; y = 1;");
+        let size = 4 + self.rng.below(8);
+        self.line(&format!("p = malloc({});", size));
+        self.line("p[0] = 0; p[1] = 0; p[2] = 0; p[3] = 0;");
+        for _ in 0..1 + self.rng.below(6) {
+            self.stmt();
+        }
+        let ret = self.expr(3);
+        self.line(&format!("return {};", ret));
+        let body = std::mem::replace(&mut self.out, outer);
+
+        let counters: Vec<String> = (0..self.loops).map(|i| format!(", i{}", i)).collect();
+        let decl: Vec<String> = ps.iter().map(|p| format!("int {}", p)).collect();
+        self.out.push_str(&format!("int {}({})\n{{\n  int x, y{};\n  char *p;\n{}}}\n\n", name, decl.join(", "), counters.concat(), body));
+        self.ints = globals;
+        self.funcs.push((name.to_string(), params));
+    }
+}
+
+fn program_input(rng: &mut Rng) -> Vec<u8> {
+    let mut g = Gen { rng, out: String::new(), funcs: Vec::new(), ints: Vec::new(), ptrs: Vec::new(), depth: 0, loops: 0 };
+    let globals = g.rng.below(3);
+    for i in 0..globals {
+        g.out.push_str(&format!("int g{};\n", i));
+        g.ints.push(format!("g{}", i));
+    }
+    g.out.push('\n');
+    for i in 0..g.rng.below(4) {
+        let params = g.rng.below(3);
+        g.function(&format!("f{}", i), params);
+    }
+    g.function("main", 0);
+    g.out.into_bytes()
+}
+
+// ---- the bytecode target ----
+
+// the opcodes taking a jump target, and how many operands each opcode has
+const JUMPS: [i32; 4] = [2, 3, 4, 5];
+const OPCODES: i32 = 67;
+
+fn operand_count(op: i32) -> usize {
+    match op {
+        0..=7 | 46 | 47 => 1,
+        48 => 2,
+        _ => 0,
+    }
+}
+
+// operands at the edges of what instructions take and of memory
+const OPERANDS: [i32; 12] = [0, 1, 2, -1, -2, 8, 63, 64, 65, 1 << 20, i32::MAX, i32::MIN];
+
+// a text segment of random instructions, saved as little endian i32 words.
+// Jumps mostly land on an instruction, the rest anywhere near the text
+fn bytecode_input(rng: &mut Rng) -> Vec<u8> {
+    let mut ops = Vec::new();
+    for _ in 0..1 + rng.below(24) {
+        ops.push(if rng.chance(3) { *rng.pick(&[-1, OPCODES, 1000]) } else { rng.below(OPCODES as usize) as i32 });
+    }
+    let mut starts = Vec::new();
+    let mut at = 0;
+    for &op in &ops {
+        starts.push(at);
+        at += 1 + operand_count(op);
+    }
+    let mut text = Vec::new();
+    for &op in &ops {
+        text.push(op);
+        for _ in 0..operand_count(op) {
+            text.push(match op {
+                _ if JUMPS.contains(&op) && rng.chance(80) => *rng.pick(&starts) as i32,
+                _ if JUMPS.contains(&op) => rng.below(at + 2) as i32,
+                _ if rng.chance(50) => *rng.pick(&OPERANDS),
+                _ => rng.below(16) as i32,
+            });
+        }
+    }
+    // sometimes cut the last instruction short
+    if rng.chance(10) {
+        text.pop();
+    }
+    text.iter().flat_map(|w| w.to_le_bytes()).collect()
+}
+
+// memory for a bytecode program: small, so pushes and frames reach its ends
+const CELLS: usize = 1 << 12;
+
+// runs the text on the dispatch loop the flags pick, with no files and a
+// cycle limit, and checks the VM didn't panic
+fn run_bytecode(input: &[u8], flags: &[&str]) -> Verdict {
+    let text: Vec<i64> = input.chunks_exact(4).map(|w| i32::from_le_bytes(w.try_into().unwrap()) as i64).collect();
+    let classic = flags.contains(&"--dispatch=classic");
+    let run = std::panic::catch_unwind(move || {
+        let mut vm = VM::new(text, 0, CELLS);
+        vm.load_data(&[]);
+        vm.stdin = Box::new(&b""[..]);
+        vm.stdout = Box::new(Capture::new());
+        vm.stderr = Box::new(Capture::new());
+        vm.fs = Box::new(MemoryFs::new());
+        vm.limits.cycles = Some(10_000);
+        if classic {
+            vm.run();
+        } else if let Ok(code) = decode(&vm.text) {
+            vm.run_decoded(&code);
+        }
+    });
+    match run {
+        Ok(()) => Verdict::Ok,
+        Err(payload) => {
+            let message = payload.downcast_ref::<String>().cloned().or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()));
+            Verdict::Failed(format!("{:?}: panicked: {}", flags, message.unwrap_or_default()))
+        }
+    }
+}
+
+// ---- running the compiler ----
+
+enum Verdict {
+    Ok,
+    Failed(String),
+}
+
+// runs the compiler on the input and checks it neither panicked nor died
+fn check(input: &[u8], flags: &[&str]) -> Verdict {
+    let path = std::env::temp_dir().join(format!("c4_fuzz_{}.c", std::process::id()));
+    std::fs::write(&path, input).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_c4_rust_mleiha"))
+        .args(flags)
+        .arg(&path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if start.elapsed() > TIMEOUT {
+            child.kill().ok();
+            child.wait().ok();
+            return Verdict::Failed(format!("{:?}: still running after {:?}", flags, TIMEOUT));
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    };
+    let mut stderr = String::new();
+    std::io::Read::read_to_string(child.stderr.as_mut().unwrap(), &mut stderr).ok();
+    if stderr.contains("panicked at") {
+        let message: Vec<&str> = stderr.lines().take(2).collect();
+        return Verdict::Failed(format!("{:?}: panicked: {}", flags, message.join(" ")));
+    }
+    // any exit code is fine: programs return what they like, and a panic,
+    // exit code 101, says so on stderr
+    match status.code() {
+        Some(_) => Verdict::Ok,
+        None => Verdict::Failed(format!("{:?}: killed by a signal ({})", flags, status)),
+    }
+}
+
+fn check_all(target: &str, input: &[u8], modes: &[&[&str]]) -> Verdict {
+    for flags in modes {
+        let verdict = match target {
+            "bytecode" => run_bytecode(input, flags),
+            _ => check(input, flags),
+        };
+        if let Verdict::Failed(why) = verdict {
+            return Verdict::Failed(why);
+        }
+    }
+    Verdict::Ok
+}
+
+fn files(dir: &Path) -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries.map(|e| e.unwrap().path()).filter(|p| p.is_file()).collect(),
+        Err(_) => Vec::new(),
+    };
+    found.sort();
+    found
+}
+
+// keeps a failing input where it can be replayed from
+fn save_artifact(target: &str, input: &[u8]) -> PathBuf {
+    let dir = Path::new(ROOT).join("fuzz/artifacts").join(target);
+    std::fs::create_dir_all(&dir).unwrap();
+    let hash = input.iter().fold(0xcbf29ce484222325u64, |h, &b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+    let path = dir.join(format!("crash-{:016x}", hash));
+    std::fs::write(&path, input).unwrap();
+    path
+}
+
+struct Target {
+    name: &'static str,
+    modes: &'static [&'static [&'static str]],
+}
+
+const TARGETS: [Target; 3] = [
+    Target { name: "bytes", modes: &[&["-s"], &["-s", "-O2"]] },
+    Target { name: "programs", modes: &[&[], &["-O2"], &["--check"]] },
+    Target { name: "bytecode", modes: &[&["--dispatch=classic"], &["--dispatch=decoded"]] },
+];
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let value = |flag: &str| args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).cloned();
+    let runs: usize = value("--runs").map_or(100, |v| v.parse().expect("--runs takes a number"));
+    let seed: u64 = value("--seed").map_or(1, |v| v.parse().expect("--seed takes a number"));
+    let input = value("--input");
+    let named: Vec<&String> = args.iter().filter(|a| TARGETS.iter().any(|t| t.name == a.as_str())).collect();
+
+    let mut failures = 0;
+    for target in TARGETS.iter().filter(|t| named.is_empty() || named.iter().any(|n| n.as_str() == t.name)) {
+        let mut report = |input: &[u8], why: String| {
+            failures += 1;
+            let path = save_artifact(target.name, input);
+            println!("fuzz {}: {}\n  input saved to {}", target.name, why, path.display());
+        };
+        if let Some(file) = &input {
+            if let Verdict::Failed(why) = check_all(target.name, &std::fs::read(file).unwrap(), target.modes) {
+                report(&std::fs::read(file).unwrap(), why);
+            }
+            continue;
+        }
+
+        let corpus = files(&Path::new(ROOT).join("fuzz/corpus").join(target.name));
+        for path in &corpus {
+            let input = std::fs::read(path).unwrap();
+            if let Verdict::Failed(why) = check_all(target.name, &input, target.modes) {
+                report(&input, format!("{} regressed: {}", path.display(), why));
+            }
+        }
+        let mut rng = Rng(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1);
+        let seeds = seeds();
+        let start = Instant::now();
+        for _ in 0..runs {
+            let input = match target.name {
+                "bytes" => bytes_input(&mut rng, &seeds),
+                "bytecode" => bytecode_input(&mut rng),
+                _ => program_input(&mut rng),
+            };
+            if let Verdict::Failed(why) = check_all(target.name, &input, target.modes) {
+                report(&input, why);
+            }
+        }
+        println!("fuzz {}: {} corpus inputs and {} runs with seed {} in {:.1}s", target.name, corpus.len(), runs, seed, start.elapsed().as_secs_f64());
+        std::io::stdout().flush().ok();
+    }
+    if failures > 0 {
+        println!("\nfuzz result: FAILED. {} failing inputs", failures);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,12 @@
+// ctype.h: character classes for the ASCII range
+int isdigit(int c);
+int isxdigit(int c);
+int isalpha(int c);
+int isalnum(int c);
+int isupper(int c);
+int islower(int c);
+int isspace(int c);
+int isprint(int c);
+int ispunct(int c);
+int toupper(int c);
+int tolower(int c);
@@ -0,0 +1,2 @@
+// stdio.h: printf, open, read, write and close are system calls, so there
+// is nothing to declare here
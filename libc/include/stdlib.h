@@ -0,0 +1,8 @@
+// stdlib.h: the rest of c4's bundled library. malloc, free and exit are
+// system calls, so they need no declaration here
+int atoi(char *s);
+long strtol(char *s, char **end, int base);
+int abs(int n);
+char *calloc(int n, int size);
+int rand();
+void srand(int seed);
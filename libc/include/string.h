@@ -0,0 +1,13 @@
+// string.h: the string functions of c4's bundled library. memset and
+// memcmp are system calls, so they need no declaration here
+int strlen(char *s);
+int strcmp(char *a, char *b);
+int strncmp(char *a, char *b, int n);
+char *strcpy(char *d, char *s);
+char *strncpy(char *d, char *s, int n);
+char *strcat(char *d, char *s);
+char *strchr(char *s, int c);
+char *strrchr(char *s, int c);
+char *strstr(char *s, char *t);
+char *strdup(char *s);
+char *memcpy(char *d, char *s, int n);
@@ -0,0 +1,13 @@
+#include <ctype.h>
+#include <stdlib.h>
+
+int atoi(char *s)
+{
+  int n, neg;
+  while (isspace(*s)) s++;
+  neg = *s == '-';
+  if (*s == '-' || *s == '+') s++;
+  n = 0;
+  while (isdigit(*s)) n = n * 10 + *s++ - '0';
+  return neg ? -n : n;
+}
@@ -0,0 +1,9 @@
+#include <stdlib.h>
+
+char *calloc(int n, int size)
+{
+  char *p;
+  p = malloc(n * size);
+  if (p) memset(p, 0, n * size);
+  return p;
+}
@@ -0,0 +1,6 @@
+#include <ctype.h>
+
+int ispunct(int c)
+{
+  return isprint(c) && !isalnum(c) && c != ' ';
+}
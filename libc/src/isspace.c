@@ -0,0 +1,7 @@
+#include <ctype.h>
+
+// space, and \t \n \v \f \r
+int isspace(int c)
+{
+  return c == ' ' || (c >= 9 && c <= 13);
+}
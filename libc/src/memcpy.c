@@ -0,0 +1,9 @@
+#include <string.h>
+
+char *memcpy(char *d, char *s, int n)
+{
+  char *p;
+  p = d;
+  while (n > 0) { *p++ = *s++; n--; }
+  return d;
+}
@@ -0,0 +1,16 @@
+#include <stdlib.h>
+
+// rand and srand share the generator's state, which starts out as srand(0)
+// left it. Keeping it below 2^31 keeps the multiply from overflowing a cell
+int __c4_rand_next;
+
+int rand()
+{
+  __c4_rand_next = (__c4_rand_next * 1103515245 + 12345) & 2147483647;
+  return (__c4_rand_next >> 16) & 32767;
+}
+
+void srand(int seed)
+{
+  __c4_rand_next = seed & 2147483647;
+}
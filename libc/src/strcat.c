@@ -0,0 +1,10 @@
+#include <string.h>
+
+char *strcat(char *d, char *s)
+{
+  char *p;
+  p = d;
+  while (*p) p++;
+  while (*p++ = *s++) ;
+  return d;
+}
@@ -0,0 +1,11 @@
+#include <string.h>
+
+// the first c in s, which can be its terminating NUL
+char *strchr(char *s, int c)
+{
+  while (*s != (char)c) {
+    if (!*s) return 0;
+    s++;
+  }
+  return s;
+}
@@ -0,0 +1,8 @@
+#include <string.h>
+
+// bytes compare as unsigned char, like the C library's
+int strcmp(char *a, char *b)
+{
+  while (*a && *a == *b) { a++; b++; }
+  return (unsigned char)*a - (unsigned char)*b;
+}
@@ -0,0 +1,9 @@
+#include <string.h>
+
+char *strcpy(char *d, char *s)
+{
+  char *p;
+  p = d;
+  while (*p++ = *s++) ;
+  return d;
+}
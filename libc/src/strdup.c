@@ -0,0 +1,9 @@
+#include <string.h>
+
+char *strdup(char *s)
+{
+  char *d;
+  d = malloc(strlen(s) + 1);
+  if (d) strcpy(d, s);
+  return d;
+}
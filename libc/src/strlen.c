@@ -0,0 +1,9 @@
+#include <string.h>
+
+int strlen(char *s)
+{
+  char *p;
+  p = s;
+  while (*p) p++;
+  return p - s;
+}
@@ -0,0 +1,8 @@
+#include <string.h>
+
+int strncmp(char *a, char *b, int n)
+{
+  if (n <= 0) return 0;
+  while (n > 1 && *a && *a == *b) { a++; b++; n--; }
+  return (unsigned char)*a - (unsigned char)*b;
+}
@@ -0,0 +1,11 @@
+#include <string.h>
+
+// copies at most n bytes, padding with NULs when s is shorter
+char *strncpy(char *d, char *s, int n)
+{
+  char *p;
+  p = d;
+  while (n > 0 && *s) { *p++ = *s++; n--; }
+  while (n > 0) { *p++ = 0; n--; }
+  return d;
+}
@@ -0,0 +1,14 @@
+#include <string.h>
+
+// the last c in s, which can be its terminating NUL
+char *strrchr(char *s, int c)
+{
+  char *last;
+  last = 0;
+  while (*s) {
+    if (*s == (char)c) last = s;
+    s++;
+  }
+  if (!(char)c) return s;
+  return last;
+}
@@ -0,0 +1,13 @@
+#include <string.h>
+
+char *strstr(char *s, char *t)
+{
+  int n;
+  n = strlen(t);
+  if (!n) return s;
+  while (*s) {
+    if (!strncmp(s, t, n)) return s;
+    s++;
+  }
+  return 0;
+}
@@ -0,0 +1,30 @@
+#include <ctype.h>
+#include <stdlib.h>
+
+// base 0 reads the prefix like C does: 0x for hex, 0 for octal, else decimal
+long strtol(char *s, char **end, int base)
+{
+  long n;
+  int neg, d;
+  while (isspace(*s)) s++;
+  neg = *s == '-';
+  if (*s == '-' || *s == '+') s++;
+  if ((base == 0 || base == 16) && *s == '0' && (s[1] == 'x' || s[1] == 'X')) {
+    s = s + 2;
+    base = 16;
+  } else if (base == 0 && *s == '0') {
+    base = 8;
+  } else if (base == 0) {
+    base = 10;
+  }
+  n = 0;
+  d = 0;
+  while (d < base) {
+    if (isdigit(*s)) d = *s - '0';
+    else if (isalpha(*s)) d = tolower(*s) - 'a' + 10;
+    else d = base;
+    if (d < base) { n = n * base + d; s++; }
+  }
+  if (end) *end = s;
+  return neg ? -n : n;
+}
@@ -0,0 +1,233 @@
+// The abstract syntax tree built by the parser. Every expression node keeps
+// the line it came from and the type the parser gave it, so later passes
+// (like the semantic checker in typeck.rs) can walk the program without
+// re-deriving anything from the token stream.
+
+use crate::Type;
+
+// binary operators, in the same order as c4's precedence table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    LogOr, LogAnd,
+    Or, Xor, And,
+    Eq, Ne,
+    Lt, Gt, Le, Ge,
+    Shl, Shr,
+    Add, Sub,
+    Mul, Div, Mod,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Plus,   // +x
+    Neg,    // -x
+    Not,    // !x
+    BitNot, // ~x
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncDec {
+    PreInc,  // ++x
+    PreDec,  // --x
+    PostInc, // x++
+    PostDec, // x--
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    Num(i64),                 // integer literal or enum constant
+    Float(f64),               // floating literal
+    Str(i32),                 // address of a string literal in the data segment
+    Local(String, i32),       // local variable and its offset from bp (the LEA operand)
+    Global(String, i32),      // global variable and its data segment address, below 0 for externs
+    Call {
+        name: String,
+        sys: Option<i32>,     // the syscall opcode for Class::Sys symbols
+        args: Vec<Expr>,
+    },
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Assign(Box<Expr>, Box<Expr>),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+    Cast(Box<Expr>),          // the target type is the node's own ty
+    Sizeof(Type),
+    Deref(Box<Expr>),
+    AddrOf(Box<Expr>),
+    IncDec(IncDec, Box<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub ty: Type,  // type of the value this expression produces
+    pub line: i32, // source line the expression starts on
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, ty: Type, line: i32) -> Self {
+        Expr { kind, ty, line }
+    }
+
+    // true for the literal 0, the only integer C lets you use as a pointer
+    pub fn is_null_constant(&self) -> bool {
+        matches!(self.kind, ExprKind::Num(0))
+    }
+
+    // true for the expressions that name an object and can be assigned to
+    pub fn is_lvalue(&self) -> bool {
+        matches!(self.kind, ExprKind::Local(..) | ExprKind::Global(..) | ExprKind::Deref(_) | ExprKind::Index(..))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    While(Expr, Box<Stmt>),
+    DoWhile(Box<Stmt>, Expr),
+    Return(Option<Expr>, i32), // value and the line of the return keyword
+    Block(Vec<Stmt>),
+    Expr(Expr),
+    Empty,
+}
+
+// a named, typed declaration: a global, a parameter or a local
+#[derive(Debug, Clone)]
+pub struct Var {
+    pub name: String,
+    pub ty: Type,
+    pub line: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub ret: Type,
+    pub params: Vec<Var>,
+    pub locals: Vec<Var>,
+    pub body: Vec<Stmt>,
+    pub line: i32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    pub globals: Vec<Var>,
+    pub functions: Vec<Function>,
+    pub externs: Vec<Var>,         // extern variables; the first is at address -1, the next at -2
+    pub prototypes: Vec<Function>, // functions declared without a body
+}
+
+// renders the program as an indented tree for --dump-ast, one node per line
+// with its type and source line
+pub fn dump(program: &Program) -> String {
+    let mut out = String::new();
+    for g in &program.globals {
+        out.push_str(&format!("Global {} '{}' @{}\n", g.name, g.ty, g.line));
+    }
+    for g in &program.externs {
+        out.push_str(&format!("Extern {} '{}' @{}\n", g.name, g.ty, g.line));
+    }
+    for f in &program.prototypes {
+        out.push_str(&format!("Prototype {} '{}' @{}\n", f.name, f.ret, f.line));
+        for p in &f.params {
+            out.push_str(&format!("  Param {} '{}' @{}\n", p.name, p.ty, p.line));
+        }
+    }
+    for f in &program.functions {
+        out.push_str(&format!("Function {} '{}' @{}\n", f.name, f.ret, f.line));
+        for p in &f.params {
+            out.push_str(&format!("  Param {} '{}' @{}\n", p.name, p.ty, p.line));
+        }
+        for l in &f.locals {
+            out.push_str(&format!("  Local {} '{}' @{}\n", l.name, l.ty, l.line));
+        }
+        for s in &f.body {
+            dump_stmt(&mut out, s, 1);
+        }
+    }
+    out
+}
+
+fn dump_stmt(out: &mut String, s: &Stmt, depth: usize) {
+    let pad = "  ".repeat(depth);
+    match s {
+        Stmt::If(cond, then, els) => {
+            out.push_str(&format!("{}If\n", pad));
+            dump_expr(out, cond, depth + 1);
+            dump_stmt(out, then, depth + 1);
+            if let Some(els) = els {
+                out.push_str(&format!("{}Else\n", pad));
+                dump_stmt(out, els, depth + 1);
+            }
+        }
+        Stmt::While(cond, body) => {
+            out.push_str(&format!("{}While\n", pad));
+            dump_expr(out, cond, depth + 1);
+            dump_stmt(out, body, depth + 1);
+        }
+        Stmt::DoWhile(body, cond) => {
+            out.push_str(&format!("{}DoWhile\n", pad));
+            dump_stmt(out, body, depth + 1);
+            dump_expr(out, cond, depth + 1);
+        }
+        Stmt::Return(value, line) => {
+            out.push_str(&format!("{}Return @{}\n", pad, line));
+            if let Some(value) = value {
+                dump_expr(out, value, depth + 1);
+            }
+        }
+        Stmt::Block(body) => {
+            out.push_str(&format!("{}Block\n", pad));
+            for s in body {
+                dump_stmt(out, s, depth + 1);
+            }
+        }
+        Stmt::Expr(e) => dump_expr(out, e, depth),
+        Stmt::Empty => out.push_str(&format!("{}Empty\n", pad)),
+    }
+}
+
+fn dump_expr(out: &mut String, e: &Expr, depth: usize) {
+    let label = match &e.kind {
+        ExprKind::Num(n) => format!("Num {}", n),
+        ExprKind::Float(v) => format!("Float {:?}", v),
+        ExprKind::Str(addr) => format!("Str &{}", addr),
+        ExprKind::Local(name, offset) => format!("Local {} [bp{:+}]", name, offset),
+        ExprKind::Global(name, addr) => format!("Global {} &{}", name, addr),
+        ExprKind::Call { name, sys: Some(_), .. } => format!("Call {} (sys)", name),
+        ExprKind::Call { name, .. } => format!("Call {}", name),
+        ExprKind::Unary(op, _) => format!("Unary {:?}", op),
+        ExprKind::Binary(op, ..) => format!("Binary {:?}", op),
+        ExprKind::Assign(..) => "Assign".to_string(),
+        ExprKind::Cond(..) => "Cond".to_string(),
+        ExprKind::Cast(_) => "Cast".to_string(),
+        ExprKind::Sizeof(t) => format!("Sizeof '{}'", t),
+        ExprKind::Deref(_) => "Deref".to_string(),
+        ExprKind::AddrOf(_) => "AddrOf".to_string(),
+        ExprKind::IncDec(op, _) => format!("{:?}", op),
+        ExprKind::Index(..) => "Index".to_string(),
+    };
+    out.push_str(&format!("{}{} '{}' @{}\n", "  ".repeat(depth), label, e.ty, e.line));
+    match &e.kind {
+        ExprKind::Call { args, .. } => {
+            for a in args {
+                dump_expr(out, a, depth + 1);
+            }
+        }
+        ExprKind::Unary(_, inner)
+        | ExprKind::Cast(inner)
+        | ExprKind::Deref(inner)
+        | ExprKind::AddrOf(inner)
+        | ExprKind::IncDec(_, inner) => dump_expr(out, inner, depth + 1),
+        ExprKind::Binary(_, l, r) | ExprKind::Assign(l, r) | ExprKind::Index(l, r) => {
+            dump_expr(out, l, depth + 1);
+            dump_expr(out, r, depth + 1);
+        }
+        ExprKind::Cond(c, t, f) => {
+            dump_expr(out, c, depth + 1);
+            dump_expr(out, t, depth + 1);
+            dump_expr(out, f, depth + 1);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,564 @@
+// Walks the typed AST and emits the VM bytecode. This is the code generation
+// half of what Parser::expr and Parser::stmt used to do while parsing; keeping
+// it separate means later passes can work on the tree before anything is
+// emitted, or replace this backend altogether.
+
+use std::collections::HashMap;
+
+use crate::ast::{BinOp, Expr, ExprKind, Function, IncDec, Program, Stmt, UnOp};
+use crate::{Base, Type};
+use crate::{ADD, ADJ, AND, BNZ, BZ, DIV, ENT, EQ, FADD, FDIV, FEQ, FGE, FGT, FIMM, FLE, FLT, FMUL, FNE, FRND, FSUB, FTI, FTU};
+use crate::{GE, GT, IMM, ITF, ITFS, JMP, JSR, LE, LEA, LEV, LT, MOD, MUL, NE, OR, PSH, SHL, SHR, SUB, SXT};
+use crate::{UDIV, UGE, UGT, ULE, ULT, UMOD, USHR, UTF, UTFS, XOR, ZXT};
+
+// the generated program: the text segment and the entry point of every function
+pub struct Code {
+    pub text: Vec<i32>,
+    pub functions: HashMap<String, i32>,
+    pub lines: Vec<(i32, i32)>, // text address where each source line's code starts, and the line
+    pub relocs: Vec<(usize, Reloc)>, // text positions of the operands link.rs has to fix up
+}
+
+// an operand that depends on where the linker puts things. Jump and JSR
+// operands to this file's own code aren't listed: they are always text addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reloc {
+    Data,           // an IMM of an address in this file's data segment
+    Symbol(String), // an IMM or JSR of something defined in another file (or later, as extern)
+}
+
+// the source line the instruction at pc was generated from, if the table knows
+pub fn line_of(lines: &[(i32, i32)], pc: usize) -> Option<i32> {
+    let i = lines.partition_point(|&(at, _)| at as usize <= pc);
+    lines.get(i.checked_sub(1)?).map(|&(_, line)| line)
+}
+
+// c4's mnemonics, indexed by opcode
+pub const MNEMONICS: [&str; 67] = [
+    "LEA", "IMM", "JMP", "JSR", "BZ", "BNZ", "ENT", "ADJ", "LEV", "LI", "LC", "SI", "SC", "PSH",
+    "OR", "XOR", "AND", "EQ", "NE", "LT", "GT", "LE", "GE", "SHL", "SHR", "ADD", "SUB", "MUL", "DIV", "MOD",
+    "OPEN", "READ", "CLOS", "PRTF", "MALC", "FREE", "MSET", "MCMP", "EXIT",
+    "ULT", "UGT", "ULE", "UGE", "USHR", "UDIV", "UMOD", "SXT", "ZXT",
+    "FIMM", "FADD", "FSUB", "FMUL", "FDIV", "FEQ", "FNE", "FLT", "FGT", "FLE", "FGE",
+    "ITF", "UTF", "FTI", "FTU", "FRND", "ITFS", "UTFS",
+    "WRIT",
+];
+
+// how many operand words follow the opcode in the text segment
+pub fn operands(op: i32) -> usize {
+    match op {
+        LEA | IMM | JMP | JSR | BZ | BNZ | ENT | ADJ | SXT | ZXT => 1,
+        FIMM => 2,
+        _ => 0,
+    }
+}
+
+// why op can't take the operand n, for the engines to fault on: frames and
+// adjustments are a number of cells, and extensions keep 1 to 64 bits
+pub fn bad_operand(op: i32, n: i64) -> Option<String> {
+    match op {
+        ENT | ADJ if n < 0 => Some(format!("{} {}, a negative number of cells", MNEMONICS[op as usize], n)),
+        SXT | ZXT if !(1..=64).contains(&n) => Some(format!("{} {}, not a width from 1 to 64 bits", MNEMONICS[op as usize], n)),
+        _ => None,
+    }
+}
+
+// a human readable listing of the text segment, one instruction per line
+pub fn listing(text: &[i32]) -> String {
+    let mut out = String::new();
+    let mut pc = 0;
+    while pc < text.len() {
+        let op = text[pc];
+        let name = MNEMONICS.get(op as usize).copied().unwrap_or("???");
+        out.push_str(&format!("{:5}: {:4}", pc, name));
+        let n = operands(op);
+        for operand in text.iter().skip(pc + 1).take(n) {
+            out.push_str(&format!(" {}", operand));
+        }
+        out.push('\n');
+        pc += 1 + n;
+    }
+    out
+}
+
+pub fn generate(program: &Program) -> Code {
+    let mut gen = Codegen {
+        e: Vec::new(),
+        functions: HashMap::new(),
+        params: HashMap::new(),
+        calls: Vec::new(),
+        ret: Type::INT,
+        lines: Vec::new(),
+        relocs: Vec::new(),
+    };
+    for f in program.prototypes.iter().chain(&program.functions) {
+        gen.params.insert(f.name.clone(), f.params.iter().map(|p| p.ty).collect());
+    }
+    for f in &program.functions {
+        gen.function(f);
+    }
+    // calls can only be resolved once every function has its address, and
+    // those to functions only declared are left to the linker
+    for (at, name) in std::mem::take(&mut gen.calls) {
+        match gen.functions.get(&name) {
+            Some(&target) => gen.e[at] = target,
+            None => gen.relocs.push((at, Reloc::Symbol(name))),
+        }
+    }
+    gen.relocs.sort_by_key(|&(at, _)| at);
+    Code { text: gen.e, functions: gen.functions, lines: gen.lines, relocs: gen.relocs }
+}
+
+struct Codegen {
+    e: Vec<i32>,                         // emitted code
+    functions: HashMap<String, i32>,     // entry point of each function emitted so far
+    params: HashMap<String, Vec<Type>>,  // parameter types, to convert arguments
+    calls: Vec<(usize, String)>,         // JSR operands still waiting for their target
+    ret: Type,                           // return type of the current function
+    lines: Vec<(i32, i32)>,              // line table, see Code
+    relocs: Vec<(usize, Reloc)>,         // see Code
+}
+
+impl Codegen {
+    // the address the next emitted word will get
+    fn here(&self) -> i32 {
+        self.e.len() as i32
+    }
+
+    // emits a jump with a placeholder target and returns where to patch it
+    fn jump(&mut self, op: i32) -> usize {
+        self.e.push(op);
+        self.e.push(0);
+        self.e.len() - 1
+    }
+
+    fn patch(&mut self, at: usize) {
+        self.e[at] = self.here();
+    }
+
+    // emits the IMM of a global's or a string's address; externs are below 0
+    fn address(&mut self, name: &str, addr: i32) {
+        self.e.push(IMM);
+        let reloc = if addr < 0 { Reloc::Symbol(name.to_string()) } else { Reloc::Data };
+        self.relocs.push((self.e.len(), reloc));
+        self.e.push(addr);
+    }
+
+    // notes that the code emitted from here on comes from a source line
+    fn line(&mut self, line: i32) {
+        let here = self.here();
+        match self.lines.last_mut() {
+            Some(last) if last.1 == line => {}
+            Some(last) if last.0 == here => *last = (here, line),
+            _ => self.lines.push((here, line)),
+        }
+    }
+
+    fn function(&mut self, f: &Function) {
+        self.functions.insert(f.name.clone(), self.here());
+        self.line(f.line);
+        self.ret = f.ret;
+        self.e.push(ENT);
+        self.e.push(f.locals.len() as i32);
+        for s in &f.body {
+            self.stmt(s);
+        }
+        self.e.push(LEV);
+    }
+
+    fn stmt(&mut self, s: &Stmt) {
+        match s {
+            Stmt::If(cond, then, els) => {
+                self.expr(cond);
+                let b = self.jump(BZ);
+                self.stmt(then);
+                if let Some(els) = els {
+                    let end = self.jump(JMP);
+                    self.patch(b);
+                    self.stmt(els);
+                    self.patch(end);
+                } else {
+                    self.patch(b);
+                }
+            }
+            Stmt::While(cond, body) => {
+                let a = self.here();
+                self.expr(cond);
+                let b = self.jump(BZ);
+                self.stmt(body);
+                self.e.push(JMP);
+                self.e.push(a);
+                self.patch(b);
+            }
+            Stmt::DoWhile(body, cond) => {
+                let a = self.here();
+                self.stmt(body);
+                self.expr(cond);
+                self.e.push(BNZ);
+                self.e.push(a);
+            }
+            Stmt::Return(value, line) => {
+                self.line(*line);
+                if let Some(value) = value {
+                    self.expr(value);
+                    self.convert(value.ty, self.ret);
+                }
+                self.e.push(LEV);
+            }
+            Stmt::Block(body) => {
+                for s in body {
+                    self.stmt(s);
+                }
+            }
+            Stmt::Expr(e) => self.expr(e),
+            Stmt::Empty => {}
+        }
+    }
+
+    // emits the code leaving the address of the lvalue e in ax
+    fn addr(&mut self, e: &Expr) {
+        match &e.kind {
+            ExprKind::Local(_, offset) => {
+                self.e.push(LEA);
+                self.e.push(*offset);
+            }
+            ExprKind::Global(name, addr) => self.address(name, *addr),
+            ExprKind::Deref(inner) => self.expr(inner),
+            ExprKind::Index(base, index) => {
+                self.expr(base);
+                self.e.push(PSH);
+                self.expr(index);
+                self.scale(base.ty);
+                self.e.push(ADD);
+            }
+            _ => unreachable!("the parser only accepts lvalues here"),
+        }
+    }
+
+    // emits the code leaving the value of e in ax
+    fn expr(&mut self, e: &Expr) {
+        self.line(e.line);
+        match &e.kind {
+            // a literal too wide for IMM's operand is loaded as FIMM loads a double's bits
+            ExprKind::Num(val) => match i32::try_from(*val) {
+                Ok(val) => {
+                    self.e.push(IMM);
+                    self.e.push(val);
+                }
+                Err(_) => self.float_imm(f64::from_bits(*val as u64)),
+            },
+            ExprKind::Float(v) => self.float_imm(*v),
+            ExprKind::Str(addr) => self.address("", *addr),
+            ExprKind::Sizeof(t) => {
+                self.e.push(IMM);
+                self.e.push(t.size());
+            }
+            ExprKind::Local(..) | ExprKind::Global(..) | ExprKind::Deref(_) | ExprKind::Index(..) => {
+                self.addr(e);
+                self.load(e.ty);
+            }
+            ExprKind::Call { name, sys, args } => {
+                let params = self.params.get(name).cloned().unwrap_or_default();
+                for (i, a) in args.iter().enumerate() {
+                    self.expr(a);
+                    if let Some(&p) = params.get(i) {
+                        self.convert(a.ty, p);
+                    }
+                    self.e.push(PSH);
+                }
+                match sys {
+                    Some(op) => self.e.push(*op),
+                    None => {
+                        self.e.push(JSR);
+                        self.calls.push((self.e.len(), name.clone()));
+                        self.e.push(0);
+                    }
+                }
+                if !args.is_empty() {
+                    self.e.push(ADJ);
+                    self.e.push(args.len() as i32);
+                }
+            }
+            ExprKind::Unary(op, inner) => match op {
+                UnOp::Plus => {
+                    self.expr(inner);
+                    self.convert(inner.ty, e.ty);
+                }
+                UnOp::Neg => {
+                    self.e.push(IMM);
+                    self.e.push(-1);
+                    self.e.push(PSH);
+                    self.expr(inner);
+                    self.arith(Type::INT, inner.ty, MUL);
+                }
+                UnOp::Not => {
+                    self.expr(inner);
+                    self.e.push(PSH);
+                    self.e.push(IMM);
+                    self.e.push(0);
+                    self.e.push(EQ);
+                }
+                UnOp::BitNot => {
+                    self.expr(inner);
+                    self.e.push(PSH);
+                    self.e.push(IMM);
+                    self.e.push(-1);
+                    self.e.push(XOR);
+                    self.wrap(e.ty);
+                }
+            },
+            ExprKind::Binary(op, lhs, rhs) => self.binary(e, *op, lhs, rhs),
+            ExprKind::Assign(lhs, rhs) => {
+                self.addr(lhs);
+                self.e.push(PSH);
+                self.expr(rhs);
+                self.store(rhs.ty, lhs.ty);
+            }
+            ExprKind::Cond(cond, then, els) => {
+                self.expr(cond);
+                let b = self.jump(BZ);
+                self.expr(then);
+                self.convert(then.ty, e.ty);
+                let end = self.jump(JMP);
+                self.patch(b);
+                self.expr(els);
+                self.convert(els.ty, e.ty);
+                self.patch(end);
+            }
+            ExprKind::Cast(inner) => {
+                self.expr(inner);
+                self.convert(inner.ty, e.ty);
+            }
+            ExprKind::AddrOf(inner) => self.addr(inner),
+            ExprKind::IncDec(op, inner) => {
+                let t = inner.ty;
+                let wide = if t.is_float() { Type::DOUBLE } else { t.promote() };
+                let inc = matches!(op, IncDec::PreInc | IncDec::PostInc);
+                self.addr(inner);
+                self.e.push(PSH);
+                self.load(t);
+                self.step(t, if inc { ADD } else { SUB });
+                self.store(wide, t);
+                if matches!(op, IncDec::PostInc | IncDec::PostDec) {
+                    // undo the step on the stored value to get the old one back
+                    self.step(t, if inc { SUB } else { ADD });
+                    self.convert(wide, t);
+                }
+            }
+        }
+    }
+
+    fn binary(&mut self, e: &Expr, op: BinOp, lhs: &Expr, rhs: &Expr) {
+        let (l, r) = (lhs.ty, rhs.ty);
+        match op {
+            BinOp::LogOr | BinOp::LogAnd => {
+                self.expr(lhs);
+                let b = self.jump(if op == BinOp::LogOr { BNZ } else { BZ });
+                self.expr(rhs);
+                self.patch(b);
+                // c4 leaves the deciding operand in ax, C wants 0 or 1
+                self.e.push(PSH);
+                self.e.push(IMM);
+                self.e.push(0);
+                self.e.push(NE);
+                return;
+            }
+            _ => {
+                // unsigned int operands compute at 32 bits, so a signed one
+                // is converted to it first, as C does
+                let c = Type::common(l, r);
+                let narrow = c.is_unsigned_int() && !matches!(op, BinOp::Shl | BinOp::Shr);
+                self.expr(lhs);
+                if narrow {
+                    self.convert(l, c);
+                }
+                self.e.push(PSH);
+                self.expr(rhs);
+                if narrow {
+                    self.convert(r, c);
+                }
+            }
+        }
+        match op {
+            BinOp::Add if l.is_ptr() => {
+                self.scale(l);
+                self.e.push(ADD);
+            }
+            BinOp::Sub if l.is_ptr() && r.is_ptr() => {
+                self.e.push(SUB);
+                if l.deref().size() > 1 {
+                    self.e.push(PSH);
+                    self.e.push(IMM);
+                    self.e.push(l.deref().size());
+                    self.e.push(DIV);
+                }
+            }
+            BinOp::Sub if l.is_ptr() => {
+                self.scale(l);
+                self.e.push(SUB);
+            }
+            BinOp::Shl => {
+                self.e.push(SHL);
+                self.wrap(e.ty);
+            }
+            BinOp::Shr => self.e.push(if e.ty.is_unsigned() { USHR } else { SHR }),
+            _ => {
+                let code = match op {
+                    BinOp::Or => OR,
+                    BinOp::Xor => XOR,
+                    BinOp::And => AND,
+                    BinOp::Eq => EQ,
+                    BinOp::Ne => NE,
+                    BinOp::Lt => LT,
+                    BinOp::Gt => GT,
+                    BinOp::Le => LE,
+                    BinOp::Ge => GE,
+                    BinOp::Add => ADD,
+                    BinOp::Sub => SUB,
+                    BinOp::Mul => MUL,
+                    BinOp::Div => DIV,
+                    _ => MOD,
+                };
+                self.arith(l, r, code);
+            }
+        }
+    }
+
+    // multiplies the integer in ax by the size of what pointer type t points to
+    fn scale(&mut self, t: Type) {
+        if t.deref().size() > 1 {
+            self.e.push(PSH);
+            self.e.push(IMM);
+            self.e.push(t.deref().size());
+            self.e.push(MUL);
+        }
+    }
+
+    // emits the code converting the value in ax from type `from` to type `to`.
+    // int, long and long long share the cell width, so only char, short and
+    // unsigned int targets ever need narrowing
+    fn convert(&mut self, mut from: Type, to: Type) {
+        if to.is_ptr() || from == to {
+            return;
+        }
+        if to.is_float() {
+            if !from.is_float() {
+                self.e.push(if from.is_unsigned() { UTF } else { ITF });
+            }
+            if to.base == Base::Float {
+                self.e.push(FRND);
+            }
+            return;
+        }
+        if from.is_float() {
+            self.e.push(if to.unsigned { FTU } else { FTI });
+            from = Type { base: Base::LongLong, unsigned: to.unsigned, ptr: 0 };
+        }
+        let bits = match to.base {
+            Base::Char => 8,
+            Base::Short => 16,
+            Base::Int if to.unsigned => 32,
+            _ => return,
+        };
+        // a narrower value fits as long as it doesn't lose its sign
+        if !from.is_ptr() && from.size() < to.size() && (from.unsigned || !to.unsigned) {
+            return;
+        }
+        self.e.push(if to.unsigned { ZXT } else { SXT });
+        self.e.push(bits);
+    }
+
+    // narrows the result of arithmetic in type t that can carry past 32 bits
+    // back to them, if t is unsigned int
+    fn wrap(&mut self, t: Type) {
+        if t.is_unsigned_int() {
+            self.e.push(ZXT);
+            self.e.push(32);
+        }
+    }
+
+    // emits the load for a value of type t from the address in ax
+    fn load(&mut self, t: Type) {
+        self.e.push(t.load_op());
+        // LC zero extends, so signed char needs its sign back
+        if !t.is_ptr() && t.base == Base::Char && !t.unsigned {
+            self.e.push(SXT);
+            self.e.push(8);
+        }
+    }
+
+    // emits the store of ax (of type `from`) into an lvalue of type `to`
+    fn store(&mut self, from: Type, to: Type) {
+        // SC already keeps only the low byte
+        if to.is_ptr() || to.base != Base::Char {
+            self.convert(from, to);
+        }
+        self.e.push(to.store_op());
+    }
+
+    // emits a binary arithmetic instruction after both operands have been
+    // converted to their common type; picks the unsigned or floating variant
+    // when needed. The left operand (of type l) is on the stack, the right
+    // (of type r) in ax
+    fn arith(&mut self, l: Type, r: Type, op: i32) {
+        let c = Type::common(l, r);
+        if c.is_float() {
+            if !r.is_float() {
+                self.e.push(if r.is_unsigned() { UTF } else { ITF });
+            }
+            if !l.is_float() {
+                self.e.push(if l.is_unsigned() { UTFS } else { ITFS });
+            }
+            self.e.push(match op {
+                ADD => FADD,
+                SUB => FSUB,
+                MUL => FMUL,
+                DIV => FDIV,
+                EQ => FEQ,
+                NE => FNE,
+                LT => FLT,
+                GT => FGT,
+                LE => FLE,
+                _ => FGE,
+            });
+        } else if c.is_unsigned() {
+            self.e.push(match op {
+                LT => ULT,
+                GT => UGT,
+                LE => ULE,
+                GE => UGE,
+                DIV => UDIV,
+                MOD => UMOD,
+                _ => op,
+            });
+        } else {
+            self.e.push(op);
+        }
+        if matches!(op, ADD | SUB | MUL) {
+            self.wrap(c);
+        }
+    }
+
+    // emits `ax = ax +/- 1` for ++ and --, stepping pointers by the size of
+    // what they point to
+    fn step(&mut self, t: Type, op: i32) {
+        self.e.push(PSH);
+        self.e.push(IMM);
+        self.e.push(if t.is_ptr() { t.deref().size() } else { 1 });
+        if t.is_float() {
+            self.e.push(ITF);
+            self.e.push(if op == ADD { FADD } else { FSUB });
+        } else {
+            self.e.push(op);
+            self.wrap(t);
+        }
+    }
+
+    // emits the FIMM loading the double v into ax
+    fn float_imm(&mut self, v: f64) {
+        let bits = v.to_bits();
+        self.e.push(FIMM);
+        self.e.push(bits as u32 as i32);
+        self.e.push((bits >> 32) as u32 as i32);
+    }
+}
@@ -0,0 +1,147 @@
+// The --coverage mode. The VM counts how often each text address runs; at
+// exit the line table turns those counts into source lines, giving a summary
+// of the lines and instructions each function ran and, with --coverage=FILE,
+// an lcov tracefile that genhtml and most editors' coverage views read:
+//
+//   SF:prog.c          the source file
+//   FN:12,main         a function and the line it starts on
+//   FNDA:1,main        how often it was called
+//   DA:13,5            how often a line ran
+//   LF:20 / LH:17      lines found and hit, then end_of_record
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::codegen::{line_of, operands};
+
+struct Func {
+    name: String,
+    start: usize, // text addresses [start, end)
+    end: usize,
+}
+
+pub struct Coverage {
+    counts: Vec<u64>,    // executions per text address
+    insns: Vec<usize>,   // the address of every instruction in the program
+    funcs: Vec<Func>,    // by address
+    source: String,      // the file the lines are in
+    lcov: Option<String>, // where to write the tracefile
+}
+
+// a function's coverage: its lines with how often each ran, and its instructions run
+struct Summary {
+    lines: BTreeMap<i32, u64>,
+    insns: usize,
+    insns_run: usize,
+}
+
+impl Coverage {
+    // covers the first len words of text, the program proper without the
+    // exit stub after it; functions maps every function's name to its entry
+    pub fn new(text: &[i64], len: usize, functions: &HashMap<String, i32>, source: &str, lcov: Option<String>) -> Self {
+        let mut insns = Vec::new();
+        let mut pc = 0;
+        while pc < len {
+            insns.push(pc);
+            pc += 1 + operands(text[pc] as i32);
+        }
+        let mut starts: Vec<(usize, &String)> = functions.iter().map(|(name, &at)| (at as usize, name)).collect();
+        starts.sort();
+        let funcs = starts
+            .iter()
+            .enumerate()
+            .map(|(i, &(start, name))| Func { name: name.clone(), start, end: starts.get(i + 1).map_or(len, |&(at, _)| at) })
+            .collect();
+        let source = std::fs::canonicalize(source).map_or(source.to_string(), |p| p.display().to_string());
+        Coverage { counts: vec![0; text.len()], insns, funcs, source, lcov }
+    }
+
+    pub fn step(&mut self, pc: usize) {
+        if let Some(n) = self.counts.get_mut(pc) {
+            *n += 1;
+        }
+    }
+
+    // a line runs as often as the busiest instruction generated from it
+    fn summary(&self, f: &Func, lines: &[(i32, i32)]) -> Summary {
+        let mut s = Summary { lines: BTreeMap::new(), insns: 0, insns_run: 0 };
+        for &pc in self.insns.iter().filter(|&&pc| pc >= f.start && pc < f.end) {
+            let n = self.counts[pc];
+            s.insns += 1;
+            s.insns_run += (n > 0) as usize;
+            if let Some(line) = line_of(lines, pc) {
+                let count = s.lines.entry(line).or_default();
+                *count = (*count).max(n);
+            }
+        }
+        s
+    }
+
+    // the report printed at exit, and the tracefile written if asked for
+    pub fn report(&self, lines: &[(i32, i32)]) -> String {
+        let summaries: Vec<Summary> = self.funcs.iter().map(|f| self.summary(f, lines)).collect();
+        let percent = |n: usize, of: usize| if of == 0 { 100.0 } else { n as f64 * 100.0 / of as f64 };
+        let hit = |s: &Summary| s.lines.values().filter(|&&n| n > 0).count();
+
+        let (lines_hit, lines_found) = summaries.iter().fold((0, 0), |(h, f), s| (h + hit(s), f + s.lines.len()));
+        let (insns_run, insns) = summaries.iter().fold((0, 0), |(r, n), s| (r + s.insns_run, n + s.insns));
+        let mut out = format!(
+            "coverage: {:.1}% of lines ({}/{}), {:.1}% of instructions ({}/{})\n",
+            percent(lines_hit, lines_found),
+            lines_hit,
+            lines_found,
+            percent(insns_run, insns),
+            insns_run,
+            insns
+        );
+        for (f, s) in self.funcs.iter().zip(&summaries) {
+            let missed: Vec<String> = s.lines.iter().filter(|&(_, &n)| n == 0).map(|(line, _)| line.to_string()).collect();
+            out.push_str(&format!(
+                "  {:<20} lines {:>3}/{:<3} {:>5.1}%  instructions {:>4}/{:<4} {:>5.1}%",
+                f.name,
+                hit(s),
+                s.lines.len(),
+                percent(hit(s), s.lines.len()),
+                s.insns_run,
+                s.insns,
+                percent(s.insns_run, s.insns)
+            ));
+            if !missed.is_empty() {
+                out.push_str(&format!("  not run: {}", missed.join(", ")));
+            }
+            out.push('\n');
+        }
+
+        if let Some(path) = &self.lcov {
+            match std::fs::write(path, self.lcov(&summaries, lines)) {
+                Ok(()) => out.push_str(&format!("  lcov data written to {}\n", path)),
+                Err(e) => out.push_str(&format!("  could not write {}: {}\n", path, e)),
+            }
+        }
+        out
+    }
+
+    fn lcov(&self, summaries: &[Summary], lines: &[(i32, i32)]) -> String {
+        let mut out = format!("TN:\nSF:{}\n", self.source);
+        for f in &self.funcs {
+            out.push_str(&format!("FN:{},{}\n", line_of(lines, f.start).unwrap_or(0), f.name));
+        }
+        for f in &self.funcs {
+            out.push_str(&format!("FNDA:{},{}\n", self.counts[f.start], f.name)); // the ENT runs once per call
+        }
+        let called = self.funcs.iter().filter(|f| self.counts[f.start] > 0).count();
+        out.push_str(&format!("FNF:{}\nFNH:{}\n", self.funcs.len(), called));
+        let mut all: BTreeMap<i32, u64> = BTreeMap::new();
+        for s in summaries {
+            for (&line, &n) in &s.lines {
+                let count = all.entry(line).or_default();
+                *count = (*count).max(n);
+            }
+        }
+        for (line, n) in &all {
+            out.push_str(&format!("DA:{},{}\n", line, n));
+        }
+        let hit = all.values().filter(|&&n| n > 0).count();
+        out.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", all.len(), hit));
+        out
+    }
+}
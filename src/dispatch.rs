@@ -0,0 +1,392 @@
+// Pre-decoded dispatch. `VM::run` fetches every opcode and operand from the
+// i64 text segment and matches on the raw number; `decode` does that work
+// once up front, turning the text into typed instructions whose operands are
+// already unpacked and whose jump targets are instruction indices. The
+// memory layout, the stack contents (return addresses stay text addresses)
+// and the system calls are exactly those of `VM::run`.
+
+use crate::codegen::{bad_operand, operands};
+use crate::sandbox::Limit;
+use crate::vm::{RuntimeError, VM};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Insn {
+    Lea(i64),
+    Imm(i64),           // IMM and FIMM alike
+    Jmp(usize),
+    Jsr(usize, i64),    // target, and the text address to return to
+    Bz(usize),
+    Bnz(usize),
+    Ent(usize),
+    Adj(usize),
+    Lev,
+    Li,
+    Lc,
+    Si,
+    Sc,
+    Psh,
+    Or,
+    Xor,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Sys(i64, usize),    // system call and, for printf, its argument count
+    Ult,
+    Ugt,
+    Ule,
+    Uge,
+    Ushr,
+    Udiv,
+    Umod,
+    Sxt(u32),           // shift that keeps the low bits
+    Zxt(u32),
+    Fadd,
+    Fsub,
+    Fmul,
+    Fdiv,
+    Feq,
+    Fne,
+    Flt,
+    Fgt,
+    Fle,
+    Fge,
+    Itf,
+    Utf,
+    Fti,
+    Ftu,
+    Frnd,
+    Itfs,
+    Utfs,
+}
+
+pub struct Decoded {
+    pub insns: Vec<Insn>,
+    index: Vec<usize>, // text address -> instruction index, usize::MAX inside an instruction
+    addrs: Vec<usize>, // instruction index -> text address, for reporting faults
+}
+
+impl Decoded {
+    // the instruction starting at a text address
+    fn get(&self, addr: usize) -> Option<usize> {
+        match self.index.get(addr) {
+            Some(&i) if i != usize::MAX && i < self.insns.len() => Some(i),
+            _ => None,
+        }
+    }
+}
+
+// decodes a whole text segment, or gives the text address of the first
+// instruction that can't run and why, for VM::fault
+pub fn decode(text: &[i64]) -> Result<Decoded, (usize, String)> {
+    let mut index = vec![usize::MAX; text.len() + 1];
+    let mut at = Vec::new();
+    let mut pc = 0;
+    while pc < text.len() {
+        index[pc] = at.len();
+        at.push(pc);
+        pc += 1 + operands(text[pc] as i32);
+    }
+    index[text.len()] = at.len();
+
+    let insns = at
+        .iter()
+        .map(|&pc| {
+            let arg = |k: usize| text.get(pc + k).copied().unwrap_or(0);
+            let op = i32::try_from(text[pc]).unwrap_or(-1);
+            if pc + operands(op) >= text.len() {
+                return Err((pc, "the program ends inside an instruction".to_string()));
+            }
+            if let Some(why) = bad_operand(op, arg(1)) {
+                return Err((pc, why));
+            }
+            let target = |addr: i64| match index.get(addr as usize) {
+                Some(&i) if i != usize::MAX => Ok(i),
+                _ => Err((pc, format!("jump to {}, which is not the start of an instruction", addr))),
+            };
+            Ok(match text[pc] {
+                0 => Insn::Lea(arg(1)),
+                1 => Insn::Imm(arg(1)),
+                2 => Insn::Jmp(target(arg(1))?),
+                3 => Insn::Jsr(target(arg(1))?, pc as i64 + 2),
+                4 => Insn::Bz(target(arg(1))?),
+                5 => Insn::Bnz(target(arg(1))?),
+                6 => Insn::Ent(arg(1) as usize),
+                7 => Insn::Adj(arg(1) as usize),
+                8 => Insn::Lev,
+                9 => Insn::Li,
+                10 => Insn::Lc,
+                11 => Insn::Si,
+                12 => Insn::Sc,
+                13 => Insn::Psh,
+                14 => Insn::Or,
+                15 => Insn::Xor,
+                16 => Insn::And,
+                17 => Insn::Eq,
+                18 => Insn::Ne,
+                19 => Insn::Lt,
+                20 => Insn::Gt,
+                21 => Insn::Le,
+                22 => Insn::Ge,
+                23 => Insn::Shl,
+                24 => Insn::Shr,
+                25 => Insn::Add,
+                26 => Insn::Sub,
+                27 => Insn::Mul,
+                28 => Insn::Div,
+                29 => Insn::Mod,
+                // printf reads its argument count from the ADJ after it, like c4
+                33 => Insn::Sys(33, if text.get(pc + 1) == Some(&7) { arg(2) as usize } else { 0 }),
+                op @ (30..=38 | 66) => Insn::Sys(op, 0),
+                39 => Insn::Ult,
+                40 => Insn::Ugt,
+                41 => Insn::Ule,
+                42 => Insn::Uge,
+                43 => Insn::Ushr,
+                44 => Insn::Udiv,
+                45 => Insn::Umod,
+                46 => Insn::Sxt(64 - arg(1) as u32),
+                47 => Insn::Zxt(64 - arg(1) as u32),
+                48 => Insn::Imm(((arg(2) as u32 as u64) << 32 | arg(1) as u32 as u64) as i64),
+                49 => Insn::Fadd,
+                50 => Insn::Fsub,
+                51 => Insn::Fmul,
+                52 => Insn::Fdiv,
+                53 => Insn::Feq,
+                54 => Insn::Fne,
+                55 => Insn::Flt,
+                56 => Insn::Fgt,
+                57 => Insn::Fle,
+                58 => Insn::Fge,
+                59 => Insn::Itf,
+                60 => Insn::Utf,
+                61 => Insn::Fti,
+                62 => Insn::Ftu,
+                63 => Insn::Frnd,
+                64 => Insn::Itfs,
+                65 => Insn::Utfs,
+                op => return Err((pc, format!("unknown instruction {}", op))),
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Decoded { insns, index, addrs: at })
+}
+
+fn f(cell: i64) -> f64 {
+    f64::from_bits(cell as u64)
+}
+
+fn cell(v: f64) -> i64 {
+    v.to_bits() as i64
+}
+
+impl VM {
+    // runs the program like `run`, but from the decoded instructions
+    pub fn run_decoded(&mut self, code: &Decoded) {
+        let insns = &code.insns[..];
+        let Some(mut pc) = code.get(self.pc) else {
+            self.fault(format!("jump to {}, which is not the start of an instruction", self.pc));
+            return;
+        };
+        // the registers live in locals while running, and go back at every system call
+        let (mut sp, mut bp, mut ax) = (self.sp, self.bp, self.ax);
+        let mut cycle = self.cycle;
+        let len = self.stack.len();
+        let mut depth = self.depth;
+        let max_cycles = self.limits.cycles.unwrap_or(u64::MAX);
+        let max_depth = self.limits.depth.unwrap_or(usize::MAX);
+        while self.running {
+            // falling off the end of the text, like run() does
+            let Some(&insn) = insns.get(pc) else {
+                (self.sp, self.bp, self.ax) = (sp, bp, ax);
+                (self.cycle, self.depth) = (cycle, depth);
+                self.pc = self.text.len();
+                self.fault(format!("jump to {}, outside the program", self.pc));
+                break;
+            };
+            pc += 1;
+            cycle += 1;
+            // stops on a fault or a limit the way run() does, at the instruction's text address
+            macro_rules! stop {
+                ($error:expr) => {{
+                    (self.sp, self.bp, self.ax) = (sp, bp, ax);
+                    (self.cycle, self.depth) = (cycle, depth);
+                    self.pc = code.addrs[pc - 1];
+                    self.stop($error);
+                    break;
+                }};
+            }
+            macro_rules! fault {
+                ($($why:tt)*) => {
+                    stop!(RuntimeError::Fault(format!($($why)*)))
+                };
+            }
+            if cycle > max_cycles {
+                stop!(RuntimeError::LimitExceeded(Limit::Cycles(max_cycles)));
+            }
+            // binary operators take their left operand off the stack
+            macro_rules! pop {
+                () => {{
+                    if sp >= len {
+                        fault!("stack underflow");
+                    }
+                    sp += 1;
+                    self.stack[sp - 1]
+                }};
+            }
+            // a cell the program reads or writes
+            macro_rules! at {
+                ($addr:expr, $what:literal) => {{
+                    let addr = $addr;
+                    if addr < 0 || addr >= len as i64 {
+                        fault!(concat!($what, " {}, outside memory"), addr);
+                    }
+                    addr as usize
+                }};
+            }
+            // the divisor, which must not be zero
+            macro_rules! divisor {
+                () => {{
+                    if ax == 0 {
+                        fault!("division by zero");
+                    }
+                    ax
+                }};
+            }
+            match insn {
+                Insn::Lea(off) => ax = (bp as i64).wrapping_add(off),
+                Insn::Imm(v) => ax = v,
+                Insn::Jmp(t) => pc = t,
+                Insn::Jsr(t, ret) => {
+                    if sp <= self.heap {
+                        fault!("stack overflow");
+                    }
+                    if depth >= max_depth {
+                        stop!(RuntimeError::LimitExceeded(Limit::Depth(max_depth)));
+                    }
+                    depth += 1;
+                    sp -= 1;
+                    self.stack[sp] = ret;
+                    pc = t;
+                }
+                Insn::Bz(t) => {
+                    if ax == 0 {
+                        pc = t;
+                    }
+                }
+                Insn::Bnz(t) => {
+                    if ax != 0 {
+                        pc = t;
+                    }
+                }
+                Insn::Ent(n) => {
+                    if sp <= self.heap + n + 1 {
+                        fault!("stack overflow");
+                    }
+                    sp -= 1;
+                    self.stack[sp] = bp as i64;
+                    bp = sp;
+                    sp -= n;
+                }
+                Insn::Adj(n) => {
+                    if n > len - sp {
+                        fault!("stack underflow");
+                    }
+                    sp += n;
+                }
+                Insn::Lev => {
+                    if bp >= len - 1 {
+                        fault!("stack underflow");
+                    }
+                    sp = bp;
+                    bp = self.stack[sp] as usize;
+                    let ret = self.stack[sp + 1];
+                    pc = match code.get(ret as usize) {
+                        Some(i) => i,
+                        None => fault!("return to {}, which is not the start of an instruction", ret),
+                    };
+                    sp += 2;
+                    depth = depth.saturating_sub(1);
+                }
+                Insn::Li => ax = self.stack[at!(ax, "read from")],
+                Insn::Lc => ax = self.stack[at!(ax, "read from")] & 0xFF,
+                Insn::Si => {
+                    let addr = at!(pop!(), "write to");
+                    self.stack[addr] = ax;
+                }
+                Insn::Sc => {
+                    let addr = at!(pop!(), "write to");
+                    self.stack[addr] = ax & 0xFF;
+                }
+                Insn::Psh => {
+                    if sp <= self.heap {
+                        fault!("stack overflow");
+                    }
+                    sp -= 1;
+                    self.stack[sp] = ax;
+                }
+                Insn::Or => ax |= pop!(),
+                Insn::Xor => ax ^= pop!(),
+                Insn::And => ax &= pop!(),
+                Insn::Eq => ax = (pop!() == ax) as i64,
+                Insn::Ne => ax = (pop!() != ax) as i64,
+                Insn::Lt => ax = (pop!() < ax) as i64,
+                Insn::Gt => ax = (pop!() > ax) as i64,
+                Insn::Le => ax = (pop!() <= ax) as i64,
+                Insn::Ge => ax = (pop!() >= ax) as i64,
+                Insn::Shl => ax = pop!().wrapping_shl(ax as u32),
+                Insn::Shr => ax = pop!().wrapping_shr(ax as u32),
+                Insn::Add => ax = ax.wrapping_add(pop!()),
+                Insn::Sub => ax = pop!().wrapping_sub(ax),
+                Insn::Mul => ax = ax.wrapping_mul(pop!()),
+                Insn::Div => ax = pop!().wrapping_div(divisor!()),
+                Insn::Mod => ax = pop!().wrapping_rem(divisor!()),
+                Insn::Ult => ax = ((pop!() as u64) < ax as u64) as i64,
+                Insn::Ugt => ax = ((pop!() as u64) > ax as u64) as i64,
+                Insn::Ule => ax = ((pop!() as u64) <= ax as u64) as i64,
+                Insn::Uge => ax = ((pop!() as u64) >= ax as u64) as i64,
+                Insn::Ushr => ax = (pop!() as u64).wrapping_shr(ax as u32) as i64,
+                Insn::Udiv => ax = ((pop!() as u64) / divisor!() as u64) as i64,
+                Insn::Umod => ax = ((pop!() as u64) % divisor!() as u64) as i64,
+                Insn::Sxt(shift) => ax = (ax << shift) >> shift,
+                Insn::Zxt(shift) => ax = ((ax as u64) << shift >> shift) as i64,
+                Insn::Fadd => ax = cell(f(pop!()) + f(ax)),
+                Insn::Fsub => ax = cell(f(pop!()) - f(ax)),
+                Insn::Fmul => ax = cell(f(pop!()) * f(ax)),
+                Insn::Fdiv => ax = cell(f(pop!()) / f(ax)),
+                Insn::Feq => ax = (f(pop!()) == f(ax)) as i64,
+                Insn::Fne => ax = (f(pop!()) != f(ax)) as i64,
+                Insn::Flt => ax = (f(pop!()) < f(ax)) as i64,
+                Insn::Fgt => ax = (f(pop!()) > f(ax)) as i64,
+                Insn::Fle => ax = (f(pop!()) <= f(ax)) as i64,
+                Insn::Fge => ax = (f(pop!()) >= f(ax)) as i64,
+                Insn::Itf => ax = cell(ax as f64),
+                Insn::Utf => ax = cell(ax as u64 as f64),
+                Insn::Fti => ax = f(ax) as i64,
+                Insn::Ftu => ax = f(ax) as u64 as i64,
+                Insn::Frnd => ax = cell(f(ax) as f32 as f64),
+                Insn::Itfs | Insn::Utfs if sp >= len => fault!("stack underflow"),
+                Insn::Itfs => self.stack[sp] = cell(self.stack[sp] as f64),
+                Insn::Utfs => self.stack[sp] = cell(self.stack[sp] as u64 as f64),
+                Insn::Sys(op, argc) => {
+                    (self.sp, self.bp, self.ax) = (sp, bp, ax);
+                    self.pc = code.addrs[pc - 1] + 1; // where run() has it during a system call
+                    self.syscall(op, argc);
+                    (sp, bp, ax) = (self.sp, self.bp, self.ax);
+                }
+            }
+        }
+        (self.sp, self.bp, self.ax) = (sp, bp, ax);
+        (self.cycle, self.depth) = (cycle, depth);
+    }
+}
@@ -0,0 +1,211 @@
+// Source-to-source targets: --emit=asm writes the native backend's code as a
+// GNU assembler file, --emit=c lowers the bytecode to C with one function per
+// c4 function. Both keep the VM's memory model and get their system calls
+// from src/runtime.c: the assembly links against it, the C embeds it.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::codegen::operands;
+use crate::native;
+
+const RUNTIME: &str = include_str!("runtime.c");
+
+// the text address each function starts at, in order
+fn entries(functions: &HashMap<String, i32>) -> BTreeMap<usize, &str> {
+    functions.iter().map(|(name, &pc)| (pc as usize, name.as_str())).collect()
+}
+
+// the data segment as a list of byte literals, 16 to a line
+fn data_bytes(data: &[u8]) -> String {
+    data.chunks(16)
+        .map(|c| c.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(", "))
+        .collect::<Vec<_>>()
+        .join(",\n    ")
+}
+
+pub fn asm(text: &[i64], functions: &HashMap<String, i32>, entry: usize, stub: usize, data: &[u8]) -> String {
+    let code = native::compile(text);
+    let names = entries(functions);
+    let mut out = String::from("# generated by c4 --emit=asm, link with src/runtime.c\n    .text\n");
+    // c4_start(mem, sp) enters main with the stack the runtime set up, and
+    // rbx at the runtime's c4_ctx, which the checks read the bounds from
+    out.push_str("    .globl c4_start\n    .type c4_start, @function\nc4_start:\n");
+    let text_len = format!("movq ${}, 16(%rbx)", text.len());
+    for line in [
+        "pushq %rbx", "pushq %r12", "pushq %r13", "pushq %r14", "pushq %r15",
+        "movq %rdi, %r15", "movq %rsi, %r12", "movq %rsi, %r13", "leaq c4_table(%rip), %r14", "leaq c4_ctx(%rip), %rbx",
+        &text_len,
+    ] {
+        out.push_str(&format!("    {}\n", line));
+    }
+    out.push_str(&format!("    jmp .L{}\n", entry));
+
+    let mut at = code.starts.iter().enumerate().filter_map(|(pc, s)| s.map(|i| (i, pc))).peekable();
+    for (i, line) in code.lines.iter().enumerate() {
+        while let Some(&(_, pc)) = at.peek().filter(|&&(j, _)| j == i) {
+            if let Some(name) = names.get(&pc) {
+                out.push_str(&format!("\n# {}\nc4_fn_{}:\n", name, name));
+            } else if pc == stub {
+                out.push_str("\n# returning from main exits\n");
+            }
+            out.push_str(&format!(".L{}:\n", pc));
+            at.next();
+        }
+        out.push_str(&format!("    {}\n", line.asm));
+    }
+    out.push_str("\n.Lexit:\n");
+    for line in ["popq %r15", "popq %r14", "popq %r13", "popq %r12", "popq %rbx", "ret"] {
+        out.push_str(&format!("    {}\n", line));
+    }
+    // c4_fault(ctx, pc, kind, value) reports a failed check and exits
+    out.push_str(".Lfault:\n    movq %rbx, %rdi\n    call c4_fault\n");
+    out.push_str(&format!(".Ltrap:\n    movl ${}, %edx\n    jmp .Lfault\n", native::RETURN));
+    for (target, pc) in code.strays() {
+        out.push_str(&format!(".L{}:\n    movl ${}, %ecx\n    movl ${}, %esi\n", target, target, pc));
+        out.push_str(&format!("    movl ${}, %edx\n    jmp .Lfault\n", native::JUMP));
+    }
+
+    // LEV returns through this table from text addresses to code
+    out.push_str("\n    .section .data.rel.ro,\"aw\"\n    .p2align 3\nc4_table:\n");
+    for (pc, s) in code.starts.iter().enumerate() {
+        match s {
+            Some(_) => out.push_str(&format!("    .quad .L{}\n", pc)),
+            None => out.push_str("    .quad .Ltrap\n"),
+        }
+    }
+    out.push_str("\n    .section .rodata\n    .globl c4_data\n    .globl c4_data_len\n    .globl c4_stub\nc4_data:\n");
+    if !data.is_empty() {
+        out.push_str(&format!("    .byte {}\n", data_bytes(data).replace(",\n    ", "\n    .byte ")));
+    }
+    out.push_str(&format!("    .p2align 3\nc4_data_len:\n    .quad {}\nc4_stub:\n    .quad {}\n", data.len(), stub));
+    out.push_str("    .section .note.GNU-stack,\"\",@progbits\n");
+    out
+}
+
+// one instruction as a C statement; ax, sp and bp are globals, a holds the
+// left operand of binary operators. A call has to go to a function
+fn statement(text: &[i64], pc: usize, names: &BTreeMap<usize, &str>) -> Result<String, String> {
+    let arg = |k: usize| text.get(pc + k).copied().unwrap_or(0);
+    let binary = |expr: &str| format!("a = M[sp++]; ax = {};", expr);
+    Ok(match text[pc] {
+        0 => format!("ax = bp + {};", arg(1)),
+        1 => format!("ax = {};", arg(1)),
+        2 => format!("goto L{};", arg(1)),
+        3 => match names.get(&(arg(1) as usize)) {
+            Some(name) => format!("M[--sp] = {}; f_{}();", pc + 2, name),
+            None => return Err(format!("call to {}, which is not a function", arg(1))),
+        },
+        4 => format!("if (!ax) goto L{};", arg(1)),
+        5 => format!("if (ax) goto L{};", arg(1)),
+        6 => format!("M[--sp] = bp; bp = sp; sp -= {};", arg(1)),
+        7 => format!("sp += {};", arg(1)),
+        8 => "sp = bp; bp = M[sp]; sp += 2; return;".to_string(),
+        9 => "ax = M[ax];".to_string(),
+        10 => "ax = M[ax] & 0xFF;".to_string(),
+        11 => "a = M[sp++]; M[a] = ax;".to_string(),
+        12 => "a = M[sp++]; M[a] = ax & 0xFF;".to_string(),
+        13 => "M[--sp] = ax;".to_string(),
+        14 => binary("a | ax"),
+        15 => binary("a ^ ax"),
+        16 => binary("a & ax"),
+        17 => binary("a == ax"),
+        18 => binary("a != ax"),
+        19 => binary("a < ax"),
+        20 => binary("a > ax"),
+        21 => binary("a <= ax"),
+        22 => binary("a >= ax"),
+        // like x86 and Rust's wrapping operations: shift counts are taken
+        // mod 64 and arithmetic wraps, where C would leave it undefined
+        23 => binary("(long long)((U)a << (ax & 63))"),
+        24 => binary("a >> (ax & 63)"),
+        25 => binary("(long long)((U)a + (U)ax)"),
+        26 => binary("(long long)((U)a - (U)ax)"),
+        27 => binary("(long long)((U)a * (U)ax)"),
+        28 => binary("a / ax"),
+        29 => binary("a % ax"),
+        38 => "c4_system(38, 0, sp, ax);".to_string(),
+        op @ (30..=37 | 66) => {
+            let argc = if op == 33 && text.get(pc + 1) == Some(&7) { arg(2) } else { 0 };
+            format!("ax = c4_system({}, {}, sp, ax);", op, argc)
+        }
+        39 => binary("(U)a < (U)ax"),
+        40 => binary("(U)a > (U)ax"),
+        41 => binary("(U)a <= (U)ax"),
+        42 => binary("(U)a >= (U)ax"),
+        43 => binary("(long long)((U)a >> (ax & 63))"),
+        44 => binary("(long long)((U)a / (U)ax)"),
+        45 => binary("(long long)((U)a % (U)ax)"),
+        46 => format!("ax = (long long)((U)ax << {0}) >> {0};", 64 - arg(1)),
+        47 => format!("ax = (long long)((U)ax << {0} >> {0});", 64 - arg(1)),
+        48 => format!("ax = (long long)0x{:x}ULL;", (arg(2) as u32 as u64) << 32 | arg(1) as u32 as u64),
+        49 => binary("c4_cell(c4_f(a) + c4_f(ax))"),
+        50 => binary("c4_cell(c4_f(a) - c4_f(ax))"),
+        51 => binary("c4_cell(c4_f(a) * c4_f(ax))"),
+        52 => binary("c4_cell(c4_f(a) / c4_f(ax))"),
+        53 => binary("c4_f(a) == c4_f(ax)"),
+        54 => binary("c4_f(a) != c4_f(ax)"),
+        55 => binary("c4_f(a) < c4_f(ax)"),
+        56 => binary("c4_f(a) > c4_f(ax)"),
+        57 => binary("c4_f(a) <= c4_f(ax)"),
+        58 => binary("c4_f(a) >= c4_f(ax)"),
+        59 => "ax = c4_cell((double)ax);".to_string(),
+        60 => "ax = c4_utf(ax);".to_string(),
+        61 => "ax = c4_fti(ax);".to_string(),
+        62 => "ax = c4_ftu(ax);".to_string(),
+        63 => "ax = c4_cell((double)(float)c4_f(ax));".to_string(),
+        64 => "M[sp] = c4_cell((double)M[sp]);".to_string(),
+        65 => "M[sp] = c4_utf(M[sp]);".to_string(),
+        op => return Err(format!("unknown instruction {}", op)),
+    })
+}
+
+pub fn c(text: &[i64], functions: &HashMap<String, i32>, entry: usize, stub: usize, data: &[u8]) -> Result<String, String> {
+    let names = entries(functions);
+    let mut targets = Vec::new();
+    let mut pc = 0;
+    while pc < text.len() {
+        if matches!(text[pc], 2 | 4 | 5) {
+            targets.push(text[pc + 1] as usize);
+        }
+        pc += 1 + operands(text[pc] as i32);
+    }
+
+    // straight-line statements for [from, to), with labels where jumps land
+    let body = |from: usize, to: usize| {
+        let mut out = String::new();
+        let mut pc = from;
+        while pc < to {
+            if targets.contains(&pc) {
+                out.push_str(&format!("L{}:;\n", pc));
+            }
+            out.push_str(&format!("  {}\n", statement(text, pc, &names)?));
+            pc += 1 + operands(text[pc] as i32);
+        }
+        Ok::<_, String>(out)
+    };
+
+    let mut out = String::from("/* generated by c4 --emit=c */\n\n");
+    out.push_str(RUNTIME);
+    out.push_str("\n#define M c4_mem\ntypedef unsigned long long U;\nstatic long long ax, sp, bp, a;\n\n");
+    for name in names.values() {
+        out.push_str(&format!("static void f_{}(void);\n", name));
+    }
+    out.push_str(&format!(
+        "\nconst unsigned char c4_data[] = {{\n    {}\n}};\nconst long long c4_data_len = {};\nconst long long c4_stub = {};\n",
+        if data.is_empty() { "0".to_string() } else { data_bytes(data) },
+        data.len(),
+        stub
+    ));
+    let starts: Vec<usize> = names.keys().copied().chain([stub]).collect();
+    for (w, (&start, name)) in starts.windows(2).zip(&names) {
+        out.push_str(&format!("\nstatic void f_{}(void)\n{{\n{}}}\n", name, body(start, w[1])?));
+    }
+    // main returns to the stub after it, which exits
+    let main = names.get(&entry).ok_or("main is not a function")?;
+    out.push_str(&format!(
+        "\nlong long c4_start(long long *mem, long long s)\n{{\n  (void)mem;\n  (void)a;\n  sp = bp = s;\n  f_{}();\n{}  return ax;\n}}\n",
+        main,
+        body(stub, text.len())?
+    ));
+    Ok(out)
+}
@@ -0,0 +1,155 @@
+// Where OPEN finds files. The VM asks its FileSystem for every path the open
+// policy lets through: HostFs is the machine's own, like c4, and MemoryFs is a
+// map from paths to contents that never touches the disk, for tests that
+// want the same input on every run and to look at what the program wrote.
+//
+// A path opens for reading when open's flags are 0 and for writing
+// otherwise, created if it isn't there and truncated if it is.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path};
+use std::rc::Rc;
+
+// an open file; the VM keeps one per descriptor it hands out
+pub trait Handle: Read + Write {}
+
+impl<T: Read + Write> Handle for T {}
+
+pub trait FileSystem {
+    fn open(&mut self, path: &Path, write: bool) -> io::Result<Box<dyn Handle>>;
+}
+
+// the host's files
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostFs;
+
+impl FileSystem for HostFs {
+    fn open(&mut self, path: &Path, write: bool) -> io::Result<Box<dyn Handle>> {
+        let f = if write { std::fs::File::create(path)? } else { std::fs::File::open(path)? };
+        Ok(Box::new(f))
+    }
+}
+
+// files kept in memory. Clones share the files, so an embedder can hand one
+// to the VM and read what the program wrote from the other.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    files: Rc<RefCell<BTreeMap<String, Vec<u8>>>>,
+}
+
+// the name a path is filed under: relative, with `.` and `..` resolved, so
+// "/in.txt", "in.txt" and "./x/../in.txt" are the same file
+fn key(path: &Path) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(p) => parts.push(p.to_str().unwrap_or("")),
+            Component::ParentDir => {
+                parts.pop();
+            }
+            _ => {}
+        }
+    }
+    parts.join("/")
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        MemoryFs::default()
+    }
+
+    // every file under dir, read now; later changes to dir aren't seen
+    pub fn snapshot(dir: &Path) -> io::Result<Self> {
+        let fs = MemoryFs::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(d) = dirs.pop() {
+            for entry in std::fs::read_dir(&d)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    fs.insert(path.strip_prefix(dir).unwrap_or(&path), std::fs::read(&path)?);
+                }
+            }
+        }
+        Ok(fs)
+    }
+
+    pub fn insert(&self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) {
+        self.files.borrow_mut().insert(key(path.as_ref()), contents.into());
+    }
+
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.files.borrow().get(&key(path.as_ref())).cloned()
+    }
+
+    // the paths of all files, in order
+    pub fn paths(&self) -> Vec<String> {
+        self.files.borrow().keys().cloned().collect()
+    }
+}
+
+impl<P: AsRef<Path>, C: Into<Vec<u8>>> FromIterator<(P, C)> for MemoryFs {
+    fn from_iter<I: IntoIterator<Item = (P, C)>>(files: I) -> Self {
+        let fs = MemoryFs::new();
+        for (path, contents) in files {
+            fs.insert(path, contents);
+        }
+        fs
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn open(&mut self, path: &Path, write: bool) -> io::Result<Box<dyn Handle>> {
+        let key = key(path);
+        let mut files = self.files.borrow_mut();
+        if write {
+            files.insert(key.clone(), Vec::new());
+        } else if !files.contains_key(&key) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        Ok(Box::new(MemoryFile { files: self.files.clone(), key, at: 0, write }))
+    }
+}
+
+// an open MemoryFs file, which reads from and writes to the map directly
+struct MemoryFile {
+    files: Rc<RefCell<BTreeMap<String, Vec<u8>>>>,
+    key: String,
+    at: usize, // the offset the next read or write starts at
+    write: bool,
+}
+
+impl Read for MemoryFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.write {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let files = self.files.borrow();
+        let contents = files.get(&self.key).map_or(&[][..], |c| &c[..]);
+        let n = buf.len().min(contents.len().saturating_sub(self.at));
+        buf[..n].copy_from_slice(&contents[self.at..self.at + n]);
+        self.at += n;
+        Ok(n)
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.write {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let mut files = self.files.borrow_mut();
+        let contents = files.entry(self.key.clone()).or_default();
+        contents.truncate(self.at);
+        contents.extend_from_slice(buf);
+        self.at += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,893 @@
+// A three-address intermediate representation in SSA form, built from the
+// typed AST for -O2. Every value is one VM cell computed by one instruction,
+// and locals whose address is never taken live in SSA values instead of
+// frame slots. ir_opt.rs optimises it and ir_lower.rs lowers it back to the
+// usual opcodes, so the VM never sees any of this.
+//
+// Construction follows Braun et al., "Simple and Efficient Construction of
+// Static Single Assignment Form": variables are looked up per block and phis
+// are only created where control flow actually merges different values.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::ast::{BinOp, Expr, ExprKind, Function, IncDec, Program, Stmt, UnOp};
+use crate::{Base, Type};
+use crate::{ADD, AND, DIV, EQ, FADD, FDIV, FEQ, FGE, FGT, FLE, FLT, FMUL, FNE, FRND, FSUB, FTI, FTU};
+use crate::{GE, GT, ITF, LE, LT, MOD, MUL, NE, OR, SHL, SHR, SUB, SXT};
+use crate::{UDIV, UGE, UGT, ULE, ULT, UMOD, USHR, UTF, XOR, ZXT};
+
+pub type ValueId = usize;
+pub type BlockId = usize;
+
+// how a Load reads and a Store writes its cell: LI/SI or LC/SC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Width {
+    Int,
+    Char,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Callee {
+    Fun(String),
+    Sys(i32),
+}
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    Const(i64),                       // any cell value, floats as their bits
+    Param(i32),                       // a parameter's value on entry, kept in its own frame slot
+    LocalAddr(i32),                   // LEA: address of a frame slot
+    GlobalAddr(i32),                  // address in the data segment, below 0 for externs
+    Load(Width, ValueId),             // value at an address
+    Store(Width, ValueId, ValueId),   // address, value
+    Bin(i32, ValueId, ValueId),       // any binary VM opcode, left and right operand
+    Un(i32, i32, ValueId),            // ITF/UTF/FTI/FTU/FRND, or SXT/ZXT with their bit count
+    Call(Callee, Vec<ValueId>),
+    Phi(Vec<(BlockId, ValueId)>),     // the value flowing in from each predecessor
+    Removed,                          // replaced by another value, no longer in any block
+}
+
+impl Op {
+    pub fn operands(&self) -> Vec<ValueId> {
+        match self {
+            Op::Load(_, a) | Op::Un(_, _, a) => vec![*a],
+            Op::Store(_, a, b) | Op::Bin(_, a, b) => vec![*a, *b],
+            Op::Call(_, args) => args.clone(),
+            Op::Phi(ins) => ins.iter().map(|&(_, v)| v).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut ValueId> {
+        match self {
+            Op::Load(_, a) | Op::Un(_, _, a) => vec![a],
+            Op::Store(_, a, b) | Op::Bin(_, a, b) => vec![a, b],
+            Op::Call(_, args) => args.iter_mut().collect(),
+            Op::Phi(ins) => ins.iter_mut().map(|(_, v)| v).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // values that are cheaper to recompute at every use than to keep around
+    pub fn is_leaf(&self) -> bool {
+        matches!(self, Op::Const(_) | Op::LocalAddr(_) | Op::GlobalAddr(_))
+    }
+
+    pub fn has_effects(&self) -> bool {
+        matches!(self, Op::Store(..) | Op::Call(..))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term {
+    Jmp(BlockId),
+    Br(ValueId, BlockId, BlockId), // taken to the first block if the value isn't zero
+    Ret(Option<ValueId>),
+}
+
+impl Term {
+    pub fn succs(&self) -> Vec<BlockId> {
+        match *self {
+            Term::Jmp(b) => vec![b],
+            Term::Br(_, t, f) => vec![t, f],
+            Term::Ret(_) => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Block {
+    pub insts: Vec<ValueId>, // phis first, then the rest in execution order
+    pub term: Option<Term>,
+    pub preds: Vec<BlockId>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Func {
+    pub name: String,
+    pub line: i32,
+    pub values: Vec<Op>,
+    pub lines: Vec<i32>,       // the source line of each value, 0 for ones the optimiser made
+    pub blocks: Vec<Block>,    // blocks[0] is the entry
+    pub reserved: Vec<i32>,    // frame offsets of locals that stay in memory
+}
+
+impl Func {
+    // a new value, not yet in any block
+    pub fn add(&mut self, op: Op, line: i32) -> ValueId {
+        self.values.push(op);
+        self.lines.push(line);
+        self.values.len() - 1
+    }
+
+    pub fn term_operand(&self, b: BlockId) -> Option<ValueId> {
+        match self.blocks[b].term {
+            Some(Term::Br(v, ..)) | Some(Term::Ret(Some(v))) => Some(v),
+            _ => None,
+        }
+    }
+
+    // blocks reachable from the entry, in reverse postorder
+    pub fn rpo(&self) -> Vec<BlockId> {
+        self.ordered(false)
+    }
+
+    // reverse postorder, visiting the false side of a branch first if asked,
+    // which puts the true side right after the branch
+    pub fn ordered(&self, false_first: bool) -> Vec<BlockId> {
+        let mut seen = vec![false; self.blocks.len()];
+        let mut post = Vec::new();
+        let mut stack = vec![(0, 0)];
+        seen[0] = true;
+        while let Some(&mut (b, ref mut i)) = stack.last_mut() {
+            let mut succs = self.blocks[b].term.as_ref().map(|t| t.succs()).unwrap_or_default();
+            if false_first {
+                succs.reverse();
+            }
+            if *i < succs.len() {
+                let s = succs[*i];
+                *i += 1;
+                if !seen[s] {
+                    seen[s] = true;
+                    stack.push((s, 0));
+                }
+            } else {
+                post.push(b);
+                stack.pop();
+            }
+        }
+        post.reverse();
+        post
+    }
+
+    // counts how often each value is used by instructions and terminators
+    pub fn use_counts(&self) -> Vec<usize> {
+        let mut uses = vec![0; self.values.len()];
+        for (b, block) in self.blocks.iter().enumerate() {
+            for &v in &block.insts {
+                for o in self.values[v].operands() {
+                    uses[o] += 1;
+                }
+            }
+            if let Some(v) = self.term_operand(b) {
+                uses[v] += 1;
+            }
+        }
+        uses
+    }
+}
+
+pub struct Module {
+    pub funcs: Vec<Func>,
+    pub externs: Vec<String>, // the extern variable behind GlobalAddr(-1), GlobalAddr(-2)...
+}
+
+// builds the IR for every function of the program
+pub fn build(program: &Program) -> Module {
+    let params: HashMap<String, Vec<Type>> =
+        program.prototypes.iter().chain(&program.functions).map(|f| (f.name.clone(), f.params.iter().map(|p| p.ty).collect())).collect();
+    Module {
+        funcs: program.functions.iter().map(|f| Builder::function(f, &params)).collect(),
+        externs: program.externs.iter().map(|g| g.name.clone()).collect(),
+    }
+}
+
+struct Builder<'a> {
+    f: Func,
+    cur: BlockId,
+    sealed: Vec<bool>,
+    defs: HashMap<(i32, BlockId), ValueId>, // current SSA value of each variable per block
+    incomplete: HashMap<BlockId, Vec<(i32, ValueId)>>, // phis of unsealed blocks waiting for operands
+    promoted: HashSet<i32>,                 // frame offsets of the variables kept in SSA values
+    ret: Type,
+    params: &'a HashMap<String, Vec<Type>>,
+    line: i32, // of the statement or expression being built
+}
+
+// the frame offsets of locals whose address is taken somewhere in e
+fn address_taken(e: &Expr, out: &mut HashSet<i32>) {
+    match &e.kind {
+        ExprKind::AddrOf(inner) => {
+            if let ExprKind::Local(_, off) = inner.kind {
+                out.insert(off);
+            }
+            address_taken(inner, out);
+        }
+        ExprKind::Call { args, .. } => args.iter().for_each(|a| address_taken(a, out)),
+        ExprKind::Unary(_, inner) | ExprKind::Cast(inner) | ExprKind::Deref(inner) | ExprKind::IncDec(_, inner) => {
+            address_taken(inner, out)
+        }
+        ExprKind::Binary(_, l, r) | ExprKind::Assign(l, r) | ExprKind::Index(l, r) => {
+            address_taken(l, out);
+            address_taken(r, out);
+        }
+        ExprKind::Cond(c, t, f) => {
+            address_taken(c, out);
+            address_taken(t, out);
+            address_taken(f, out);
+        }
+        _ => {}
+    }
+}
+
+fn stmt_address_taken(s: &Stmt, out: &mut HashSet<i32>) {
+    match s {
+        Stmt::If(c, t, e) => {
+            address_taken(c, out);
+            stmt_address_taken(t, out);
+            if let Some(e) = e {
+                stmt_address_taken(e, out);
+            }
+        }
+        Stmt::While(c, b) | Stmt::DoWhile(b, c) => {
+            address_taken(c, out);
+            stmt_address_taken(b, out);
+        }
+        Stmt::Return(Some(e), _) | Stmt::Expr(e) => address_taken(e, out),
+        Stmt::Block(body) => body.iter().for_each(|s| stmt_address_taken(s, out)),
+        _ => {}
+    }
+}
+
+impl<'a> Builder<'a> {
+    fn function(func: &Function, params: &'a HashMap<String, Vec<Type>>) -> Func {
+        let mut taken = HashSet::new();
+        func.body.iter().for_each(|s| stmt_address_taken(s, &mut taken));
+
+        // same frame layout as the parser: params above bp, locals below it
+        let n = func.params.len() as i32;
+        let param_offsets: Vec<i32> = (0..n).map(|i| n + 1 - i).collect();
+        let local_offsets: Vec<i32> = (1..=func.locals.len() as i32).map(|i| -i).collect();
+        let all: Vec<i32> = param_offsets.iter().chain(&local_offsets).copied().collect();
+
+        let mut b = Builder {
+            f: Func {
+                name: func.name.clone(),
+                line: func.line,
+                values: Vec::new(),
+                lines: Vec::new(),
+                blocks: vec![Block::default()],
+                reserved: all.iter().copied().filter(|o| taken.contains(o)).collect(),
+            },
+            cur: 0,
+            sealed: vec![true],
+            defs: HashMap::new(),
+            incomplete: HashMap::new(),
+            promoted: all.iter().copied().filter(|o| !taken.contains(o)).collect(),
+            ret: func.ret,
+            params,
+            line: func.line,
+        };
+        for &off in &param_offsets {
+            if b.promoted.contains(&off) {
+                let v = b.value(Op::Param(off));
+                b.defs.insert((off, 0), v);
+            }
+        }
+        for s in &func.body {
+            b.stmt(s);
+        }
+        if b.f.blocks[b.cur].term.is_none() {
+            b.terminate(Term::Ret(None));
+        }
+        b.f
+    }
+
+    // a value that isn't placed in any block (constants, addresses, params)
+    fn value(&mut self, op: Op) -> ValueId {
+        self.f.add(op, self.line)
+    }
+
+    fn emit(&mut self, op: Op) -> ValueId {
+        if op.is_leaf() {
+            return self.value(op);
+        }
+        let v = self.value(op);
+        self.f.blocks[self.cur].insts.push(v);
+        v
+    }
+
+    fn konst(&mut self, c: i64) -> ValueId {
+        self.value(Op::Const(c))
+    }
+
+    fn bin(&mut self, op: i32, l: ValueId, r: ValueId) -> ValueId {
+        self.emit(Op::Bin(op, l, r))
+    }
+
+    fn un(&mut self, op: i32, n: i32, v: ValueId) -> ValueId {
+        self.emit(Op::Un(op, n, v))
+    }
+
+    fn new_block(&mut self, sealed: bool) -> BlockId {
+        self.f.blocks.push(Block::default());
+        self.sealed.push(sealed);
+        self.f.blocks.len() - 1
+    }
+
+    fn terminate(&mut self, term: Term) {
+        // a branch to the same block either way is just a jump
+        let term = match term {
+            Term::Br(_, t, f) if t == f => Term::Jmp(t),
+            t => t,
+        };
+        for s in term.succs() {
+            self.f.blocks[s].preds.push(self.cur);
+        }
+        self.f.blocks[self.cur].term = Some(term);
+    }
+
+    /////////////////////////////// SSA variables ///////////////////////////////
+
+    fn write_var(&mut self, var: i32, b: BlockId, v: ValueId) {
+        self.defs.insert((var, b), v);
+    }
+
+    fn read_var(&mut self, var: i32, b: BlockId) -> ValueId {
+        if let Some(&v) = self.defs.get(&(var, b)) {
+            return v;
+        }
+        let v = if !self.sealed[b] {
+            let phi = self.phi(b);
+            self.incomplete.entry(b).or_default().push((var, phi));
+            phi
+        } else if self.f.blocks[b].preds.len() == 1 {
+            let p = self.f.blocks[b].preds[0];
+            self.read_var(var, p)
+        } else if self.f.blocks[b].preds.is_empty() {
+            self.konst(0) // read before any assignment
+        } else {
+            let phi = self.phi(b);
+            self.write_var(var, b, phi);
+            self.add_phi_operands(var, phi, b);
+            phi
+        };
+        self.write_var(var, b, v);
+        v
+    }
+
+    fn phi(&mut self, b: BlockId) -> ValueId {
+        let v = self.value(Op::Phi(Vec::new()));
+        self.f.blocks[b].insts.insert(0, v);
+        v
+    }
+
+    fn add_phi_operands(&mut self, var: i32, phi: ValueId, b: BlockId) {
+        for p in self.f.blocks[b].preds.clone() {
+            let v = self.read_var(var, p);
+            if let Op::Phi(ins) = &mut self.f.values[phi] {
+                ins.push((p, v));
+            }
+        }
+    }
+
+    fn seal(&mut self, b: BlockId) {
+        for (var, phi) in self.incomplete.remove(&b).unwrap_or_default() {
+            self.add_phi_operands(var, phi, b);
+        }
+        self.sealed[b] = true;
+    }
+
+    /////////////////////////////// statements ///////////////////////////////
+
+    fn stmt(&mut self, s: &Stmt) {
+        match s {
+            Stmt::If(cond, then, els) => {
+                let c = self.expr(cond);
+                let then_b = self.new_block(false);
+                let else_b = self.new_block(false);
+                self.terminate(Term::Br(c, then_b, else_b));
+                self.seal(then_b);
+                self.seal(else_b);
+                self.cur = then_b;
+                self.stmt(then);
+                match els {
+                    Some(els) => {
+                        let end = self.new_block(false);
+                        self.terminate(Term::Jmp(end));
+                        self.cur = else_b;
+                        self.stmt(els);
+                        self.terminate(Term::Jmp(end));
+                        self.seal(end);
+                        self.cur = end;
+                    }
+                    None => {
+                        self.terminate(Term::Jmp(else_b));
+                        self.cur = else_b;
+                    }
+                }
+            }
+            Stmt::While(cond, body) => {
+                let header = self.new_block(false);
+                self.terminate(Term::Jmp(header));
+                self.cur = header;
+                let c = self.expr(cond);
+                let body_b = self.new_block(false);
+                let exit = self.new_block(false);
+                self.terminate(Term::Br(c, body_b, exit));
+                self.seal(body_b);
+                self.cur = body_b;
+                self.stmt(body);
+                self.terminate(Term::Jmp(header));
+                self.seal(header);
+                self.seal(exit);
+                self.cur = exit;
+            }
+            Stmt::DoWhile(body, cond) => {
+                let body_b = self.new_block(false);
+                self.terminate(Term::Jmp(body_b));
+                self.cur = body_b;
+                self.stmt(body);
+                let c = self.expr(cond);
+                let exit = self.new_block(false);
+                self.terminate(Term::Br(c, body_b, exit));
+                self.seal(body_b);
+                self.seal(exit);
+                self.cur = exit;
+            }
+            Stmt::Return(value, line) => {
+                self.line = *line;
+                let v = value.as_ref().map(|e| {
+                    let v = self.expr(e);
+                    self.convert(v, e.ty, self.ret)
+                });
+                self.terminate(Term::Ret(v));
+                // anything after a return is unreachable, but still has to go somewhere
+                self.cur = self.new_block(true);
+            }
+            Stmt::Block(body) => body.iter().for_each(|s| self.stmt(s)),
+            Stmt::Expr(e) => {
+                self.expr(e);
+            }
+            Stmt::Empty => {}
+        }
+    }
+
+    /////////////////////////////// expressions ///////////////////////////////
+    // Everything below mirrors codegen.rs instruction for instruction, so -O2
+    // computes exactly the cells the plain bytecode would.
+
+    fn promoted_local(&self, e: &Expr) -> Option<i32> {
+        match e.kind {
+            ExprKind::Local(_, off) if self.promoted.contains(&off) => Some(off),
+            _ => None,
+        }
+    }
+
+    fn addr(&mut self, e: &Expr) -> ValueId {
+        match &e.kind {
+            ExprKind::Local(_, off) => self.value(Op::LocalAddr(*off)),
+            ExprKind::Global(_, addr) => self.value(Op::GlobalAddr(*addr)),
+            ExprKind::Deref(inner) => self.expr(inner),
+            ExprKind::Index(base, index) => {
+                let b = self.expr(base);
+                let i = self.expr(index);
+                let i = self.scale(i, base.ty);
+                self.bin(ADD, b, i)
+            }
+            _ => unreachable!("the parser only accepts lvalues here"),
+        }
+    }
+
+    fn expr(&mut self, e: &Expr) -> ValueId {
+        self.line = e.line;
+        match &e.kind {
+            ExprKind::Num(val) => self.konst(*val),
+            ExprKind::Float(v) => self.konst(v.to_bits() as i64),
+            ExprKind::Str(addr) => self.value(Op::GlobalAddr(*addr)),
+            ExprKind::Sizeof(t) => self.konst(t.size() as i64),
+            ExprKind::Local(..) if self.promoted_local(e).is_some() => {
+                let off = self.promoted_local(e).unwrap();
+                self.read_var(off, self.cur)
+            }
+            ExprKind::Local(..) | ExprKind::Global(..) | ExprKind::Deref(_) | ExprKind::Index(..) => {
+                let a = self.addr(e);
+                self.load(e.ty, a)
+            }
+            ExprKind::Call { name, sys, args } => {
+                let params = self.params.get(name).cloned().unwrap_or_default();
+                let mut vals = Vec::new();
+                for (i, a) in args.iter().enumerate() {
+                    let mut v = self.expr(a);
+                    if let Some(&p) = params.get(i) {
+                        v = self.convert(v, a.ty, p);
+                    }
+                    vals.push(v);
+                }
+                let callee = match sys {
+                    Some(op) => Callee::Sys(*op),
+                    None => Callee::Fun(name.clone()),
+                };
+                self.emit(Op::Call(callee, vals))
+            }
+            ExprKind::Unary(op, inner) => match op {
+                UnOp::Plus => {
+                    let v = self.expr(inner);
+                    self.convert(v, inner.ty, e.ty)
+                }
+                UnOp::Neg => {
+                    let m = self.konst(-1);
+                    let v = self.expr(inner);
+                    self.arith(m, v, Type::INT, inner.ty, MUL)
+                }
+                UnOp::Not => {
+                    let v = self.expr(inner);
+                    let z = self.konst(0);
+                    self.bin(EQ, v, z)
+                }
+                UnOp::BitNot => {
+                    let v = self.expr(inner);
+                    let m = self.konst(-1);
+                    let x = self.bin(XOR, v, m);
+                    self.wrap(x, e.ty)
+                }
+            },
+            ExprKind::Binary(op, lhs, rhs) => self.binary(e, *op, lhs, rhs),
+            ExprKind::Assign(lhs, rhs) => {
+                if let Some(off) = self.promoted_local(lhs) {
+                    let r = self.expr(rhs);
+                    let (stored, result) = self.narrow(r, rhs.ty, lhs.ty);
+                    self.write_var(off, self.cur, stored);
+                    return result;
+                }
+                let a = self.addr(lhs);
+                let r = self.expr(rhs);
+                self.store(a, r, rhs.ty, lhs.ty)
+            }
+            ExprKind::Cond(cond, then, els) => {
+                let c = self.expr(cond);
+                let then_b = self.new_block(true);
+                let else_b = self.new_block(true);
+                self.terminate(Term::Br(c, then_b, else_b));
+                let end = self.new_block(false);
+                self.cur = then_b;
+                let t = self.expr(then);
+                let t = self.convert(t, then.ty, e.ty);
+                let then_end = self.cur;
+                self.terminate(Term::Jmp(end));
+                self.cur = else_b;
+                let f = self.expr(els);
+                let f = self.convert(f, els.ty, e.ty);
+                let else_end = self.cur;
+                self.terminate(Term::Jmp(end));
+                self.seal(end);
+                self.cur = end;
+                let phi = self.phi(end);
+                self.f.values[phi] = Op::Phi(vec![(then_end, t), (else_end, f)]);
+                phi
+            }
+            ExprKind::Cast(inner) => {
+                let v = self.expr(inner);
+                self.convert(v, inner.ty, e.ty)
+            }
+            ExprKind::AddrOf(inner) => self.addr(inner),
+            ExprKind::IncDec(op, inner) => {
+                let t = inner.ty;
+                let wide = if t.is_float() { Type::DOUBLE } else { t.promote() };
+                let inc = matches!(op, IncDec::PreInc | IncDec::PostInc);
+                let result = if let Some(off) = self.promoted_local(inner) {
+                    let old = self.read_var(off, self.cur);
+                    let new = self.step(old, t, inc);
+                    let (stored, result) = self.narrow(new, wide, t);
+                    self.write_var(off, self.cur, stored);
+                    result
+                } else {
+                    let a = self.addr(inner);
+                    let old = self.load(t, a);
+                    let new = self.step(old, t, inc);
+                    self.store(a, new, wide, t)
+                };
+                if matches!(op, IncDec::PostInc | IncDec::PostDec) {
+                    let back = self.step(result, t, !inc);
+                    self.convert(back, wide, t)
+                } else {
+                    result
+                }
+            }
+        }
+    }
+
+    fn binary(&mut self, e: &Expr, op: BinOp, lhs: &Expr, rhs: &Expr) -> ValueId {
+        let (l, r) = (lhs.ty, rhs.ty);
+        if let BinOp::LogOr | BinOp::LogAnd = op {
+            // the deciding operand short circuits straight to the result
+            let a = self.expr(lhs);
+            let rhs_b = self.new_block(true);
+            let end = self.new_block(false);
+            let short = self.konst(if op == BinOp::LogOr { 1 } else { 0 });
+            let a_end = self.cur;
+            if op == BinOp::LogOr {
+                self.terminate(Term::Br(a, end, rhs_b));
+            } else {
+                self.terminate(Term::Br(a, rhs_b, end));
+            }
+            self.cur = rhs_b;
+            let b = self.expr(rhs);
+            let z = self.konst(0);
+            let b = self.bin(NE, b, z);
+            let b_end = self.cur;
+            self.terminate(Term::Jmp(end));
+            self.seal(end);
+            self.cur = end;
+            let phi = self.phi(end);
+            self.f.values[phi] = Op::Phi(vec![(a_end, short), (b_end, b)]);
+            return phi;
+        }
+        let a = self.expr(lhs);
+        let b = self.expr(rhs);
+        match op {
+            BinOp::Add if l.is_ptr() => {
+                let b = self.scale(b, l);
+                self.bin(ADD, a, b)
+            }
+            BinOp::Sub if l.is_ptr() && r.is_ptr() => {
+                let d = self.bin(SUB, a, b);
+                if l.deref().size() > 1 {
+                    let s = self.konst(l.deref().size() as i64);
+                    self.bin(DIV, d, s)
+                } else {
+                    d
+                }
+            }
+            BinOp::Sub if l.is_ptr() => {
+                let b = self.scale(b, l);
+                self.bin(SUB, a, b)
+            }
+            BinOp::Shl => {
+                let s = self.bin(SHL, a, b);
+                self.wrap(s, e.ty)
+            }
+            BinOp::Shr => self.bin(if e.ty.is_unsigned() { USHR } else { SHR }, a, b),
+            _ => {
+                let code = match op {
+                    BinOp::Or => OR,
+                    BinOp::Xor => XOR,
+                    BinOp::And => AND,
+                    BinOp::Eq => EQ,
+                    BinOp::Ne => NE,
+                    BinOp::Lt => LT,
+                    BinOp::Gt => GT,
+                    BinOp::Le => LE,
+                    BinOp::Ge => GE,
+                    BinOp::Add => ADD,
+                    BinOp::Sub => SUB,
+                    BinOp::Mul => MUL,
+                    BinOp::Div => DIV,
+                    _ => MOD,
+                };
+                self.arith(a, b, l, r, code)
+            }
+        }
+    }
+
+    fn scale(&mut self, i: ValueId, t: Type) -> ValueId {
+        if t.deref().size() > 1 {
+            let s = self.konst(t.deref().size() as i64);
+            self.bin(MUL, i, s)
+        } else {
+            i
+        }
+    }
+
+    fn load(&mut self, t: Type, a: ValueId) -> ValueId {
+        if !t.is_ptr() && t.base == Base::Char {
+            let v = self.emit(Op::Load(Width::Char, a));
+            if t.unsigned {
+                v
+            } else {
+                self.un(SXT, 8, v)
+            }
+        } else {
+            self.emit(Op::Load(Width::Int, a))
+        }
+    }
+
+    // stores v (of type from) through address a into an lvalue of type to and
+    // returns what's left in ax, like codegen's store
+    fn store(&mut self, a: ValueId, v: ValueId, from: Type, to: Type) -> ValueId {
+        if to.is_ptr() || to.base != Base::Char {
+            let v = self.convert(v, from, to);
+            self.emit(Op::Store(Width::Int, a, v));
+            v
+        } else {
+            self.emit(Op::Store(Width::Char, a, v));
+            v
+        }
+    }
+
+    // the same store for a promoted variable: returns the value a later load
+    // would see and the value left in ax
+    fn narrow(&mut self, v: ValueId, from: Type, to: Type) -> (ValueId, ValueId) {
+        if to.is_ptr() || to.base != Base::Char {
+            let v = self.convert(v, from, to);
+            (v, v)
+        } else {
+            let mut m = self.un(ZXT, 8, v);
+            if !to.unsigned {
+                m = self.un(SXT, 8, m);
+            }
+            (m, v)
+        }
+    }
+
+    fn convert(&mut self, mut v: ValueId, mut from: Type, to: Type) -> ValueId {
+        if to.is_ptr() || from == to {
+            return v;
+        }
+        if to.is_float() {
+            if !from.is_float() {
+                v = self.un(if from.is_unsigned() { UTF } else { ITF }, 0, v);
+            }
+            if to.base == Base::Float {
+                v = self.un(FRND, 0, v);
+            }
+            return v;
+        }
+        if from.is_float() {
+            v = self.un(if to.unsigned { FTU } else { FTI }, 0, v);
+            from = Type { base: Base::LongLong, unsigned: to.unsigned, ptr: 0 };
+        }
+        let bits = match to.base {
+            Base::Char => 8,
+            Base::Short => 16,
+            Base::Int if to.unsigned => 32,
+            _ => return v,
+        };
+        if !from.is_ptr() && from.size() < to.size() && (from.unsigned || !to.unsigned) {
+            return v;
+        }
+        self.un(if to.unsigned { ZXT } else { SXT }, bits, v)
+    }
+
+    fn arith(&mut self, mut a: ValueId, mut b: ValueId, l: Type, r: Type, op: i32) -> ValueId {
+        let c = Type::common(l, r);
+        if c.is_float() {
+            if !r.is_float() {
+                b = self.un(if r.is_unsigned() { UTF } else { ITF }, 0, b);
+            }
+            if !l.is_float() {
+                a = self.un(if l.is_unsigned() { UTF } else { ITF }, 0, a);
+            }
+            let fop = match op {
+                ADD => FADD,
+                SUB => FSUB,
+                MUL => FMUL,
+                DIV => FDIV,
+                EQ => FEQ,
+                NE => FNE,
+                LT => FLT,
+                GT => FGT,
+                LE => FLE,
+                _ => FGE,
+            };
+            return self.bin(fop, a, b);
+        }
+        let op = if c.is_unsigned() {
+            match op {
+                LT => ULT,
+                GT => UGT,
+                LE => ULE,
+                GE => UGE,
+                DIV => UDIV,
+                MOD => UMOD,
+                _ => op,
+            }
+        } else {
+            op
+        };
+        // unsigned int operands compute at 32 bits, like codegen's
+        if c.is_unsigned_int() {
+            a = self.convert(a, l, c);
+            b = self.convert(b, r, c);
+        }
+        let v = self.bin(op, a, b);
+        if matches!(op, ADD | SUB | MUL) {
+            self.wrap(v, c)
+        } else {
+            v
+        }
+    }
+
+    // narrows unsigned int arithmetic back to 32 bits, like codegen's wrap
+    fn wrap(&mut self, v: ValueId, t: Type) -> ValueId {
+        if t.is_unsigned_int() {
+            self.un(ZXT, 32, v)
+        } else {
+            v
+        }
+    }
+
+    fn step(&mut self, v: ValueId, t: Type, inc: bool) -> ValueId {
+        let d = self.konst(if t.is_ptr() { t.deref().size() as i64 } else { 1 });
+        if t.is_float() {
+            let d = self.un(ITF, 0, d);
+            self.bin(if inc { FADD } else { FSUB }, v, d)
+        } else {
+            let v = self.bin(if inc { ADD } else { SUB }, v, d);
+            self.wrap(v, t)
+        }
+    }
+}
+
+/////////////////////////////// printing ///////////////////////////////
+
+fn name(f: &Func, v: ValueId) -> String {
+    match f.values[v] {
+        Op::Const(c) => c.to_string(),
+        Op::LocalAddr(off) => format!("&bp[{}]", off),
+        Op::GlobalAddr(a) => format!("&data[{}]", a),
+        _ => format!("v{}", v),
+    }
+}
+
+// a readable listing of the IR for --dump-ir
+pub fn dump(module: &Module) -> String {
+    use crate::codegen::MNEMONICS;
+    let mut out = String::new();
+    for f in &module.funcs {
+        let _ = writeln!(out, "function {}:", f.name);
+        for b in f.rpo() {
+            let block = &f.blocks[b];
+            let preds: Vec<String> = block.preds.iter().map(|p| format!("b{}", p)).collect();
+            let _ = writeln!(out, "  b{}:{}", b, if preds.is_empty() { String::new() } else { format!(" ; preds {}", preds.join(" ")) });
+            for &v in &block.insts {
+                let n = |x: ValueId| name(f, x);
+                let text = match &f.values[v] {
+                    Op::Param(off) => format!("param bp[{}]", off),
+                    Op::Load(w, a) => format!("load.{:?} {}", w, n(*a)).to_lowercase(),
+                    Op::Store(w, a, x) => format!("store.{} {}, {}", format!("{:?}", w).to_lowercase(), n(*a), n(*x)),
+                    Op::Bin(op, a, x) => format!("{} {}, {}", MNEMONICS[*op as usize].to_lowercase(), n(*a), n(*x)),
+                    Op::Un(op, 0, a) => format!("{} {}", MNEMONICS[*op as usize].to_lowercase(), n(*a)),
+                    Op::Un(op, bits, a) => format!("{} {} {}", MNEMONICS[*op as usize].to_lowercase(), bits, n(*a)),
+                    Op::Call(c, args) => {
+                        let args: Vec<String> = args.iter().map(|&a| n(a)).collect();
+                        let callee = match c {
+                            Callee::Fun(name) => name.clone(),
+                            Callee::Sys(op) => MNEMONICS[*op as usize].to_lowercase(),
+                        };
+                        format!("call {}({})", callee, args.join(", "))
+                    }
+                    Op::Phi(ins) => {
+                        let ins: Vec<String> = ins.iter().map(|&(p, x)| format!("b{}: {}", p, n(x))).collect();
+                        format!("phi [{}]", ins.join(", "))
+                    }
+                    op => format!("{:?}", op),
+                };
+                if f.values[v].has_effects() && !matches!(f.values[v], Op::Call(..)) {
+                    let _ = writeln!(out, "    {}", text);
+                } else {
+                    let _ = writeln!(out, "    v{} = {}", v, text);
+                }
+            }
+            let term = match &block.term {
+                Some(Term::Jmp(t)) => format!("jmp b{}", t),
+                Some(Term::Br(c, t, e)) => format!("br {}, b{}, b{}", name(f, *c), t, e),
+                Some(Term::Ret(Some(v))) => format!("ret {}", name(f, *v)),
+                Some(Term::Ret(None)) | None => "ret".to_string(),
+            };
+            let _ = writeln!(out, "    {}", term);
+        }
+    }
+    out
+}
@@ -0,0 +1,722 @@
+// Lowers the SSA IR back to the VM's stack-machine opcodes.
+//
+// Values are rebuilt into expression trees where that is what the original
+// code did: a value used once, right where it's computed, is evaluated
+// straight into ax (or onto the stack for the left operand), exactly like
+// codegen.rs would, and what goes into a slot while such a tree waits for
+// its user is written between the operands it pushes, the way `*p++ = *s++`
+// does it. Everything else lives in a frame slot. Slots are allocated like
+// registers: values whose live ranges don't overlap share a slot, and each
+// phi is coalesced with its incoming values where possible so that
+// `i = i + 1` still writes i's own slot instead of copying through a
+// temporary.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::codegen::{Code, Reloc};
+use crate::ir::{Callee, Func, Module, Op, Term, ValueId, BlockId, Width};
+use crate::{ADJ, BNZ, BZ, ENT, FIMM, IMM, JMP, JSR, LC, LEA, LEV, LI, PSH, SC, SI, SXT, ZXT};
+
+pub fn lower(module: &Module) -> Code {
+    let mut code = Code { text: Vec::new(), functions: HashMap::new(), lines: Vec::new(), relocs: Vec::new() };
+    let mut calls = Vec::new();
+    for f in &module.funcs {
+        let mut f = f.clone();
+        split_critical_edges(&mut f);
+        Lowering::new(&f, &module.externs, &mut code, &mut calls).function();
+    }
+    // calls can only be resolved once every function has its address, and
+    // those to functions only declared are left to the linker
+    for (at, name) in calls {
+        match code.functions.get(&name) {
+            Some(&target) => code.text[at] = target,
+            None => code.relocs.push((at, Reloc::Symbol(name))),
+        }
+    }
+    code.relocs.sort_by_key(|&(at, _)| at);
+    code
+}
+
+// phi copies go at the end of the predecessor, so a predecessor that can
+// branch elsewhere too gets a block of its own for them
+fn split_critical_edges(f: &mut Func) {
+    for b in f.rpo() {
+        let Some(Term::Br(c, t, e)) = f.blocks[b].term else { continue };
+        let mut targets = [t, e];
+        for s in targets.iter_mut() {
+            let has_phis = f.blocks[*s].insts.first().is_some_and(|&v| matches!(f.values[v], Op::Phi(_)));
+            if !has_phis {
+                continue;
+            }
+            let n = f.blocks.len();
+            f.blocks.push(crate::ir::Block { insts: Vec::new(), term: Some(Term::Jmp(*s)), preds: vec![b] });
+            for p in f.blocks[*s].preds.iter_mut().filter(|p| **p == b) {
+                *p = n;
+            }
+            for i in 0..f.blocks[*s].insts.len() {
+                let v = f.blocks[*s].insts[i];
+                if let Op::Phi(ins) = &mut f.values[v] {
+                    ins.iter_mut().filter(|(p, _)| *p == b).for_each(|(p, _)| *p = n);
+                }
+            }
+            *s = n;
+        }
+        f.blocks[b].term = Some(Term::Br(c, targets[0], targets[1]));
+    }
+}
+
+// what happens, in order, in a block before its terminator
+enum Item {
+    Def(ValueId),                     // evaluate the value's tree into its slot
+    Eval(ValueId),                    // evaluate it for its side effects only
+    Copies(Vec<(ValueId, ValueId)>),  // the phi copies into a successor: phi, incoming
+}
+
+struct Lowering<'a> {
+    f: &'a Func,
+    externs: &'a [String],
+    code: &'a mut Code,
+    calls: &'a mut Vec<(usize, String)>,
+    layout: Vec<BlockId>,
+    items: Vec<Vec<Item>>,      // per block
+    inlined: Vec<bool>,         // evaluated as part of its user's tree
+    inner: HashMap<ValueId, Vec<(usize, ValueId)>>, // slot values written inside a tree, after which operand
+    slot: Vec<i32>,             // frame offset of every value that lives in one
+    frame: i32,                 // slots ENT reserves
+    ax: Option<i32>,            // the slot whose value ax is known to hold
+    stored: Option<ValueId>,    // the tree a store just left in ax
+    scratch: bool,              // a phi copy cycle needed the slot below the others
+}
+
+impl<'a> Lowering<'a> {
+    fn new(f: &'a Func, externs: &'a [String], code: &'a mut Code, calls: &'a mut Vec<(usize, String)>) -> Self {
+        Lowering {
+            f,
+            externs,
+            code,
+            calls,
+            // the true side of a branch right after it, like in codegen.rs
+            layout: f.ordered(true),
+            items: Vec::new(),
+            inlined: vec![false; f.values.len()],
+            inner: HashMap::new(),
+            slot: vec![0; f.values.len()],
+            frame: 0,
+            ax: None,
+            stored: None,
+            scratch: false,
+        }
+    }
+
+    fn is_slot(&self, v: ValueId) -> bool {
+        !self.f.values[v].is_leaf() && !self.inlined[v]
+    }
+
+    fn function(mut self) {
+        self.schedule();
+        self.allocate();
+        self.emit();
+    }
+
+    /////////////////////////////// scheduling ///////////////////////////////
+
+    // decides which values become part of their user's tree and which get
+    // evaluated into a slot, keeping everything in its original order
+    fn schedule(&mut self) {
+        let f = self.f;
+        let uses = f.use_counts();
+        // the block each value's only user is in, if it is an instruction or terminator there
+        let mut user_block = vec![None; f.values.len()];
+        for &b in &self.layout {
+            for &v in &f.blocks[b].insts {
+                if !matches!(f.values[v], Op::Phi(_)) {
+                    f.values[v].operands().into_iter().for_each(|o| user_block[o] = Some(b));
+                }
+            }
+            if let Some(v) = f.term_operand(b) {
+                user_block[v] = Some(b);
+            }
+        }
+        let phi_used: HashSet<ValueId> = self
+            .layout
+            .iter()
+            .flat_map(|&b| f.blocks[b].insts.iter())
+            .flat_map(|&v| match &f.values[v] {
+                Op::Phi(ins) => ins.iter().map(|&(_, x)| x).collect(),
+                _ => Vec::new(),
+            })
+            .collect();
+        // a value stored right before the block branches on it, as in
+        // `while (*p++ = *s++)`, is still in ax for the branch
+        let stored = |v: ValueId, b: BlockId| {
+            let last = f.blocks[b].insts.last().map(|&s| &f.values[s]);
+            uses[v] == 2 && f.term_operand(b) == Some(v) && matches!(last, Some(Op::Store(_, _, x)) if *x == v)
+        };
+        let inlinable = |v: ValueId, b: BlockId| {
+            (uses[v] == 1 || stored(v, b)) && user_block[v] == Some(b) && !phi_used.contains(&v) && !matches!(f.values[v], Op::Phi(_))
+        };
+
+        self.items = (0..f.blocks.len()).map(|_| Vec::new()).collect();
+        for b in self.layout.clone() {
+            let mut items = Vec::new();
+            // the trees waiting for their user, and the values without effects
+            // that came while they waited, by how many of the trees came first
+            let mut pending: Vec<ValueId> = Vec::new();
+            let mut waiting: Vec<(usize, ValueId)> = Vec::new();
+            let flush = |pending: &mut Vec<ValueId>, waiting: &mut Vec<(usize, ValueId)>, items: &mut Vec<Item>| {
+                for i in 0..=pending.len() {
+                    items.extend(waiting.iter().filter(|&&(k, _)| k == i).map(|&(_, d)| Item::Def(d)));
+                    items.extend(pending.get(i).map(|&p| Item::Def(p)));
+                }
+                pending.clear();
+                waiting.clear();
+            };
+            for &v in &f.blocks[b].insts {
+                if matches!(f.values[v], Op::Phi(_)) {
+                    continue;
+                }
+                if !self.consume(b, Some(v), &mut pending, &mut waiting) {
+                    flush(&mut pending, &mut waiting, &mut items);
+                }
+                if inlinable(v, b) {
+                    pending.push(v);
+                } else if !pending.is_empty() && uses[v] > 0 && !f.values[v].has_effects() {
+                    waiting.push((pending.len(), v));
+                } else {
+                    flush(&mut pending, &mut waiting, &mut items);
+                    items.push(if uses[v] > 0 { Item::Def(v) } else { Item::Eval(v) });
+                }
+            }
+            if !self.consume(b, None, &mut pending, &mut waiting) {
+                flush(&mut pending, &mut waiting, &mut items);
+            }
+            flush(&mut pending, &mut waiting, &mut items);
+            if let Some(Term::Jmp(s)) = f.blocks[b].term {
+                let copies: Vec<(ValueId, ValueId)> = f.blocks[s]
+                    .insts
+                    .iter()
+                    .filter_map(|&p| match &f.values[p] {
+                        Op::Phi(ins) => ins.iter().find(|&&(from, _)| from == b).map(|&(_, x)| (p, x)),
+                        _ => None,
+                    })
+                    .collect();
+                if !copies.is_empty() {
+                    items.push(Item::Copies(copies));
+                }
+            }
+            self.items[b] = items;
+        }
+    }
+
+    // makes the trees at the end of pending part of v's, or of the block's
+    // terminator for None, if they are its operands in order. The values that
+    // waited in between them get evaluated inside v's tree, right after the
+    // operand before them is pushed; false if that or the order doesn't work
+    // out and everything pending has to go into slots instead
+    fn consume(&mut self, b: BlockId, v: Option<ValueId>, pending: &mut Vec<ValueId>, waiting: &mut Vec<(usize, ValueId)>) -> bool {
+        let all = match v {
+            Some(v) => self.f.values[v].operands(),
+            None => self.f.term_operand(b).into_iter().collect(),
+        };
+        let ops: Vec<ValueId> = all.iter().copied().filter(|o| pending.contains(o)).collect();
+        if ops.is_empty() {
+            return true;
+        }
+        if !pending.ends_with(&ops) {
+            return false;
+        }
+        let first = pending.len() - ops.len();
+        // every operand but the last is pushed, and all of a call's arguments
+        let pushed = |j: usize| match v.map(|v| &self.f.values[v]) {
+            Some(Op::Call(..)) => true,
+            Some(Op::Bin(..) | Op::Store(..)) => j == 0,
+            _ => false,
+        };
+        let mut inner = Vec::new();
+        for &(k, d) in waiting.iter().filter(|&&(k, _)| k > first) {
+            let j = all.iter().position(|&o| o == pending[k - 1]).unwrap();
+            if !pushed(j) {
+                return false;
+            }
+            inner.push((j, d));
+        }
+        waiting.retain(|&(k, _)| k <= first);
+        pending.truncate(first);
+        ops.iter().for_each(|&o| self.inlined[o] = true);
+        if let (Some(v), false) = (v, inner.is_empty()) {
+            self.inner.insert(v, inner);
+        }
+        true
+    }
+
+    // walks a value's tree backwards from the values live after it, like
+    // transfer does a block
+    fn tree_back(&self, v: ValueId, live: &mut HashSet<ValueId>, conflict: &mut dyn FnMut(ValueId, ValueId)) {
+        let inner = self.inner.get(&v).map_or(&[][..], Vec::as_slice);
+        for (j, &o) in self.f.values[v].operands().iter().enumerate().rev() {
+            for &(_, d) in inner.iter().rev().filter(|&&(i, _)| i == j) {
+                self.def_back(d, live, conflict);
+            }
+            self.use_back(o, live, conflict);
+        }
+    }
+
+    fn use_back(&self, v: ValueId, live: &mut HashSet<ValueId>, conflict: &mut dyn FnMut(ValueId, ValueId)) {
+        if self.inlined[v] {
+            self.tree_back(v, live, conflict);
+        } else if self.is_slot(v) {
+            live.insert(v);
+        }
+    }
+
+    fn def_back(&self, v: ValueId, live: &mut HashSet<ValueId>, conflict: &mut dyn FnMut(ValueId, ValueId)) {
+        for &x in live.iter() {
+            if x != v {
+                conflict(v, x);
+            }
+        }
+        live.remove(&v);
+        self.tree_back(v, live, conflict);
+    }
+
+    /////////////////////////////// slot allocation ///////////////////////////////
+
+    fn allocate(&mut self) {
+        let f = self.f;
+        let n = f.values.len();
+
+        // liveness of slot values at block boundaries
+        let mut live_in: Vec<HashSet<ValueId>> = vec![HashSet::new(); f.blocks.len()];
+        let mut live_out: Vec<HashSet<ValueId>> = vec![HashSet::new(); f.blocks.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in self.layout.iter().rev() {
+                let mut live: HashSet<ValueId> = HashSet::new();
+                for s in f.blocks[b].term.as_ref().map(|t| t.succs()).unwrap_or_default() {
+                    live.extend(&live_in[s]);
+                }
+                live_out[b] = live.clone();
+                self.transfer(b, &mut live, &mut |_, _| {});
+                if live != live_in[b] {
+                    live_in[b] = live;
+                    changed = true;
+                }
+            }
+        }
+
+        // interference: a value conflicts with everything live where it's written
+        let mut adj: Vec<HashSet<ValueId>> = vec![HashSet::new(); n];
+        for &b in &self.layout {
+            let mut live = live_out[b].clone();
+            self.transfer(b, &mut live, &mut |d, x| {
+                adj[d].insert(x);
+                adj[x].insert(d);
+            });
+        }
+        let params: Vec<ValueId> = live_in[0].iter().copied().collect();
+        for &p in &params {
+            for &q in &params {
+                if p != q {
+                    adj[p].insert(q);
+                }
+            }
+        }
+
+        // every value that needs a slot, in a stable order
+        let mut slotted: Vec<ValueId> = Vec::new();
+        let mut seen = vec![false; n];
+        let mut add = |v: ValueId, slotted: &mut Vec<ValueId>| {
+            if !seen[v] {
+                seen[v] = true;
+                slotted.push(v);
+            }
+        };
+        for &b in &self.layout {
+            for &v in &f.blocks[b].insts {
+                if matches!(f.values[v], Op::Phi(_)) {
+                    add(v, &mut slotted);
+                }
+            }
+            for item in &self.items[b] {
+                if let Item::Def(v) = item {
+                    add(*v, &mut slotted);
+                }
+            }
+        }
+        let mut inner: Vec<ValueId> = self.inner.values().flatten().map(|&(_, d)| d).collect();
+        inner.sort();
+        for v in inner {
+            add(v, &mut slotted);
+        }
+        for v in 0..n {
+            if matches!(f.values[v], Op::Param(_)) {
+                add(v, &mut slotted);
+            }
+        }
+
+        // coalesce each phi with its incoming values unless they interfere
+        let mut class: Vec<usize> = (0..n).collect();
+        let mut members: HashMap<usize, Vec<ValueId>> = slotted.iter().map(|&v| (v, vec![v])).collect();
+        let mut color: HashMap<usize, i32> = HashMap::new();
+        for &v in &slotted {
+            if let Op::Param(off) = f.values[v] {
+                color.insert(v, off);
+            }
+        }
+        for &b in &self.layout {
+            for &p in &f.blocks[b].insts {
+                let Op::Phi(ins) = &f.values[p] else { continue };
+                for &(_, a) in ins {
+                    if !self.is_slot(a) {
+                        continue;
+                    }
+                    let (ra, rp) = (class[a], class[p]);
+                    if ra == rp {
+                        continue;
+                    }
+                    if let (Some(x), Some(y)) = (color.get(&ra), color.get(&rp)) {
+                        if x != y {
+                            continue;
+                        }
+                    }
+                    let conflict = members[&ra].iter().any(|&x| members[&rp].iter().any(|y| adj[x].contains(y)));
+                    if conflict {
+                        continue;
+                    }
+                    let moved = members.remove(&ra).unwrap();
+                    for &x in &moved {
+                        class[x] = rp;
+                    }
+                    members.get_mut(&rp).unwrap().extend(moved);
+                    if let Some(c) = color.remove(&ra) {
+                        color.insert(rp, c);
+                    }
+                }
+            }
+        }
+
+        // colour the classes with frame offsets below bp, params keep theirs
+        let reserved: HashSet<i32> = f.reserved.iter().copied().collect();
+        let roots: Vec<usize> = slotted.iter().map(|&v| class[v]).collect();
+        let mut done = HashSet::new();
+        for r in roots {
+            if !done.insert(r) || color.contains_key(&r) {
+                continue;
+            }
+            let taken: HashSet<i32> = members[&r]
+                .iter()
+                .flat_map(|&x| adj[x].iter())
+                .filter_map(|&y| color.get(&class[y]).copied())
+                .collect();
+            let c = (1..).map(|i: i32| -i).find(|c| !reserved.contains(c) && !taken.contains(c)).unwrap();
+            color.insert(r, c);
+        }
+        for &v in &slotted {
+            self.slot[v] = color[&class[v]];
+        }
+        let deepest = color.values().chain(&f.reserved).map(|&c| -c).max().unwrap_or(0);
+        self.frame = deepest.max(0);
+    }
+
+    // walks a block backwards from the values live at its end, reporting
+    // every (written value, value live at that point) pair to `conflict`
+    fn transfer(&self, b: BlockId, live: &mut HashSet<ValueId>, conflict: &mut dyn FnMut(ValueId, ValueId)) {
+        if let Some(v) = self.f.term_operand(b) {
+            self.use_back(v, live, conflict);
+        }
+        for item in self.items[b].iter().rev() {
+            match item {
+                Item::Def(v) => self.def_back(*v, live, conflict),
+                Item::Eval(v) => self.tree_back(*v, live, conflict),
+                Item::Copies(copies) => {
+                    for &(p, _) in copies {
+                        for &x in live.iter() {
+                            if x != p {
+                                conflict(p, x);
+                            }
+                        }
+                        for &(q, _) in copies {
+                            if q != p {
+                                conflict(p, q);
+                            }
+                        }
+                    }
+                    for (p, _) in copies {
+                        live.remove(p);
+                    }
+                    live.extend(copies.iter().map(|&(_, a)| a).filter(|&a| self.is_slot(a)));
+                }
+            }
+        }
+    }
+
+    /////////////////////////////// emission ///////////////////////////////
+
+    // notes that the code emitted from here on comes from a source line,
+    // like codegen.rs does; values the optimiser made have none
+    fn line(&mut self, line: i32) {
+        if line == 0 {
+            return;
+        }
+        let here = self.code.text.len() as i32;
+        match self.code.lines.last_mut() {
+            Some(last) if last.1 == line => {}
+            Some(last) if last.0 == here => *last = (here, line),
+            _ => self.code.lines.push((here, line)),
+        }
+    }
+
+    fn op(&mut self, op: i32, args: &[i32]) {
+        self.code.text.push(op);
+        self.code.text.extend(args);
+        if !matches!(op, BZ | BNZ) {
+            self.ax = None;
+        }
+        self.stored = None;
+    }
+
+    fn read_slot(&mut self, s: i32) {
+        if self.ax != Some(s) {
+            self.op(LEA, &[s]);
+            self.op(LI, &[]);
+            self.ax = Some(s);
+        }
+    }
+
+    fn write_slot(&mut self, s: i32, value: impl FnOnce(&mut Self)) {
+        self.op(LEA, &[s]);
+        self.op(PSH, &[]);
+        value(self);
+        self.op(SI, &[]);
+        self.ax = Some(s);
+    }
+
+    fn leaf(&mut self, v: ValueId) {
+        match self.f.values[v] {
+            Op::Const(c) => match i32::try_from(c) {
+                Ok(c) => self.op(IMM, &[c]),
+                Err(_) => self.op(FIMM, &[c as u32 as i32, (c as u64 >> 32) as u32 as i32]),
+            },
+            Op::LocalAddr(off) => self.op(LEA, &[off]),
+            Op::GlobalAddr(a) => {
+                let reloc = if a < 0 { Reloc::Symbol(self.externs[(-a - 1) as usize].clone()) } else { Reloc::Data };
+                self.code.relocs.push((self.code.text.len() + 1, reloc));
+                self.op(IMM, &[a]);
+            }
+            _ => self.read_slot(self.slot[v]),
+        }
+    }
+
+    fn operand(&mut self, v: ValueId) {
+        if self.stored == Some(v) {
+            return;
+        }
+        if self.inlined[v] {
+            self.tree(v);
+        } else {
+            self.leaf(v);
+        }
+    }
+
+    // the values that waited inside v's tree for its operand j to be pushed
+    fn inner_defs(&mut self, v: ValueId, j: usize) {
+        let defs: Vec<ValueId> = self.inner.get(&v).into_iter().flatten().filter(|&&(i, _)| i == j).map(|&(_, d)| d).collect();
+        for d in defs {
+            self.line(self.f.lines[d]);
+            let s = self.slot[d];
+            self.write_slot(s, |me| me.tree(d));
+        }
+    }
+
+    fn tree(&mut self, v: ValueId) {
+        match self.f.values[v].clone() {
+            Op::Load(w, a) => {
+                self.operand(a);
+                self.op(if w == Width::Char { LC } else { LI }, &[]);
+            }
+            Op::Store(w, a, x) => {
+                self.operand(a);
+                self.op(PSH, &[]);
+                self.inner_defs(v, 0);
+                self.operand(x);
+                self.op(if w == Width::Char { SC } else { SI }, &[]);
+                // a store leaves what it stored in ax
+                if self.inlined[x] {
+                    self.stored = Some(x);
+                } else if self.is_slot(x) {
+                    self.ax = Some(self.slot[x]);
+                }
+            }
+            Op::Bin(op, a, b) => {
+                self.operand(a);
+                self.op(PSH, &[]);
+                self.inner_defs(v, 0);
+                self.operand(b);
+                self.op(op, &[]);
+            }
+            Op::Un(op, n, a) => {
+                self.operand(a);
+                if op == SXT || op == ZXT {
+                    self.op(op, &[n]);
+                } else {
+                    self.op(op, &[]);
+                }
+            }
+            Op::Call(callee, args) => {
+                for (j, &a) in args.iter().enumerate() {
+                    self.operand(a);
+                    self.op(PSH, &[]);
+                    self.inner_defs(v, j);
+                }
+                match callee {
+                    Callee::Sys(op) => self.op(op, &[]),
+                    Callee::Fun(name) => {
+                        self.op(JSR, &[0]);
+                        self.calls.push((self.code.text.len() - 1, name));
+                    }
+                }
+                if !args.is_empty() {
+                    self.op(ADJ, &[args.len() as i32]);
+                }
+            }
+            _ => self.leaf(v),
+        }
+    }
+
+    // the phi copies on one edge, ordered so no slot is overwritten before
+    // it's been read, going through a scratch slot to break cycles
+    fn copies(&mut self, copies: &[(ValueId, ValueId)]) {
+        enum Src {
+            Slot(i32),
+            Leaf(ValueId),
+        }
+        let mut todo: Vec<(i32, Src)> = Vec::new();
+        for &(p, a) in copies {
+            let src = if self.is_slot(a) { Src::Slot(self.slot[a]) } else { Src::Leaf(a) };
+            if !matches!(src, Src::Slot(s) if s == self.slot[p]) {
+                todo.push((self.slot[p], src));
+            }
+        }
+        while !todo.is_empty() {
+            let ready = (0..todo.len()).find(|&i| {
+                let d = todo[i].0;
+                !todo.iter().any(|(_, s)| matches!(s, Src::Slot(x) if *x == d))
+            });
+            match ready {
+                Some(i) => {
+                    let (d, src) = todo.remove(i);
+                    self.write_slot(d, |me| match src {
+                        Src::Slot(s) => me.read_slot(s),
+                        Src::Leaf(v) => me.leaf(v),
+                    });
+                }
+                None => {
+                    let d = todo[0].0;
+                    let scratch = -(self.frame + 1);
+                    self.scratch = true;
+                    self.write_slot(scratch, |me| me.read_slot(d));
+                    for (_, s) in todo.iter_mut() {
+                        if matches!(s, Src::Slot(x) if *x == d) {
+                            *s = Src::Slot(scratch);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn emit(mut self) {
+        let f = self.f;
+        self.code.functions.insert(f.name.clone(), self.code.text.len() as i32);
+        self.line(f.line);
+        self.op(ENT, &[0]);
+        let ent = self.code.text.len() - 1;
+
+        let mut at: HashMap<BlockId, i32> = HashMap::new();
+        let mut fixups: Vec<(usize, BlockId)> = Vec::new();
+        let mut ax_at_end: HashMap<BlockId, Option<i32>> = HashMap::new();
+        let layout = self.layout.clone();
+        for (i, &b) in layout.iter().enumerate() {
+            at.insert(b, self.code.text.len() as i32);
+            // ax survives into a block that can only be entered from one place
+            self.ax = match f.blocks[b].preds[..] {
+                [p] if b != 0 => ax_at_end.get(&p).copied().flatten(),
+                _ => None,
+            };
+            let mut items = std::mem::take(&mut self.items[b]);
+            for k in 0..items.len() {
+                match &items[k] {
+                    &Item::Def(v) => {
+                        self.line(f.lines[v]);
+                        let s = self.slot[v];
+                        // a phi copy of the value right after it goes into its
+                        // slot along with it, like `d = p = x` does, unless
+                        // another copy still has to read what it overwrites
+                        let chained = match &items.get(k + 1) {
+                            Some(Item::Copies(copies)) => copies.iter().position(|&(p, x)| {
+                                let d = self.slot[p];
+                                x == v && d != s && !copies.iter().any(|&(_, y)| self.is_slot(y) && self.slot[y] == d)
+                            }),
+                            _ => None,
+                        };
+                        match chained {
+                            Some(c) => {
+                                let Item::Copies(copies) = &mut items[k + 1] else { unreachable!() };
+                                let d = self.slot[copies.remove(c).0];
+                                self.op(LEA, &[d]);
+                                self.op(PSH, &[]);
+                                self.write_slot(s, |me| me.tree(v));
+                                self.op(SI, &[]);
+                                self.ax = Some(d);
+                            }
+                            None => self.write_slot(s, |me| me.tree(v)),
+                        }
+                    }
+                    Item::Eval(v) => {
+                        self.line(f.lines[*v]);
+                        self.tree(*v);
+                    }
+                    Item::Copies(copies) => self.copies(copies),
+                }
+            }
+            let next = layout.get(i + 1).copied();
+            let mut jump = |me: &mut Self, op: i32, to: BlockId| {
+                me.op(op, &[0]);
+                fixups.push((me.code.text.len() - 1, to));
+            };
+            match f.blocks[b].term.clone() {
+                Some(Term::Jmp(t)) => {
+                    if Some(t) != next {
+                        jump(&mut self, JMP, t);
+                    }
+                }
+                Some(Term::Br(c, t, e)) => {
+                    self.line(f.lines[c]);
+                    self.operand(c);
+                    if Some(e) == next {
+                        jump(&mut self, BNZ, t);
+                    } else {
+                        jump(&mut self, BZ, e);
+                        if Some(t) != next {
+                            jump(&mut self, JMP, t);
+                        }
+                    }
+                }
+                Some(Term::Ret(v)) => {
+                    if let Some(v) = v {
+                        self.line(f.lines[v]);
+                        self.operand(v);
+                    }
+                    self.op(LEV, &[]);
+                }
+                None => self.op(LEV, &[]),
+            }
+            ax_at_end.insert(b, self.ax);
+        }
+        for (pos, b) in fixups {
+            self.code.text[pos] = at[&b];
+        }
+        self.code.text[ent] = self.frame + self.scratch as i32;
+    }
+}
@@ -0,0 +1,598 @@
+// The -O2 passes over the SSA IR: copy propagation (trivial phis and
+// algebraic identities), constant folding, dominator-based common
+// subexpression elimination, loop-invariant code motion and dead code
+// elimination. Only pure instructions are ever moved or merged; loads, stores
+// and calls stay where the source put them, apart from loads that read the
+// same cell twice with nothing in between that could have changed it.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{Block, Func, Module, Op, Term, ValueId, BlockId};
+use crate::optimize::eval;
+use crate::{ADD, AND, DIV, EQ, FADD, FDIV, FEQ, FGE, FGT, FLE, FLT, FMUL, FNE, FRND, FSUB, FTI, FTU, ITF};
+use crate::{MOD, MUL, NE, OR, SHL, SHR, SUB, SXT, UDIV, UMOD, USHR, UTF, XOR, ZXT};
+
+// runs every pass over every function
+pub fn optimize(module: &mut Module) {
+    for f in &mut module.funcs {
+        loop {
+            let mut changed = simplify_phis(f);
+            changed |= fold(f);
+            changed |= remove_unreachable(f);
+            changed |= cse(f);
+            changed |= dce(f);
+            if !changed {
+                break;
+            }
+        }
+        if licm(f) {
+            cse(f);
+            dce(f);
+        }
+    }
+}
+
+/////////////////////////////// helpers ///////////////////////////////
+
+fn resolve(fwd: &[Option<ValueId>], mut v: ValueId) -> ValueId {
+    while let Some(n) = fwd[v] {
+        v = n;
+    }
+    v
+}
+
+// points every use of a forwarded value at its replacement and drops the
+// forwarded instructions from their blocks
+fn apply(f: &mut Func, fwd: &[Option<ValueId>]) {
+    for b in 0..f.blocks.len() {
+        f.blocks[b].insts.retain(|&v| fwd[v].is_none());
+        for i in 0..f.blocks[b].insts.len() {
+            let v = f.blocks[b].insts[i];
+            for o in f.values[v].operands_mut() {
+                *o = resolve(fwd, *o);
+            }
+        }
+        match &mut f.blocks[b].term {
+            Some(Term::Br(v, ..)) | Some(Term::Ret(Some(v))) => *v = resolve(fwd, *v),
+            _ => {}
+        }
+    }
+    for (v, to) in fwd.iter().enumerate() {
+        if to.is_some() {
+            f.values[v] = Op::Removed;
+        }
+    }
+}
+
+fn konst(f: &mut Func, c: i64) -> ValueId {
+    f.add(Op::Const(c), 0)
+}
+
+fn const_of(f: &Func, v: ValueId) -> Option<i64> {
+    match f.values[v] {
+        Op::Const(c) => Some(c),
+        _ => None,
+    }
+}
+
+// which block each instruction is in, None for values outside any block
+fn block_of(f: &Func) -> Vec<Option<BlockId>> {
+    let mut at = vec![None; f.values.len()];
+    for (b, block) in f.blocks.iter().enumerate() {
+        for &v in &block.insts {
+            at[v] = Some(b);
+        }
+    }
+    at
+}
+
+// removes the operands a phi in b gets from pred p
+fn drop_phi_operands(f: &mut Func, b: BlockId, p: BlockId) {
+    for i in 0..f.blocks[b].insts.len() {
+        let v = f.blocks[b].insts[i];
+        if let Op::Phi(ins) = &mut f.values[v] {
+            ins.retain(|&(from, _)| from != p);
+        }
+    }
+}
+
+// the immediate dominator of every reachable block, indexed by block
+pub fn dominators(f: &Func) -> Vec<Option<BlockId>> {
+    let rpo = f.rpo();
+    let mut order = vec![usize::MAX; f.blocks.len()];
+    for (i, &b) in rpo.iter().enumerate() {
+        order[b] = i;
+    }
+    let mut idom: Vec<Option<BlockId>> = vec![None; f.blocks.len()];
+    idom[0] = Some(0);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &b in &rpo[1..] {
+            let mut new: Option<BlockId> = None;
+            for &p in &f.blocks[b].preds {
+                if idom[p].is_none() {
+                    continue;
+                }
+                new = Some(match new {
+                    None => p,
+                    Some(mut a) => {
+                        let mut p = p;
+                        while a != p {
+                            while order[a] > order[p] {
+                                a = idom[a].unwrap();
+                            }
+                            while order[p] > order[a] {
+                                p = idom[p].unwrap();
+                            }
+                        }
+                        a
+                    }
+                });
+            }
+            if new.is_some() && idom[b] != new {
+                idom[b] = new;
+                changed = true;
+            }
+        }
+    }
+    idom
+}
+
+fn dominates(idom: &[Option<BlockId>], a: BlockId, mut b: BlockId) -> bool {
+    loop {
+        if a == b {
+            return true;
+        }
+        match idom[b] {
+            Some(d) if d != b => b = d,
+            _ => return false,
+        }
+    }
+}
+
+/////////////////////////////// copy propagation ///////////////////////////////
+
+// replaces phis whose operands are all the same value (or the phi itself)
+// by that value
+fn simplify_phis(f: &mut Func) -> bool {
+    let mut any = false;
+    loop {
+        let mut fwd = vec![None; f.values.len()];
+        let mut changed = false;
+        for b in 0..f.blocks.len() {
+            for &v in &f.blocks[b].insts {
+                let Op::Phi(ins) = &f.values[v] else { continue };
+                let mut same = None;
+                let mut trivial = true;
+                for &(_, o) in ins {
+                    let o = resolve(&fwd, o);
+                    if o == v || Some(o) == same {
+                        continue;
+                    }
+                    if same.is_some() {
+                        trivial = false;
+                        break;
+                    }
+                    same = Some(o);
+                }
+                if trivial {
+                    fwd[v] = same;
+                    changed |= same.is_some();
+                }
+            }
+        }
+        // a phi with no operands at all only happens in dead code
+        let undef: Vec<ValueId> = (0..f.blocks.len())
+            .flat_map(|b| f.blocks[b].insts.clone())
+            .filter(|&v| matches!(&f.values[v], Op::Phi(ins) if ins.iter().all(|&(_, o)| o == v)))
+            .collect();
+        for v in undef {
+            fwd.resize(f.values.len(), None);
+            fwd[v] = Some(konst(f, 0));
+            fwd.resize(f.values.len(), None);
+            changed = true;
+        }
+        if !changed {
+            return any;
+        }
+        fwd.resize(f.values.len(), None);
+        apply(f, &fwd);
+        any = true;
+    }
+}
+
+/////////////////////////////// constant folding ///////////////////////////////
+
+fn float_binary(op: i32, a: i64, b: i64) -> Option<i64> {
+    let (x, y) = (f64::from_bits(a as u64), f64::from_bits(b as u64));
+    let cell = |v: f64| v.to_bits() as i64;
+    Some(match op {
+        FADD => cell(x + y),
+        FSUB => cell(x - y),
+        FMUL => cell(x * y),
+        FDIV => cell(x / y),
+        FEQ => (x == y) as i64,
+        FNE => (x != y) as i64,
+        FLT => (x < y) as i64,
+        FGT => (x > y) as i64,
+        FLE => (x <= y) as i64,
+        FGE => (x >= y) as i64,
+        _ => return None,
+    })
+}
+
+fn unary(op: i32, n: i32, a: i64) -> i64 {
+    let f = f64::from_bits(a as u64);
+    match op {
+        SXT => (a << (64 - n)) >> (64 - n),
+        ZXT => ((a as u64) << (64 - n) >> (64 - n)) as i64,
+        ITF => (a as f64).to_bits() as i64,
+        UTF => (a as u64 as f64).to_bits() as i64,
+        FTI => f as i64,
+        FTU => f as u64 as i64,
+        FRND => (f as f32 as f64).to_bits() as i64,
+        _ => unreachable!("not a unary opcode"),
+    }
+}
+
+// what v simplifies to, if anything
+fn simplified(f: &mut Func, v: ValueId) -> Option<ValueId> {
+    match f.values[v].clone() {
+        Op::Bin(op, a, b) => {
+            let (ca, cb) = (const_of(f, a), const_of(f, b));
+            if let (Some(x), Some(y)) = (ca, cb) {
+                let r = eval(op, x, y).or_else(|| float_binary(op, x, y))?;
+                return Some(konst(f, r));
+            }
+            match (op, ca, cb) {
+                (ADD | SUB | OR | XOR | SHL | SHR | USHR, _, Some(0)) => Some(a),
+                (MUL | DIV | UDIV, _, Some(1)) => Some(a),
+                (ADD | OR | XOR, Some(0), _) => Some(b),
+                (MUL, Some(1), _) => Some(b),
+                _ => None,
+            }
+        }
+        Op::Un(op, n, a) => {
+            if let Some(c) = const_of(f, a) {
+                return Some(konst(f, unary(op, n, c)));
+            }
+            match (op, f.values[a].clone()) {
+                // a char load is already zero extended
+                (ZXT, Op::Load(crate::ir::Width::Char, _)) if n >= 8 => Some(a),
+                // extending what was just extended the same way to no more bits
+                (SXT | ZXT, Op::Un(inner, m, _)) if inner == op && m <= n => Some(a),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// folds constant instructions and branches and removes no-op arithmetic
+fn fold(f: &mut Func) -> bool {
+    let mut changed = false;
+    loop {
+        let mut fwd = vec![None; f.values.len()];
+        let mut any = false;
+        for b in 0..f.blocks.len() {
+            for i in 0..f.blocks[b].insts.len() {
+                let v = f.blocks[b].insts[i];
+                if let Some(to) = simplified(f, v) {
+                    fwd.resize(f.values.len(), None);
+                    fwd[v] = Some(to);
+                    any = true;
+                }
+            }
+        }
+        if !any {
+            break;
+        }
+        fwd.resize(f.values.len(), None);
+        apply(f, &fwd);
+        changed = true;
+    }
+    for b in 0..f.blocks.len() {
+        let Some(Term::Br(c, t, e)) = f.blocks[b].term else { continue };
+        let Some(c) = const_of(f, c) else { continue };
+        let (taken, dropped) = if c != 0 { (t, e) } else { (e, t) };
+        f.blocks[b].term = Some(Term::Jmp(taken));
+        let preds = &mut f.blocks[dropped].preds;
+        if let Some(i) = preds.iter().position(|&p| p == b) {
+            preds.remove(i);
+        }
+        drop_phi_operands(f, dropped, b);
+        changed = true;
+    }
+    changed
+}
+
+// forgets blocks that can no longer be reached from the entry
+fn remove_unreachable(f: &mut Func) -> bool {
+    let reachable: HashSet<BlockId> = f.rpo().into_iter().collect();
+    let mut changed = false;
+    for b in 0..f.blocks.len() {
+        if reachable.contains(&b) {
+            continue;
+        }
+        let block = std::mem::take(&mut f.blocks[b]);
+        if block.term.is_none() && block.insts.is_empty() && block.preds.is_empty() {
+            continue;
+        }
+        for v in block.insts {
+            f.values[v] = Op::Removed;
+        }
+        changed = true;
+        for s in block.term.map(|t| t.succs()).unwrap_or_default() {
+            if reachable.contains(&s) {
+                f.blocks[s].preds.retain(|&p| p != b);
+                drop_phi_operands(f, s, b);
+            }
+        }
+    }
+    changed
+}
+
+/////////////////////////////// common subexpressions ///////////////////////////////
+
+// constants and addresses compare by what they are, everything else by identity
+fn operand_key(f: &Func, v: ValueId) -> (u8, i64) {
+    match f.values[v] {
+        Op::Const(c) => (1, c),
+        Op::LocalAddr(off) => (2, off as i64),
+        Op::GlobalAddr(a) => (3, a as i64),
+        _ => (0, v as i64),
+    }
+}
+
+type Key = (u8, i32, i32, (u8, i64), (u8, i64));
+
+fn key(f: &Func, op: &Op) -> Option<Key> {
+    match *op {
+        Op::Bin(op, a, b) => {
+            let (mut ka, mut kb) = (operand_key(f, a), operand_key(f, b));
+            if matches!(op, ADD | MUL | AND | OR | XOR | EQ | NE | FADD | FMUL | FEQ | FNE) && ka > kb {
+                std::mem::swap(&mut ka, &mut kb);
+            }
+            Some((0, op, 0, ka, kb))
+        }
+        Op::Un(op, n, a) => Some((1, op, n, operand_key(f, a), (0, 0))),
+        // loading a cell at a fixed address again costs no more than reading
+        // the first load back from the frame slot it would need
+        Op::Load(_, a) if f.values[a].is_leaf() => None,
+        Op::Load(w, a) => Some((2, w as i32, 0, operand_key(f, a), (0, 0))),
+        _ => None,
+    }
+}
+
+// global value numbering over the dominator tree for pure instructions, and
+// per block for loads
+fn cse(f: &mut Func) -> bool {
+    let idom = dominators(f);
+    let mut children = vec![Vec::new(); f.blocks.len()];
+    for (b, d) in idom.iter().enumerate() {
+        if let Some(d) = *d {
+            if d != b {
+                children[d].push(b);
+            }
+        }
+    }
+    let mut fwd = vec![None; f.values.len()];
+    let mut table: HashMap<Key, ValueId> = HashMap::new();
+    walk(f, 0, &children, &mut table, &mut fwd);
+    if fwd.iter().all(|x| x.is_none()) {
+        return false;
+    }
+    apply(f, &fwd);
+    true
+}
+
+fn walk(
+    f: &Func,
+    b: BlockId,
+    children: &[Vec<BlockId>],
+    table: &mut HashMap<Key, ValueId>,
+    fwd: &mut [Option<ValueId>],
+) {
+    let mut added = Vec::new();
+    let mut loads: HashMap<Key, ValueId> = HashMap::new();
+    for &v in &f.blocks[b].insts {
+        if f.values[v].has_effects() {
+            loads.clear();
+            continue;
+        }
+        // the operands may themselves have just been found redundant
+        let mut op = f.values[v].clone();
+        for o in op.operands_mut() {
+            *o = resolve(fwd, *o);
+        }
+        let k = match key(f, &op) {
+            Some(k) => k,
+            None => continue,
+        };
+        let scope = if let Op::Load(..) = op { &mut loads } else { &mut *table };
+        match scope.get(&k) {
+            Some(&same) => fwd[v] = Some(same),
+            None => {
+                scope.insert(k, v);
+                if !matches!(op, Op::Load(..)) {
+                    added.push(k);
+                }
+            }
+        }
+    }
+    for &c in &children[b] {
+        walk(f, c, children, table, fwd);
+    }
+    for k in added {
+        table.remove(&k);
+    }
+}
+
+/////////////////////////////// loop-invariant code motion ///////////////////////////////
+
+// a natural loop: its header and every block in its body, header included
+struct Loop {
+    header: BlockId,
+    body: HashSet<BlockId>,
+}
+
+fn loops(f: &Func) -> Vec<Loop> {
+    let idom = dominators(f);
+    let mut by_header: HashMap<BlockId, HashSet<BlockId>> = HashMap::new();
+    for b in f.rpo() {
+        for s in f.blocks[b].term.as_ref().map(|t| t.succs()).unwrap_or_default() {
+            if !dominates(&idom, s, b) {
+                continue;
+            }
+            // a back edge b -> s: everything that reaches b without passing s
+            let body = by_header.entry(s).or_insert_with(|| HashSet::from([s]));
+            let mut work = vec![b];
+            while let Some(x) = work.pop() {
+                if body.insert(x) {
+                    work.extend(f.blocks[x].preds.iter().copied());
+                }
+            }
+        }
+    }
+    let mut loops: Vec<Loop> = by_header.into_iter().map(|(header, body)| Loop { header, body }).collect();
+    // inner loops first, so their invariants can move on outwards
+    loops.sort_by_key(|l| (l.body.len(), l.header));
+    loops
+}
+
+// gives the loop a block of its own to enter it through, if it doesn't
+// already have one, and returns it
+fn preheader(f: &mut Func, l: &Loop) -> Option<BlockId> {
+    let outside: Vec<BlockId> = f.blocks[l.header].preds.iter().copied().filter(|p| !l.body.contains(p)).collect();
+    if let [p] = outside[..] {
+        if f.blocks[p].term == Some(Term::Jmp(l.header)) {
+            return None;
+        }
+    }
+    let pre = f.blocks.len();
+    f.blocks.push(Block { insts: Vec::new(), term: Some(Term::Jmp(l.header)), preds: outside.clone() });
+    for &p in &outside {
+        if let Some(term) = &mut f.blocks[p].term {
+            *term = match *term {
+                Term::Jmp(_) => Term::Jmp(pre),
+                Term::Br(c, t, e) => Term::Br(c, if t == l.header { pre } else { t }, if e == l.header { pre } else { e }),
+                Term::Ret(v) => Term::Ret(v),
+            };
+        }
+    }
+    f.blocks[l.header].preds.retain(|p| l.body.contains(p));
+    f.blocks[l.header].preds.push(pre);
+    // the header's phis now get whatever came from outside through the preheader
+    for i in 0..f.blocks[l.header].insts.len() {
+        let v = f.blocks[l.header].insts[i];
+        let Op::Phi(ins) = f.values[v].clone() else { continue };
+        let (out, inside): (Vec<_>, Vec<_>) = ins.into_iter().partition(|(p, _)| outside.contains(p));
+        let incoming = if out.iter().all(|&(_, x)| x == out[0].1) {
+            out[0].1
+        } else {
+            let phi = f.add(Op::Phi(out), 0);
+            f.blocks[pre].insts.push(phi);
+            phi
+        };
+        let mut ins = inside;
+        ins.push((pre, incoming));
+        f.values[v] = Op::Phi(ins);
+    }
+    Some(pre)
+}
+
+// true if v can run on every trip into the loop without changing behaviour,
+// whether or not the loop body would have run it
+fn hoistable(f: &Func, v: ValueId) -> bool {
+    match f.values[v] {
+        Op::Bin(DIV | MOD | UDIV | UMOD, _, d) => !matches!(const_of(f, d), None | Some(0) | Some(-1)),
+        Op::Bin(SHL | SHR | USHR, _, n) => matches!(const_of(f, n), Some(0..=63)),
+        Op::Bin(..) | Op::Un(..) => true,
+        _ => false,
+    }
+}
+
+// moves pure computations whose operands don't change inside a loop into
+// its preheader
+fn licm(f: &mut Func) -> bool {
+    // every loop gets its preheader first, since adding one changes the loops
+    while let Some(l) = loops(f).into_iter().find(|l| {
+        let outside: Vec<BlockId> = f.blocks[l.header].preds.iter().copied().filter(|p| !l.body.contains(p)).collect();
+        !(outside.len() == 1 && f.blocks[outside[0]].term == Some(Term::Jmp(l.header)))
+    }) {
+        if preheader(f, &l).is_none() {
+            break;
+        }
+    }
+    let mut changed = false;
+    for l in loops(f) {
+        let pre = f.blocks[l.header].preds.iter().copied().find(|p| !l.body.contains(p));
+        let Some(pre) = pre else { continue };
+        let at = block_of(f);
+        let mut hoisted: HashSet<ValueId> = HashSet::new();
+        let invariant = |o: ValueId, hoisted: &HashSet<ValueId>| match at.get(o).copied().flatten() {
+            Some(b) => !l.body.contains(&b) || hoisted.contains(&o),
+            None => true,
+        };
+        for b in f.rpo() {
+            if !l.body.contains(&b) {
+                continue;
+            }
+            for &v in &f.blocks[b].insts {
+                if hoistable(f, v) && f.values[v].operands().iter().all(|&o| invariant(o, &hoisted)) {
+                    hoisted.insert(v);
+                }
+            }
+        }
+        if hoisted.is_empty() {
+            continue;
+        }
+        // keep the hoisted instructions in their original order
+        let order: Vec<ValueId> = f
+            .rpo()
+            .into_iter()
+            .filter(|b| l.body.contains(b))
+            .flat_map(|b| f.blocks[b].insts.clone())
+            .filter(|v| hoisted.contains(v))
+            .collect();
+        for b in l.body.iter().copied() {
+            f.blocks[b].insts.retain(|v| !hoisted.contains(v));
+        }
+        f.blocks[pre].insts.extend(order);
+        changed = true;
+    }
+    changed
+}
+
+/////////////////////////////// dead code ///////////////////////////////
+
+// removes every instruction whose value nothing needs
+fn dce(f: &mut Func) -> bool {
+    let mut live = vec![false; f.values.len()];
+    let mut work = Vec::new();
+    for (b, block) in f.blocks.iter().enumerate() {
+        for &v in &block.insts {
+            if f.values[v].has_effects() {
+                work.push(v);
+            }
+        }
+        work.extend(f.term_operand(b));
+    }
+    while let Some(v) = work.pop() {
+        if !live[v] {
+            live[v] = true;
+            work.extend(f.values[v].operands());
+        }
+    }
+    let mut changed = false;
+    for b in 0..f.blocks.len() {
+        let dead: Vec<ValueId> = f.blocks[b].insts.iter().copied().filter(|&v| !live[v]).collect();
+        for &v in &dead {
+            f.values[v] = Op::Removed;
+        }
+        changed |= !dead.is_empty();
+        f.blocks[b].insts.retain(|&v| live[v]);
+    }
+    changed
+}
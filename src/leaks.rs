@@ -0,0 +1,56 @@
+// The --leaks report. MALC and FREE tell Leaks about every block they hand
+// out and take back, along with the pc of the MALC that allocated it; when
+// the program exits, whatever is still allocated is summed up by the place
+// that allocated it.
+
+use std::collections::HashMap;
+
+use crate::codegen::line_of;
+
+pub struct Leaks {
+    live: HashMap<usize, (usize, usize)>, // block address -> its size and the pc of the MALC
+    blocks: usize,                        // allocated in total
+}
+
+impl Leaks {
+    pub fn new() -> Self {
+        Leaks { live: HashMap::new(), blocks: 0 }
+    }
+
+    pub fn malloc(&mut self, addr: usize, size: usize, pc: usize) {
+        self.live.insert(addr, (size, pc));
+        self.blocks += 1;
+    }
+
+    // pointers malloc did not return are left to --check
+    pub fn free(&mut self, addr: usize) {
+        self.live.remove(&addr);
+    }
+
+    // the summary printed at exit: the blocks nobody freed, grouped by where
+    // they were allocated, the biggest leak first
+    pub fn report(&self, lines: &[(i32, i32)]) -> String {
+        if self.live.is_empty() {
+            return format!("leaks: none, all {} block{} freed\n", self.blocks, if self.blocks == 1 { " was" } else { "s were" });
+        }
+        let mut sites: HashMap<usize, (usize, usize)> = HashMap::new(); // pc -> bytes and blocks
+        for &(size, pc) in self.live.values() {
+            let site = sites.entry(pc).or_default();
+            site.0 += size;
+            site.1 += 1;
+        }
+        let mut sites: Vec<(usize, (usize, usize))> = sites.into_iter().collect();
+        sites.sort_by_key(|&(pc, (bytes, _))| (std::cmp::Reverse(bytes), pc));
+
+        let bytes: usize = sites.iter().map(|(_, (b, _))| b).sum();
+        let mut out = format!("leaks: {} bytes in {} of {} blocks were never freed\n", bytes, self.live.len(), self.blocks);
+        for (pc, (bytes, blocks)) in sites {
+            let at = match line_of(lines, pc) {
+                Some(line) => format!("pc {} (line {})", pc, line),
+                None => format!("pc {}", pc),
+            };
+            out.push_str(&format!("  {} bytes in {} block{} allocated at {}\n", bytes, blocks, if blocks == 1 { "" } else { "s" }, at));
+        }
+        out
+    }
+}
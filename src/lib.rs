@@ -0,0 +1,2099 @@
+// Constants for tokens and opcodes- add more
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, IsTerminal, Read, Write}; // Import Read and Write traits
+use std::path::PathBuf;
+
+mod ast;
+mod codegen;
+mod coverage;
+pub mod dispatch;
+mod emit;
+pub mod fs;
+mod ir;
+mod ir_lower;
+mod ir_opt;
+mod leaks;
+mod link;
+mod llvm;
+mod memcheck;
+mod native;
+mod optimize;
+mod profile;
+pub mod repl;
+pub mod sandbox;
+pub mod stdio;
+mod stdlib;
+mod typeck;
+pub mod vm;
+mod wasm;
+
+use ast::{BinOp, Expr, ExprKind, Function, IncDec, Program, Stmt, UnOp, Var};
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+enum Token {
+    None, 
+    Num(i64),
+    Id(String),
+    CharLit(char),
+    FloatLit(u64), // bit pattern of the f64 value so Token stays Eq + Hash
+    Str(String),
+    Include(String), // #include <name>, which Parser::new replaces by the header
+    Else, Enum, If, Int, Return, Sizeof, While, Do, Extern,
+    Char, Short, Long, Unsigned, Signed, Float, Double,
+    Assign, Cond, Lor, Lan, Or, Xor, And, Eq, Lt, Shl, Add, Mul, Inc,
+    Ne, Le, Gt, Ge, Shr, Sub, Div, Mod, Dec,
+    Brak, RBrak,
+    LParen, RParen, RBrace, LBrace, Comma, Colon, Semicolon, Not, BitNot,
+}
+
+impl Token {
+    // binding strength of the operator, None for tokens that aren't operators
+    fn precedence(&self) -> Option<i32> {
+        Some(match self {
+            Token::Assign => 1,
+            Token::Cond   => 2,
+            Token::Lor    => 3,
+            Token::Lan    => 4,
+            Token::Or     => 5,
+            Token::Xor    => 6,
+            Token::And    => 7,
+            Token::Eq | Token::Ne => 8,
+            Token::Lt | Token::Gt | Token::Le | Token::Ge => 9,
+            Token::Shl | Token::Shr => 10,
+            Token::Add | Token::Sub => 11,
+            Token::Mul | Token::Div | Token::Mod => 12,
+            Token::Inc | Token::Dec => 13,
+            Token::Brak   => 14,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Hash, Clone, PartialEq)]
+enum Class {
+    Sys,  // System function
+    Fun,  // User-defined function
+    Num,  // Immediate number
+    Loc,  // Local variable
+    Glo,  // Global variable
+}
+
+#[derive(Debug, Hash, Clone)]
+struct Symbol {
+    class: Class,   // Class of symbol (Sys, Fun, Num, Loc, Glo)
+    val: i32,       // Address or value
+    typ: Type,      // Type (e.g., int, unsigned char*, long, etc.)
+}
+
+// these are opcode constants the vm can execute
+const LEA: i32 = 0; // load effective address
+const IMM: i32 = 1; // load immediate value
+const JMP: i32 = 2; // unconditional jump
+const JSR: i32 = 3; // jump to subroutine (function call)
+const BZ: i32 = 4; // branch if zero
+const BNZ: i32 = 5; // branch if not zero
+const ENT: i32 = 6; // enter function (setup stack frame)
+const ADJ: i32 = 7; // adjust stack
+const LEV: i32 = 8; // leave function
+const LI: i32 = 9; // load integer from memory
+const LC: i32 = 10; // load character from memory
+const SI: i32 = 11; // store integer to memory
+const SC: i32 = 12; // store character to memory
+const PSH: i32 = 13; // push value onto stack
+
+// the rest below are arithmetic and logical operations
+const OR: i32 = 14;
+const XOR: i32 = 15;
+const AND: i32 = 16;
+const EQ: i32 = 17;
+const NE: i32 = 18;
+const LT: i32 = 19;
+const GT: i32 = 20;
+const LE: i32 = 21;
+const GE: i32 = 22;
+const SHL: i32 = 23;
+const SHR: i32 = 24;
+const ADD: i32 = 25;
+const SUB: i32 = 26;
+const MUL: i32 = 27;
+const DIV: i32 = 28;
+const MOD: i32 = 29;
+
+// below are system calls
+const OPEN: i32 = 30;
+const READ: i32 = 31;
+const CLOS: i32 = 32;
+const PRTF: i32 = 33;
+const MALC: i32 = 34;
+const FREE: i32 = 35;
+const MSET: i32 = 36;
+const MCMP: i32 = 37;
+const EXIT: i32 = 38;
+
+// unsigned variants of the signed operations above, picked by the parser
+// when the usual arithmetic conversions give an unsigned type
+const ULT: i32 = 39;
+const UGT: i32 = 40;
+const ULE: i32 = 41;
+const UGE: i32 = 42;
+const USHR: i32 = 43;
+const UDIV: i32 = 44;
+const UMOD: i32 = 45;
+
+// integer conversions, both take the bit width to narrow ax to as operand
+const SXT: i32 = 46; // keep the low n bits and sign extend them
+const ZXT: i32 = 47; // keep the low n bits and zero extend them
+
+// floating point: float and double values live in a cell as f64 bits
+const FIMM: i32 = 48; // load a double, the operands are the low and high 32 bits
+const FADD: i32 = 49;
+const FSUB: i32 = 50;
+const FMUL: i32 = 51;
+const FDIV: i32 = 52;
+const FEQ: i32 = 53;
+const FNE: i32 = 54;
+const FLT: i32 = 55;
+const FGT: i32 = 56;
+const FLE: i32 = 57;
+const FGE: i32 = 58;
+const ITF: i32 = 59; // signed integer in ax to double
+const UTF: i32 = 60; // unsigned integer in ax to double
+const FTI: i32 = 61; // double in ax to signed integer (truncating)
+const FTU: i32 = 62; // double in ax to unsigned integer (truncating)
+const FRND: i32 = 63; // round the double in ax to float precision
+const ITFS: i32 = 64; // like ITF, but for the left operand on top of the stack
+const UTFS: i32 = 65; // like UTF, but for the left operand on top of the stack
+
+// a system call c4 didn't have, numbered after the extensions
+const WRIT: i32 = 66;
+
+// data types
+// c4 encoded types as CHAR=0, INT=1 plus multiples of PTR, which has no room
+// for signedness or any other width. A Type keeps the base integer type, its
+// signedness and the number of pointer levels on top of it separately.
+//
+// The VM works on 64 bit cells, so int, long and long long all compute at the
+// full cell width (like c4 on 64 bit hosts); char, short and unsigned int are
+// narrowed with SXT/ZXT, unsigned int after every result that can carry past
+// 32 bits as well. Plain char is unsigned here since LC zero extends the byte.
+// float and double both compute as f64; float is only rounded when stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Base {
+    Char,
+    Short,
+    Int,
+    Long,
+    LongLong,
+    Float,
+    Double,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Type {
+    base: Base,     // integer type at the bottom of the pointer chain
+    unsigned: bool, // signedness of the base type
+    ptr: u32,       // levels of indirection (c4's "+ PTR")
+}
+
+impl Type {
+    const CHAR: Type = Type { base: Base::Char, unsigned: true, ptr: 0 };
+    const INT: Type = Type { base: Base::Int, unsigned: false, ptr: 0 };
+    const DOUBLE: Type = Type { base: Base::Double, unsigned: false, ptr: 0 };
+
+    fn is_ptr(self) -> bool {
+        self.ptr > 0
+    }
+
+    fn is_unsigned(self) -> bool {
+        self.ptr == 0 && self.unsigned
+    }
+
+    fn is_float(self) -> bool {
+        self.ptr == 0 && self.base >= Base::Float
+    }
+
+    // the one type narrower than a cell that arithmetic is done in
+    fn is_unsigned_int(self) -> bool {
+        self.is_unsigned() && self.base == Base::Int
+    }
+
+    // an integer literal is an int, or a long if it doesn't fit one
+    fn literal(val: i64) -> Type {
+        if i32::try_from(val).is_ok() { Type::INT } else { Type { base: Base::Long, ..Type::INT } }
+    }
+
+    // the type of &x when x has this type
+    fn ptr_to(self) -> Type {
+        Type { ptr: self.ptr + 1, ..self }
+    }
+
+    // the type of *x when x has this type, only meaningful for pointers
+    fn deref(self) -> Type {
+        Type { ptr: self.ptr - 1, ..self }
+    }
+
+    // size in bytes as reported by sizeof and used to scale pointer arithmetic
+    fn size(self) -> i32 {
+        if self.is_ptr() {
+            return std::mem::size_of::<i32>() as i32;
+        }
+        match self.base {
+            Base::Char => 1,
+            Base::Short => 2,
+            Base::Int | Base::Float => 4,
+            Base::Long | Base::LongLong | Base::Double => 8,
+        }
+    }
+
+    // rank used by the integer promotions and the usual arithmetic conversions
+    fn rank(self) -> Base {
+        if self.is_ptr() { Base::Long } else { self.base }
+    }
+
+    // integer promotion: anything smaller than int becomes int
+    fn promote(self) -> Type {
+        if !self.is_ptr() && self.base < Base::Int {
+            Type::INT
+        } else {
+            self
+        }
+    }
+
+    // the usual arithmetic conversions for the operands of a binary operator
+    fn common(a: Type, b: Type) -> Type {
+        let (a, b) = (a.promote(), b.promote());
+        if a.is_ptr() || b.is_ptr() {
+            return Type::INT;
+        }
+        if a.is_float() || b.is_float() {
+            return if a.rank() >= b.rank() { a } else { b };
+        }
+        if a.unsigned == b.unsigned {
+            return if a.rank() >= b.rank() { a } else { b };
+        }
+        let (u, s) = if a.unsigned { (a, b) } else { (b, a) };
+        if u.rank() >= s.rank() {
+            u
+        } else if s.size() > u.size() {
+            s // the signed type can hold every value of the unsigned one
+        } else {
+            Type { unsigned: true, ..s }
+        }
+    }
+
+    // the instruction that loads a value of this type from the address in ax
+    fn load_op(self) -> i32 {
+        if !self.is_ptr() && self.base == Base::Char { LC } else { LI }
+    }
+
+    // the instruction that stores ax as this type to the address on the stack
+    fn store_op(self) -> i32 {
+        if !self.is_ptr() && self.base == Base::Char { SC } else { SI }
+    }
+
+    // builds a type out of the specifier keywords in front of a declarator,
+    // e.g. [Unsigned, Long, Long, Int] is unsigned long long
+    fn from_specifiers(specs: &[Token]) -> Option<Type> {
+        let mut unsigned = None;
+        let mut base = None;
+        let mut longs = 0;
+        for spec in specs {
+            match spec {
+                Token::Unsigned | Token::Signed if unsigned.is_some() => return None,
+                Token::Unsigned => unsigned = Some(true),
+                Token::Signed => unsigned = Some(false),
+                Token::Long => longs += 1,
+                Token::Int if base == Some(Base::Short) => {} // short int
+                Token::Char | Token::Short | Token::Int | Token::Float | Token::Double if base.is_some() => return None,
+                Token::Char => base = Some(Base::Char),
+                Token::Short => base = Some(Base::Short),
+                Token::Int => base = Some(Base::Int),
+                Token::Float => base = Some(Base::Float),
+                Token::Double => base = Some(Base::Double),
+                _ => return None,
+            }
+        }
+        if base.is_some_and(|b| b >= Base::Float) {
+            // floating types can't be signed or unsigned; long double is just double
+            return match (base, longs, unsigned) {
+                (Some(b), 0, None) => Some(Type { base: b, unsigned: false, ptr: 0 }),
+                (Some(Base::Double), 1, None) => Some(Type::DOUBLE),
+                _ => None,
+            };
+        }
+        let base = match (base, longs) {
+            (None, 0) if unsigned.is_none() => return None,
+            (None, 0) | (Some(Base::Int), 0) => Base::Int,
+            (None, 1) | (Some(Base::Int), 1) => Base::Long,
+            (None, 2) | (Some(Base::Int), 2) => Base::LongLong,
+            (Some(b), 0) => b,
+            _ => return None,
+        };
+        // plain char follows LC and is unsigned, every other type is signed by default
+        let unsigned = unsigned.unwrap_or(base == Base::Char);
+        Some(Type { base, unsigned, ptr: 0 })
+    }
+}
+
+// prints the type the way C spells it, e.g. "unsigned short" or "char **"
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self.base {
+            Base::Char => if self.unsigned { "char" } else { "signed char" },
+            Base::Short => "short",
+            Base::Int => "int",
+            Base::Long => "long",
+            Base::LongLong => "long long",
+            Base::Float => "float",
+            Base::Double => "double",
+        };
+        if self.unsigned && self.base != Base::Char {
+            write!(f, "unsigned ")?;
+        }
+        write!(f, "{}", name)?;
+        if self.ptr > 0 {
+            write!(f, " {}", "*".repeat(self.ptr as usize))?;
+        }
+        Ok(())
+    }
+}
+
+// true for the keywords that can start a type name
+fn is_type_specifier(tk: &Token) -> bool {
+    matches!(
+        tk,
+        Token::Char | Token::Short | Token::Int | Token::Long | Token::Unsigned | Token::Signed | Token::Float | Token::Double
+    )
+}
+
+
+// FROM C NEXT() TO RUST LEXER CLASS LOGIC EXPLAINED 
+// in the original c4 compiler the next function used a manual character 
+// pointer to iterate over the source code and classify tokens...
+
+// since pointers are considered unsafe in rust, other data structures like 
+// string slices (&str), indexes and options can track the source code.
+
+struct Lexer<'a> {
+    source: &'a str, // the full input source code to tokenize
+    position: usize, // current index in the source string
+    line: usize, // current line number- for debugging like c4
+    current_char: Option<char>, // currently read character 
+    keywords: HashMap<&'a str, Token>, // hashmap that maps strings like "if" and "return" to token types
+    error: Option<String>, // a bad literal, which lex() reports
+}
+
+// SInce structs in Rust can act like classes, we can define the 
+// following constructors and methods to build this lexer
+
+// new(): constructor to initialize the lexer - setting position, line, first char, populate keywords
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Self { // constructor that initializes a new lexer instance
+
+        // default constructor --= default values
+        let mut lexer = Lexer {
+            source, // full source code
+            position: 0, // start reading from pos 0 = the beginning
+            line: 1, // error tracking from line 1 = the first line
+            current_char: None,
+            keywords: HashMap::new(), // empty keyword map for now
+            error: None,
+        };
+
+        // we need to populate the keyword map with reserved words
+        lexer.keywords.insert("else", Token::Else);
+        lexer.keywords.insert("enum", Token::Enum);
+        lexer.keywords.insert("if", Token::If);
+        lexer.keywords.insert("int", Token::Int);
+        lexer.keywords.insert("return", Token::Return);
+        lexer.keywords.insert("sizeof", Token::Sizeof);
+        lexer.keywords.insert("while", Token::While);
+        lexer.keywords.insert("do", Token::Do);
+        lexer.keywords.insert("extern", Token::Extern);
+        lexer.keywords.insert("char", Token::Char);
+        lexer.keywords.insert("short", Token::Short);
+        lexer.keywords.insert("long", Token::Long);
+        lexer.keywords.insert("unsigned", Token::Unsigned);
+        lexer.keywords.insert("signed", Token::Signed);
+        lexer.keywords.insert("float", Token::Float);
+        lexer.keywords.insert("double", Token::Double);
+        lexer.keywords.insert("void", Token::Char); // like c4, void is handled as char
+
+        // advance to the first character of the source code
+        // this mimics how c4 manually reads the first char into a variable
+        lexer.advance();
+        lexer
+    }
+
+    // here this function moves to the next character in the source code
+    fn advance(&mut self) {
+        // if we're not at the end of the code, get the next byte and convert it to a char
+        self.current_char = if self.position < self.source.len() {
+            Some(self.source.as_bytes()[self.position] as char)
+        } else { // we reached the end of the source code
+            None
+        };
+        self.position += 1; // move the reading position to the next 
+    }
+
+    // this function allows us to look at the next character in the source code 
+    // without actually advancing the current reading position (used for lookahead logic)
+    fn peek(&self) -> Option<char> { // sampe implementation as advance() method 
+        if self.position < self.source.len() {
+            Some(self.source.as_bytes()[self.position] as char)
+        } else {
+            None
+        }
+    }
+
+    // this function gets the next token from the source code
+    fn next_token(&mut self) -> Option<Token> {
+        while let Some(c) = self.current_char { // loop while there is a current character to process
+            match c {
+                ' ' | '\t' | '\r' => self.advance(), // skip whitespace characters
+                '\n' => { // a newline is found?
+                    self.line += 1; // then increment line number 
+                    self.advance();
+                }
+
+                // handle single-line, block and hash comments
+                '/' => {
+                    if self.peek() == Some('/') {
+                        while self.current_char != Some('\n') && self.current_char.is_some() {
+                            self.advance();
+                        }
+                    } else if self.peek() == Some('*') {
+                        self.advance();
+                        self.advance();
+                        while self.current_char.is_some() && !(self.current_char == Some('*') && self.peek() == Some('/')) {
+                            if self.current_char == Some('\n') {
+                                self.line += 1;
+                            }
+                            self.advance();
+                        }
+                        self.advance(); // skip the closing */
+                        self.advance();
+                    } else {
+                        self.advance();
+                        return Some(Token::Div);
+                    }
+                }
+                // preprocessor lines are skipped like c4 does, except that
+                // #include <name> hands the header's name to the parser
+                '#' => {
+                    let mut directive = String::new();
+                    while let Some(c) = self.current_char.filter(|&c| c != '\n') {
+                        directive.push(c);
+                        self.advance();
+                    }
+                    let header = directive[1..].trim_start().strip_prefix("include").and_then(|r| r.trim().strip_prefix('<')?.strip_suffix('>'));
+                    if let Some(name) = header {
+                        return Some(Token::Include(name.trim().to_string()));
+                    }
+                }
+                // handle string literal
+                '"' => {
+                    self.advance();
+                    let mut string = String::new();
+                    while let Some(ch) = self.current_char {
+                        if ch == '"' {
+                            break;
+                        }
+                        string.push(self.escaped_char()?);
+                    }
+                    self.advance(); // skip the closing "
+                    return Some(Token::Str(string));
+                }
+                // handle character literal
+                '\'' => {
+                    self.advance();
+                    let ch = self.escaped_char()?;
+                    self.advance(); // skip closing '
+                    return Some(Token::CharLit(ch));
+                }
+                // handle operators
+                '=' => {
+                    self.advance();
+                    if self.current_char == Some('=') { // if it's == then the token is Eq
+                        self.advance();
+                        return Some(Token::Eq);
+                    }
+                    return Some(Token::Assign); // else its an assignment 
+                }
+                '!' => {
+                    self.advance();
+                    if self.current_char == Some('=') {
+                        self.advance();
+                        return Some(Token::Ne);
+                    }
+                    return Some(Token::Not);
+                }
+                '<' => {
+                    self.advance();
+                    if self.current_char == Some('=') {
+                        self.advance();
+                        return Some(Token::Le);
+                    }
+                    if self.current_char == Some('<') {
+                        self.advance();
+                        return Some(Token::Shl);
+                    }
+                    return Some(Token::Lt);
+                }
+                '>' => {
+                    self.advance();
+                    if self.current_char == Some('=') {
+                        self.advance();
+                        return Some(Token::Ge);
+                    }
+                    if self.current_char == Some('>') {
+                        self.advance();
+                        return Some(Token::Shr);
+                    }
+                    return Some(Token::Gt);
+                }
+                '+' => {
+                    self.advance();
+                    if self.current_char == Some('+') {
+                        self.advance();
+                        return Some(Token::Inc);
+                    }
+                    return Some(Token::Add);
+                }
+                '-' => {
+                    self.advance();
+                    if self.current_char == Some('-') {
+                        self.advance();
+                        return Some(Token::Dec);
+                    }
+                    return Some(Token::Sub);
+                }
+                '*' => {
+                    self.advance();
+                    return Some(Token::Mul);
+                }
+                '%' => {
+                    self.advance();
+                    return Some(Token::Mod);
+                }
+                '&' => {
+                    self.advance();
+                    if self.current_char == Some('&') {
+                        self.advance();
+                        return Some(Token::Lan);
+                    }
+                    return Some(Token::And);
+                }
+                '|' => {
+                    self.advance();
+                    if self.current_char == Some('|') {
+                        self.advance();
+                        return Some(Token::Lor);
+                    }
+                    return Some(Token::Or);
+                }
+                '^' => {
+                    self.advance();
+                    return Some(Token::Xor);
+                }
+                '~' => {
+                    self.advance();
+                    return Some(Token::BitNot);
+                }
+                '?' => {
+                    self.advance();
+                    return Some(Token::Cond);
+                }
+                '[' => {
+                    self.advance();
+                    return Some(Token::Brak);
+                }
+                ']' => {
+                    self.advance();
+                    return Some(Token::RBrak);
+                }
+                '(' => {
+                    self.advance();
+                    return Some(Token::LParen);
+                }
+                ')' => {
+                    self.advance();
+                    return Some(Token::RParen);
+                }
+                '{' => {
+                    self.advance();
+                    return Some(Token::LBrace);
+                }
+                '}' => {
+                    self.advance();
+                    return Some(Token::RBrace);
+                }
+                ';' => {
+                    self.advance();
+                    return Some(Token::Semicolon);
+                }
+                ':' => {
+                    self.advance();
+                    return Some(Token::Colon);
+                }
+                ',' => {
+                    self.advance();
+                    return Some(Token::Comma);
+                }
+
+                '0'..='9' => return Some(self.lex_number()), // if a digit is found, parse a number token
+                '.' if self.peek().is_some_and(|c| c.is_ascii_digit()) => return Some(self.lex_number()), // .5
+
+                // if a letter or underscore is found, parse an identifier or keyword
+                'a'..='z' | 'A'..='Z' | '_' => return Some(self.lex_identifier()), 
+
+                _ => { // if an unknown character is found, just skip it
+                    self.advance(); // skip
+                }
+            }
+        }
+        None 
+    }
+
+    // reads one character of a string or character literal, translating
+    // the escape sequences c4 programs use (\n, \t, \0, \\, \' and \")
+    fn escaped_char(&mut self) -> Option<char> {
+        let mut ch = self.current_char?;
+        if ch == '\\' {
+            self.advance();
+            ch = match self.current_char? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                other => other,
+            };
+        }
+        self.advance();
+        Some(ch)
+    }
+
+    // parses a sequence of digits into a number token, or a floating
+    // literal such as 3.14, .5, 1e-3 or 2.5f if a fraction or exponent follows
+    fn lex_number(&mut self) -> Token {
+        let start = self.position - 1;
+        let mut value: i64 = 0;
+        // like c4: 0x1F is hex, a leading 0 makes it octal, values wrap
+        let radix = if self.current_char == Some('0') && matches!(self.peek(), Some('x' | 'X')) {
+            self.advance();
+            self.advance();
+            16
+        } else if self.current_char == Some('0') && self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            8
+        } else {
+            10
+        };
+        while let Some(c) = self.current_char {
+            // if the character is a digit, convert it to a number and build the full value
+            if let Some(d) = c.to_digit(radix) {
+                value = value.wrapping_mul(radix as i64).wrapping_add(d as i64);
+                self.advance();
+            } else {
+                break; // stop reading if it's not a digit
+            }
+        }
+        if radix != 16 && matches!(self.current_char, Some('.' | 'e' | 'E')) {
+            return self.lex_float(start);
+        }
+        // integer suffixes (10u, 10L, 10UL...) are accepted but don't change the value
+        while matches!(self.current_char, Some('u' | 'U' | 'l' | 'L')) {
+            self.advance();
+        }
+        Token::Num(value) // return the number as a token
+    }
+
+    // the rest of a floating literal whose integer part starts at `start`
+    fn lex_float(&mut self, start: usize) -> Token {
+        if self.current_char == Some('.') {
+            self.advance();
+            while self.current_char.is_some_and(|c| c.is_ascii_digit()) {
+                self.advance();
+            }
+        }
+        if matches!(self.current_char, Some('e' | 'E')) {
+            self.advance();
+            if matches!(self.current_char, Some('+' | '-')) {
+                self.advance();
+            }
+            while self.current_char.is_some_and(|c| c.is_ascii_digit()) {
+                self.advance();
+            }
+        }
+        let text = &self.source[start..self.position - 1];
+        let value: f64 = text.parse().unwrap_or_else(|_| {
+            self.error = Some(format!("{}: bad floating literal {}", self.line, text));
+            0.0
+        });
+        // f and l suffixes are accepted, every literal is kept as a double
+        while matches!(self.current_char, Some('f' | 'F' | 'l' | 'L')) {
+            self.advance();
+        }
+        Token::FloatLit(value.to_bits())
+    }
+
+    // this method parses an identifier or a reserved keyword from c
+    fn lex_identifier(&mut self) -> Token {
+        let start = self.position - 1; // this si the satrting pos of the identifier
+
+        while let Some(c) = self.current_char { // keep reading letters, digits, or underscores
+            if c.is_ascii_alphanumeric() || c == '_' {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+
+        let identifier = &self.source[start..self.position - 1]; // get identifier from source code
+
+        if let Some(keyword) = self.keywords.get(identifier) { // check if the keyword is known 
+            keyword.clone() // if known, return the keyword token
+        } else {
+            // otherwise, return it as a regular identifier
+            Token::Id(identifier.to_string())
+        }
+    }
+}
+    
+///////////////////////// Parser Implementation Begins ////////////////////////
+fn binary(op: BinOp, lhs: Expr, rhs: Expr) -> ExprKind {
+    ExprKind::Binary(op, Box::new(lhs), Box::new(rhs))
+}
+
+#[derive(Debug)]
+struct Parser {
+    tk: Token,     // Current token
+    ty: Type,    // Current expression type
+    loc: i32,    // Local variable offset
+    line: i32,   // Current line number
+    data: Vec<u8>, // <--- memory area to simulate global string storage
+    strings: Vec<(i32, String)>, // the string pool: address and contents of every literal
+    pos: i32, 
+    symbols: HashMap<String, Symbol>,
+    tokens: Vec<(Token, i32)>, // the lexed source with the line of each token
+}
+
+// lexes source onto tokens with the line of each, splicing in the bundled
+// headers it includes, once each. A header's tokens all get the line of the
+// #include, so errors in it point there
+fn lex(source: &str, at: Option<i32>, included: &mut Vec<String>, tokens: &mut Vec<(Token, i32)>) -> Result<(), String> {
+    let mut lexer = Lexer::new(source);
+    while let Some(tk) = lexer.next_token() {
+        if let Some(e) = lexer.error.take() {
+            return Err(e);
+        }
+        let line = at.unwrap_or(lexer.line as i32);
+        match tk {
+            // headers the library doesn't have are skipped, as c4 skips every # line
+            Token::Include(name) => {
+                if let Some(header) = stdlib::header(&name).filter(|_| !included.contains(&name)) {
+                    included.push(name);
+                    lex(header, Some(line), included, tokens)?;
+                }
+            }
+            tk => tokens.push((tk, line)),
+        }
+    }
+    Ok(())
+}
+
+impl Parser {
+    // lexes the whole source up front and registers the library functions
+    // c4 provides as system calls
+    fn new(source: &str) -> Result<Self, String> {
+        let mut tokens = Vec::new();
+        lex(source, None, &mut Vec::new(), &mut tokens)?;
+
+        let mut symbols = HashMap::new();
+        let syscalls = [
+            ("open", OPEN), ("read", READ), ("close", CLOS), ("printf", PRTF),
+            ("malloc", MALC), ("free", FREE), ("memset", MSET), ("memcmp", MCMP), ("exit", EXIT),
+            ("write", WRIT),
+        ];
+        for (name, op) in syscalls {
+            // malloc hands back a char*, c4's stand-in for void*
+            let typ = if op == MALC { Type::CHAR.ptr_to() } else { Type::INT };
+            symbols.insert(name.to_string(), Symbol { class: Class::Sys, val: op, typ });
+        }
+
+        let mut parser = Self {
+            tk: Token::None,
+            ty: Type::INT,
+            loc: 0,
+            line: 1,
+            data: Vec::new(),
+            strings: Vec::new(),
+            pos: 0,
+            symbols,
+            tokens,
+        };
+        parser.next();
+        Ok(parser)
+    }
+
+    // advances to the next token, Token::None once the input is exhausted
+    fn next(&mut self) {
+        if let Some((tk, line)) = self.tokens.get(self.pos as usize) {
+            self.tk = tk.clone();
+            self.line = *line;
+            self.pos += 1;
+        } else {
+            self.tk = Token::None;
+        }
+    }
+
+    // consumes the type specifier keywords at the current token and returns
+    // the base type they name, e.g. "unsigned short" or "long long int"
+    fn type_specifiers(&mut self) -> Result<Type, String> {
+        let mut specs = Vec::new();
+        while is_type_specifier(&self.tk) {
+            specs.push(self.tk.clone());
+            self.next();
+        }
+        match Type::from_specifiers(&specs) {
+            Some(t) => Ok(t),
+            None => Err(format!("{}: bad type specifiers {:?}", self.line, specs)),
+        }
+    }
+
+    // the type of a binary arithmetic operator whose left operand has type t
+    // and right operand the current type, after the usual arithmetic conversions
+    fn arith_type(&self, t: Type, op: BinOp) -> Result<Type, String> {
+        let c = Type::common(t, self.ty);
+        if c.is_float() && matches!(op, BinOp::Or | BinOp::Xor | BinOp::And | BinOp::Mod) {
+            return Err(format!("{}: bad operand type for floating point", self.line));
+        }
+        Ok(c)
+    }
+
+    fn expr(&mut self, lev: i32) -> Result<Expr, String> {
+        let token = self.tk.clone();
+        let line = self.line;
+
+        let kind = match token {
+            Token::None => {
+                return Err(format!("{}: unexpected eof in expression", self.line));
+            }
+            Token::Num(val) => {
+                self.next();
+                self.ty = Type::literal(val);
+                ExprKind::Num(val)
+            }
+            Token::CharLit(c) => {
+                self.next();
+                self.ty = Type::INT; // like c4, character literals are ints
+                ExprKind::Num(c as i64)
+            }
+            Token::FloatLit(bits) => {
+                self.next();
+                self.ty = Type::DOUBLE;
+                ExprKind::Float(f64::from_bits(bits))
+            }
+            Token::Str(mut s) => {
+                self.next();
+                // adjacent literals are one string, as in "abc" "def"
+                while let Token::Str(more) = &self.tk {
+                    s.push_str(more);
+                    self.next();
+                }
+                let addr = self.store_string(&s); // Now no conflict with borrowing `self`
+                self.ty = Type::CHAR.ptr_to(); // string literals are char*
+                ExprKind::Str(addr)
+            }
+
+            Token::Sizeof => {
+                self.next();
+                if self.tk != Token::LParen {
+                    return Err(format!("{}: open paren expected in sizeof", self.line));
+                }
+                self.next();
+            
+                if !is_type_specifier(&self.tk) {
+                    return Err(format!("{}: type name expected in sizeof", self.line));
+                }
+                let mut t = self.type_specifiers()?;
+                while self.tk == Token::Mul {
+                    self.next();
+                    t = t.ptr_to();
+                }
+                if self.tk != Token::RParen {
+                    return Err(format!("{}: close paren expected in sizeof", self.line));
+                }
+                self.next();
+                self.ty = Type::INT;
+                ExprKind::Sizeof(t)
+            }         
+
+            Token::Id(ref name) => {
+                // Lookup the symbol table entry by identifier name
+                if let Some(d) = self.symbols.get(name.as_str()).cloned() {
+                    self.next(); // consume identifier
+
+                    if self.tk == Token::LParen {
+                        self.next();
+                        let mut args = Vec::new();
+                        while self.tk != Token::RParen {
+                            args.push(self.expr(Token::Assign.precedence().unwrap())?);
+                            if self.tk == Token::Comma {
+                                self.next();
+                            }
+                        }
+                        self.next();
+                        let sys = match d.class {
+                            Class::Sys => Some(d.val),
+                            Class::Fun => None,
+                            _ => {
+                                return Err(format!("{}: bad function call", self.line));
+                            }
+                        };
+                        self.ty = d.typ;
+                        ExprKind::Call { name: name.clone(), sys, args }
+                    } else if d.class == Class::Num {
+                        self.ty = Type::INT;
+                        ExprKind::Num(d.val as i64)
+                    } else {
+                        self.ty = d.typ;
+                        match d.class {
+                            Class::Loc => ExprKind::Local(name.clone(), self.loc - d.val),
+                            Class::Glo => ExprKind::Global(name.clone(), d.val),
+                            _ => {
+                                return Err(format!("{}: undefined variable '{}'", self.line, name));
+                            }
+                        }
+                    }
+                } else {
+                    return Err(format!("{}: undefined variable '{}'", self.line, name));
+                }
+            }            
+            
+            Token::Mul => {
+                self.next();
+                let inner = self.expr(Token::Inc.precedence().unwrap())?;
+                if self.ty.is_ptr() {
+                    self.ty = self.ty.deref();
+                } else {
+                    return Err(format!("{}: bad dereference", self.line));
+                }
+                ExprKind::Deref(Box::new(inner))
+            }
+
+            Token::And => {
+                self.next();
+                let inner = self.expr(Token::Inc.precedence().unwrap())?;
+                if !inner.is_lvalue() {
+                    return Err(format!("{}: bad address-of", self.line));
+                }
+                self.ty = self.ty.ptr_to();
+                ExprKind::AddrOf(Box::new(inner))
+            }
+
+            Token::LParen => {
+                self.next(); // consume '('
+                match self.tk {
+                    ref tk if is_type_specifier(tk) => {
+                        // Handle cast to any integer or pointer type
+                        let mut t = self.type_specifiers()?;
+            
+                        // Check for pointer dereferencing (*)
+                        while let Token::Mul = self.tk {
+                            self.next(); // consume '*'
+                            t = t.ptr_to(); // Adjust type for pointers
+                        }
+            
+                        // Ensure we have a closing parenthesis ')'
+                        if let Token::RParen = self.tk {
+                            self.next(); // consume ')'
+                        } else {
+                            return Err(format!("{}: bad cast", self.line)); // Handle bad cast
+                        }
+            
+                        // Handle the casted expression
+                        let inner = self.expr(Token::Inc.precedence().unwrap())?;
+                        self.ty = t; // Set the type for the expression
+                        ExprKind::Cast(Box::new(inner))
+                    }
+                    _ => {
+                        // Regular parenthesis group
+                        let inner = self.expr(Token::Assign.precedence().unwrap())?;
+            
+                        // Ensure we have a closing parenthesis ')'
+                        if let Token::RParen = self.tk {
+                            self.next(); // consume ')'
+                        } else {
+                            return Err(format!("{}: close paren expected", self.line)); // Handle missing closing parenthesis
+                        }
+                        inner.kind
+                    }
+                }
+            }            
+
+            
+            Token::Not => {
+                self.next();
+                let inner = self.expr(Token::Inc.precedence().unwrap())?; // Inc is the precedence level
+                self.ty = Type::INT;
+                ExprKind::Unary(UnOp::Not, Box::new(inner))
+            }
+            Token::BitNot => {
+                self.next();
+                let inner = self.expr(Token::Inc.precedence().unwrap())?;
+                self.ty = self.ty.promote();
+                ExprKind::Unary(UnOp::BitNot, Box::new(inner))
+            }
+            Token::Add => {
+                self.next();
+                let inner = self.expr(Token::Inc.precedence().unwrap())?;
+                self.ty = self.ty.promote();
+                ExprKind::Unary(UnOp::Plus, Box::new(inner))
+            }
+            
+            Token::Sub => {
+                self.next();
+                match self.tk.clone() {
+                    Token::Num(val) => {
+                        self.next();
+                        self.ty = Type::literal(val.wrapping_neg());
+                        ExprKind::Num(val.wrapping_neg())
+                    }
+                    Token::FloatLit(bits) => {
+                        self.next();
+                        self.ty = Type::DOUBLE;
+                        ExprKind::Float(-f64::from_bits(bits))
+                    }
+                    _ => {
+                        let inner = self.expr(Token::Inc.precedence().unwrap())?;
+                        self.ty = self.arith_type(Type::INT, BinOp::Mul)?;
+                        ExprKind::Unary(UnOp::Neg, Box::new(inner))
+                    }
+                }
+            }
+
+            Token::Inc | Token::Dec => {
+                let t = self.tk.clone(); // Clone the current token (Inc or Dec)
+                self.next(); // Consume the token (either Inc or Dec)
+                
+                // Evaluate the expression for the operand
+                let inner = self.expr(Token::Inc.precedence().unwrap())?;
+                if !inner.is_lvalue() {
+                    return Err(format!("{}: bad lvalue in pre-increment", self.line)); // Handle bad lvalue
+                }
+                let op = if t == Token::Inc { IncDec::PreInc } else { IncDec::PreDec };
+                ExprKind::IncDec(op, Box::new(inner))
+            }
+            
+            _ => {
+                return Err(format!("{}: bad expression", self.line));
+            }
+        };
+        let mut node = Expr::new(kind, self.ty, line);
+    
+        // precedence climbing would go here
+    let mut t; // Type of the left operand
+
+    while let Some(precedence) = self.tk.precedence() {
+        if precedence < lev {
+            break; // Exit if the current token's precedence is less than the level
+        }
+        t = self.ty; // Store the current type
+
+        let kind = match self.tk {
+            Token::Assign => {
+                self.next();
+                if !node.is_lvalue() {
+                    return Err(format!("{}: bad lvalue in assignment", self.line));
+                }
+                let rhs = self.expr(Token::Assign.precedence().unwrap())?;
+                self.ty = t;
+                ExprKind::Assign(Box::new(node), Box::new(rhs))
+            }
+            Token::Cond => {
+                self.next();
+                let then = self.expr(Token::Assign.precedence().unwrap())?;
+                if let Token::Colon = self.tk {
+                    self.next();
+                } else {
+                    return Err(format!("{}: conditional missing colon", self.line));
+                }
+                let els = self.expr(Token::Cond.precedence().unwrap())?;
+                self.ty = if then.ty.is_ptr() {
+                    then.ty
+                } else if els.ty.is_ptr() {
+                    els.ty
+                } else {
+                    Type::common(then.ty, els.ty)
+                };
+                ExprKind::Cond(Box::new(node), Box::new(then), Box::new(els))
+            }
+            Token::Lor => {
+                self.next();
+                let rhs = self.expr(Token::Lan.precedence().unwrap())?;
+                self.ty = Type::INT;
+                binary(BinOp::LogOr, node, rhs)
+            }
+            Token::Lan => {
+                self.next();
+                let rhs = self.expr(Token::Or.precedence().unwrap())?;
+                self.ty = Type::INT;
+                binary(BinOp::LogAnd, node, rhs)
+            }
+            Token::Or => {
+                self.next();
+                let rhs = self.expr(Token::Xor.precedence().unwrap())?;
+                self.ty = self.arith_type(t, BinOp::Or)?;
+                binary(BinOp::Or, node, rhs)
+            }
+            Token::Xor => {
+                self.next();
+                let rhs = self.expr(Token::And.precedence().unwrap())?;
+                self.ty = self.arith_type(t, BinOp::Xor)?;
+                binary(BinOp::Xor, node, rhs)
+            }
+            Token::And => {
+                self.next();
+                let rhs = self.expr(Token::Eq.precedence().unwrap())?;
+                self.ty = self.arith_type(t, BinOp::And)?;
+                binary(BinOp::And, node, rhs)
+            }
+            Token::Eq | Token::Ne => {
+                let op = if self.tk == Token::Eq { BinOp::Eq } else { BinOp::Ne };
+                self.next();
+                let rhs = self.expr(Token::Lt.precedence().unwrap())?;
+                self.ty = Type::INT;
+                binary(op, node, rhs)
+            }
+            Token::Lt | Token::Gt | Token::Le | Token::Ge => {
+                let op = match self.tk {
+                    Token::Lt => BinOp::Lt,
+                    Token::Gt => BinOp::Gt,
+                    Token::Le => BinOp::Le,
+                    _ => BinOp::Ge,
+                };
+                self.next();
+                let rhs = self.expr(Token::Shl.precedence().unwrap())?;
+                self.ty = Type::INT;
+                binary(op, node, rhs)
+            }
+            Token::Shl | Token::Shr => {
+                let op = if self.tk == Token::Shl { BinOp::Shl } else { BinOp::Shr };
+                self.next();
+                let rhs = self.expr(Token::Add.precedence().unwrap())?;
+                self.ty = t.promote(); // shifts take the type of the left operand
+                if self.ty.is_float() || rhs.ty.is_float() {
+                    return Err(format!("{}: bad operand type for shift", self.line));
+                }
+                binary(op, node, rhs)
+            }
+            Token::Add => {
+                self.next();
+                let rhs = self.expr(Token::Mul.precedence().unwrap())?;
+                self.ty = if t.is_ptr() { t } else { self.arith_type(t, BinOp::Add)? };
+                binary(BinOp::Add, node, rhs)
+            }
+            Token::Sub => {
+                self.next();
+                let rhs = self.expr(Token::Mul.precedence().unwrap())?;
+                self.ty = if t.is_ptr() && t == self.ty {
+                    Type::INT
+                } else if t.is_ptr() {
+                    t
+                } else {
+                    self.arith_type(t, BinOp::Sub)?
+                };
+                binary(BinOp::Sub, node, rhs)
+            }
+            Token::Mul | Token::Div | Token::Mod => {
+                let op = match self.tk {
+                    Token::Mul => BinOp::Mul,
+                    Token::Div => BinOp::Div,
+                    _ => BinOp::Mod,
+                };
+                self.next();
+                let rhs = self.expr(Token::Inc.precedence().unwrap())?;
+                self.ty = self.arith_type(t, op)?;
+                binary(op, node, rhs)
+            }
+            Token::Inc | Token::Dec => {
+                if !node.is_lvalue() {
+                    return Err(format!("{}: bad lvalue in post-increment", self.line));
+                }
+                let op = if self.tk == Token::Inc { IncDec::PostInc } else { IncDec::PostDec };
+                self.next();
+                ExprKind::IncDec(op, Box::new(node))
+            }
+            Token::Brak => {
+                self.next();
+                let index = self.expr(Token::Assign.precedence().unwrap())?;
+                if let Token::RBrak = self.tk {
+                    self.next();
+                } else {
+                    return Err(format!("{}: close bracket expected", self.line));
+                }
+                if !t.is_ptr() {
+                    return Err(format!("{}: pointer type expected", self.line));
+                }
+                self.ty = t.deref(); // Assign the new type
+                ExprKind::Index(Box::new(node), Box::new(index))
+            }
+            _ => {
+                return Err(format!("{}: compiler error tk={:?}", self.line, self.tk));
+            }
+        };
+        node = Expr::new(kind, self.ty, line);
+    }
+    Ok(node)
+    }
+
+    fn store_string(&mut self, s: &str) -> i32 {
+        // Align to 4 bytes (simulate C4's `sizeof(int) & -sizeof(int)`)
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+
+        let address = self.data.len() as i32; // get the current offset (address)
+        self.strings.push((address, s.to_string()));
+
+        // Store the string bytes
+        self.data.extend_from_slice(s.as_bytes());
+        self.data.push(0); // null-terminator
+
+        // Optional: align after string for next storage
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+
+        address
+    }
+
+    // reserves `size` zeroed bytes of the data segment for a global
+    fn reserve(&mut self, size: usize) -> i32 {
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+        let address = self.data.len() as i32;
+        self.data.resize(self.data.len() + size, 0);
+        address
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, String> {
+        Ok(match self.tk {
+            Token::If => {
+                self.next();
+                if let Token::LParen = self.tk {
+                    self.next();
+                } else {
+                    return Err(format!("{}: open paren expected", self.line));
+                }
+                let cond = self.expr(Token::Assign.precedence().unwrap())?;
+                if let Token::RParen = self.tk {
+                    self.next();
+                } else {
+                    return Err(format!("{}: close paren expected", self.line));
+                }
+                let then = self.stmt()?;
+                let mut els = None;
+                if let Token::Else = self.tk {
+                    self.next();
+                    els = Some(Box::new(self.stmt()?));
+                }
+                Stmt::If(cond, Box::new(then), els)
+            }
+            Token::While => {
+                self.next();
+                if let Token::LParen = self.tk {
+                    self.next();
+                } else {
+                    return Err(format!("{}: open paren expected", self.line));
+                }
+                let cond = self.expr(Token::Assign.precedence().unwrap())?;
+                if let Token::RParen = self.tk {
+                    self.next();
+                } else {
+                    return Err(format!("{}: close paren expected", self.line));
+                }
+                let body = self.stmt()?;
+                Stmt::While(cond, Box::new(body))
+            }
+            Token::Do => {
+                self.next();
+                let body = self.stmt()?;
+                if let Token::While = self.tk {
+                    self.next();
+                    if let Token::LParen = self.tk {
+                        self.next();
+                    } else {
+                        return Err(format!("{}: open paren expected after while in do-while", self.line));
+                    }
+                    let cond = self.expr(Token::Assign.precedence().unwrap())?;
+                    if let Token::RParen = self.tk {
+                        self.next();
+                    } else {
+                        return Err(format!("{}: close paren expected after while in do-while", self.line));
+                    }
+                    if let Token::Semicolon = self.tk {
+                        self.next();
+                    } else {
+                        return Err(format!("{}: semicolon expected after do-while", self.line));
+                    }
+                    Stmt::DoWhile(Box::new(body), cond)
+                } else {
+                    return Err(format!("{}: while expected after do statement", self.line));
+                }
+            }
+            Token::Return => {
+                let line = self.line;
+                self.next();
+                let mut value = None;
+                if self.tk != Token::Semicolon {
+                    value = Some(self.expr(Token::Assign.precedence().unwrap())?);
+                }
+                if let Token::Semicolon = self.tk {
+                    self.next();
+                } else {
+                    return Err(format!("{}: semicolon expected", self.line));
+                }
+                Stmt::Return(value, line)
+            }
+            Token::LBrace => {
+                self.next();
+                let mut body = Vec::new();
+                while self.tk != Token::RBrace {
+                    body.push(self.stmt()?);
+                }
+                self.next();
+                Stmt::Block(body)
+            }
+            Token::Semicolon => {
+                self.next();
+                Stmt::Empty
+            }
+            _ => {
+                let value = self.expr(Token::Assign.precedence().unwrap())?;
+                if let Token::Semicolon = self.tk {
+                    self.next();
+                } else {
+                    return Err(format!("{}: semicolon expected", self.line));
+                }
+                Stmt::Expr(value)
+            }
+        })
+    }
+
+    // parses a whole translation unit: enums, globals and function definitions,
+    // emitting each function's code as it goes, like c4's main loop
+    fn program(&mut self) -> Result<Program, String> {
+        let mut program = Program::default();
+        while self.tk != Token::None {
+            self.declaration(&mut program)?;
+        }
+        Ok(program)
+    }
+
+    // parses one declaration at the top level, up to its `;` or the end of
+    // its function body, into program
+    fn declaration(&mut self, program: &mut Program) -> Result<(), String> {
+        let mut bt = Type::INT; // base type
+        // extern declares a global some other file defines
+        let external = self.tk == Token::Extern;
+        if external {
+            self.next();
+        }
+        if is_type_specifier(&self.tk) {
+            bt = self.type_specifiers()?;
+        } else if self.tk == Token::Enum {
+            self.next();
+            if self.tk != Token::LBrace {
+                // enum tags are accepted and ignored
+                self.next();
+            }
+            if self.tk == Token::LBrace {
+                self.next();
+                let mut i = 0;
+                while self.tk != Token::RBrace {
+                    let name = match self.tk.clone() {
+                        Token::Id(name) => name,
+                        _ => {
+                            return Err(format!("{}: bad enum identifier {:?}", self.line, self.tk));
+                        }
+                    };
+                    self.next();
+                    if self.tk == Token::Assign {
+                        self.next();
+                        let negative = self.tk == Token::Sub;
+                        if negative {
+                            self.next();
+                        }
+                        i = match self.tk {
+                            Token::Num(val) => (if negative { -val } else { val }) as i32,
+                            _ => {
+                                return Err(format!("{}: bad enum initializer", self.line));
+                            }
+                        };
+                        self.next();
+                    }
+                    self.symbols.insert(name, Symbol { class: Class::Num, val: i, typ: Type::INT });
+                    i += 1;
+                    if self.tk == Token::Comma {
+                        self.next();
+                    }
+                }
+                self.next();
+            }
+        }
+
+        while self.tk != Token::Semicolon && self.tk != Token::RBrace {
+            let line = self.line;
+            let mut ty = bt;
+            while self.tk == Token::Mul {
+                self.next();
+                ty = ty.ptr_to();
+            }
+            let name = match self.tk.clone() {
+                Token::Id(name) => name,
+                _ => {
+                    return Err(format!("{}: bad global declaration", self.line));
+                }
+            };
+            // a name can be declared any number of times but defined only
+            // once; a negative val means only declared so far
+            let declared = self.symbols.get(&name).filter(|s| s.class != Class::Sys).cloned();
+            self.next();
+            let is_fun = self.tk == Token::LParen;
+            let clash = match &declared {
+                Some(s) if is_fun => s.class != Class::Fun,
+                Some(s) => s.class != Class::Glo || (s.val >= 0 && !external),
+                None => false,
+            };
+            if clash {
+                return Err(format!("{}: duplicate global definition", line));
+            }
+            if is_fun {
+                if declared.is_none() {
+                    self.symbols.insert(name.clone(), Symbol { class: Class::Fun, val: -1, typ: ty });
+                }
+                let f = self.function(name.clone(), ty, line)?;
+                if self.tk == Token::Semicolon {
+                    program.prototypes.push(f);
+                } else {
+                    self.symbols.insert(name, Symbol { class: Class::Fun, val: program.functions.len() as i32, typ: ty });
+                    program.functions.push(f);
+                }
+                break; // a function body or prototype ends the declaration
+            }
+            if external {
+                // externs get addresses below 0, which only the linker resolves
+                if declared.is_none() {
+                    let val = -(program.externs.len() as i32) - 1;
+                    self.symbols.insert(name.clone(), Symbol { class: Class::Glo, val, typ: ty });
+                    program.externs.push(Var { name, ty, line });
+                }
+            } else {
+                let val = self.reserve(4);
+                self.symbols.insert(name.clone(), Symbol { class: Class::Glo, val, typ: ty });
+                program.globals.push(Var { name, ty, line });
+            }
+            if self.tk == Token::Comma {
+                self.next();
+            }
+        }
+        self.next();
+        Ok(())
+    }
+
+    // parses the parameter list, locals and body of a function whose name
+    // and return type have already been consumed
+    fn function(&mut self, name: String, ret: Type, line: i32) -> Result<Function, String> {
+        let mut shadowed: Vec<(String, Option<Symbol>)> = Vec::new();
+        let mut params = Vec::new();
+        let mut locals = Vec::new();
+        let mut i = 0;
+
+        self.next(); // consume '('
+        while self.tk != Token::RParen {
+            let mut ty = if is_type_specifier(&self.tk) { self.type_specifiers()? } else { Type::INT };
+            while self.tk == Token::Mul {
+                self.next();
+                ty = ty.ptr_to();
+            }
+            let pname = match self.tk.clone() {
+                Token::Id(pname) => pname,
+                _ => {
+                    return Err(format!("{}: bad parameter declaration", self.line));
+                }
+            };
+            if self.symbols.get(&pname).is_some_and(|s| s.class == Class::Loc) {
+                return Err(format!("{}: duplicate parameter definition", self.line));
+            }
+            let old = self.symbols.insert(pname.clone(), Symbol { class: Class::Loc, val: i, typ: ty });
+            shadowed.push((pname.clone(), old));
+            params.push(Var { name: pname, ty, line: self.line });
+            i += 1;
+            self.next();
+            if self.tk == Token::Comma {
+                self.next();
+            }
+        }
+        self.next();
+        // a prototype ends at the parameters
+        if self.tk == Token::Semicolon {
+            self.restore(shadowed);
+            return Ok(Function { name, ret, params, locals, body: Vec::new(), line });
+        }
+        if self.tk != Token::LBrace {
+            return Err(format!("{}: bad function definition", self.line));
+        }
+        if self.symbols.get(&name).is_some_and(|s| s.class == Class::Fun && s.val >= 0) {
+            return Err(format!("{}: duplicate global definition", line));
+        }
+        i += 1;
+        self.loc = i;
+        self.next();
+
+        while is_type_specifier(&self.tk) {
+            let bt = self.type_specifiers()?;
+            while self.tk != Token::Semicolon {
+                let mut ty = bt;
+                while self.tk == Token::Mul {
+                    self.next();
+                    ty = ty.ptr_to();
+                }
+                let lname = match self.tk.clone() {
+                    Token::Id(lname) => lname,
+                    _ => {
+                        return Err(format!("{}: bad local declaration", self.line));
+                    }
+                };
+                if self.symbols.get(&lname).is_some_and(|s| s.class == Class::Loc) {
+                    return Err(format!("{}: duplicate local definition", self.line));
+                }
+                i += 1;
+                let old = self.symbols.insert(lname.clone(), Symbol { class: Class::Loc, val: i, typ: ty });
+                shadowed.push((lname.clone(), old));
+                locals.push(Var { name: lname, ty, line: self.line });
+                self.next();
+                if self.tk == Token::Comma {
+                    self.next();
+                }
+            }
+            self.next();
+        }
+
+        let mut body = Vec::new();
+        while self.tk != Token::RBrace {
+            body.push(self.stmt()?);
+        }
+
+        self.restore(shadowed);
+        Ok(Function { name, ret, params, locals, body, line })
+    }
+
+    // the object file for the code generated from this parser's program:
+    // its data segment and the globals it defines
+    fn object(&self, name: &str, code: codegen::Code) -> link::Object {
+        let globals = self.symbols.iter().filter(|(_, s)| s.class == Class::Glo && s.val >= 0).map(|(n, s)| (n.clone(), s.val)).collect();
+        link::Object { name: name.to_string(), code, data: self.data.clone(), globals }
+    }
+
+    // unwinds a function's parameters and locals, bringing back any globals they hid
+    fn restore(&mut self, shadowed: Vec<(String, Option<Symbol>)>) {
+        for (sname, old) in shadowed.into_iter().rev() {
+            match old {
+                Some(sym) => self.symbols.insert(sname, sym),
+                None => self.symbols.remove(&sname),
+            };
+        }
+    }
+}
+
+// fn main() {
+//     let mut state = Parser::new();
+    
+//     // Test with a number
+//     state.tk = Token::Num(42);
+//     state.expr(0);
+//     println!("Emitted code (number): {:?}", state.e);
+
+//     // Reset state
+//     state.e.clear();
+
+//     // Test with a string
+//     state.tk = Token::Str("hello".to_string());
+//     state.expr(0);
+//     println!("Emitted code (string): {:?}", state.e);
+//     println!("Data section (string): {:?}", state.data);
+// }
+
+const POOL_SIZE: usize = 2 * 1024 * 1024; // Define POOL_SIZE
+
+// A compiled program, for running it from Rust instead of through the c4
+// command line:
+//
+//   let image = c4_rust_mleiha::compile(source)?;
+//   let mut vm = image.vm(&["prog.c"]);
+//   vm.stdout = Box::new(out.clone()); // an stdio::Capture
+//   vm.run();
+pub struct Image {
+    pub text: Vec<i64>,                  // the code, with the stub main returns into after it
+    pub data: Vec<u8>,                   // the data segment
+    pub entry: usize,                    // main
+    pub stub: usize,                     // the PSH; EXIT main returns into, like c4 sets up
+    pub functions: HashMap<String, i32>, // every function's entry point
+    pub lines: Vec<(i32, i32)>,          // the line table
+}
+
+impl Image {
+    // appends the exit stub to the generated code; None if there is no main
+    fn link(code: codegen::Code, data: Vec<u8>) -> Option<Self> {
+        let entry = *code.functions.get("main")? as usize;
+        let mut text: Vec<i64> = code.text.iter().map(|&w| w as i64).collect();
+        let stub = text.len();
+        text.push(PSH as i64);
+        text.push(EXIT as i64);
+        Some(Image { text, data, entry, stub, functions: code.functions, lines: code.lines })
+    }
+
+    // a VM about to call main(argc, argv) with args, the program's name
+    // first; it prints to the process's stdout until told otherwise
+    pub fn vm<S: AsRef<str>>(&self, args: &[S]) -> vm::VM {
+        let args: Vec<String> = args.iter().map(|a| a.as_ref().to_string()).collect();
+        let mut vm = vm::VM::new(self.text.clone(), 0, POOL_SIZE);
+        vm.load_data(&self.data);
+        let argv = vm.load_args(&args);
+        vm.sp -= 1;
+        vm.stack[vm.sp] = args.len() as i64; // argc
+        vm.sp -= 1;
+        vm.stack[vm.sp] = argv;
+        vm.sp -= 1;
+        vm.stack[vm.sp] = self.stub as i64;
+        vm.pc = self.entry;
+        vm.lines = self.lines.clone();
+        vm
+    }
+}
+
+// compiles a program the way the command line does without options, or
+// returns its errors: the syntax error that stopped it, or its type errors
+pub fn compile(source: &str) -> Result<Image, Vec<String>> {
+    compile_files(&[("<input>", source)])
+}
+
+// compiles each (name, source) file on its own and links them, like
+// `c4 a.c b.c --`; type errors name the file when there is more than one
+pub fn compile_files(files: &[(&str, &str)]) -> Result<Image, Vec<String>> {
+    let mut objects = Vec::new();
+    let mut errors = Vec::new();
+    for &(name, source) in files {
+        match compile_object(name, source) {
+            Ok(object) => objects.push(object),
+            Err(e) if files.len() > 1 => errors.extend(e.iter().map(|e| format!("{}:{}", name, e))),
+            Err(e) => errors.extend(e),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let libs = if link::incomplete(&objects) { vec![stdlib::archive()] } else { Vec::new() };
+    let (code, data) = link::link(&objects, &libs)?;
+    Image::link(code, data).ok_or_else(|| vec!["main() not defined".to_string()])
+}
+
+// compiles one file into an object without options, or returns its errors
+fn compile_object(name: &str, source: &str) -> Result<link::Object, Vec<String>> {
+    let mut parser = Parser::new(source).map_err(|e| vec![e])?;
+    let program = parser.program().map_err(|e| vec![e])?;
+    let errors: Vec<String> =
+        typeck::check(&program, &typeck::Options::default()).iter().filter(|d| d.severity == typeck::Severity::Error).map(|d| d.to_string()).collect();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(parser.object(name, codegen::generate(&program)))
+}
+
+// the c4 command line, which src/main.rs runs
+pub fn cli() {
+    let mut src = false;
+    let mut debug = false;
+    let mut dump_ast = false;
+    let mut optimize = false;
+    let mut ssa = false;
+    let mut dump_ir = false;
+    let mut classic = false;
+    let mut stats = false;
+    let mut check = false;
+    let mut leaks = false;
+    let mut profile = false;
+    let mut folded = None;
+    let mut coverage = false;
+    let mut lcov = None;
+    let mut limits = sandbox::Limits::default();
+    let mut vfs = None;
+    let mut vfs_out = None;
+    let mut libs = Vec::new();
+    let mut nostdlib = false;
+    let mut archive = None;
+    let mut jit = false;
+    let mut emit = None;
+    let mut opts = typeck::Options::default();
+    let mut werror = false;
+    let mut permissive = false;
+    let args: Vec<String> = std::env::args().collect();
+    // `c4 repl` reads C from stdin a line at a time instead of compiling a file
+    if args.len() == 2 && args[1] == "repl" {
+        let code = repl::Repl::new().run(io::stdin().lock(), io::stdin().is_terminal());
+        std::process::exit(code as i32);
+    }
+    
+    let mut argc = args.len() - 1; // Exclude the program name
+    let mut argv = &args[1..];
+
+    while argc > 0 && argv[0].starts_with('-') {
+        match argv[0].as_str() {
+            "-s" => src = true,
+            "-d" => debug = true,
+            "--dump-ast" => dump_ast = true,
+            "-O" => optimize = true,
+            "-O2" => {
+                optimize = true;
+                ssa = true; // go through the SSA IR as well
+            }
+            "--dump-ir" => dump_ir = true,
+            "--dispatch=classic" => classic = true, // the opcode-matching loop instead of the decoded one
+            "--dispatch=decoded" => classic = false,
+            "--stats" => stats = true,
+            "--check" => check = true, // report out of bounds accesses, bad frees and uninitialized locals
+            "--leaks" => leaks = true, // report the blocks never freed when the program exits
+            "--profile" => profile = true, // report the hot functions and lines when the program exits
+            flag if flag.starts_with("--profile=") => {
+                profile = true;
+                folded = Some(flag["--profile=".len()..].to_string()); // and write the call stacks there
+            }
+            // limits for programs nobody has reviewed
+            flag if flag.starts_with("--max-") => {
+                let (name, value) = flag["--max-".len()..].split_once('=').unwrap_or((flag, ""));
+                let Ok(n) = value.parse::<usize>() else {
+                    eprintln!("{} needs a number", flag);
+                    return;
+                };
+                match name {
+                    "cycles" => limits.cycles = Some(n as u64),
+                    "heap" => limits.heap = Some(n),
+                    "depth" => limits.depth = Some(n),
+                    "output" => limits.output = Some(n),
+                    _ => {
+                        eprintln!("unknown option {}", flag);
+                        return;
+                    }
+                }
+            }
+            "--no-open" => limits.open = sandbox::OpenPolicy::Deny,
+            flag if flag.starts_with("--allow-open=") => {
+                let dir = PathBuf::from(&flag["--allow-open=".len()..]);
+                match &mut limits.open {
+                    sandbox::OpenPolicy::Allow(dirs) => dirs.push(dir),
+                    open => *open = sandbox::OpenPolicy::Allow(vec![dir]),
+                }
+            }
+            flag if flag.starts_with("--root=") => limits.open = sandbox::OpenPolicy::Root(PathBuf::from(&flag["--root=".len()..])),
+            // open files from a copy of DIR in memory, which writes go to as well
+            flag if flag.starts_with("--vfs=") => vfs = Some(PathBuf::from(&flag["--vfs=".len()..])),
+            // and save its files to DIR once the program is done
+            flag if flag.starts_with("--vfs-out=") => vfs_out = Some(PathBuf::from(&flag["--vfs-out=".len()..])),
+            flag if flag.starts_with("--lib=") => libs.push(PathBuf::from(&flag["--lib=".len()..])), // link with an archive --archive wrote
+            "-nostdlib" => nostdlib = true, // don't link the bundled C library
+            // save the compiled files as an archive instead of linking them
+            flag if flag.starts_with("--archive=") => archive = Some(PathBuf::from(&flag["--archive=".len()..])),
+            "--coverage" => coverage = true, // report the lines and instructions run when the program exits
+            flag if flag.starts_with("--coverage=") => {
+                coverage = true;
+                lcov = Some(flag["--coverage=".len()..].to_string()); // and write an lcov tracefile there
+            }
+            "--emit=asm" | "--emit=c" | "--emit=wasm" | "--emit=wat" | "--emit=llvm" => emit = Some(argv[0][7..].to_string()), // write the program out instead of running it
+            "--jit" => jit = true, // translate to x86-64 and run that instead
+            "-Wall" => opts.wall = true,
+            "-Werror" => werror = true,
+            "-fpermissive" => permissive = true, // c4's behaviour: no semantic checks at all
+            flag => {
+                eprintln!("unknown option {}", flag);
+                return;
+            }
+        }
+        argc -= 1;
+        argv = &argv[1..];
+    }
+    if argc < 1 {
+        eprintln!("usage: c4 [-s] [-d] [-O] [-O2] [--dump-ast] [--dump-ir] [--dispatch=classic|decoded] [--jit] [--emit=asm|c|wasm|wat|llvm] [--stats] [--check] [--leaks] [--profile[=FILE]] [--coverage[=FILE]] [--max-cycles|heap|depth|output=N] [--no-open] [--allow-open=DIR] [--root=DIR] [--vfs=DIR] [--vfs-out=DIR] [--lib=FILE] [-nostdlib] [--archive=FILE] [-Wall] [-Werror] [-fpermissive] file [arg ...] | file ... -- [arg ...] | repl");
+        return;
+    }
+
+    // several source files are listed up to a `--`; without one only the
+    // first argument is, and everything after it goes to the program
+    let (files, program_args) = match argv.iter().position(|a| a == "--") {
+        Some(n) => (&argv[..n], [&argv[..1], &argv[n + 1..]].concat()),
+        None => (&argv[..1], argv.to_vec()),
+    };
+    if files.is_empty() {
+        eprintln!("no source file before --");
+        return;
+    }
+    let file_path = &files[0];
+    if files.len() > 1 && (emit.as_deref() == Some("llvm") || coverage) {
+        eprintln!("{} takes a single source file", if coverage { "--coverage" } else { "--emit=llvm" });
+        return;
+    }
+
+    let mut objects = Vec::new();
+    // the unoptimized and optimized sizes, so -O can report what it saved
+    let (mut before, mut after) = (0, 0);
+    let mut main_args = 0; // the parameters main declares, for --check
+    for file_path in files {
+        let mut file = match File::open(file_path) {
+            Ok(f) => f,
+            Err(_) => {
+                eprintln!("could not open({})", file_path);
+                return;
+            }
+        };
+
+        // Read the source file; bytes that aren't UTF-8 can only be in strings
+        // and comments, and become U+FFFD there
+        let mut bytes = Vec::new();
+        if let Err(e) = file.read_to_end(&mut bytes) {
+            eprintln!("could not read({}): {}", file_path, e);
+            std::process::exit(-1);
+        }
+        let source = String::from_utf8_lossy(&bytes).into_owned();
+
+        // Parse declarations; a syntax error ends it, as it does in c4
+        let (parser, mut program) = match Parser::new(&source).and_then(|mut p| p.program().map(|program| (p, program))) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                if files.len() > 1 {
+                    eprint!("{}:", file_path);
+                }
+                eprintln!("{}", e);
+                std::process::exit(-1);
+            }
+        };
+
+        if !permissive {
+            let mut failed = false;
+            for d in typeck::check(&program, &opts) {
+                if files.len() > 1 {
+                    eprint!("{}:", file_path);
+                }
+                eprintln!("{}", d);
+                failed |= d.severity == typeck::Severity::Error || werror;
+            }
+            if failed {
+                std::process::exit(-1);
+            }
+        }
+
+        if let Some(main) = program.functions.iter().find(|f| f.name == "main") {
+            main_args = main.params.len();
+        }
+        if optimize {
+            before += optimize::instruction_count(&codegen::generate(&program).text);
+            optimize::fold(&mut program);
+        }
+        if dump_ast {
+            print!("{}", ast::dump(&program));
+            continue;
+        }
+
+        if emit.as_deref() == Some("llvm") {
+            // the module calls and loads everything by name, so there is nothing
+            // to link, but what is only declared is fine as long as it isn't used
+            let relocs = codegen::generate(&program).relocs;
+            if let Some(name) = relocs.iter().find_map(|(_, r)| if let codegen::Reloc::Symbol(name) = r { Some(name) } else { None }) {
+                eprintln!("--emit=llvm doesn't link: '{}' isn't defined in {}", name, file_path);
+                std::process::exit(-1);
+            }
+            let mut module = ir::build(&program);
+            if ssa {
+                ir_opt::optimize(&mut module);
+            }
+            let mut globals: Vec<(String, i32)> =
+                parser.symbols.iter().filter(|(_, s)| s.class == Class::Glo).map(|(n, s)| (n.clone(), s.val)).collect();
+            globals.sort_by_key(|&(_, addr)| addr);
+            print!("{}", llvm::emit(&program, &module, &globals, &parser.strings, &parser.data, file_path));
+            return;
+        }
+
+        let mut code = if ssa || dump_ir {
+            let mut module = ir::build(&program);
+            if ssa {
+                ir_opt::optimize(&mut module);
+            }
+            if dump_ir {
+                print!("{}", ir::dump(&module));
+                continue;
+            }
+            ir_lower::lower(&module)
+        } else {
+            codegen::generate(&program)
+        };
+        if optimize {
+            optimize::peephole(&mut code);
+            after += optimize::instruction_count(&code.text);
+        }
+        objects.push(parser.object(file_path, code));
+    }
+    if dump_ast || dump_ir {
+        return;
+    }
+    if optimize {
+        let flag = if ssa { "-O2" } else { "-O" };
+        // copying loop tests to the bottom can make the text longer than it was
+        let change = if after <= before { format!("{} saved", before - after) } else { format!("{} more", after - before) };
+        eprintln!("{}: {} instructions, {} before optimizing ({})", flag, after, before, change);
+    }
+
+    if let Some(path) = &archive {
+        if let Err(e) = std::fs::write(path, link::Archive { members: objects }.to_bytes()) {
+            eprintln!("could not write {}: {}", path.display(), e);
+            std::process::exit(-1);
+        }
+        return;
+    }
+    let mut archives = Vec::new();
+    for path in &libs {
+        match std::fs::read(path).map_err(|e| e.to_string()).and_then(|bytes| link::Archive::from_bytes(&bytes)) {
+            Ok(lib) => archives.push(lib),
+            Err(e) => {
+                eprintln!("could not read {}: {}", path.display(), e);
+                std::process::exit(-1);
+            }
+        }
+    }
+    // the bundled library is compiled from its sources, so only when it can be needed
+    if !nostdlib && link::incomplete(&objects) {
+        archives.push(stdlib::archive());
+    }
+    // the file's own code comes first, which is all --coverage looks at
+    let own = objects.first().map_or(0, |o| o.code.text.len());
+    let (code, data) = match link::link(&objects, &archives) {
+        Ok(linked) => linked,
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{}", e);
+            }
+            std::process::exit(-1);
+        }
+    };
+    if src && jit {
+        let text: Vec<i64> = code.text.iter().map(|&w| w as i64).collect();
+        print!("{}", native::compile(&text).listing());
+        return;
+    }
+    if src {
+        print!("{}", codegen::listing(&code.text));
+        return;
+    }
+    let Some(image) = Image::link(code, data) else {
+        eprintln!("main() not defined");
+        std::process::exit(-1);
+    };
+    let Image { text, data, entry, stub, functions, .. } = &image;
+
+    // code from an archive can call or jump where a backend has no function
+    // or block to go to
+    if let Some(target) = &emit {
+        let emitted = match target.as_str() {
+            "asm" => Ok(emit::asm(text, functions, *entry, *stub, data).into_bytes()),
+            "wasm" => wasm::build(text, functions, *entry, *stub, data, POOL_SIZE).map(|m| m.binary()),
+            "wat" => wasm::build(text, functions, *entry, *stub, data, POOL_SIZE).map(|m| m.wat().into_bytes()),
+            _ => emit::c(text, functions, *entry, *stub, data).map(String::into_bytes),
+        };
+        match emitted {
+            Ok(out) => {
+                let _ = io::stdout().write_all(&out);
+                return;
+            }
+            Err(e) => {
+                eprintln!("--emit={}: {}", target, e);
+                std::process::exit(-1);
+            }
+        }
+    }
+
+    let mut vm = image.vm(&program_args); // the first source file and the arguments after the files
+    vm.debug = debug;
+    if check {
+        vm.memcheck = Some(memcheck::MemCheck::new(&vm, data.len(), main_args));
+    }
+    if leaks {
+        vm.leaks = Some(leaks::Leaks::new());
+    }
+    let sandboxed = limits != sandbox::Limits::default();
+    vm.limits = limits;
+    let files = match &vfs {
+        Some(dir) => match fs::MemoryFs::snapshot(dir) {
+            Ok(files) => Some(files),
+            Err(e) => {
+                eprintln!("could not read {}: {}", dir.display(), e);
+                return;
+            }
+        },
+        None => vfs_out.as_ref().map(|_| fs::MemoryFs::new()),
+    };
+    if let Some(files) = &files {
+        vm.fs = Box::new(files.clone());
+    }
+    if profile {
+        vm.profile = Some(profile::Profiler::new(functions, *entry, vm.text.len(), folded));
+    }
+    if coverage {
+        let covered: HashMap<String, i32> = functions.iter().filter(|&(_, &at)| (at as usize) < own).map(|(n, &at)| (n.clone(), at)).collect();
+        vm.coverage = Some(coverage::Coverage::new(&vm.text, own, &covered, file_path, lcov));
+    }
+    let start = std::time::Instant::now();
+    // the trace, the checker, the profiler and coverage need the raw text, so -d,
+    // --check, --profile and --coverage always use the classic loop
+    let classic = classic || debug || check || profile || coverage;
+    // and native code doesn't keep pc up to date, which --leaks needs for the
+    // allocation sites, nor count cycles or calls for the limits, so leak
+    // reports and any limit or open policy run on the interpreter as well
+    let jit = jit && !leaks && !sandboxed;
+    if classic {
+        vm.run();
+    } else if jit {
+        vm.run_native();
+    } else {
+        match dispatch::decode(&vm.text) {
+            Ok(decoded) => vm.run_decoded(&decoded),
+            Err((pc, why)) => {
+                vm.pc = pc;
+                vm.fault(why);
+            }
+        }
+    }
+    if let (Some(files), Some(dir)) = (&files, &vfs_out) {
+        for path in files.paths() {
+            let to = dir.join(&path);
+            let saved = to.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|()| std::fs::write(&to, files.get(&path).unwrap_or_default()));
+            if let Err(e) = saved {
+                eprintln!("could not write {}: {}", to.display(), e);
+            }
+        }
+    }
+    if vm.error.is_some() {
+        std::process::exit(-1);
+    }
+    if stats {
+        let secs = start.elapsed().as_secs_f64();
+        if jit && !classic {
+            // native code doesn't count its instructions
+            eprintln!("ran natively in {:.3}s", secs);
+        } else {
+            eprintln!("{} cycles in {:.3}s ({:.1}M cycles/s)", vm.cycle, secs, vm.cycle as f64 / secs / 1e6);
+        }
+    }
+    // like c4, what main returned is the exit code
+    std::process::exit(vm.exit.unwrap_or(0) as i32);
+}
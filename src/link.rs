@@ -0,0 +1,287 @@
+// Separate compilation. Every source file compiles on its own into an Object
+// whose text and data both start at address 0, with the functions and
+// globals it defines and the operands that depend on where it ends up.
+// link() lays the objects out one after another and fixes those operands
+// up: jumps and calls within a file move with its text, the IMMs of its
+// globals and strings move with its data, and the names it only declared
+// (prototypes and externs) are looked up among what every file defines.
+//
+// An Archive is a static library of objects: the linker only takes the
+// members that define a name the program still needs, the members those
+// need, and so on. Archives are saved as one file, see Archive::to_bytes.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::codegen::{bad_operand, operands, Code, Reloc, MNEMONICS};
+use crate::{ADJ, BNZ, BZ, ENT, IMM, JMP, JSR, POOL_SIZE};
+
+pub struct Object {
+    pub name: String, // the source file, for messages
+    pub code: Code,
+    pub data: Vec<u8>,
+    pub globals: HashMap<String, i32>, // the data address of every variable it defines
+}
+
+impl Object {
+    fn defines(&self, name: &str) -> bool {
+        self.code.functions.contains_key(name) || self.globals.contains_key(name)
+    }
+
+    // the names it uses but leaves to the linker
+    fn uses(&self) -> impl Iterator<Item = &str> {
+        self.code.relocs.iter().filter_map(|(_, r)| match r {
+            Reloc::Symbol(name) => Some(name.as_str()),
+            Reloc::Data => None,
+        })
+    }
+
+    // true if the text decodes into known instructions with operands they
+    // can take, frames and adjustments no bigger than memory, its own jumps
+    // and calls land on instructions, and its functions, lines and
+    // relocations point into it, as codegen leaves them; link() and the
+    // backends rely on that for archive members
+    fn valid(&self) -> bool {
+        let text = &self.code.text;
+        let mut starts = HashSet::new();
+        let mut pc = 0;
+        while pc < text.len() {
+            if !(0..MNEMONICS.len() as i32).contains(&text[pc]) || pc + operands(text[pc]) >= text.len() {
+                return false;
+            }
+            let operand = text.get(pc + 1).copied().unwrap_or(0);
+            if bad_operand(text[pc], operand as i64).is_some() || matches!(text[pc], ENT | ADJ) && operand as usize > POOL_SIZE {
+                return false;
+            }
+            starts.insert(pc);
+            pc += 1 + operands(text[pc]);
+        }
+        let start = |a: i32| usize::try_from(a).is_ok_and(|a| starts.contains(&a));
+        let relocs: HashMap<usize, &Reloc> = self.code.relocs.iter().map(|(at, r)| (*at, r)).collect();
+        let jumps_land = starts.iter().all(|&pc| !matches!(text[pc], JMP | JSR | BZ | BNZ) || relocs.contains_key(&(pc + 1)) || start(text[pc + 1]));
+        let relocs_fit = self.code.relocs.iter().all(|(at, r)| {
+            let op = at.checked_sub(1).filter(|pc| starts.contains(pc)).map(|pc| text[pc]);
+            match r {
+                Reloc::Data => op == Some(IMM),
+                Reloc::Symbol(_) => matches!(op, Some(IMM | JSR)),
+            }
+        });
+        jumps_land
+            && relocs_fit
+            && self.code.functions.values().all(|&a| start(a))
+            && self.code.lines.iter().all(|&(at, _)| (0..=text.len() as i32).contains(&at))
+            && self.globals.values().all(|&a| (0..=self.data.len() as i32).contains(&a))
+    }
+}
+
+// true if the objects use a name none of them defines, so only then do
+// they need any library
+pub fn incomplete(objects: &[Object]) -> bool {
+    objects.iter().flat_map(|o| o.uses()).any(|n| !objects.iter().any(|o| o.defines(n)))
+}
+
+#[derive(Default)]
+pub struct Archive {
+    pub members: Vec<Object>,
+}
+
+// where a name ended up, and the file that defined it
+#[derive(Debug, Clone, Copy)]
+enum Def<'a> {
+    Text(i32, &'a str),
+    Data(i32, &'a str),
+}
+
+// links the objects, in order, and the archive members they need into one
+// program and its data segment, or returns every duplicate and undefined name
+pub fn link(objects: &[Object], libs: &[Archive]) -> Result<(Code, Vec<u8>), Vec<String>> {
+    let mut objects: Vec<&Object> = objects.iter().collect();
+    let mut taken = HashSet::new();
+    loop {
+        let missing: HashSet<&str> = objects.iter().flat_map(|o| o.uses()).filter(|n| !objects.iter().any(|o| o.defines(n))).collect();
+        let member = libs.iter().flat_map(|l| &l.members).enumerate().find(|(i, m)| !taken.contains(i) && missing.iter().any(|n| m.defines(n)));
+        let Some((i, member)) = member else { break };
+        taken.insert(i);
+        objects.push(member);
+    }
+    let mut errors = Vec::new();
+
+    // each file's text and data start where the previous one's end, its data
+    // aligned like Parser::reserve does for a single global
+    let mut bases = Vec::new();
+    let (mut text_len, mut data_len) = (0, 0);
+    for o in &objects {
+        data_len = (data_len + 3) & !3;
+        bases.push((text_len as i32, data_len as i32));
+        text_len += o.code.text.len();
+        data_len += o.data.len();
+    }
+
+    let mut symbols: HashMap<&str, Def> = HashMap::new();
+    for (o, &(text, data)) in objects.iter().zip(&bases) {
+        let mut defs: Vec<(&str, Def)> = o.code.functions.iter().map(|(n, &a)| (n.as_str(), Def::Text(text + a, &o.name))).collect();
+        defs.extend(o.globals.iter().map(|(n, &a)| (n.as_str(), Def::Data(data + a, &o.name))));
+        defs.sort_by_key(|&(n, _)| n);
+        for (name, def) in defs {
+            if let Some(Def::Text(_, other) | Def::Data(_, other)) = symbols.insert(name, def) {
+                errors.push(format!("duplicate symbol '{}' in {} and {}", name, other, o.name));
+            }
+        }
+    }
+
+    let mut code = Code { text: Vec::with_capacity(text_len), functions: HashMap::new(), lines: Vec::new(), relocs: Vec::new() };
+    let mut data = Vec::with_capacity(data_len);
+    for (o, &(text, base)) in objects.iter().zip(&bases) {
+        let relocs: HashMap<usize, &Reloc> = o.code.relocs.iter().map(|(at, r)| (*at, r)).collect();
+        let mut pc = code.text.len();
+        code.text.extend(&o.code.text);
+        while pc < code.text.len() {
+            let op = code.text[pc];
+            let at = pc + 1;
+            pc += 1 + operands(op);
+            if at >= code.text.len() {
+                break; // the last instruction has no operand
+            }
+            match relocs.get(&(at - text as usize)) {
+                Some(Reloc::Data) => code.text[at] += base,
+                Some(Reloc::Symbol(name)) => {
+                    let error = match (symbols.get(name.as_str()), op == JSR) {
+                        (Some(Def::Text(a, _)), true) | (Some(Def::Data(a, _)), false) => {
+                            code.text[at] = *a;
+                            None
+                        }
+                        (Some(Def::Data(_, file)), true) => Some(format!("'{}' called in {} is a variable in {}", name, o.name, file)),
+                        (Some(Def::Text(_, file)), false) => Some(format!("'{}' used in {} is a function in {}", name, o.name, file)),
+                        (None, _) => Some(format!("undefined symbol '{}' in {}", name, o.name)),
+                    };
+                    if let Some(e) = error.filter(|e| !errors.contains(e)) {
+                        errors.push(e);
+                    }
+                }
+                None if matches!(op, JMP | JSR | BZ | BNZ) => code.text[at] += text,
+                None => {}
+            }
+        }
+        code.lines.extend(o.code.lines.iter().map(|&(at, line)| (at + text, line)));
+        data.resize(base as usize, 0);
+        data.extend(&o.data);
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    for (name, def) in symbols {
+        if let Def::Text(a, _) = def {
+            code.functions.insert(name.to_string(), a);
+        }
+    }
+    Ok((code, data))
+}
+
+// An archive file: the magic "c4ar" and the member count, then each member's
+// name, text, functions, globals, line table, relocations and data. Numbers
+// are 32-bit little endian, strings and lists are preceded by their length,
+// and a relocation is 0 for Data or 1 and a name for Symbol.
+impl Archive {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = b"c4ar".to_vec();
+        let int = |out: &mut Vec<u8>, n: i32| out.extend(n.to_le_bytes());
+        let string = |out: &mut Vec<u8>, s: &str| {
+            out.extend((s.len() as i32).to_le_bytes());
+            out.extend(s.as_bytes());
+        };
+        // sorted, so the same library always saves the same bytes
+        let sorted = |map: &HashMap<String, i32>| {
+            let mut v: Vec<(String, i32)> = map.iter().map(|(n, &a)| (n.clone(), a)).collect();
+            v.sort();
+            v
+        };
+        int(&mut out, self.members.len() as i32);
+        for o in &self.members {
+            string(&mut out, &o.name);
+            int(&mut out, o.code.text.len() as i32);
+            o.code.text.iter().for_each(|&w| int(&mut out, w));
+            for map in [&o.code.functions, &o.globals] {
+                let entries = sorted(map);
+                int(&mut out, entries.len() as i32);
+                for (name, at) in entries {
+                    string(&mut out, &name);
+                    int(&mut out, at);
+                }
+            }
+            int(&mut out, o.code.lines.len() as i32);
+            for &(at, line) in &o.code.lines {
+                int(&mut out, at);
+                int(&mut out, line);
+            }
+            int(&mut out, o.code.relocs.len() as i32);
+            for (at, reloc) in &o.code.relocs {
+                int(&mut out, *at as i32);
+                match reloc {
+                    Reloc::Data => out.push(0),
+                    Reloc::Symbol(name) => {
+                        out.push(1);
+                        string(&mut out, name);
+                    }
+                }
+            }
+            int(&mut out, o.data.len() as i32);
+            out.extend(&o.data);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Archive, String> {
+        let mut r = Reader { bytes, at: 0 };
+        if r.take(4)? != b"c4ar" {
+            return Err("not a c4 archive".to_string());
+        }
+        let mut members = Vec::new();
+        for _ in 0..r.int()? {
+            let name = r.string()?;
+            let text = (0..r.int()?).map(|_| r.int()).collect::<Result<_, _>>()?;
+            let mut maps = [HashMap::new(), HashMap::new()];
+            for map in &mut maps {
+                for _ in 0..r.int()? {
+                    map.insert(r.string()?, r.int()?);
+                }
+            }
+            let [functions, globals] = maps;
+            let lines = (0..r.int()?).map(|_| Ok((r.int()?, r.int()?))).collect::<Result<_, String>>()?;
+            let mut relocs = Vec::new();
+            for _ in 0..r.int()? {
+                let at = r.int()? as usize;
+                relocs.push((at, if r.take(1)?[0] == 0 { Reloc::Data } else { Reloc::Symbol(r.string()?) }));
+            }
+            let n = r.int()? as usize;
+            let data = r.take(n)?.to_vec();
+            let member = Object { name, code: Code { text, functions, lines, relocs }, data, globals };
+            if !member.valid() {
+                return Err("corrupt archive".to_string());
+            }
+            members.push(member);
+        }
+        Ok(Archive { members })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.at.checked_add(n).filter(|&end| end <= self.bytes.len()).ok_or("truncated archive")?;
+        let taken = &self.bytes[self.at..end];
+        self.at = end;
+        Ok(taken)
+    }
+
+    fn int(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let n = self.int()? as usize;
+        String::from_utf8(self.take(n)?.to_vec()).map_err(|_| "bad name in archive".to_string())
+    }
+}
@@ -0,0 +1,359 @@
+// The LLVM backend: --emit=llvm prints the SSA IR of ir.rs as textual LLVM IR
+// (.ll) that llc can compile, typed pointers and all so LLVM 14 reads it.
+//
+// The program keeps the VM's memory model: one i64 cell per address, in a
+// single @c4.memory array holding the data segment, the heap and the stack.
+// Globals and string literals are aliases into it, and addresses stay cell
+// indices. Every c4 function becomes an internal function taking and
+// returning i64 cells; those with locals in memory move @c4.sp down for a
+// frame laid out like the VM's. System calls go to src/runtime.c built with
+// -DC4_NO_MAIN, and the module's own main sets up argc and argv like the Rust
+// driver does.
+
+use std::collections::HashMap;
+
+use crate::ast::Program;
+use crate::ir::{Callee, Func, Module, Op, Term, Width};
+use crate::{ADD, AND, DIV, EQ, FADD, FDIV, FEQ, FGE, FGT, FLE, FLT, FMUL, FNE, FRND, FSUB, FTI, FTU};
+use crate::{GE, GT, ITF, LE, LT, MOD, MUL, NE, OR, SHL, SHR, SUB, SXT};
+use crate::{UDIV, UGE, UGT, ULE, ULT, UMOD, USHR, UTF, WRIT, XOR, ZXT};
+
+// the system calls by opcode, OPEN to EXIT and then WRIT, as the runtime names them
+const SYSCALLS: [&str; 10] = ["open", "read", "close", "printf", "malloc", "free", "memset", "memcmp", "exit", "write"];
+
+fn syscall(op: i32) -> &'static str {
+    SYSCALLS[if op == WRIT { 9 } else { (op - 30) as usize }]
+}
+
+// a string literal's bytes as an LLVM comment
+fn printable(s: &str) -> String {
+    s.chars().flat_map(|c| c.escape_default()).collect()
+}
+
+struct Emitter<'a> {
+    f: &'a Func,
+    out: String,
+    tmp: usize,
+    globals: &'a HashMap<i32, String>, // data address -> alias of the global there
+    params: usize,
+}
+
+impl Emitter<'_> {
+    fn fresh(&mut self) -> String {
+        self.tmp += 1;
+        format!("%t{}", self.tmp)
+    }
+
+    fn line(&mut self, s: &str) {
+        self.out.push_str("  ");
+        self.out.push_str(s);
+        self.out.push('\n');
+    }
+
+    // a value as an i64 operand: constants and addresses inline, params as arguments
+    fn operand(&self, v: usize) -> String {
+        match self.f.values[v] {
+            Op::Const(c) => c.to_string(),
+            Op::GlobalAddr(off) => off.to_string(),
+            Op::Param(off) => format!("%p{}", self.params as i32 + 2 - off),
+            _ => format!("%v{}", v),
+        }
+    }
+
+    // a pointer to the cell at the address in v
+    fn cell(&mut self, v: usize) -> String {
+        if let Op::GlobalAddr(off) = self.f.values[v] {
+            if let Some(name) = self.globals.get(&off) {
+                return format!("@{}", name);
+            }
+        }
+        let p = self.fresh();
+        let a = self.operand(v);
+        self.line(&format!("{} = getelementptr inbounds [{} x i64], [{} x i64]* @c4.memory, i64 0, i64 {}", p, POOL, POOL, a));
+        p
+    }
+
+    fn as_double(&mut self, v: usize) -> String {
+        let d = self.fresh();
+        let a = self.operand(v);
+        self.line(&format!("{} = bitcast i64 {} to double", d, a));
+        d
+    }
+
+    fn bin(&mut self, dst: &str, op: i32, a: usize, b: usize) {
+        let int = |op| match op {
+            ADD => Some("add"),
+            SUB => Some("sub"),
+            MUL => Some("mul"),
+            DIV => Some("sdiv"),
+            MOD => Some("srem"),
+            UDIV => Some("udiv"),
+            UMOD => Some("urem"),
+            AND => Some("and"),
+            OR => Some("or"),
+            XOR => Some("xor"),
+            _ => None,
+        };
+        let icmp = |op| match op {
+            EQ => Some("eq"),
+            NE => Some("ne"),
+            LT => Some("slt"),
+            GT => Some("sgt"),
+            LE => Some("sle"),
+            GE => Some("sge"),
+            ULT => Some("ult"),
+            UGT => Some("ugt"),
+            ULE => Some("ule"),
+            UGE => Some("uge"),
+            _ => None,
+        };
+        let (x, y) = (self.operand(a), self.operand(b));
+        if let Some(name) = int(op) {
+            self.line(&format!("{} = {} i64 {}, {}", dst, name, x, y));
+        } else if let Some(cond) = icmp(op) {
+            let c = self.fresh();
+            self.line(&format!("{} = icmp {} i64 {}, {}", c, cond, x, y));
+            self.line(&format!("{} = zext i1 {} to i64", dst, c));
+        } else if matches!(op, SHL | SHR | USHR) {
+            // the count is taken mod 64, as on x86, where LLVM would make it poison
+            let n = self.fresh();
+            self.line(&format!("{} = and i64 {}, 63", n, y));
+            let name = match op {
+                SHL => "shl",
+                SHR => "ashr",
+                _ => "lshr",
+            };
+            self.line(&format!("{} = {} i64 {}, {}", dst, name, x, n));
+        } else {
+            let (x, y) = (self.as_double(a), self.as_double(b));
+            let fcmp = match op {
+                FEQ => "oeq",
+                FNE => "une",
+                FLT => "olt",
+                FGT => "ogt",
+                FLE => "ole",
+                FGE => "oge",
+                _ => "",
+            };
+            if fcmp.is_empty() {
+                let name = match op {
+                    FADD => "fadd",
+                    FSUB => "fsub",
+                    FMUL => "fmul",
+                    FDIV => "fdiv",
+                    op => panic!("not a binary operator: {}", op),
+                };
+                let r = self.fresh();
+                self.line(&format!("{} = {} double {}, {}", r, name, x, y));
+                self.line(&format!("{} = bitcast double {} to i64", dst, r));
+            } else {
+                let c = self.fresh();
+                self.line(&format!("{} = fcmp {} double {}, {}", c, fcmp, x, y));
+                self.line(&format!("{} = zext i1 {} to i64", dst, c));
+            }
+        }
+    }
+
+    fn un(&mut self, dst: &str, op: i32, bits: i32, a: usize) {
+        let x = self.operand(a);
+        match op {
+            SXT | ZXT => {
+                let s = self.fresh();
+                self.line(&format!("{} = shl i64 {}, {}", s, x, 64 - bits));
+                let name = if op == SXT { "ashr" } else { "lshr" };
+                self.line(&format!("{} = {} i64 {}, {}", dst, name, s, 64 - bits));
+            }
+            ITF | UTF => {
+                let d = self.fresh();
+                self.line(&format!("{} = {} i64 {} to double", d, if op == ITF { "sitofp" } else { "uitofp" }, x));
+                self.line(&format!("{} = bitcast double {} to i64", dst, d));
+            }
+            FTI | FTU => {
+                // the saturating conversions are what Rust's `as` does in the VM
+                let d = self.as_double(a);
+                let name = if op == FTI { "fptosi" } else { "fptoui" };
+                self.line(&format!("{} = call i64 @llvm.{}.sat.i64.f64(double {})", dst, name, d));
+            }
+            FRND => {
+                let d = self.as_double(a);
+                let (f, e) = (self.fresh(), self.fresh());
+                self.line(&format!("{} = fptrunc double {} to float", f, d));
+                self.line(&format!("{} = fpext float {} to double", e, f));
+                self.line(&format!("{} = bitcast double {} to i64", dst, e));
+            }
+            op => panic!("not a unary operator: {}", op),
+        }
+    }
+
+    fn inst(&mut self, v: usize) {
+        let dst = format!("%v{}", v);
+        match &self.f.values[v] {
+            Op::Load(w, a) => {
+                let p = self.cell(*a);
+                if *w == Width::Char {
+                    let t = self.fresh();
+                    self.line(&format!("{} = load i64, i64* {}", t, p));
+                    self.line(&format!("{} = and i64 {}, 255", dst, t));
+                } else {
+                    self.line(&format!("{} = load i64, i64* {}", dst, p));
+                }
+            }
+            Op::Store(w, a, x) => {
+                let p = self.cell(*a);
+                let mut val = self.operand(*x);
+                if *w == Width::Char {
+                    let t = self.fresh();
+                    self.line(&format!("{} = and i64 {}, 255", t, val));
+                    val = t;
+                }
+                self.line(&format!("store i64 {}, i64* {}", val, p));
+            }
+            &Op::Bin(op, a, b) => self.bin(&dst, op, a, b),
+            &Op::Un(op, bits, a) => self.un(&dst, op, bits, a),
+            Op::Call(Callee::Fun(name), args) => {
+                let args: Vec<String> = args.iter().map(|&a| format!("i64 {}", self.operand(a))).collect();
+                self.line(&format!("{} = call i64 @c4.{}({})", dst, name, args.join(", ")));
+            }
+            Op::Call(Callee::Sys(op), args) => {
+                // the runtime reads the arguments off the stack, pushed like the VM does
+                let sp = self.fresh();
+                self.line(&format!("{} = load i64, i64* @c4.sp", sp));
+                for (i, &a) in args.iter().enumerate() {
+                    let (at, p) = (self.fresh(), self.fresh());
+                    self.line(&format!("{} = sub i64 {}, {}", at, sp, i + 1));
+                    self.line(&format!("{} = getelementptr inbounds [{} x i64], [{} x i64]* @c4.memory, i64 0, i64 {}", p, POOL, POOL, at));
+                    let x = self.operand(a);
+                    self.line(&format!("store i64 {}, i64* {}", x, p));
+                }
+                let top = self.fresh();
+                self.line(&format!("{} = sub i64 {}, {}", top, sp, args.len()));
+                // the last argument pushed is still in ax, which exit prints
+                let ax = args.last().map_or("0".to_string(), |&a| self.operand(a));
+                let name = syscall(*op);
+                self.line(&format!("{} = call i64 @c4_{}(i64 {}, i64 {}, i64 {})", dst, name, top, args.len(), ax));
+            }
+            Op::Phi(ins) => {
+                let ins: Vec<String> = ins.iter().map(|&(b, x)| format!("[ {}, %b{} ]", self.operand(x), b)).collect();
+                self.line(&format!("{} = phi i64 {}", dst, ins.join(", ")));
+            }
+            op => panic!("{:?} inside a block", op),
+        }
+    }
+
+    fn function(&mut self) {
+        let f = self.f;
+        let params: Vec<String> = (1..=self.params).map(|i| format!("i64 %p{}", i)).collect();
+        self.out.push_str(&format!("\ndefine internal i64 @c4.{}({}) {{\nentry:\n", f.name, params.join(", ")));
+
+        // a frame like the VM's when anything lives in memory: arguments,
+        // return address and saved bp above bp, locals below it
+        let n = self.params as i32;
+        let lowest = f.values.iter().filter_map(|op| if let Op::LocalAddr(o) = op { Some(*o) } else { None }).min();
+        if let Some(lowest) = lowest {
+            self.line("%sp = load i64, i64* @c4.sp");
+            self.line(&format!("%bp = sub i64 %sp, {}", n + 2));
+            for &off in f.reserved.iter().filter(|&&o| o > 0) {
+                let slot = self.fresh();
+                self.line(&format!("{} = add i64 %bp, {}", slot, off));
+                let p = self.fresh();
+                self.line(&format!("{} = getelementptr inbounds [{} x i64], [{} x i64]* @c4.memory, i64 0, i64 {}", p, POOL, POOL, slot));
+                self.line(&format!("store i64 %p{}, i64* {}", n + 2 - off, p));
+            }
+            self.line(&format!("%frame = add i64 %bp, {}", lowest.min(0)));
+            self.line("store i64 %frame, i64* @c4.sp");
+            for (v, op) in f.values.iter().enumerate() {
+                if let Op::LocalAddr(off) = op {
+                    self.line(&format!("%v{} = add i64 %bp, {}", v, off));
+                }
+            }
+        }
+        self.line("br label %b0");
+
+        for (b, block) in f.blocks.iter().enumerate() {
+            self.out.push_str(&format!("b{}:\n", b));
+            for &v in &block.insts {
+                self.inst(v);
+            }
+            match &block.term {
+                Some(Term::Jmp(t)) => self.line(&format!("br label %b{}", t)),
+                Some(Term::Br(c, t, e)) => {
+                    let x = self.operand(*c);
+                    let cond = self.fresh();
+                    self.line(&format!("{} = icmp ne i64 {}, 0", cond, x));
+                    self.line(&format!("br i1 {}, label %b{}, label %b{}", cond, t, e));
+                }
+                Some(Term::Ret(v)) => {
+                    let x = v.map_or("0".to_string(), |v| self.operand(v));
+                    if lowest.is_some() {
+                        self.line("store i64 %sp, i64* @c4.sp");
+                    }
+                    self.line(&format!("ret i64 {}", x));
+                }
+                None => self.line("unreachable"),
+            }
+        }
+        self.out.push_str("}\n");
+    }
+}
+
+// cells of memory, POOL_SIZE in lib.rs
+const POOL: usize = 2 * 1024 * 1024;
+
+// the whole module: memory and the data segment, aliases for the globals
+// and the string pool, the functions and a C main calling c4's
+pub fn emit(program: &Program, module: &Module, globals: &[(String, i32)], strings: &[(i32, String)], data: &[u8], name: &str) -> String {
+    let mut out = format!("; ModuleID = '{}'\nsource_filename = \"{}\"\n\n", name, name);
+
+    // memory is zero, main copies the data segment in before running c4's
+    let cells: Vec<String> = data.iter().map(|b| format!("i64 {}", b)).collect();
+    let init = if data.is_empty() { "zeroinitializer".to_string() } else { format!("[{}]", cells.join(", ")) };
+    out.push_str(&format!("@c4.data = private unnamed_addr constant [{} x i64] {}, align 8\n", data.len(), init));
+    out.push_str(&format!("@c4.memory = global [{} x i64] zeroinitializer, align 8\n", POOL));
+    out.push_str("@c4.sp = internal global i64 0\n\n");
+    let at = |off: i32| format!("getelementptr inbounds ([{0} x i64], [{0} x i64]* @c4.memory, i64 0, i64 {1})", POOL, off);
+    for (i, (off, s)) in strings.iter().enumerate() {
+        out.push_str(&format!("@.str.{} = private alias i64, i64* {} ; \"{}\"\n", i, at(*off), printable(s)));
+    }
+    for (name, off) in globals {
+        out.push_str(&format!("@{} = alias i64, i64* {}\n", name, at(*off)));
+    }
+
+    out.push('\n');
+    for name in SYSCALLS {
+        out.push_str(&format!("declare i64 @c4_{}(i64, i64, i64)\n", name));
+    }
+    out.push_str("declare void @c4_init(i64*, i64)\ndeclare i64 @c4_args(i32, i8**, i64*)\n");
+    out.push_str("declare void @llvm.memcpy.p0i8.p0i8.i64(i8*, i8*, i64, i1)\n");
+    out.push_str("declare i64 @llvm.fptosi.sat.i64.f64(double)\ndeclare i64 @llvm.fptoui.sat.i64.f64(double)\n");
+
+    let aliases: HashMap<i32, String> = globals.iter().map(|(n, off)| (*off, n.clone())).collect();
+    for (f, ast) in module.funcs.iter().zip(&program.functions) {
+        let mut e = Emitter { f, out: String::new(), tmp: 0, globals: &aliases, params: ast.params.len() };
+        e.function();
+        out.push_str(&e.out);
+    }
+
+    // main(argc, argv) with main's frame where the VM puts it, below the
+    // arguments the runtime copies in, then exit with what c4's main returns
+    let main_params = program.functions.iter().find(|f| f.name == "main").map_or(0, |f| f.params.len());
+    let args = ["i64 %argc", "i64 %argv"][..main_params.min(2)].join(", ");
+    out.push_str(&format!(
+        "\ndefine i32 @main(i32 %0, i8** %1) {{\n\
+         entry:\n  %argc = sext i32 %0 to i64\n\
+         \x20 %mem = getelementptr inbounds [{0} x i64], [{0} x i64]* @c4.memory, i64 0, i64 0\n\
+         \x20 %to = bitcast i64* %mem to i8*\n\
+         \x20 %from = bitcast [{1} x i64]* @c4.data to i8*\n\
+         \x20 call void @llvm.memcpy.p0i8.p0i8.i64(i8* %to, i8* %from, i64 {2}, i1 false)\n\
+         \x20 call void @c4_init(i64* %mem, i64 {1})\n\
+         \x20 store i64 {0}, i64* @c4.sp\n\
+         \x20 %argv = call i64 @c4_args(i32 %0, i8** %1, i64* @c4.sp)\n\
+         \x20 %ret = call i64 @c4.main({3})\n\
+         \x20 %exit = call i64 @c4_exit(i64 {0}, i64 0, i64 %ret)\n\
+         \x20 ret i32 0\n}}\n",
+        POOL,
+        data.len(),
+        data.len() * 8,
+        args
+    ));
+    out
+}
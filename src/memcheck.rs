@@ -0,0 +1,217 @@
+// The --check mode. The VM's memory is one array of cells, so a program can
+// read and write anywhere in it without the VM noticing; MemCheck keeps a
+// shadow of what each part of that array is for and stops the program the
+// first time it touches a cell it has no business touching:
+//
+//   data segment   [0, data_end)   globals and string literals
+//   heap           [data_end, low) blocks handed out by malloc, and free memory
+//   dead stack     [low, vm.sp)    frames that have returned
+//   stack          [vm.sp, len)    live frames, pushed cells and locals
+//
+// where low is the lowest the stack pointer has been. Anything outside the
+// data segment, a live block or a live frame is out of bounds, and so is a
+// block that was freed. A live frame is a function's locals and arguments:
+// its saved bp and return address, and what is pushed between frames, are
+// the VM's alone. On the stack, a local that nothing has been stored into
+// since ENT made room for it is uninitialized.
+//
+// Arguments sit right below the caller's cells, so a pointer taken to one
+// can reach the caller's locals without leaving a live frame. MemCheck
+// follows such pointers through pushes, stores, loads and pointer arithmetic,
+// and bounds them by the arguments of the frame they were taken in.
+
+use std::collections::BTreeMap;
+
+use crate::vm::VM;
+use crate::ADJ;
+
+struct Block {
+    size: usize,
+    freed: bool,
+}
+
+// the cells of a live frame: locals in [bp - locals, bp), arguments in
+// [bp + 2, bp + 2 + args)
+struct Frame {
+    bp: usize,
+    locals: usize,
+    args: usize,
+}
+
+pub struct MemCheck {
+    data_end: usize,
+    low: usize,                     // the lowest stack pointer so far
+    blocks: BTreeMap<usize, Block>, // malloc'd blocks by address; c4 never reuses memory
+    init: Vec<bool>,                // stack cells stored into since their frame was entered
+    frames: Vec<Frame>,             // innermost last
+    main_args: usize,               // the parameters main declares
+    args: usize,                    // where the argv array and strings above main's arguments start
+    from: Vec<usize>,               // the frame (its bp) whose arguments each cell points into, 0 for none
+    ax_from: usize,                 // the same for ax
+}
+
+impl MemCheck {
+    // starts tracking a VM that is about to run: the data segment is as long
+    // as the parser left it and everything already on the stack (argc, argv
+    // and main's return address) is initialized
+    pub fn new(vm: &VM, data_end: usize, main_args: usize) -> Self {
+        let mut init = vec![false; vm.stack.len()];
+        init[vm.sp..].fill(true);
+        let from = vec![0; vm.stack.len()];
+        MemCheck { data_end, low: vm.sp, blocks: BTreeMap::new(), init, frames: Vec::new(), main_args, args: vm.sp + 3, from, ax_from: 0 }
+    }
+
+    // what is wrong with touching the stack cell at addr, which is live
+    fn stack(&self, addr: usize, write: bool) -> Option<String> {
+        let what = if write { "write to" } else { "read from" };
+        if addr >= self.args {
+            return None;
+        }
+        match self.frames.iter().rev().find(|f| addr + f.locals >= f.bp && addr < f.bp + 2 + f.args) {
+            Some(f) if addr == f.bp => Some(format!("{} {}, the saved frame pointer of the stack frame at {}", what, addr, f.bp)),
+            Some(f) if addr == f.bp + 1 => Some(format!("{} {}, the return address of the stack frame at {}", what, addr, f.bp)),
+            Some(_) if !write && !self.init[addr] => Some(format!("read from {}, an uninitialized stack slot", addr)),
+            Some(_) => None,
+            None => Some(format!("{} {}, outside the locals and arguments of every stack frame", what, addr)),
+        }
+    }
+
+    // what is wrong with reading or writing the cell at addr, which is inside
+    // memory, while the stack starts at sp
+    fn access(&self, addr: usize, write: bool, sp: usize) -> Option<String> {
+        let what = if write { "write to" } else { "read from" };
+        if addr < self.data_end {
+            None
+        } else if addr < self.low {
+            // blame the block the access is in or closest to, or the data
+            // segment if there is none
+            let before = self.blocks.range(..=addr).next_back();
+            let after = self.blocks.range(addr + 1..).next();
+            match (before, after) {
+                (Some((&start, b)), _) if addr < start + b.size && b.freed => {
+                    Some(format!("{} {}, in the {} byte block at {} after it was freed", what, addr, b.size, start))
+                }
+                (Some((&start, b)), _) if addr < start + b.size => None,
+                (Some((&start, b)), next) if next.is_none_or(|(&next, _)| addr - start - b.size <= next - addr) => {
+                    Some(format!("{} {}, {} bytes after the {} byte block at {}", what, addr, addr - start - b.size, b.size, start))
+                }
+                (_, Some((&start, b))) => {
+                    Some(format!("{} {}, {} bytes before the {} byte block at {}", what, addr, start - addr, b.size, start))
+                }
+                _ => Some(format!("{} {}, {} bytes after the data segment", what, addr, addr - self.data_end)),
+            }
+        } else if addr < sp {
+            Some(format!("{} {}, in a stack frame that has returned", what, addr))
+        } else {
+            self.stack(addr, write)
+        }
+    }
+
+    // what is wrong with touching addr through a pointer into the arguments
+    // of the frame at from, if that frame is still live
+    fn derived(&self, addr: usize, from: usize, write: bool) -> Option<String> {
+        let what = if write { "write to" } else { "read from" };
+        let f = self.frames.iter().rev().find(|f| f.bp == from)?;
+        if addr >= f.bp + 2 && addr < f.bp + 2 + f.args {
+            return None;
+        }
+        Some(format!("{} {}, outside the arguments of the stack frame at {} the pointer was taken in", what, addr, f.bp))
+    }
+
+    // checks the instruction op is about to execute against the registers
+    // and memory as they are, and records the stack cells it initializes
+    pub fn step(&mut self, op: i64, operand: i64, vm: &VM) -> Option<String> {
+        // where ax points once op has run, for the instructions that leave it be
+        // or make a pointer out of it
+        let ax_from = match op {
+            0 if operand >= 2 => vm.bp, // LEA of an argument
+            2..=8 | 11..=13 | 64 | 65 => self.ax_from,
+            25 if self.from[vm.sp] != 0 => self.from[vm.sp], // ADD
+            25 => self.ax_from,
+            26 if self.ax_from == 0 => self.from[vm.sp], // SUB of an offset, not of two pointers
+            _ => 0,
+        };
+        let ax = std::mem::replace(&mut self.ax_from, ax_from);
+        match op {
+            3 | 13 => { // JSR, PSH
+                self.init[vm.sp - 1] = true;
+                self.from[vm.sp - 1] = if op == 13 { ax } else { 0 };
+                self.low = self.low.min(vm.sp - 1);
+            }
+            6 => { // ENT: bp is pushed, the locals below it are not set yet
+                self.init[vm.sp - 1] = true;
+                let n = operand.max(0) as usize;
+                self.init[vm.sp - 1 - n..vm.sp - 1].fill(false);
+                self.from[vm.sp - 1 - n..vm.sp].fill(0);
+                self.low = self.low.min(vm.sp - 1 - n);
+                // the caller's ADJ says how many arguments it pushed; main's come from the driver
+                let ret = vm.stack[vm.sp] as usize;
+                let args = match vm.text.get(ret) {
+                    _ if self.frames.is_empty() => self.main_args,
+                    Some(&op) if op == ADJ as i64 => vm.text.get(ret + 1).map_or(0, |&n| n.max(0) as usize),
+                    _ => 0,
+                };
+                self.frames.push(Frame { bp: vm.sp - 1, locals: n, args });
+            }
+            8 => { // LEV
+                while self.frames.last().is_some_and(|f| f.bp <= vm.bp) {
+                    self.frames.pop();
+                }
+            }
+            9 | 10 => { // LI, LC
+                let addr = vm.ax as usize;
+                if let Some(why) = self.access(addr, false, vm.sp).or_else(|| self.derived(addr, ax, false)) {
+                    return Some(why);
+                }
+                if op == 9 {
+                    self.ax_from = self.from[addr];
+                }
+            }
+            11 | 12 => { // SI, SC
+                let addr = vm.stack[vm.sp] as usize;
+                if let Some(why) = self.access(addr, true, vm.sp + 1).or_else(|| self.derived(addr, self.from[vm.sp], true)) {
+                    return Some(why);
+                }
+                self.init[addr] = true;
+                self.from[addr] = if op == 11 { ax } else { 0 };
+            }
+            64 | 65 => self.from[vm.sp] = 0, // ITFS, UTFS
+            _ => {}
+        }
+        None
+    }
+
+    // checks a system call's access to the n cells at from, which are inside
+    // memory, marking them initialized if it writes them
+    pub fn range(&mut self, from: usize, n: usize, write: bool, vm: &VM) -> Option<String> {
+        for addr in from..from + n {
+            if let Some(why) = self.access(addr, write, vm.sp) {
+                return Some(why);
+            }
+            if write {
+                self.init[addr] = true;
+                self.from[addr] = 0;
+            }
+        }
+        None
+    }
+
+    pub fn malloc(&mut self, addr: usize, size: usize) {
+        self.blocks.insert(addr, Block { size, freed: false });
+    }
+
+    // what is wrong with freeing p, if anything, marking its block freed otherwise
+    pub fn free(&mut self, p: i64) -> Option<String> {
+        if p == 0 {
+            return None;
+        }
+        match self.blocks.get_mut(&(p as usize)) {
+            Some(b) if b.freed => Some(format!("double free of the {} byte block at {}", b.size, p)),
+            Some(b) => {
+                b.freed = true;
+                None
+            }
+            None => Some(format!("free of {}, which malloc did not return", p)),
+        }
+    }
+}
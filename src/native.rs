@@ -0,0 +1,679 @@
+// The native backend: translates the text segment to x86-64 machine code and
+// runs it in place of the interpreter (--jit).
+//
+// The generated code works on the VM's own memory, so the memory model is the
+// interpreter's: addresses are cell indices, sp and bp are cell indices and the
+// stack holds text addresses as return addresses. Registers:
+//
+//   rax  ax                      r12  sp (cell index)
+//   r13  bp (cell index)         r15  base of the cells
+//   r14  text address -> native address table, for LEV
+//   rbx  the Context, for system calls and bounds
+//
+// System calls and the few conversions x86 doesn't do the way Rust does go
+// through `extern "C"` functions, so PRTF, MALC and friends behave exactly as
+// they do in the interpreter. Every load, store, push, pop, frame, ADJ and
+// return is checked the way the interpreter checks it, against both the heap
+// and the top of memory, and so is every divisor; a check that fails jumps
+// to .Lfault, which reports it through VM::fault and leaves the code like
+// EXIT does.
+
+use crate::codegen::operands;
+use crate::vm::VM;
+
+// one emitted instruction, as machine code and as AT&T assembly
+pub struct Line {
+    pub asm: String,
+    pub bytes: Vec<u8>,
+}
+
+pub struct Native {
+    pub lines: Vec<Line>,
+    pub starts: Vec<Option<usize>>,      // text address -> index of its first line
+    jumps: Vec<(usize, usize, usize)>,   // line with a rel32 at its end, the text address it targets and its own
+    pub exits: Vec<usize>,               // lines that jump to the epilogue
+    faults: Vec<usize>,                  // lines that jump to .Lfault
+    traps: Vec<usize>,                   // lines that jump to the trap
+    pc: usize,                           // the text address being translated
+}
+
+// what rbx points at while the code runs
+#[repr(C)]
+struct Context {
+    vm: *mut VM,
+    cells: u64, // 8(%rbx): the size of memory, loads, stores and pops stay below
+    text: u64,  // 16(%rbx): the size of the text, returns land below
+    heap: u64,  // 24(%rbx): where the heap ends, pushes stay above
+}
+
+// what went wrong, for c4_fault; src/runtime.c has the same numbers
+const READ: i32 = 0;
+const WRITE: i32 = 1;
+const DIVIDE: i32 = 2;
+pub const JUMP: i32 = 3;
+const OVERFLOW: i32 = 4;
+const UNDERFLOW: i32 = 5;
+const OPCODE: i32 = 6;
+pub const RETURN: i32 = 7;
+
+// ax, and whether the call stopped the program
+#[repr(C)]
+struct Returned {
+    ax: i64,
+    stop: i64,
+}
+
+extern "C" fn c4_syscall(ctx: *mut Context, op: i64, argc: i64, sp: i64, ax: i64) -> Returned {
+    // SAFETY: the context run_native made, whose VM outlives the code
+    let ctx = unsafe { &mut *ctx };
+    let vm = unsafe { &mut *ctx.vm };
+    vm.sp = sp as usize;
+    vm.ax = ax;
+    vm.syscall(op, argc as usize);
+    ctx.heap = vm.heap as u64;
+    Returned { ax: vm.ax, stop: (vm.error.is_some() || vm.exit.is_some()) as i64 }
+}
+
+// reports a failed check with the interpreter's words; the code leaves
+// right after
+extern "C" fn c4_fault(ctx: *mut Context, pc: i64, kind: i64, value: i64) {
+    // SAFETY: as for c4_syscall
+    let ctx = unsafe { &mut *ctx };
+    let vm = unsafe { &mut *ctx.vm };
+    vm.pc = pc as usize;
+    vm.fault(match kind as i32 {
+        READ => format!("read from {}, outside memory", value),
+        WRITE => format!("write to {}, outside memory", value),
+        DIVIDE => "division by zero".to_string(),
+        JUMP => format!("jump to {}, which is not the start of an instruction", value),
+        RETURN => format!("return to {}, which is not the start of an instruction", value),
+        OVERFLOW => "stack overflow".to_string(),
+        UNDERFLOW => "stack underflow".to_string(),
+        _ => format!("unknown instruction {}", value),
+    });
+}
+
+extern "C" fn c4_utf(v: i64) -> i64 {
+    (v as u64 as f64).to_bits() as i64
+}
+
+extern "C" fn c4_fti(v: i64) -> i64 {
+    f64::from_bits(v as u64) as i64
+}
+
+extern "C" fn c4_ftu(v: i64) -> i64 {
+    f64::from_bits(v as u64) as u64 as i64
+}
+
+fn helper_address(name: &str) -> u64 {
+    match name {
+        "c4_syscall" => c4_syscall as *const () as u64,
+        "c4_fault" => c4_fault as *const () as u64,
+        "c4_utf" => c4_utf as *const () as u64,
+        "c4_fti" => c4_fti as *const () as u64,
+        _ => c4_ftu as *const () as u64,
+    }
+}
+
+impl Native {
+    fn i(&mut self, asm: &str, bytes: &[u8]) {
+        self.lines.push(Line { asm: asm.to_string(), bytes: bytes.to_vec() });
+    }
+
+    fn jump(&mut self, asm: &str, opcode: &[u8], target: i64) {
+        let mut bytes = opcode.to_vec();
+        bytes.extend([0; 4]);
+        self.i(&format!("{} .L{}", asm, target), &bytes);
+        self.jumps.push((self.lines.len() - 1, target as usize, self.pc));
+    }
+
+    fn imm(&mut self, v: i64) {
+        match i32::try_from(v) {
+            Ok(v32) => self.i(&format!("movq ${}, %rax", v), &[&[0x48, 0xC7, 0xC0][..], &v32.to_le_bytes()].concat()),
+            Err(_) => self.i(&format!("movabsq ${}, %rax", v), &[&[0x48, 0xB8][..], &v.to_le_bytes()].concat()),
+        }
+    }
+
+    // goes on to the next instruction if the flags pass the jcc, and to
+    // .Lfault with the pc, the kind of fault and rcx (or ax) otherwise
+    fn unless(&mut self, jcc: &str, code: u8, kind: i32, value_in_rax: bool) {
+        let skip = if value_in_rax { 18 } else { 15 };
+        self.i(&format!("{} 1f", jcc), &[code, skip]);
+        self.fault(kind, value_in_rax);
+        self.i("1:", &[]);
+    }
+
+    fn fault(&mut self, kind: i32, value_in_rax: bool) {
+        let pc = self.pc as i32;
+        self.i(&format!("movl ${}, %esi", pc), &[&[0xBE][..], &pc.to_le_bytes()].concat());
+        self.i(&format!("movl ${}, %edx", kind), &[&[0xBA][..], &kind.to_le_bytes()].concat());
+        if value_in_rax {
+            self.i("movq %rax, %rcx", &[0x48, 0x89, 0xC1]);
+        }
+        self.i("jmp .Lfault", &[0xE9, 0, 0, 0, 0]);
+        self.faults.push(self.lines.len() - 1);
+    }
+
+    // faults unless sp points into memory, before reading (%r15,%r12,8)
+    fn check_pop(&mut self) {
+        self.i("cmpq 8(%rbx), %r12", &[0x4C, 0x3B, 0x63, 0x08]);
+        self.unless("jb", 0x72, UNDERFLOW, false);
+    }
+
+    // pops the left operand of a binary operator into rcx
+    fn pop_rcx(&mut self) {
+        self.check_pop();
+        self.i("movq (%r15,%r12,8), %rcx", &[0x4B, 0x8B, 0x0C, 0xE7]);
+        self.i("incq %r12", &[0x49, 0xFF, 0xC4]);
+    }
+
+    // faults if sp is past the top of memory
+    fn check_top(&mut self) {
+        self.i("cmpq 8(%rbx), %r12", &[0x4C, 0x3B, 0x63, 0x08]);
+        self.unless("jbe", 0x76, UNDERFLOW, false);
+    }
+
+    // faults if a push would go into the heap or past the top of memory
+    fn check_push(&mut self) {
+        self.check_top();
+        self.i("cmpq 24(%rbx), %r12", &[0x4C, 0x3B, 0x63, 0x18]);
+        self.unless("jg", 0x7F, OVERFLOW, false);
+    }
+
+    fn push_rax(&mut self) {
+        self.check_push();
+        self.i("decq %r12", &[0x49, 0xFF, 0xCC]);
+        self.i("movq %rax, (%r15,%r12,8)", &[0x4B, 0x89, 0x04, 0xE7]);
+    }
+
+    // loads the cell ax points at
+    fn load(&mut self) {
+        self.i("cmpq 8(%rbx), %rax", &[0x48, 0x3B, 0x43, 0x08]);
+        self.unless("jb", 0x72, READ, true);
+        self.i("movq (%r15,%rax,8), %rax", &[0x49, 0x8B, 0x04, 0xC7]);
+    }
+
+    // pops the address a store goes to into rcx
+    fn pop_address(&mut self) {
+        self.pop_rcx();
+        self.i("cmpq 8(%rbx), %rcx", &[0x48, 0x3B, 0x4B, 0x08]);
+        self.unless("jb", 0x72, WRITE, false);
+    }
+
+    // an absolute call through r11 in the machine code, a plain call in the
+    // assembly, where the linker resolves it
+    fn call(&mut self, helper: &str) {
+        let addr = helper_address(helper);
+        self.i(&format!("call {}", helper), &[&[0x49, 0xBB][..], &addr.to_le_bytes(), &[0x41, 0xFF, 0xD3]].concat());
+    }
+
+    fn setcc(&mut self, cc: &str, code: u8) {
+        self.pop_rcx();
+        self.i("cmpq %rax, %rcx", &[0x48, 0x39, 0xC1]);
+        self.i(&format!("set{} %al", cc), &[0x0F, code, 0xC0]);
+        self.i("movzbl %al, %eax", &[0x0F, 0xB6, 0xC0]);
+    }
+
+    // left operand in xmm0, right in xmm1
+    fn float_operands(&mut self) {
+        self.pop_rcx();
+        self.i("movq %rcx, %xmm0", &[0x66, 0x48, 0x0F, 0x6E, 0xC1]);
+        self.i("movq %rax, %xmm1", &[0x66, 0x48, 0x0F, 0x6E, 0xC8]);
+    }
+
+    fn float_arith(&mut self, name: &str, code: u8) {
+        self.float_operands();
+        self.i(&format!("{} %xmm1, %xmm0", name), &[0xF2, 0x0F, code, 0xC1]);
+        self.i("movq %xmm0, %rax", &[0x66, 0x48, 0x0F, 0x7E, 0xC0]);
+    }
+
+    // a float comparison that is false when either side is NaN
+    fn float_compare(&mut self, swap: bool, cc: &str, code: u8) {
+        self.float_operands();
+        if swap {
+            self.i("ucomisd %xmm0, %xmm1", &[0x66, 0x0F, 0x2E, 0xC8]);
+        } else {
+            self.i("ucomisd %xmm1, %xmm0", &[0x66, 0x0F, 0x2E, 0xC1]);
+        }
+        self.i(&format!("set{} %al", cc), &[0x0F, code, 0xC0]);
+        self.i("movzbl %al, %eax", &[0x0F, 0xB6, 0xC0]);
+    }
+
+    fn shift(&mut self, name: &str, ext: u8, bits: u8) {
+        self.i(&format!("{} ${}, %rax", name, bits), &[0x48, 0xC1, 0xC0 | ext << 3, bits]);
+    }
+
+    fn translate(&mut self, text: &[i64], pc: usize) {
+        self.pc = pc;
+        let op = text[pc];
+        let arg = |k: usize| text.get(pc + k).copied().unwrap_or(0);
+        match op {
+            0 => {
+                // LEA
+                self.i("movq %r13, %rax", &[0x4C, 0x89, 0xE8]);
+                self.i(&format!("addq ${}, %rax", arg(1)), &[&[0x48, 0x05][..], &(arg(1) as i32).to_le_bytes()].concat());
+            }
+            1 => self.imm(arg(1)),
+            2 => self.jump("jmp", &[0xE9], arg(1)),
+            3 => {
+                // JSR: the return address on the stack is a text address, like the interpreter's
+                self.check_push();
+                self.i("decq %r12", &[0x49, 0xFF, 0xCC]);
+                let ret = pc as i32 + 2;
+                self.i(&format!("movq ${}, %rcx", ret), &[&[0x48, 0xC7, 0xC1][..], &ret.to_le_bytes()].concat());
+                self.i("movq %rcx, (%r15,%r12,8)", &[0x4B, 0x89, 0x0C, 0xE7]);
+                self.jump("jmp", &[0xE9], arg(1));
+            }
+            4 | 5 => {
+                self.i("testq %rax, %rax", &[0x48, 0x85, 0xC0]);
+                if op == 4 {
+                    self.jump("jz", &[0x0F, 0x84], arg(1));
+                } else {
+                    self.jump("jnz", &[0x0F, 0x85], arg(1));
+                }
+            }
+            6 => {
+                // ENT, if the frame stays above the heap
+                self.check_top();
+                let low = -(arg(1) as i32) - 1;
+                self.i(&format!("leaq {}(%r12), %rcx", low), &[&[0x49, 0x8D, 0x8C, 0x24][..], &low.to_le_bytes()].concat());
+                self.i("cmpq 24(%rbx), %rcx", &[0x48, 0x3B, 0x4B, 0x18]);
+                self.unless("jg", 0x7F, OVERFLOW, false);
+                self.i("decq %r12", &[0x49, 0xFF, 0xCC]);
+                self.i("movq %r13, (%r15,%r12,8)", &[0x4F, 0x89, 0x2C, 0xE7]);
+                self.i("movq %r12, %r13", &[0x4D, 0x89, 0xE5]);
+                self.i(&format!("subq ${}, %r12", arg(1)), &[&[0x49, 0x81, 0xEC][..], &(arg(1) as i32).to_le_bytes()].concat());
+            }
+            7 => {
+                // ADJ, if sp stays inside memory
+                let n = arg(1) as i32;
+                self.i(&format!("leaq {}(%r12), %rcx", n), &[&[0x49, 0x8D, 0x8C, 0x24][..], &n.to_le_bytes()].concat());
+                self.i("cmpq 8(%rbx), %rcx", &[0x48, 0x3B, 0x4B, 0x08]);
+                self.unless("jbe", 0x76, UNDERFLOW, false);
+                self.i("movq %rcx, %r12", &[0x49, 0x89, 0xCC]);
+            }
+            8 => {
+                // LEV: returns through the table from text addresses to code,
+                // once the saved bp and return address are known to be in memory;
+                // the trap takes returns outside the text, and the table sends
+                // the ones inside an instruction there too
+                self.i("leaq 1(%r13), %rcx", &[0x49, 0x8D, 0x4D, 0x01]);
+                self.i("cmpq 8(%rbx), %rcx", &[0x48, 0x3B, 0x4B, 0x08]);
+                self.unless("jb", 0x72, UNDERFLOW, false);
+                self.i("movq %r13, %r12", &[0x4D, 0x89, 0xEC]);
+                self.i("movq (%r15,%r12,8), %r13", &[0x4F, 0x8B, 0x2C, 0xE7]);
+                self.i("movq 8(%r15,%r12,8), %rcx", &[0x4B, 0x8B, 0x4C, 0xE7, 0x08]);
+                self.i("addq $2, %r12", &[0x49, 0x83, 0xC4, 0x02]);
+                self.i(&format!("movl ${}, %esi", pc), &[&[0xBE][..], &(pc as i32).to_le_bytes()].concat());
+                self.i("cmpq 16(%rbx), %rcx", &[0x48, 0x3B, 0x4B, 0x10]);
+                self.i("jae .Ltrap", &[0x0F, 0x83, 0, 0, 0, 0]);
+                self.traps.push(self.lines.len() - 1);
+                self.i("jmp *(%r14,%rcx,8)", &[0x41, 0xFF, 0x24, 0xCE]);
+            }
+            9 => self.load(),
+            10 => {
+                self.load();
+                self.i("movzbl %al, %eax", &[0x0F, 0xB6, 0xC0]);
+            }
+            11 => {
+                self.pop_address();
+                self.i("movq %rax, (%r15,%rcx,8)", &[0x49, 0x89, 0x04, 0xCF]);
+            }
+            12 => {
+                self.pop_address();
+                self.i("movzbl %al, %edx", &[0x0F, 0xB6, 0xD0]);
+                self.i("movq %rdx, (%r15,%rcx,8)", &[0x49, 0x89, 0x14, 0xCF]);
+            }
+            13 => self.push_rax(),
+            14 | 15 | 16 | 25 => {
+                let (name, code) = match op {
+                    14 => ("orq", 0x09),
+                    15 => ("xorq", 0x31),
+                    16 => ("andq", 0x21),
+                    _ => ("addq", 0x01),
+                };
+                self.pop_rcx();
+                self.i(&format!("{} %rcx, %rax", name), &[0x48, code, 0xC8]);
+            }
+            17 => self.setcc("e", 0x94),
+            18 => self.setcc("ne", 0x95),
+            19 => self.setcc("l", 0x9C),
+            20 => self.setcc("g", 0x9F),
+            21 => self.setcc("le", 0x9E),
+            22 => self.setcc("ge", 0x9D),
+            39 => self.setcc("b", 0x92),
+            40 => self.setcc("a", 0x97),
+            41 => self.setcc("be", 0x96),
+            42 => self.setcc("ae", 0x93),
+            23 | 24 | 43 => {
+                let (name, ext) = match op {
+                    23 => ("shlq", 4),
+                    24 => ("sarq", 7),
+                    _ => ("shrq", 5),
+                };
+                self.pop_rcx();
+                self.i("xchgq %rax, %rcx", &[0x48, 0x91]);
+                self.i(&format!("{} %cl, %rax", name), &[0x48, 0xD3, 0xC0 | ext << 3]);
+            }
+            26 => {
+                self.pop_rcx();
+                self.i("subq %rax, %rcx", &[0x48, 0x29, 0xC1]);
+                self.i("movq %rcx, %rax", &[0x48, 0x89, 0xC8]);
+            }
+            27 => {
+                self.pop_rcx();
+                self.i("imulq %rcx, %rax", &[0x48, 0x0F, 0xAF, 0xC1]);
+            }
+            28 | 29 | 44 | 45 => {
+                self.i("testq %rax, %rax", &[0x48, 0x85, 0xC0]);
+                self.unless("jnz", 0x75, DIVIDE, false);
+                self.pop_rcx();
+                self.i("xchgq %rax, %rcx", &[0x48, 0x91]);
+                if op == 28 || op == 29 {
+                    self.i("cqto", &[0x48, 0x99]);
+                    self.i("idivq %rcx", &[0x48, 0xF7, 0xF9]);
+                } else {
+                    self.i("xorl %edx, %edx", &[0x31, 0xD2]);
+                    self.i("divq %rcx", &[0x48, 0xF7, 0xF1]);
+                }
+                if op == 29 || op == 45 {
+                    self.i("movq %rdx, %rax", &[0x48, 0x89, 0xD0]);
+                }
+            }
+            30..=38 | 66 => {
+                // system calls go back into the VM: c4_syscall(vm, op, argc, sp, ax)
+                let argc = if op == 33 && text.get(pc + 1) == Some(&7) { arg(2) as i32 } else { 0 };
+                self.i("movq %rbx, %rdi", &[0x48, 0x89, 0xDF]);
+                self.i(&format!("movq ${}, %rsi", op), &[&[0x48, 0xC7, 0xC6][..], &(op as i32).to_le_bytes()].concat());
+                self.i(&format!("movq ${}, %rdx", argc), &[&[0x48, 0xC7, 0xC2][..], &argc.to_le_bytes()].concat());
+                self.i("movq %r12, %rcx", &[0x4C, 0x89, 0xE1]);
+                self.i("movq %rax, %r8", &[0x49, 0x89, 0xC0]);
+                self.call("c4_syscall");
+                // a fault or EXIT ends the program
+                self.i("testq %rdx, %rdx", &[0x48, 0x85, 0xD2]);
+                self.i("jnz .Lexit", &[0x0F, 0x85, 0, 0, 0, 0]);
+                self.exits.push(self.lines.len() - 1);
+            }
+            46 | 47 => {
+                let shift = (64 - arg(1)) as u8;
+                if shift > 0 {
+                    self.shift("shlq", 4, shift);
+                    if op == 46 {
+                        self.shift("sarq", 7, shift);
+                    } else {
+                        self.shift("shrq", 5, shift);
+                    }
+                }
+            }
+            48 => self.imm(((arg(2) as u32 as u64) << 32 | arg(1) as u32 as u64) as i64),
+            49 => self.float_arith("addsd", 0x58),
+            50 => self.float_arith("subsd", 0x5C),
+            51 => self.float_arith("mulsd", 0x59),
+            52 => self.float_arith("divsd", 0x5E),
+            53 | 54 => {
+                self.float_operands();
+                self.i("ucomisd %xmm1, %xmm0", &[0x66, 0x0F, 0x2E, 0xC1]);
+                if op == 53 {
+                    self.i("sete %al", &[0x0F, 0x94, 0xC0]);
+                    self.i("setnp %cl", &[0x0F, 0x9B, 0xC1]);
+                    self.i("andb %cl, %al", &[0x20, 0xC8]);
+                } else {
+                    self.i("setne %al", &[0x0F, 0x95, 0xC0]);
+                    self.i("setp %cl", &[0x0F, 0x9A, 0xC1]);
+                    self.i("orb %cl, %al", &[0x08, 0xC8]);
+                }
+                self.i("movzbl %al, %eax", &[0x0F, 0xB6, 0xC0]);
+            }
+            55 => self.float_compare(true, "a", 0x97),
+            56 => self.float_compare(false, "a", 0x97),
+            57 => self.float_compare(true, "ae", 0x93),
+            58 => self.float_compare(false, "ae", 0x93),
+            59 => {
+                self.i("cvtsi2sdq %rax, %xmm0", &[0xF2, 0x48, 0x0F, 0x2A, 0xC0]);
+                self.i("movq %xmm0, %rax", &[0x66, 0x48, 0x0F, 0x7E, 0xC0]);
+            }
+            60..=62 => {
+                self.i("movq %rax, %rdi", &[0x48, 0x89, 0xC7]);
+                self.call(match op {
+                    60 => "c4_utf",
+                    61 => "c4_fti",
+                    _ => "c4_ftu",
+                });
+            }
+            63 => {
+                self.i("movq %rax, %xmm0", &[0x66, 0x48, 0x0F, 0x6E, 0xC0]);
+                self.i("cvtsd2ss %xmm0, %xmm0", &[0xF2, 0x0F, 0x5A, 0xC0]);
+                self.i("cvtss2sd %xmm0, %xmm0", &[0xF3, 0x0F, 0x5A, 0xC0]);
+                self.i("movq %xmm0, %rax", &[0x66, 0x48, 0x0F, 0x7E, 0xC0]);
+            }
+            64 => {
+                self.check_pop();
+                self.i("movq (%r15,%r12,8), %rcx", &[0x4B, 0x8B, 0x0C, 0xE7]);
+                self.i("cvtsi2sdq %rcx, %xmm0", &[0xF2, 0x48, 0x0F, 0x2A, 0xC1]);
+                self.i("movq %xmm0, %rcx", &[0x66, 0x48, 0x0F, 0x7E, 0xC1]);
+                self.i("movq %rcx, (%r15,%r12,8)", &[0x4B, 0x89, 0x0C, 0xE7]);
+            }
+            65 => {
+                // ax has to survive the call; two pushes keep the stack aligned
+                self.check_pop();
+                self.i("pushq %rax", &[0x50]);
+                self.i("pushq %rax", &[0x50]);
+                self.i("movq (%r15,%r12,8), %rdi", &[0x4B, 0x8B, 0x3C, 0xE7]);
+                self.call("c4_utf");
+                self.i("movq %rax, (%r15,%r12,8)", &[0x4B, 0x89, 0x04, 0xE7]);
+                self.i("popq %rax", &[0x58]);
+                self.i("popq %rax", &[0x58]);
+            }
+            op => {
+                self.imm(op);
+                self.fault(OPCODE, true);
+            }
+        }
+    }
+}
+
+// translates a whole text segment
+pub fn compile(text: &[i64]) -> Native {
+    let mut n = Native {
+        lines: Vec::new(),
+        starts: vec![None; text.len() + 1],
+        jumps: Vec::new(),
+        exits: Vec::new(),
+        faults: Vec::new(),
+        traps: Vec::new(),
+        pc: 0,
+    };
+    let mut pc = 0;
+    while pc < text.len() {
+        n.starts[pc] = Some(n.lines.len());
+        n.translate(text, pc);
+        pc += 1 + operands(text[pc] as i32);
+    }
+    n
+}
+
+impl Native {
+    // the text addresses jumps go to that aren't instructions, with the
+    // first jump to each
+    pub fn strays(&self) -> Vec<(usize, usize)> {
+        let mut strays: Vec<(usize, usize)> = Vec::new();
+        for &(_, target, pc) in &self.jumps {
+            if self.starts.get(target).copied().flatten().is_none() && !strays.iter().any(|&(t, _)| t == target) {
+                strays.push((target, pc));
+            }
+        }
+        strays
+    }
+
+    // the translated text as AT&T assembly, with a label per text address
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            if let Some(pc) = self.starts.iter().position(|&s| s == Some(i)) {
+                out.push_str(&format!(".L{}:\n", pc));
+            }
+            out.push_str(&format!("    {}\n", line.asm));
+        }
+        out
+    }
+}
+
+// entry(vm, cells, table, sp, code, bp) saves the registers the generated
+// code uses, jumps to `code` and returns ax once EXIT jumps to .Lexit
+const PROLOGUE: [(&str, &[u8]); 11] = [
+    ("pushq %rbx", &[0x53]),
+    ("pushq %r12", &[0x41, 0x54]),
+    ("pushq %r13", &[0x41, 0x55]),
+    ("pushq %r14", &[0x41, 0x56]),
+    ("pushq %r15", &[0x41, 0x57]),
+    ("movq %rdi, %rbx", &[0x48, 0x89, 0xFB]),
+    ("movq %rsi, %r15", &[0x49, 0x89, 0xF7]),
+    ("movq %rdx, %r14", &[0x49, 0x89, 0xD6]),
+    ("movq %rcx, %r12", &[0x49, 0x89, 0xCC]),
+    ("movq %r9, %r13", &[0x4D, 0x89, 0xCD]),
+    ("jmp *%r8", &[0x41, 0xFF, 0xE0]),
+];
+
+const EPILOGUE: [(&str, &[u8]); 6] = [
+    ("popq %r15", &[0x41, 0x5F]),
+    ("popq %r14", &[0x41, 0x5E]),
+    ("popq %r13", &[0x41, 0x5D]),
+    ("popq %r12", &[0x41, 0x5C]),
+    ("popq %rbx", &[0x5B]),
+    ("ret", &[0xC3]),
+];
+
+impl Native {
+    // lays the code out as prologue, translated text, epilogue, .Lfault and a
+    // trap for returns to addresses that aren't instructions. Returns the
+    // machine code and the offset of every text address into it (the trap's
+    // for the rest).
+    pub fn assemble(&self) -> (Vec<u8>, Vec<usize>) {
+        let mut code: Vec<u8> = PROLOGUE.iter().flat_map(|(_, b)| b.iter().copied()).collect();
+        let mut offset = Vec::with_capacity(self.lines.len());
+        for line in &self.lines {
+            offset.push(code.len());
+            code.extend(&line.bytes);
+        }
+        let exit = code.len();
+        code.extend(EPILOGUE.iter().flat_map(|(_, b)| b.iter().copied()));
+        // .Lfault: c4_fault(ctx, pc in esi, kind in edx, value in rcx), then out
+        let fault = code.len();
+        code.extend([0x48, 0x89, 0xDF]); // movq %rbx, %rdi
+        code.extend([&[0x49, 0xBB][..], &helper_address("c4_fault").to_le_bytes(), &[0x41, 0xFF, 0xD3]].concat());
+        code.push(0xE9);
+        code.extend(((exit as i64 - code.len() as i64 - 4) as i32).to_le_bytes());
+        // the trap, with the LEV's pc in esi and where it returns to in rcx
+        let trap = code.len();
+        code.extend([&[0xBA][..], &RETURN.to_le_bytes()].concat()); // movl $RETURN, %edx
+        code.push(0xE9);
+        code.extend(((fault as i64 - code.len() as i64 - 4) as i32).to_le_bytes());
+
+        let line_at = |i: usize| if i < offset.len() { offset[i] } else { exit };
+        let patch = |code: &mut Vec<u8>, line: usize, to: usize| {
+            let at = offset[line] + self.lines[line].bytes.len();
+            code[at - 4..at].copy_from_slice(&((to as i64 - at as i64) as i32).to_le_bytes());
+        };
+        for &(line, target, pc) in &self.jumps {
+            let to = match self.starts.get(target).copied().flatten() {
+                Some(i) => line_at(i),
+                None => {
+                    // movl $target, %ecx; movl $pc, %esi; movl $JUMP, %edx; jmp .Lfault
+                    let stub = code.len();
+                    code.extend([&[0xB9][..], &(target as i32).to_le_bytes()].concat());
+                    code.extend([&[0xBE][..], &(pc as i32).to_le_bytes()].concat());
+                    code.extend([&[0xBA][..], &JUMP.to_le_bytes()].concat());
+                    code.push(0xE9);
+                    code.extend(((fault as i64 - code.len() as i64 - 4) as i32).to_le_bytes());
+                    stub
+                }
+            };
+            patch(&mut code, line, to);
+        }
+        for &line in &self.exits {
+            patch(&mut code, line, exit);
+        }
+        for &line in &self.faults {
+            patch(&mut code, line, fault);
+        }
+        for &line in &self.traps {
+            patch(&mut code, line, trap);
+        }
+        let addresses = self.starts.iter().map(|s| s.map_or(trap, line_at)).collect();
+        (code, addresses)
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+mod map {
+    use std::arch::asm;
+
+    // anonymous memory holding a copy of code, writable while it is copied
+    // in and executable, but no longer writable, once it is
+    pub fn code(code: &[u8]) -> Option<*mut u8> {
+        let ret: isize;
+        // SAFETY: a plain mmap(NULL, len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+        unsafe {
+            asm!("syscall", inlateout("rax") 9isize => ret, in("rdi") 0usize, in("rsi") code.len(), in("rdx") 3usize,
+                 in("r10") 0x22usize, in("r8") -1isize, in("r9") 0usize, lateout("rcx") _, lateout("r11") _,
+                 options(nostack));
+        }
+        if ret < 0 {
+            return None;
+        }
+        let mem = ret as *mut u8;
+        // SAFETY: mem is a fresh mapping of code.len() bytes
+        unsafe { std::ptr::copy_nonoverlapping(code.as_ptr(), mem, code.len()) };
+        let ret: isize;
+        // SAFETY: mprotect(mem, len, PROT_READ | PROT_EXEC) on that mapping
+        unsafe {
+            asm!("syscall", inlateout("rax") 10isize => ret, in("rdi") mem, in("rsi") code.len(), in("rdx") 5usize,
+                 lateout("rcx") _, lateout("r11") _, options(nostack));
+        }
+        if ret < 0 {
+            free(mem, code.len());
+            return None;
+        }
+        Some(mem)
+    }
+
+    pub fn free(ptr: *mut u8, len: usize) {
+        // SAFETY: unmaps exactly what code mapped
+        unsafe {
+            asm!("syscall", inlateout("rax") 11isize => _, in("rdi") ptr, in("rsi") len,
+                 lateout("rcx") _, lateout("r11") _, options(nostack));
+        }
+    }
+}
+
+impl VM {
+    // runs the program as native code, from pc with the stack as it is, or on
+    // the interpreter if the code can't be mapped
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    pub fn run_native(&mut self) {
+        let native = compile(&self.text);
+        let (code, offsets) = native.assemble();
+        let Some(mem) = map::code(&code) else {
+            self.run();
+            return;
+        };
+        let table: Vec<u64> = offsets.iter().map(|&o| mem as u64 + o as u64).collect();
+        let entry: extern "C" fn(*mut Context, *mut i64, *const u64, i64, u64, i64) -> i64 =
+            // SAFETY: the prologue at offset 0 follows the C calling convention above
+            unsafe { std::mem::transmute(mem) };
+        let cells = self.stack.as_mut_ptr();
+        let (sp, bp) = (self.sp as i64, self.bp as i64);
+        let mut ctx = Context { vm: self, cells: self.stack.len() as u64, text: self.text.len() as u64, heap: self.heap as u64 };
+        let start = table.get(self.pc).copied().unwrap_or(table[self.text.len()]);
+        let ax = entry(&mut ctx, cells, table.as_ptr(), sp, start, bp);
+        map::free(mem, code.len());
+        if self.error.is_none() {
+            self.ax = ax;
+        }
+    }
+
+    #[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+    pub fn run_native(&mut self) {
+        self.run();
+    }
+}
@@ -0,0 +1,576 @@
+// The -O passes. `fold` evaluates constant subexpressions in the AST before
+// code generation; `peephole` then cleans up the emitted bytecode: it folds
+// what's left of `IMM a; PSH; IMM b; OP`, drops no-op arithmetic and `ADJ 0`,
+// threads jumps to jumps, removes code that can never run after a `LEV` or
+// `JMP` and moves loop tests to the bottom of their loops. Both keep the VM
+// contract untouched.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{BinOp, Expr, ExprKind, Function, IncDec, Program, Stmt, UnOp};
+use crate::codegen::{operands, Code, Reloc};
+use crate::{Base, Type};
+use crate::{ADD, ADJ, AND, BNZ, BZ, DIV, EQ, FIMM, GE, GT, IMM, ITF, JMP, JSR, LE, LEA, LEV, LT, MOD, MUL, NE, OR, PSH};
+use crate::{SHL, SHR, SUB, SXT, UDIV, UGE, UGT, ULE, ULT, UMOD, USHR, XOR, ZXT};
+
+/////////////////////////////// constant folding ///////////////////////////////
+
+// folds the constant subexpressions of every function in place
+pub fn fold(program: &mut Program) {
+    for f in &mut program.functions {
+        fold_function(f);
+    }
+}
+
+fn fold_function(f: &mut Function) {
+    for s in &mut f.body {
+        fold_stmt(s);
+    }
+}
+
+fn fold_stmt(s: &mut Stmt) {
+    match s {
+        Stmt::If(cond, then, els) => {
+            fold_expr(cond);
+            fold_stmt(then);
+            if let Some(els) = els {
+                fold_stmt(els);
+            }
+        }
+        Stmt::While(cond, body) => {
+            fold_expr(cond);
+            fold_stmt(body);
+        }
+        Stmt::DoWhile(body, cond) => {
+            fold_stmt(body);
+            fold_expr(cond);
+        }
+        Stmt::Return(Some(e), _) => fold_expr(e),
+        Stmt::Expr(e) => {
+            fold_expr(e);
+            // i++ on its own line: nobody needs the old value, so skip
+            // computing it back from the new one
+            if let ExprKind::IncDec(op @ (IncDec::PostInc | IncDec::PostDec), _) = &mut e.kind {
+                *op = if *op == IncDec::PostInc { IncDec::PreInc } else { IncDec::PreDec };
+            }
+        }
+        Stmt::Block(body) => body.iter_mut().for_each(fold_stmt),
+        Stmt::Return(None, _) | Stmt::Empty => {}
+    }
+}
+
+fn fold_expr(e: &mut Expr) {
+    // children first, so constants bubble up
+    match &mut e.kind {
+        ExprKind::Call { args, .. } => args.iter_mut().for_each(fold_expr),
+        ExprKind::Unary(_, inner)
+        | ExprKind::Cast(inner)
+        | ExprKind::Deref(inner)
+        | ExprKind::AddrOf(inner)
+        | ExprKind::IncDec(_, inner) => fold_expr(inner),
+        ExprKind::Binary(_, l, r) | ExprKind::Assign(l, r) | ExprKind::Index(l, r) => {
+            fold_expr(l);
+            fold_expr(r);
+        }
+        ExprKind::Cond(c, t, f) => {
+            fold_expr(c);
+            fold_expr(t);
+            fold_expr(f);
+        }
+        _ => {}
+    }
+    if let Some(kind) = folded(e) {
+        e.kind = kind;
+    }
+}
+
+// the integer value of a literal that computes as a plain signed int
+fn int_const(e: &Expr) -> Option<i64> {
+    match e.kind {
+        ExprKind::Num(n) if e.ty == Type::INT => Some(n),
+        _ => None,
+    }
+}
+
+fn float_const(e: &Expr) -> Option<f64> {
+    match e.kind {
+        ExprKind::Float(v) if e.ty == Type::DOUBLE => Some(v),
+        _ => None,
+    }
+}
+
+// a folded integer only replaces the node if it still fits the IMM operand
+fn num(v: i64) -> Option<ExprKind> {
+    i32::try_from(v).ok().map(|v| ExprKind::Num(v as i64))
+}
+
+// the constant e folds to, if any
+fn folded(e: &Expr) -> Option<ExprKind> {
+    match &e.kind {
+        ExprKind::Sizeof(t) => num(t.size() as i64),
+        ExprKind::Unary(op, inner) => {
+            if let Some(v) = int_const(inner) {
+                return num(match op {
+                    UnOp::Plus => v,
+                    UnOp::Neg => -v,
+                    UnOp::Not => (v == 0) as i64,
+                    UnOp::BitNot => !v,
+                });
+            }
+            match (op, float_const(inner)) {
+                (UnOp::Neg, Some(v)) => Some(ExprKind::Float(-v)),
+                (UnOp::Plus, Some(v)) => Some(ExprKind::Float(v)),
+                _ => None,
+            }
+        }
+        ExprKind::Binary(op, l, r) => {
+            if let (Some(a), Some(b)) = (int_const(l), int_const(r)) {
+                return num(int_binary(*op, a, b)?);
+            }
+            let (a, b) = (float_const(l)?, float_const(r)?);
+            match op {
+                BinOp::Add => Some(ExprKind::Float(a + b)),
+                BinOp::Sub => Some(ExprKind::Float(a - b)),
+                BinOp::Mul => Some(ExprKind::Float(a * b)),
+                BinOp::Div => Some(ExprKind::Float(a / b)),
+                BinOp::Eq => num((a == b) as i64),
+                BinOp::Ne => num((a != b) as i64),
+                BinOp::Lt => num((a < b) as i64),
+                BinOp::Gt => num((a > b) as i64),
+                BinOp::Le => num((a <= b) as i64),
+                BinOp::Ge => num((a >= b) as i64),
+                _ => None,
+            }
+        }
+        ExprKind::Cond(c, t, f) => {
+            let chosen = if int_const(c)? != 0 { t } else { f };
+            // the branch has to already have the conditional's type
+            if chosen.ty == e.ty {
+                Some(chosen.kind.clone())
+            } else {
+                None
+            }
+        }
+        ExprKind::Cast(inner) => {
+            let t = e.ty;
+            if let Some(v) = int_const(inner) {
+                if t.is_float() {
+                    let v = v as f64;
+                    return Some(ExprKind::Float(if t.base == Base::Float { v as f32 as f64 } else { v }));
+                }
+                if t.is_ptr() {
+                    return None;
+                }
+                return num(match (t.base, t.unsigned) {
+                    (Base::Char, true) => v as u8 as i64,
+                    (Base::Char, false) => v as i8 as i64,
+                    (Base::Short, true) => v as u16 as i64,
+                    (Base::Short, false) => v as i16 as i64,
+                    (Base::Int, true) => v as u32 as i64,
+                    // the rest keep the full cell, so the value doesn't change
+                    _ => v,
+                });
+            }
+            let v = float_const(inner)?;
+            if t.is_float() {
+                return Some(ExprKind::Float(if t.base == Base::Float { v as f32 as f64 } else { v }));
+            }
+            if t.is_ptr() || t.base < Base::Int || !v.is_finite() {
+                return None;
+            }
+            // only values both FTI and FTU agree on
+            if v.trunc() >= 0.0 || !t.unsigned {
+                num(v as i64)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+// evaluates a binary operator on two int constants the way the VM would
+fn int_binary(op: BinOp, a: i64, b: i64) -> Option<i64> {
+    Some(match op {
+        BinOp::LogOr => (a != 0 || b != 0) as i64,
+        BinOp::LogAnd => (a != 0 && b != 0) as i64,
+        BinOp::Or => a | b,
+        BinOp::Xor => a ^ b,
+        BinOp::And => a & b,
+        BinOp::Eq => (a == b) as i64,
+        BinOp::Ne => (a != b) as i64,
+        BinOp::Lt => (a < b) as i64,
+        BinOp::Gt => (a > b) as i64,
+        BinOp::Le => (a <= b) as i64,
+        BinOp::Ge => (a >= b) as i64,
+        BinOp::Shl => a.checked_shl(u32::try_from(b).ok()?)?,
+        BinOp::Shr => a.checked_shr(u32::try_from(b).ok()?)?,
+        BinOp::Add => a.checked_add(b)?,
+        BinOp::Sub => a.checked_sub(b)?,
+        BinOp::Mul => a.checked_mul(b)?,
+        BinOp::Div => a.checked_div(b)?,
+        BinOp::Mod => a.checked_rem(b)?,
+    })
+}
+
+////////////////////////////////// peephole //////////////////////////////////
+
+// one decoded instruction; jump operands hold instruction indices, not addresses
+#[derive(Debug, Clone)]
+struct Insn {
+    op: i32,
+    args: Vec<i32>,
+    line: i32,            // source line from the line table, 0 if it has none
+    reloc: Option<Reloc>, // the operand is an address only the linker knows
+}
+
+impl Insn {
+    // a jump within this file, whose operand is an instruction index
+    fn is_jump(&self) -> bool {
+        matches!(self.op, JMP | JSR | BZ | BNZ) && self.reloc.is_none()
+    }
+}
+
+// how many instructions (not words) a text segment holds
+pub fn instruction_count(text: &[i32]) -> usize {
+    let mut pc = 0;
+    let mut n = 0;
+    while pc < text.len() {
+        pc += 1 + operands(text[pc]);
+        n += 1;
+    }
+    n
+}
+
+// runs the peephole rules over the text until none of them applies anymore
+pub fn peephole(code: &mut Code) {
+    let mut p = Peephole::decode(code);
+    while p.pass() {}
+    if p.rotate() {
+        while p.pass() {}
+    }
+    p.encode(code);
+}
+
+// the longest loop test rotate copies
+const MAX_TEST: usize = 8;
+
+struct Peephole {
+    insns: Vec<Insn>,
+    entries: Vec<(String, usize)>, // function name and the index of its first instruction
+}
+
+impl Peephole {
+    fn decode(code: &Code) -> Self {
+        let text = &code.text;
+        let mut index = vec![usize::MAX; text.len() + 1];
+        let mut insns = Vec::new();
+        let relocs: HashMap<usize, Reloc> = code.relocs.iter().cloned().collect();
+        let mut pc = 0;
+        while pc < text.len() {
+            index[pc] = insns.len();
+            let n = operands(text[pc]);
+            let line = crate::codegen::line_of(&code.lines, pc).unwrap_or(0);
+            let reloc = relocs.get(&(pc + 1)).cloned();
+            insns.push(Insn { op: text[pc], args: text[pc + 1..pc + 1 + n].to_vec(), line, reloc });
+            pc += 1 + n;
+        }
+        index[text.len()] = insns.len();
+        for insn in &mut insns {
+            if insn.is_jump() {
+                insn.args[0] = index[insn.args[0] as usize] as i32;
+            }
+        }
+        let entries = code.functions.iter().map(|(name, &at)| (name.clone(), index[at as usize])).collect();
+        Peephole { insns, entries }
+    }
+
+    fn encode(self, code: &mut Code) {
+        let mut addr = Vec::with_capacity(self.insns.len() + 1);
+        let mut pc = 0;
+        for insn in &self.insns {
+            addr.push(pc as i32);
+            pc += 1 + insn.args.len();
+        }
+        addr.push(pc as i32);
+        code.text.clear();
+        code.lines.clear();
+        code.relocs.clear();
+        for insn in &self.insns {
+            if insn.line != 0 && code.lines.last().map(|&(_, line)| line) != Some(insn.line) {
+                code.lines.push((code.text.len() as i32, insn.line));
+            }
+            code.text.push(insn.op);
+            if let Some(reloc) = &insn.reloc {
+                code.relocs.push((code.text.len(), reloc.clone()));
+            }
+            if insn.is_jump() {
+                code.text.push(addr[insn.args[0] as usize]);
+            } else {
+                code.text.extend(&insn.args);
+            }
+        }
+        for (name, at) in self.entries {
+            code.functions.insert(name, addr[at]);
+        }
+    }
+
+    // instructions control can arrive at from somewhere other than the one before
+    fn targets(&self) -> HashSet<usize> {
+        let mut targets: HashSet<usize> = self.entries.iter().map(|&(_, at)| at).collect();
+        for insn in &self.insns {
+            if insn.is_jump() {
+                targets.insert(insn.args[0] as usize);
+            }
+        }
+        targets
+    }
+
+    // applies every rule once; true if anything changed
+    fn pass(&mut self) -> bool {
+        let targets = self.targets();
+        let mut dead = vec![false; self.insns.len()];
+        let mut changed = false;
+        let mut i = 0;
+        while i < self.insns.len() {
+            // how many instructions from i on form a straight line nobody jumps into
+            let run = (1..self.insns.len() - i).take_while(|k| !targets.contains(&(i + k))).count() + 1;
+            // addresses the linker fills in don't take part in any rule
+            let ops: Vec<i32> = self.insns[i..i + run.min(4)].iter().map(|x| if x.reloc.is_some() { -1 } else { x.op }).collect();
+            let arg = |k: usize| self.insns[i + k].args.first().copied().unwrap_or(0);
+
+            // IMM a; PSH; IMM b; OP  =>  IMM (a OP b)
+            if ops.len() == 4 && ops[0] == IMM && ops[1] == PSH && ops[2] == IMM {
+                if let Some(v) = eval(ops[3], arg(0) as i64, arg(2) as i64).and_then(|v| i32::try_from(v).ok()) {
+                    self.insns[i].args[0] = v;
+                    dead[i + 1..i + 4].fill(true);
+                    changed = true;
+                    i += 4;
+                    continue;
+                }
+            }
+            // LEA a; PSH; LEA a  =>  LEA a; PSH: ax still holds a, which goes
+            // for the same global's address too, whatever the linker makes it
+            let (first, third) = (&self.insns[i], self.insns.get(i + 2));
+            let again = third.is_some_and(|x| x.op == first.op && x.args == first.args && x.reloc == first.reloc);
+            if run >= 3 && matches!(first.op, LEA | IMM) && ops[1] == PSH && again {
+                dead[i + 2] = true;
+                changed = true;
+                i += 3;
+                continue;
+            }
+            // PSH; IMM 0; ADD  and the like leave ax as it was
+            if ops.len() >= 3 && ops[0] == PSH && ops[1] == IMM && is_identity(ops[2], arg(1)) {
+                dead[i..i + 3].fill(true);
+                changed = true;
+                i += 3;
+                continue;
+            }
+            // IMM a; SXT/ZXT n  =>  IMM (narrowed a)
+            if ops.len() >= 2 && ops[0] == IMM && (ops[1] == SXT || ops[1] == ZXT) {
+                let shift = 64 - arg(1);
+                let v = arg(0) as i64;
+                let v = if ops[1] == SXT { (v << shift) >> shift } else { ((v as u64) << shift >> shift) as i64 };
+                if let Ok(v) = i32::try_from(v) {
+                    self.insns[i].args[0] = v;
+                    dead[i + 1] = true;
+                    changed = true;
+                    i += 2;
+                    continue;
+                }
+            }
+            // IMM a; ITF  =>  FIMM (double)a
+            if ops.len() >= 2 && ops[0] == IMM && ops[1] == ITF {
+                let bits = (arg(0) as f64).to_bits();
+                self.insns[i] = Insn { op: FIMM, args: vec![bits as u32 as i32, (bits >> 32) as u32 as i32], line: self.insns[i].line, reloc: None };
+                dead[i + 1] = true;
+                changed = true;
+                i += 2;
+                continue;
+            }
+            // PSH; ADJ n  =>  ADJ n-1: the pushed value is dropped right away
+            if ops.len() >= 2 && ops[0] == PSH && ops[1] == ADJ && arg(1) > 0 {
+                dead[i] = true;
+                self.insns[i + 1].args[0] -= 1;
+                changed = true;
+                i += 2;
+                continue;
+            }
+            // IMM c; BZ/BNZ t: the branch outcome is already known
+            if ops.len() >= 2 && ops[0] == IMM && (ops[1] == BZ || ops[1] == BNZ) {
+                if (arg(0) == 0) == (ops[1] == BZ) {
+                    self.insns[i + 1].op = JMP;
+                } else {
+                    dead[i + 1] = true;
+                }
+                changed = true;
+                i += 1;
+                continue;
+            }
+            // BZ t; JMP u; t:  =>  BNZ u
+            if ops.len() >= 2 && (ops[0] == BZ || ops[0] == BNZ) && ops[1] == JMP && arg(0) as usize == i + 2 {
+                let to = arg(1);
+                self.insns[i].op = if ops[0] == BZ { BNZ } else { BZ };
+                self.insns[i].args[0] = to;
+                dead[i + 1] = true;
+                changed = true;
+                i += 2;
+                continue;
+            }
+            match ops[0] {
+                ADJ if arg(0) == 0 => {
+                    dead[i] = true;
+                    changed = true;
+                }
+                JMP | BZ | BNZ => {
+                    // follow jumps to jumps, giving up on cycles
+                    let mut to = arg(0) as usize;
+                    let mut hops = 0;
+                    while to < self.insns.len() && self.insns[to].op == JMP && hops < self.insns.len() {
+                        to = self.insns[to].args[0] as usize;
+                        hops += 1;
+                    }
+                    if to != arg(0) as usize && hops < self.insns.len() {
+                        self.insns[i].args[0] = to as i32;
+                        changed = true;
+                    }
+                    // a jump to the very next instruction does nothing
+                    if to == i + 1 {
+                        dead[i] = true;
+                        changed = true;
+                    }
+                }
+                _ => {}
+            }
+            // nothing after an unconditional transfer runs until the next target
+            if ops[0] == JMP || ops[0] == LEV {
+                for k in 1..run {
+                    dead[i + k] = true;
+                    changed = true;
+                }
+                i += run;
+                continue;
+            }
+            i += 1;
+        }
+        if changed {
+            self.compact(&dead);
+        }
+        changed
+    }
+
+    // replaces the jump back to a short loop test by a copy of the test, so
+    // every iteration but the last runs one jump less:
+    //   L: test; BZ E; body; JMP L   =>   L: test; BZ E; body; test; BZ E; JMP body
+    // which pass() then turns around into  ...; test; BNZ body; E:
+    fn rotate(&mut self) -> bool {
+        let targets = self.targets();
+        let mut copies = HashMap::new();
+        for (j, insn) in self.insns.iter().enumerate() {
+            let l = insn.args.first().copied().unwrap_or(0) as usize;
+            if insn.op != JMP || !insn.is_jump() || l >= j {
+                continue;
+            }
+            let Some(b) = (l..j.min(l + MAX_TEST)).find(|&k| matches!(self.insns[k].op, BZ | BNZ)) else { continue };
+            // the test has to be straight-line code nobody jumps into
+            let straight = self.insns[l..b].iter().all(|x| !x.is_jump() && x.op != LEV);
+            if !straight || (l + 1..=b).any(|k| targets.contains(&k)) {
+                continue;
+            }
+            let mut copy = self.insns[l..=b].to_vec();
+            copy.push(Insn { op: JMP, args: vec![b as i32 + 1], line: insn.line, reloc: None });
+            copies.insert(j, copy);
+        }
+        if copies.is_empty() {
+            return false;
+        }
+        // where each old instruction ends up once the copies are in
+        let mut remap = Vec::with_capacity(self.insns.len() + 1);
+        let mut next = 0;
+        for i in 0..self.insns.len() {
+            remap.push(next);
+            next += copies.get(&i).map_or(1, Vec::len);
+        }
+        remap.push(next);
+        let old = std::mem::take(&mut self.insns);
+        for (i, insn) in old.into_iter().enumerate() {
+            for mut insn in copies.remove(&i).unwrap_or_else(|| vec![insn]) {
+                if insn.is_jump() {
+                    insn.args[0] = remap[insn.args[0] as usize] as i32;
+                }
+                self.insns.push(insn);
+            }
+        }
+        for entry in &mut self.entries {
+            entry.1 = remap[entry.1];
+        }
+        true
+    }
+
+    // drops the dead instructions, pointing jumps at the next live one instead
+    fn compact(&mut self, dead: &[bool]) {
+        let n = self.insns.len();
+        let mut remap = vec![0; n + 1];
+        let mut next = dead.iter().filter(|&&d| !d).count();
+        remap[n] = next;
+        for i in (0..n).rev() {
+            if !dead[i] {
+                next -= 1;
+            }
+            remap[i] = next;
+        }
+        let old = std::mem::take(&mut self.insns);
+        for (i, mut insn) in old.into_iter().enumerate() {
+            if dead[i] {
+                continue;
+            }
+            if insn.is_jump() {
+                insn.args[0] = remap[insn.args[0] as usize] as i32;
+            }
+            self.insns.push(insn);
+        }
+        for entry in &mut self.entries {
+            entry.1 = remap[entry.1];
+        }
+    }
+}
+
+// true if `PSH; IMM v; op` leaves ax unchanged
+fn is_identity(op: i32, v: i32) -> bool {
+    match v {
+        0 => matches!(op, ADD | SUB | OR | XOR | SHL | SHR | USHR),
+        1 => matches!(op, MUL | DIV | UDIV),
+        _ => false,
+    }
+}
+
+// the VM's integer binary operators on constant operands
+pub(crate) fn eval(op: i32, a: i64, b: i64) -> Option<i64> {
+    let (ua, ub) = (a as u64, b as u64);
+    Some(match op {
+        OR => a | b,
+        XOR => a ^ b,
+        AND => a & b,
+        EQ => (a == b) as i64,
+        NE => (a != b) as i64,
+        LT => (a < b) as i64,
+        GT => (a > b) as i64,
+        LE => (a <= b) as i64,
+        GE => (a >= b) as i64,
+        SHL => a.checked_shl(u32::try_from(b).ok()?)?,
+        SHR => a.checked_shr(u32::try_from(b).ok()?)?,
+        ADD => a.wrapping_add(b),
+        SUB => a.wrapping_sub(b),
+        MUL => a.wrapping_mul(b),
+        DIV => a.checked_div(b)?,
+        MOD => a.checked_rem(b)?,
+        ULT => (ua < ub) as i64,
+        UGT => (ua > ub) as i64,
+        ULE => (ua <= ub) as i64,
+        UGE => (ua >= ub) as i64,
+        USHR => ua.checked_shr(u32::try_from(b).ok()?)? as i64,
+        UDIV => ua.checked_div(ub)? as i64,
+        UMOD => ua.checked_rem(ub)? as i64,
+        _ => return None,
+    })
+}
@@ -0,0 +1,158 @@
+// The --profile mode. Every instruction the VM executes is charged to its
+// text address and to the call path it ran on: JSR goes down the path to the
+// function it calls and LEV comes back up. At exit that gives the hot
+// functions (calls, cycles spent in the function itself and in everything it
+// called), the hot source lines, and with --profile=FILE the call paths in
+// the collapsed-stack format flamegraph.pl and speedscope read:
+//
+//   main;fib;fib 1234
+
+use std::collections::HashMap;
+
+use crate::codegen::line_of;
+use crate::{JSR, LEV};
+
+// one call path; node 0 is where main is called from
+struct Node {
+    func: usize,                       // index into Profiler::funcs
+    parent: usize,
+    children: HashMap<usize, usize>,   // callee -> node
+    cycles: u64,                       // instructions executed on exactly this path
+}
+
+pub struct Profiler {
+    funcs: Vec<(usize, String)>, // entry point and name, by entry point
+    calls: Vec<u64>,             // per function
+    nodes: Vec<Node>,
+    at: usize,                   // the node the current instruction runs on
+    counts: Vec<u64>,            // per text address
+    folded: Option<String>,      // where to write the collapsed stacks
+}
+
+impl Profiler {
+    // profiles a program about to call main at entry; functions maps every
+    // function's name to its entry point
+    pub fn new(functions: &HashMap<String, i32>, entry: usize, text_len: usize, folded: Option<String>) -> Self {
+        let mut funcs: Vec<(usize, String)> = functions.iter().map(|(name, &at)| (at as usize, name.clone())).collect();
+        funcs.sort();
+        let root = Node { func: usize::MAX, parent: 0, children: HashMap::new(), cycles: 0 };
+        let mut p = Profiler { calls: vec![0; funcs.len()], funcs, nodes: vec![root], at: 0, counts: vec![0; text_len], folded };
+        p.call(entry);
+        p
+    }
+
+    fn call(&mut self, target: usize) {
+        let Ok(func) = self.funcs.binary_search_by_key(&target, |&(at, _)| at) else { return };
+        self.calls[func] += 1;
+        let (parent, next) = (self.at, self.nodes.len());
+        self.at = *self.nodes[parent].children.entry(func).or_insert(next);
+        if self.at == next {
+            self.nodes.push(Node { func, parent, children: HashMap::new(), cycles: 0 });
+        }
+    }
+
+    // charges the instruction op at pc, with its first operand, before it runs
+    pub fn step(&mut self, op: i64, pc: usize, operand: i64) {
+        if let Some(n) = self.counts.get_mut(pc) {
+            *n += 1;
+        }
+        self.nodes[self.at].cycles += 1;
+        match op as i32 {
+            JSR => self.call(operand as usize),
+            LEV => self.at = self.nodes[self.at].parent,
+            _ => {}
+        }
+    }
+
+    // the names on the path from main down to a node
+    fn path(&self, mut node: usize) -> Vec<&str> {
+        let mut names = Vec::new();
+        while node != 0 {
+            names.push(self.funcs[self.nodes[node].func].1.as_str());
+            node = self.nodes[node].parent;
+        }
+        names.reverse();
+        names
+    }
+
+    // the report printed at exit, and the collapsed stacks written if asked for
+    pub fn report(&self, lines: &[(i32, i32)]) -> String {
+        let total: u64 = self.counts.iter().sum();
+        let percent = |n: u64| if total == 0 { 0.0 } else { n as f64 * 100.0 / total as f64 };
+
+        // a function's total is what ran below its outermost calls, so a
+        // recursive function doesn't count its own calls twice
+        let mut own = vec![0u64; self.funcs.len()];
+        let mut inclusive = vec![0u64; self.funcs.len()];
+        let mut below = vec![0u64; self.nodes.len()]; // cycles on a node and everything under it
+        let mut active = vec![0usize; self.funcs.len()]; // calls of each function on the path walked
+        let mut walk = vec![(0, true)];
+        while let Some((i, entering)) = walk.pop() {
+            let node = &self.nodes[i];
+            if entering {
+                if i != 0 {
+                    active[node.func] += 1;
+                }
+                walk.push((i, false));
+                walk.extend(node.children.values().map(|&c| (c, true)));
+                continue;
+            }
+            below[i] += node.cycles;
+            if i != 0 {
+                own[node.func] += node.cycles;
+                active[node.func] -= 1;
+                if active[node.func] == 0 {
+                    inclusive[node.func] += below[i];
+                }
+                below[node.parent] += below[i];
+            }
+        }
+
+        let mut out = format!("profile: {} instructions\n", total);
+        out.push_str(&format!("  {:<20} {:>8} {:>12} {:>7} {:>12} {:>7}\n", "function", "calls", "self", "self%", "total", "total%"));
+        let mut order: Vec<usize> = (0..self.funcs.len()).filter(|&f| self.calls[f] > 0).collect();
+        order.sort_by_key(|&f| (std::cmp::Reverse(own[f]), f));
+        for f in order {
+            out.push_str(&format!(
+                "  {:<20} {:>8} {:>12} {:>6.1}% {:>12} {:>6.1}%\n",
+                self.funcs[f].1,
+                self.calls[f],
+                own[f],
+                percent(own[f]),
+                inclusive[f],
+                percent(inclusive[f])
+            ));
+        }
+
+        let mut by_line: HashMap<i32, u64> = HashMap::new();
+        for (pc, &n) in self.counts.iter().enumerate().filter(|&(_, &n)| n > 0) {
+            if let Some(line) = line_of(lines, pc) {
+                *by_line.entry(line).or_default() += n;
+            }
+        }
+        let mut hot: Vec<(i32, u64)> = by_line.into_iter().collect();
+        hot.sort_by_key(|&(line, n)| (std::cmp::Reverse(n), line));
+        if !hot.is_empty() {
+            out.push_str("  hottest lines:\n");
+            for (line, n) in hot.into_iter().take(10) {
+                out.push_str(&format!("  {:>6} {:>12} {:>6.1}%\n", line, n, percent(n)));
+            }
+        }
+
+        if let Some(path) = &self.folded {
+            match std::fs::write(path, self.collapsed()) {
+                Ok(()) => out.push_str(&format!("  call stacks written to {}\n", path)),
+                Err(e) => out.push_str(&format!("  could not write {}: {}\n", path, e)),
+            }
+        }
+        out
+    }
+
+    // one line per call path with the instructions executed right on it
+    fn collapsed(&self) -> String {
+        let mut stacks: Vec<(String, u64)> =
+            self.nodes.iter().enumerate().skip(1).filter(|(_, n)| n.cycles > 0).map(|(i, n)| (self.path(i).join(";"), n.cycles)).collect();
+        stacks.sort();
+        stacks.iter().map(|(path, n)| format!("{} {}\n", path, n)).collect()
+    }
+}
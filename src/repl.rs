@@ -0,0 +1,298 @@
+// `c4 repl`: C typed in a line at a time. Every entry is compiled like a file
+// of its own, but against one Parser that lives as long as the session, so
+// its symbols and data segment carry over, and its code is appended to the
+// text of one VM, which keeps the globals' values and the heap between
+// entries. The bundled C library is loaded up front.
+//
+// What starts with a type, extern or enum declares globals and functions,
+// like the top level of a file. Anything else is statements, which are
+// wrapped in a function the VM calls right away once the entry's
+// declarations are in; an expression without a closing `;` also prints its
+// value. Entries with more `{` than `}`, or an open `/*`, go on over the
+// following lines.
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use crate::ast::{Function, Program, Stmt};
+use crate::codegen::{self, operands, Reloc};
+use crate::vm::VM;
+use crate::{is_type_specifier, lex, link, stdlib, typeck, Base, Class, Parser, Token, Type};
+use crate::{BNZ, BZ, JMP, JSR, POOL_SIZE};
+
+// memory below the heap for the globals and strings of a whole session
+const DATA_SIZE: usize = 1 << 16;
+
+// the function an entry's statements run in
+const REPL: &str = "<repl>";
+
+pub struct Repl {
+    pub vm: VM,
+    parser: Parser,
+    loaded: usize,                   // how much of the parser's data segment the VM has
+    functions: HashMap<String, i32>, // where every function defined so far is loaded, the library's too
+    declared: Vec<Function>,         // the signature of every function declared so far
+    included: Vec<String>,           // the headers already read
+}
+
+// what an entry turned out to be
+enum Entry {
+    Declarations,
+    Statements(Option<Type>), // and the type of the value to print, if any
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        let library = stdlib::archive();
+        let (code, data) = link::link(&library.members, &[]).expect("the C library links");
+        let mut vm = VM::new(code.text.iter().map(|&w| w as i64).collect(), 0, POOL_SIZE);
+        vm.load_data(&data);
+        vm.heap = DATA_SIZE;
+        let mut parser = Parser::new("").expect("nothing to lex");
+        parser.data = data;
+        let loaded = parser.data.len();
+        Repl { vm, parser, loaded, functions: code.functions, declared: Vec::new(), included: Vec::new() }
+    }
+
+    // compiles one entry and runs it if it is statements. Returns the syntax,
+    // type and link errors that kept it from compiling, and the session goes
+    // on as if the entry had never been typed; warnings go to vm.stderr
+    pub fn eval(&mut self, entry: &str) -> Result<(), Vec<String>> {
+        let symbols = self.parser.symbols.clone();
+        let (data, strings, declared) = (self.parser.data.len(), self.parser.strings.len(), self.declared.len());
+        let loaded = match self.compile(entry) {
+            Ok(None) => return Ok(()),
+            Ok(Some((program, kind))) => self.load(&program).map(|_| kind),
+            Err(errors) => Err(errors),
+        };
+        let kind = match loaded {
+            Ok(loaded) => loaded,
+            Err(errors) => {
+                self.parser.symbols = symbols;
+                self.parser.data.truncate(data);
+                self.parser.strings.truncate(strings);
+                self.declared.truncate(declared);
+                return Err(errors);
+            }
+        };
+        if let Entry::Statements(print) = kind {
+            self.vm.call(self.functions[REPL] as usize);
+            if let Some(ty) = print.filter(|_| self.vm.error.is_none() && self.vm.exit.is_none()) {
+                let value = self.show(self.vm.ax, ty);
+                let _ = writeln!(self.vm.stdout, "{}", value);
+            }
+            let _ = self.vm.stdout.flush();
+        }
+        Ok(())
+    }
+
+    // the exit code once the program has called exit, which ends the session
+    pub fn exited(&self) -> Option<i64> {
+        self.vm.exit
+    }
+
+    // reads entries from input until it ends or the program exits, reporting
+    // errors on vm.stderr, and returns the exit code. prompt is for a terminal:
+    // "c4> ", or "...> " while an entry goes on
+    pub fn run(&mut self, input: impl BufRead, prompt: bool) -> i64 {
+        let mut entry = String::new();
+        let mut depth = 0;
+        let mut comment = false;
+        let mut lines = input.lines();
+        while self.exited().is_none() {
+            if prompt {
+                let _ = write!(self.vm.stdout, "{}", if entry.is_empty() { "c4> " } else { "...> " });
+                let _ = self.vm.stdout.flush();
+            }
+            let line = lines.next().and_then(Result::ok);
+            if let Some(line) = &line {
+                depth += braces(line, &mut comment);
+                entry.push_str(line);
+                entry.push('\n');
+            }
+            // an unfinished entry is tried anyway once the input ends
+            if (depth > 0 || comment) && line.is_some() {
+                continue;
+            }
+            if let Err(errors) = self.eval(&entry) {
+                for e in errors {
+                    let _ = writeln!(self.vm.stderr, "{}", e);
+                }
+            }
+            if line.is_none() {
+                break;
+            }
+            entry.clear();
+            depth = 0;
+            comment = false;
+        }
+        self.exited().unwrap_or(0)
+    }
+
+    // parses and checks an entry, None if there is nothing in it
+    fn compile(&mut self, entry: &str) -> Result<Option<(Program, Entry)>, Vec<String>> {
+        let parser = &mut self.parser;
+        parser.tokens.clear();
+        lex(entry, None, &mut self.included, &mut parser.tokens).map_err(|e| vec![e])?;
+        let Some((last, line)) = parser.tokens.last().cloned() else { return Ok(None) };
+        // an expression left without its `;` is one to print
+        let print = !matches!(last, Token::Semicolon | Token::RBrace);
+        if print {
+            parser.tokens.push((Token::Semicolon, line));
+        }
+        parser.pos = 0;
+        parser.next();
+
+        // declarations and statements can take turns; only a statement last
+        // can be an expression to print
+        let mut program = Program::default();
+        let mut body = Vec::new();
+        let mut last = false;
+        while parser.tk != Token::None {
+            last = !(is_type_specifier(&parser.tk) || matches!(parser.tk, Token::Extern | Token::Enum));
+            if last {
+                body.push(parser.stmt().map_err(|e| vec![e])?);
+            } else {
+                parser.declaration(&mut program).map_err(|e| vec![e])?;
+            }
+        }
+
+        // checked and generated against every function declared before
+        let signature = |f: &Function| Function { locals: Vec::new(), body: Vec::new(), ..f.clone() };
+        self.declared.extend(program.prototypes.iter().map(signature));
+        self.declared.extend(program.functions.iter().map(signature));
+        program.prototypes = self.declared.clone();
+
+        let kind = if body.is_empty() {
+            Entry::Declarations
+        } else {
+            // the statements go in a function that returns the value to print
+            let mut ret = None;
+            if let (true, Some(Stmt::Expr(e))) = (print && last, body.last()) {
+                ret = Some(e.ty);
+                let e = e.clone();
+                *body.last_mut().unwrap() = Stmt::Return(Some(e), line);
+            }
+            let f = Function { name: REPL.to_string(), ret: ret.unwrap_or(Type::INT), params: Vec::new(), locals: Vec::new(), body, line: 1 };
+            program.functions.push(f);
+            Entry::Statements(ret)
+        };
+        let mut errors = Vec::new();
+        for d in typeck::check(&program, &typeck::Options::default()) {
+            match d.severity {
+                typeck::Severity::Error => errors.push(d.to_string()),
+                typeck::Severity::Warning => {
+                    let _ = writeln!(self.vm.stderr, "{}", d);
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Some((program, kind)))
+    }
+
+    // appends a program's code to the VM's text and the data it added to
+    // memory, and records where its functions start
+    fn load(&mut self, program: &Program) -> Result<(), Vec<String>> {
+        if self.parser.data.len() > DATA_SIZE {
+            return Err(vec![format!("out of room for globals and strings ({} bytes)", DATA_SIZE)]);
+        }
+        // the fixups link::link makes, against what is loaded already. Data
+        // addresses need none, there is only the session's data segment
+        let code = codegen::generate(program);
+        let base = self.vm.text.len() as i32;
+        let relocs: HashMap<usize, &Reloc> = code.relocs.iter().map(|(at, r)| (*at, r)).collect();
+        let mut text = code.text;
+        let mut errors = Vec::new();
+        let mut pc = 0;
+        while pc < text.len() {
+            let op = text[pc];
+            let at = pc + 1;
+            match relocs.get(&at) {
+                Some(Reloc::Data) => {}
+                Some(Reloc::Symbol(name)) => {
+                    let global = self.parser.symbols.get(name).filter(|s| s.class == Class::Glo && s.val >= 0);
+                    match (op == JSR, self.functions.get(name), global) {
+                        (true, Some(&a), _) => text[at] = a,
+                        (false, _, Some(s)) => text[at] = s.val,
+                        _ => {
+                            let e = format!("undefined symbol '{}'", name);
+                            if !errors.contains(&e) {
+                                errors.push(e);
+                            }
+                        }
+                    }
+                }
+                None if matches!(op, JMP | JSR | BZ | BNZ) => text[at] += base,
+                None => {}
+            }
+            pc += 1 + operands(op);
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        self.vm.text.extend(text.iter().map(|&w| w as i64));
+        for (name, at) in code.functions {
+            self.functions.insert(name, base + at);
+        }
+        for (i, &b) in self.parser.data.iter().enumerate().skip(self.loaded) {
+            self.vm.stack[i] = b as i64;
+        }
+        self.loaded = self.parser.data.len();
+        Ok(())
+    }
+
+    // how the value of an expression of type ty is printed
+    fn show(&self, ax: i64, ty: Type) -> String {
+        if ty == Type::CHAR.ptr_to() && ax != 0 {
+            return format!("\"{}\"", String::from_utf8_lossy(&self.vm.string_at(ax as usize)));
+        }
+        match ty.base {
+            _ if ty.is_ptr() => ax.to_string(),
+            Base::Float | Base::Double => f64::from_bits(ax as u64).to_string(),
+            Base::Char if (32..127).contains(&ax) => format!("'{}'", ax as u8 as char),
+            // ax can have bits past the type's width, which a store would drop
+            _ if ty.is_unsigned() => ((ax as u64) << (64 - 8 * ty.size()) >> (64 - 8 * ty.size())).to_string(),
+            _ => (ax << (64 - 8 * ty.size()) >> (64 - 8 * ty.size())).to_string(),
+        }
+    }
+}
+
+// how many more `{` than `}` a line has, outside strings, characters and
+// comments; comment says whether a `/*` is open, before the line and after it
+fn braces(line: &str, comment: &mut bool) -> i32 {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            _ if *comment && c == '*' && chars.clone().next() == Some('/') => {
+                chars.next();
+                *comment = false;
+            }
+            _ if *comment => {}
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '/') if chars.clone().next() == Some('/') => break,
+            (None, '/') if chars.clone().next() == Some('*') => {
+                chars.next();
+                *comment = true;
+            }
+            (None, '{') => depth += 1,
+            (None, '}') => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
@@ -0,0 +1,275 @@
+/* The runtime behind --emit=asm and --emit=c: the system calls of the VM
+ * over the same cell memory (one 64-bit cell per byte of data, the heap
+ * after the data and the stack at the top), and a main that loads the data
+ * segment and sets up main's stack like the Rust driver does.
+ *
+ * The generated code provides c4_data, c4_data_len, c4_stub and c4_start.
+ * Code that brings its own memory and main (the LLVM backend's) builds this
+ * with -DC4_NO_MAIN and calls c4_init instead. */
+
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+#define C4_POOL (2 * 1024 * 1024) /* cells, POOL_SIZE in lib.rs */
+
+long long *c4_mem;
+
+/* what --emit=asm code checks its loads, stores, pushes and returns
+ * against, through %rbx: the cells in memory, the text's length, which
+ * c4_start fills in, and the end of the heap */
+struct c4_context { void *vm; long long cells, text, heap; } c4_ctx;
+
+/* memory whose data segment, data_len cells long, is already loaded */
+void c4_init(long long *mem, long long data_len)
+{
+  c4_mem = mem;
+  c4_ctx.cells = C4_POOL;
+  c4_ctx.heap = ((data_len ? data_len : 1) + 7) & ~7LL; /* malloc never hands out NULL */
+}
+
+/* a failed check in --emit=asm code, with the interpreter's words; the
+ * kinds are the ones in native.rs */
+void c4_fault(struct c4_context *ctx, long long pc, long long kind, long long value)
+{
+  (void)ctx;
+  fflush(stdout);
+  fprintf(stderr, "fault at pc %lld: ", pc);
+  switch (kind) {
+  case 0: fprintf(stderr, "read from %lld, outside memory\n", value); break;
+  case 1: fprintf(stderr, "write to %lld, outside memory\n", value); break;
+  case 2: fprintf(stderr, "division by zero\n"); break;
+  case 3: fprintf(stderr, "jump to %lld, which is not the start of an instruction\n", value); break;
+  case 4: fprintf(stderr, "stack overflow\n"); break;
+  case 5: fprintf(stderr, "stack underflow\n"); break;
+  case 7: fprintf(stderr, "return to %lld, which is not the start of an instruction\n", value); break;
+  default: fprintf(stderr, "unknown instruction %lld\n", value);
+  }
+  exit(-1);
+}
+
+static double c4_f(long long cell) { double d; memcpy(&d, &cell, 8); return d; }
+static long long c4_cell(double d) { long long cell; memcpy(&cell, &d, 8); return cell; }
+
+/* the conversions Rust's `as` does, which saturate instead of being undefined */
+long long c4_utf(long long v) { return c4_cell((double)(unsigned long long)v); }
+
+long long c4_fti(long long v)
+{
+  double d = c4_f(v);
+  if (d != d) return 0;
+  if (d >= 9223372036854775807.0) return 0x7fffffffffffffffLL;
+  if (d <= -9223372036854775808.0) return (long long)0x8000000000000000ULL;
+  return (long long)d;
+}
+
+long long c4_ftu(long long v)
+{
+  double d = c4_f(v);
+  if (d != d || d <= 0) return 0;
+  if (d >= 18446744073709551615.0) return -1;
+  return (long long)(unsigned long long)d;
+}
+
+/* the NUL terminated string at a cell address, as bytes */
+static char *c4_string(long long addr)
+{
+  long long n = 0;
+  char *s;
+  while (c4_mem[addr + n]) n++;
+  s = malloc(n + 1);
+  for (long long i = 0; i < n; i++) s[i] = (char)c4_mem[addr + i];
+  s[n] = 0;
+  return s;
+}
+
+/* printf over cells: each conversion goes through snprintf with a full cell */
+static long long c4_format(long long fmt, long long *args, long long nargs)
+{
+  char *f = c4_string(fmt), *p = f, spec[64], buf[512];
+  long long len = 0, next = 0;
+  while (*p) {
+    if (*p != '%') { putchar(*p++); len++; continue; }
+    char *start = p++;
+    int flags;
+    while (*p && strchr("-+ 0#", *p)) p++;
+    memcpy(spec, start, p - start);
+    flags = (int)(p - start);
+    /* a '*' width or precision comes from the next argument */
+    if (*p == '*') { p++; flags += sprintf(spec + flags, "%lld", next < nargs ? args[next++] : 0); }
+    while (*p >= '0' && *p <= '9') spec[flags++] = *p++;
+    if (*p == '.') {
+      spec[flags++] = *p++;
+      if (*p == '*') {
+        long long prec = next < nargs ? args[next++] : 0;
+        p++;
+        if (prec < 0) flags--; /* taken as if it were left out */
+        else flags += sprintf(spec + flags, "%lld", prec);
+      }
+      while (*p >= '0' && *p <= '9') spec[flags++] = *p++;
+    }
+    while (*p && strchr("hlLqjzt", *p)) p++;
+    if (!*p) break;
+    char conv = *p++;
+    if (conv == '%') { putchar('%'); len++; continue; }
+    long long arg = next < nargs ? args[next++] : 0;
+    int n;
+    spec[flags] = 0;
+    if (strchr("diuxXo", conv)) {
+      strcat(spec, "ll");
+      spec[flags + 2] = conv; spec[flags + 3] = 0;
+      n = snprintf(buf, sizeof buf, spec, arg);
+    } else if (strchr("fFeEgG", conv)) {
+      spec[flags] = conv; spec[flags + 1] = 0;
+      n = snprintf(buf, sizeof buf, spec, c4_f(arg));
+    } else if (conv == 'c') {
+      spec[flags] = conv; spec[flags + 1] = 0;
+      n = snprintf(buf, sizeof buf, spec, (unsigned char)arg);
+    } else if (conv == 's') {
+      char *s = c4_string(arg);
+      spec[flags] = conv; spec[flags + 1] = 0;
+      n = snprintf(buf, sizeof buf, spec, s);
+      free(s);
+    } else if (conv == 'p') {
+      n = snprintf(buf, sizeof buf, "0x%llx", arg);
+    } else {
+      /* unknown conversion, printed back verbatim */
+      n = snprintf(buf, sizeof buf, "%%%c", conv);
+    }
+    if (n > (int)sizeof buf - 1) n = sizeof buf - 1;
+    fwrite(buf, 1, n, stdout);
+    len += n;
+  }
+  free(f);
+  fflush(stdout);
+  return len;
+}
+
+/* the system calls of the C and LLVM backends */
+long long c4_system(long long op, long long argc, long long sp, long long ax)
+{
+  long long *s = c4_mem + sp;
+  switch (op) {
+  case 30: { /* OPEN: for writing if flags isn't 0 */
+    char *path = c4_string(s[1]);
+    ax = s[0] ? open(path, O_WRONLY | O_CREAT | O_TRUNC, 0644) : open(path, O_RDONLY);
+    free(path);
+    return ax;
+  }
+  case 31: { /* READ */
+    long long buf = s[1], n = s[0];
+    unsigned char *bytes = malloc(n > 0 ? n : 1);
+    ax = read(s[2], bytes, n);
+    for (long long i = 0; i < ax; i++) c4_mem[buf + i] = bytes[i];
+    free(bytes);
+    return ax;
+  }
+  case 32: /* CLOS */
+    return close(s[0]);
+  case 33: { /* PRTF */
+    long long args[64], t = sp + argc;
+    for (long long i = 2; i <= argc && i - 2 < 64; i++) args[i - 2] = c4_mem[t - i];
+    return c4_format(c4_mem[t - 1], args, argc > 1 ? argc - 1 : 0);
+  }
+  case 34: { /* MALC */
+    long long n = s[0] < 0 ? 0 : s[0];
+    if (c4_ctx.heap + n < sp) {
+      ax = c4_ctx.heap;
+      c4_ctx.heap = (c4_ctx.heap + n + 7) & ~7LL;
+      return ax;
+    }
+    return 0;
+  }
+  case 35: /* FREE */
+    return ax;
+  case 36: /* MSET */
+    for (long long i = 0; i < s[0]; i++) c4_mem[s[2] + i] = s[1] & 0xFF;
+    return s[2];
+  case 37: /* MCMP */
+    for (long long i = 0; i < s[0]; i++) {
+      long long d = (c4_mem[s[2] + i] & 0xFF) - (c4_mem[s[1] + i] & 0xFF);
+      if (d) return d;
+    }
+    return 0;
+  case 66: { /* WRIT */
+    long long buf = s[1], n = s[0];
+    unsigned char *bytes = malloc(n > 0 ? n : 1);
+    for (long long i = 0; i < n; i++) bytes[i] = c4_mem[buf + i];
+    fflush(stdout); /* after what printf buffered */
+    ax = write(s[2], bytes, n);
+    free(bytes);
+    return ax;
+  }
+  default: /* EXIT: what main returned is the exit code, like c4 */
+    printf("Program exited with value: %lld\n", ax);
+    exit((int)ax);
+  }
+}
+
+/* the same for --emit=asm code, which leaves when stop, in rdx, is set;
+ * here EXIT never returns, so it never is */
+struct c4_returned { long long ax, stop; };
+
+struct c4_returned c4_syscall(struct c4_context *ctx, long long op, long long argc, long long sp, long long ax)
+{
+  struct c4_returned r;
+  (void)ctx;
+  r.ax = c4_system(op, argc, sp, ax);
+  r.stop = 0;
+  return r;
+}
+
+/* copies the arguments to the top of memory, below *sp, as NUL terminated
+ * strings with a NULL terminated char* array after them, like the Rust
+ * driver's load_args; returns the array's address and leaves *sp below it */
+long long c4_args(int argc, char **argv, long long *sp)
+{
+  long long *addrs = malloc((argc + 1) * sizeof(long long));
+  for (int i = 0; i < argc; i++) {
+    long long n = strlen(argv[i]);
+    *sp -= n + 1;
+    addrs[i] = *sp;
+    for (long long j = 0; j <= n; j++) c4_mem[*sp + j] = (unsigned char)argv[i][j];
+  }
+  addrs[argc] = 0;
+  /* a char* is sizeof(int) wide, like every pointer */
+  *sp = (*sp - (argc + 1) * 4) & ~7LL;
+  for (int i = 0; i <= argc; i++) c4_mem[*sp + i * 4] = addrs[i];
+  free(addrs);
+  return *sp;
+}
+
+/* one entry point per system call, taking what c4_system does */
+long long c4_open(long long sp, long long argc, long long ax) { return c4_system(30, argc, sp, ax); }
+long long c4_read(long long sp, long long argc, long long ax) { return c4_system(31, argc, sp, ax); }
+long long c4_close(long long sp, long long argc, long long ax) { return c4_system(32, argc, sp, ax); }
+long long c4_printf(long long sp, long long argc, long long ax) { return c4_system(33, argc, sp, ax); }
+long long c4_malloc(long long sp, long long argc, long long ax) { return c4_system(34, argc, sp, ax); }
+long long c4_free(long long sp, long long argc, long long ax) { return c4_system(35, argc, sp, ax); }
+long long c4_memset(long long sp, long long argc, long long ax) { return c4_system(36, argc, sp, ax); }
+long long c4_memcmp(long long sp, long long argc, long long ax) { return c4_system(37, argc, sp, ax); }
+long long c4_exit(long long sp, long long argc, long long ax) { return c4_system(38, argc, sp, ax); }
+long long c4_write(long long sp, long long argc, long long ax) { return c4_system(66, argc, sp, ax); }
+
+#ifndef C4_NO_MAIN
+extern const unsigned char c4_data[];
+extern const long long c4_data_len;
+extern const long long c4_stub;
+long long c4_start(long long *mem, long long sp);
+
+int main(int argc, char **argv)
+{
+  long long sp = C4_POOL, args;
+  long long *mem = calloc(C4_POOL, sizeof(long long));
+  for (long long i = 0; i < c4_data_len; i++) mem[i] = c4_data[i];
+  c4_init(mem, c4_data_len);
+  args = c4_args(argc, argv, &sp);
+  c4_mem[--sp] = argc;
+  c4_mem[--sp] = args;
+  c4_mem[--sp] = c4_stub;
+  c4_start(c4_mem, sp);
+  return 0;
+}
+#endif
@@ -0,0 +1,282 @@
+// Runs a module from --emit=wasm under node: node src/runtime.mjs prog.wasm [args...]
+// main gets the module's path and the arguments as argc and argv, and what it
+// returns is the exit code.
+//
+// Provides the "c4" imports, the VM's system calls over the module's memory
+// of 64-bit cells. Each one gets (sp, argc, ax) and returns the new ax.
+
+import { readFileSync, openSync, readSync, closeSync, writeSync } from 'node:fs';
+
+let cells, heap;
+const files = new Set();
+
+class Exit extends Error {
+  constructor(code) {
+    super('exit');
+    this.code = code;
+  }
+}
+
+const bits = new BigInt64Array(1);
+const float = new Float64Array(bits.buffer);
+const toF64 = (cell) => { bits[0] = cell; return float[0]; };
+
+// the NUL terminated string starting at addr, one char per cell
+function stringAt(addr) {
+  let s = '';
+  for (let i = Number(addr); cells[i] !== 0n; i++) s += String.fromCharCode(Number(cells[i] & 0xFFn));
+  return s;
+}
+
+const withPrecision = (digits, prec) =>
+  prec === 0 && digits === '0' ? '' : prec !== null && digits.length < prec ? '0'.repeat(prec - digits.length) + digits : digits;
+
+// v * 10^k rounded to an integer, half to even on v's exact binary value the
+// way the VM and glibc round; toFixed and toExponential round halves up
+function scaled(v, k) {
+  const view = new DataView(new ArrayBuffer(8));
+  view.setFloat64(0, v);
+  const bits = view.getBigUint64(0);
+  const exp = Number((bits >> 52n) & 0x7FFn);
+  const frac = bits & ((1n << 52n) - 1n);
+  let num = exp === 0 ? frac : frac | (1n << 52n);
+  let den = 1n;
+  const e = (exp === 0 ? 1 : exp) - 1075;
+  if (e >= 0) num <<= BigInt(e); else den <<= BigInt(-e);
+  if (k >= 0) num *= 10n ** BigInt(k); else den *= 10n ** BigInt(-k);
+  let q = num / den;
+  const twice = (num % den) * 2n;
+  if (twice > den || (twice === den && (q & 1n) === 1n)) q++;
+  return q;
+}
+
+// d.ddde+xx with prec digits after the point and at least two exponent digits
+function expForm(v, prec) {
+  let e = v === 0 ? 0 : Math.floor(Math.log10(v));
+  let digits = scaled(v, prec - e);
+  // log10 can be one off, and rounding can carry into another digit
+  if (v !== 0 && digits >= 10n ** BigInt(prec + 1)) digits = scaled(v, prec - ++e);
+  else if (v !== 0 && digits < 10n ** BigInt(prec)) digits = scaled(v, prec - --e);
+  const d = digits.toString().padStart(prec + 1, '0');
+  const mantissa = prec > 0 ? `${d[0]}.${d.slice(1)}` : d;
+  return `${mantissa}e${e < 0 ? '-' : '+'}${String(Math.abs(e)).padStart(2, '0')}`;
+}
+
+function fixed(v, prec) {
+  const d = scaled(v, prec).toString().padStart(prec + 1, '0');
+  return prec > 0 ? `${d.slice(0, -prec)}.${d.slice(-prec)}` : d;
+}
+
+function stripZeros(s) {
+  const i = s.indexOf('e');
+  let [num, exp] = i < 0 ? [s, ''] : [s.slice(0, i), s.slice(i)];
+  if (num.includes('.')) num = num.replace(/0+$/, '').replace(/\.$/, '');
+  return num + exp;
+}
+
+// formats a non-negative double for %f, %e and %g, like the VM
+function formatFloat(v, conv, spec) {
+  let s;
+  if (Number.isNaN(v)) s = 'nan';
+  else if (!Number.isFinite(v)) s = 'inf';
+  else {
+    const prec = spec.prec ?? 6;
+    const c = conv.toLowerCase();
+    if (c === 'f') s = fixed(v, prec);
+    else if (c === 'e') s = expForm(v, prec);
+    else {
+      const p = prec === 0 ? 1 : prec;
+      const exp = v === 0 ? 0 : Number(expForm(v, p - 1).split('e')[1]);
+      s = exp < -4 || exp >= p ? expForm(v, p - 1) : fixed(v, p - 1 - exp);
+      if (!spec.alt) s = stripZeros(s);
+    }
+  }
+  return conv === conv.toUpperCase() ? s.toUpperCase() : s;
+}
+
+// printf with the flags, width and precision the VM understands
+function format(fmt, args) {
+  let out = '';
+  let next = 0;
+  let i = 0;
+  while (i < fmt.length) {
+    if (fmt[i] !== '%') { out += fmt[i++]; continue; }
+    i++;
+    const spec = { left: false, plus: false, space: false, zero: false, alt: false, width: 0, prec: null };
+    for (; i < fmt.length && '-+ 0#'.includes(fmt[i]); i++) {
+      if (fmt[i] === '-') spec.left = true;
+      else if (fmt[i] === '+') spec.plus = true;
+      else if (fmt[i] === ' ') spec.space = true;
+      else if (fmt[i] === '0') spec.zero = true;
+      else spec.alt = true;
+    }
+    // a '*' width or precision comes from the next argument
+    if (fmt[i] === '*') {
+      const width = Number(next < args.length ? args[next++] : 0n);
+      if (width < 0) spec.left = true;
+      spec.width = Math.abs(width);
+      i++;
+    }
+    for (; i < fmt.length && fmt[i] >= '0' && fmt[i] <= '9'; i++) spec.width = spec.width * 10 + Number(fmt[i]);
+    if (fmt[i] === '.') {
+      spec.prec = 0;
+      if (fmt[++i] === '*') {
+        spec.prec = Number(next < args.length ? args[next++] : 0n);
+        i++;
+      }
+      for (; i < fmt.length && fmt[i] >= '0' && fmt[i] <= '9'; i++) spec.prec = spec.prec * 10 + Number(fmt[i]);
+      if (spec.prec < 0) spec.prec = null;
+    }
+    while (i < fmt.length && 'hlLqjzt'.includes(fmt[i])) i++;
+    if (i === fmt.length) break;
+    const conv = fmt[i++];
+    if (conv === '%') { out += '%'; continue; }
+    const arg = next < args.length ? args[next++] : 0n;
+    const u = BigInt.asUintN(64, arg);
+    const sign = (negative) => (negative ? '-' : spec.plus ? '+' : spec.space ? ' ' : '');
+    const alt = (prefix, digits) => (spec.alt && arg !== 0n && !digits.startsWith('0') ? prefix + digits : digits);
+    let pre = '', body;
+    switch (conv) {
+      case 'd': case 'i':
+        pre = sign(arg < 0n);
+        body = withPrecision((arg < 0n ? -arg : arg).toString(), spec.prec);
+        break;
+      case 'u': body = withPrecision(u.toString(), spec.prec); break;
+      case 'x': body = alt('0x', withPrecision(u.toString(16), spec.prec)); break;
+      case 'X': body = alt('0X', withPrecision(u.toString(16).toUpperCase(), spec.prec)); break;
+      case 'o': body = alt('0', withPrecision(u.toString(8), spec.prec)); break;
+      case 'p': body = '0x' + u.toString(16); break;
+      case 'c': body = String.fromCharCode(Number(arg & 0xFFn)); break;
+      case 's':
+        body = stringAt(arg);
+        if (spec.prec !== null) body = body.slice(0, spec.prec);
+        break;
+      case 'f': case 'F': case 'e': case 'E': case 'g': case 'G': {
+        const v = toF64(arg);
+        pre = sign(v < 0 || Object.is(v, -0));
+        body = formatFloat(Math.abs(v), conv, spec);
+        break;
+      }
+      default:
+        // unknown conversion, printed back verbatim
+        out += '%' + conv;
+        continue;
+    }
+    const pad = Math.max(0, spec.width - pre.length - body.length);
+    const numeric = conv !== 'c' && conv !== 's';
+    const zeroOk = numeric && (spec.prec === null || 'fFeEgG'.includes(conv));
+    if (spec.left) out += pre + body + ' '.repeat(pad);
+    else if (spec.zero && zeroOk) out += pre + '0'.repeat(pad) + body;
+    else out += ' '.repeat(pad) + pre + body;
+  }
+  return out;
+}
+
+const at = (sp, k) => cells[Number(sp) + k];
+
+const imports = {
+  c4: {
+    open(sp) {
+      try {
+        const fd = openSync(stringAt(at(sp, 1)), at(sp, 0) ? 'w' : 'r');
+        files.add(fd);
+        return BigInt(fd);
+      } catch {
+        return -1n;
+      }
+    },
+    read(sp) {
+      const [fd, buf, n] = [at(sp, 2), Number(at(sp, 1)), Number(at(sp, 0))];
+      const bytes = Buffer.alloc(n);
+      try {
+        const got = readSync(Number(fd), bytes, 0, n, null);
+        for (let i = 0; i < got; i++) cells[buf + i] = BigInt(bytes[i]);
+        return BigInt(got);
+      } catch {
+        return -1n;
+      }
+    },
+    close(sp) {
+      const fd = Number(at(sp, 0));
+      if (!files.delete(fd)) return -1n;
+      closeSync(fd);
+      return 0n;
+    },
+    printf(sp, argc) {
+      const t = Number(sp) + Number(argc);
+      const args = [];
+      for (let i = 2; i <= argc; i++) args.push(cells[t - i]);
+      const out = Buffer.from(format(stringAt(cells[t - 1]), args), 'utf8');
+      writeSync(1, out);
+      return BigInt(out.length);
+    },
+    malloc(sp) {
+      const n = at(sp, 0) < 0n ? 0n : at(sp, 0);
+      if (heap + n >= sp) return 0n;
+      const p = heap;
+      heap = (heap + n + 7n) & ~7n;
+      return p;
+    },
+    free(sp, argc, ax) {
+      return ax;
+    },
+    memset(sp) {
+      const [p, c, n] = [Number(at(sp, 2)), at(sp, 1) & 0xFFn, Number(at(sp, 0))];
+      cells.fill(c, p, p + n);
+      return BigInt(p);
+    },
+    memcmp(sp) {
+      const [p, q, n] = [Number(at(sp, 2)), Number(at(sp, 1)), Number(at(sp, 0))];
+      for (let i = 0; i < n; i++) {
+        const d = (cells[p + i] & 0xFFn) - (cells[q + i] & 0xFFn);
+        if (d !== 0n) return d;
+      }
+      return 0n;
+    },
+    write(sp) {
+      const [fd, buf, n] = [Number(at(sp, 2)), Number(at(sp, 1)), Number(at(sp, 0))];
+      if (fd !== 1 && fd !== 2 && !files.has(fd)) return -1n;
+      const bytes = Buffer.alloc(n);
+      for (let i = 0; i < n; i++) bytes[i] = Number(cells[buf + i] & 0xFFn);
+      try {
+        return BigInt(writeSync(fd, bytes));
+      } catch {
+        return -1n;
+      }
+    },
+    exit(sp, argc, ax) {
+      writeSync(1, `Program exited with value: ${ax}\n`);
+      throw new Exit(Number(BigInt.asIntN(32, ax)));
+    },
+  },
+};
+
+const { instance } = await WebAssembly.instantiate(readFileSync(process.argv[2]), imports);
+cells = new BigInt64Array(instance.exports.memory.buffer);
+heap = ((instance.exports.data_len.value || 1n) + 7n) & ~7n; // malloc never hands out NULL
+// the arguments go to the top of memory as NUL terminated strings with a NULL
+// terminated char* array after them, like the Rust driver's load_args
+function loadArgs(args) {
+  let sp = cells.length;
+  const addrs = [];
+  for (const arg of args) {
+    const bytes = Buffer.from(arg, 'utf8');
+    sp -= bytes.length + 1;
+    addrs.push(BigInt(sp));
+    bytes.forEach((b, i) => { cells[sp + i] = BigInt(b); });
+    cells[sp + bytes.length] = 0n;
+  }
+  addrs.push(0n);
+  sp = (sp - addrs.length * 4) & ~7; // a char* is sizeof(int) wide, like every pointer
+  addrs.forEach((addr, i) => { cells[sp + i * 4] = addr; });
+  return sp;
+}
+
+const args = process.argv.slice(2);
+const argv = loadArgs(args);
+try {
+  instance.exports.run(BigInt(argv), BigInt(args.length), BigInt(argv));
+} catch (e) {
+  if (!(e instanceof Exit)) throw e;
+  process.exitCode = e.code;
+}
@@ -0,0 +1,80 @@
+// Limits for running programs nobody has reviewed. By default the VM runs
+// like c4 does: until the program exits, with as much of its memory as it
+// wants and the host's files open to it. Each limit here stops the program
+// with RuntimeError::LimitExceeded when it trips, and the open policy decides
+// which paths OPEN may see at all; a path it refuses opens like a missing
+// file would, returning -1.
+
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    pub cycles: Option<u64>,   // instructions executed
+    pub heap: Option<usize>,   // bytes malloc hands out, freed or not
+    pub depth: Option<usize>,  // calls active at once below main
+    pub output: Option<usize>, // bytes printf and write put on stdout
+    pub open: OpenPolicy,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum OpenPolicy {
+    #[default]
+    Host,              // any path, like c4
+    Deny,              // no file at all
+    Allow(Vec<PathBuf>), // only paths under one of these, compared as written
+    Root(PathBuf),     // paths are looked up under this directory instead of /
+}
+
+// the limit a program ran into, and what it was set to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Cycles(u64),
+    Heap(usize),
+    Depth(usize),
+    Output(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Cycles(max) => write!(f, "ran more than {} cycles", max),
+            Limit::Heap(max) => write!(f, "allocated more than {} bytes", max),
+            Limit::Depth(max) => write!(f, "nested calls more than {} deep", max),
+            Limit::Output(max) => write!(f, "printed more than {} bytes", max),
+        }
+    }
+}
+
+// the path without `.` and with every `..` resolved, or None if a `..` would
+// climb above where it starts
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::ParentDir if !out.pop() => return None,
+            Component::ParentDir => {}
+            Component::CurDir => {}
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+impl OpenPolicy {
+    // the host path a program's path opens, if the policy lets it
+    pub fn resolve(&self, path: &str) -> Option<PathBuf> {
+        match self {
+            OpenPolicy::Host => Some(PathBuf::from(path)),
+            OpenPolicy::Deny => None,
+            OpenPolicy::Allow(dirs) => {
+                let path = normalize(Path::new(path))?;
+                dirs.iter().filter_map(|d| normalize(d)).any(|d| path.starts_with(d)).then_some(path)
+            }
+            OpenPolicy::Root(root) => {
+                let path = normalize(&Path::new("/").join(path))?;
+                Some(root.join(path.strip_prefix("/").ok()?))
+            }
+        }
+    }
+}
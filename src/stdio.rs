@@ -0,0 +1,46 @@
+// The VM's standard streams are plain Read and Write trait objects, the
+// process's own unless the embedder swaps them (VM::stdin, stdout, stderr).
+// Capture is a sink to swap in for looking at a program's output afterwards:
+//
+//   let out = Capture::new();
+//   vm.stdout = Box::new(out.clone());
+//   vm.run();
+//   assert_eq!(out.text(), "hello\nProgram exited with value: 0\n");
+//
+// and any Read does for stdin, e.g. Box::new(&b"input"[..]).
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// everything written to it, shared between clones
+#[derive(Debug, Clone, Default)]
+pub struct Capture {
+    bytes: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Capture::default()
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.bytes.borrow().clone()
+    }
+
+    // the bytes as text, with anything that isn't UTF-8 replaced
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.bytes.borrow()).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+// The C library every program gets: the parts of string.h, ctype.h and
+// stdlib.h that aren't system calls, written in the C c4 compiles and
+// bundled into the binary. The headers in libc/include resolve
+// #include <...>; the sources in libc/src hold one function each, like
+// musl's, so a program that defines its own strlen still links with the
+// library's strcpy.
+
+use crate::link::Archive;
+
+const HEADERS: [(&str, &str); 4] = [
+    ("ctype.h", include_str!("../libc/include/ctype.h")),
+    ("stdio.h", include_str!("../libc/include/stdio.h")),
+    ("stdlib.h", include_str!("../libc/include/stdlib.h")),
+    ("string.h", include_str!("../libc/include/string.h")),
+];
+
+macro_rules! sources {
+    ($($name:literal),* $(,)?) => {
+        [$(($name, include_str!(concat!("../libc/src/", $name)))),*]
+    };
+}
+
+const SOURCES: [(&str, &str); 27] = sources![
+    "isalnum.c", "isalpha.c", "isdigit.c", "islower.c", "isprint.c", "ispunct.c", "isspace.c",
+    "isupper.c", "isxdigit.c", "tolower.c", "toupper.c",
+    "memcpy.c", "strcat.c", "strchr.c", "strcmp.c", "strcpy.c", "strdup.c", "strlen.c",
+    "strncmp.c", "strncpy.c", "strrchr.c", "strstr.c",
+    "abs.c", "atoi.c", "calloc.c", "rand.c", "strtol.c",
+];
+
+// the text of a bundled header
+pub fn header(name: &str) -> Option<&'static str> {
+    HEADERS.iter().find(|&&(n, _)| n == name).map(|&(_, text)| text)
+}
+
+// the library compiled into an archive, its members named like libc(strlen.c)
+pub fn archive() -> Archive {
+    let members = SOURCES.iter().map(|&(name, source)| {
+        crate::compile_object(&format!("libc({})", name), source).unwrap_or_else(|errors| panic!("libc/src/{}: {}", name, errors.join("; ")))
+    });
+    Archive { members: members.collect() }
+}
@@ -0,0 +1,356 @@
+// The semantic pass. The parser only tracks types as far as code generation
+// needs them, so it happily assigns a char* to an int. This walks the typed
+// AST afterwards and reports what a C compiler would: pointer/integer mixups,
+// bad pointer arithmetic, wrong argument counts and, with -Wall, the more
+// pedantic warnings. Nothing here changes the generated code.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::{BinOp, Expr, ExprKind, Function, Program, Stmt, UnOp};
+use crate::{Base, Type};
+use crate::{CLOS, EXIT, FREE, MALC, MCMP, MSET, OPEN, PRTF, READ, WRIT};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub line: i32,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {}: {}", self.line, kind, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    pub wall: bool, // -Wall: also report the warnings c programs usually get away with
+}
+
+// checks the whole program and returns its diagnostics in source order
+pub fn check(program: &Program, opts: &Options) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        opts: *opts,
+        diags: Vec::new(),
+        functions: HashMap::new(),
+        ret: Type::INT,
+        used: HashSet::new(),
+    };
+    let mut returns = HashMap::new();
+    for f in program.prototypes.iter().chain(&program.functions) {
+        let params: Vec<Type> = f.params.iter().map(|p| p.ty).collect();
+        let before = checker.functions.insert(f.name.clone(), params.clone());
+        if before.is_some_and(|b| b != params) || returns.insert(f.name.clone(), f.ret).is_some_and(|r| r != f.ret) {
+            checker.error(f.line, format!("conflicting types for '{}'", f.name));
+        }
+    }
+    for f in &program.functions {
+        checker.function(f);
+    }
+    checker.diags.sort_by_key(|d| d.line);
+    checker.diags
+}
+
+// the number of arguments each system call takes, None if it's variadic
+fn sys_arity(op: i32) -> Option<usize> {
+    match op {
+        OPEN => Some(2),
+        READ | MSET | MCMP | WRIT => Some(3),
+        CLOS | MALC | FREE | EXIT => Some(1),
+        _ => None,
+    }
+}
+
+fn is_integer(t: Type) -> bool {
+    !t.is_ptr() && !t.is_float()
+}
+
+// whether storing an integer value in the narrower integer type `to` can
+// drop bits. A constant that fits doesn't, so `c = 'a'` stays quiet
+fn narrows(to: Type, value: &Expr) -> bool {
+    if !is_integer(value.ty) || to.size() >= value.ty.size() {
+        return false;
+    }
+    let bits = 8 * to.size() as u32;
+    match value.kind {
+        ExprKind::Num(v) if to.is_unsigned() => !(0..1i64 << bits).contains(&v),
+        ExprKind::Num(v) => !(-(1i64 << (bits - 1))..1i64 << (bits - 1)).contains(&v),
+        _ => true,
+    }
+}
+
+// "1 argument", "2 arguments"
+fn count(n: usize, noun: &str) -> String {
+    format!("{} {}{}", n, noun, if n == 1 { "" } else { "s" })
+}
+
+// "1 was", "2 were"
+fn were(n: usize) -> String {
+    format!("{} {}", n, if n == 1 { "was" } else { "were" })
+}
+
+// c4 has no void, so char* doubles as the generic pointer
+fn is_generic_ptr(t: Type) -> bool {
+    t.ptr == 1 && t.base == Base::Char
+}
+
+fn op_name(op: BinOp) -> &'static str {
+    match op {
+        BinOp::LogOr => "||",
+        BinOp::LogAnd => "&&",
+        BinOp::Or => "|",
+        BinOp::Xor => "^",
+        BinOp::And => "&",
+        BinOp::Eq => "==",
+        BinOp::Ne => "!=",
+        BinOp::Lt => "<",
+        BinOp::Gt => ">",
+        BinOp::Le => "<=",
+        BinOp::Ge => ">=",
+        BinOp::Shl => "<<",
+        BinOp::Shr => ">>",
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        BinOp::Mod => "%",
+    }
+}
+
+struct Checker {
+    opts: Options,
+    diags: Vec<Diagnostic>,
+    functions: HashMap<String, Vec<Type>>, // parameter types of every declared function
+    ret: Type,                             // return type of the function being checked
+    used: HashSet<String>,                 // locals referenced so far in that function
+}
+
+impl Checker {
+    fn warn(&mut self, line: i32, message: String) {
+        self.diags.push(Diagnostic { line, severity: Severity::Warning, message });
+    }
+
+    fn error(&mut self, line: i32, message: String) {
+        self.diags.push(Diagnostic { line, severity: Severity::Error, message });
+    }
+
+    fn function(&mut self, f: &Function) {
+        self.ret = f.ret;
+        self.used.clear();
+        for s in &f.body {
+            self.stmt(s);
+        }
+        if self.opts.wall {
+            for local in &f.locals {
+                if !self.used.contains(&local.name) {
+                    self.warn(local.line, format!("unused variable '{}'", local.name));
+                }
+            }
+        }
+    }
+
+    fn stmt(&mut self, s: &Stmt) {
+        match s {
+            Stmt::If(cond, then, els) => {
+                self.expr(cond);
+                self.stmt(then);
+                if let Some(els) = els {
+                    self.stmt(els);
+                }
+            }
+            Stmt::While(cond, body) => {
+                self.expr(cond);
+                self.stmt(body);
+            }
+            Stmt::DoWhile(body, cond) => {
+                self.stmt(body);
+                self.expr(cond);
+            }
+            Stmt::Return(value, _) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                    self.convertible(self.ret, value, "return");
+                }
+            }
+            Stmt::Block(body) => {
+                for s in body {
+                    self.stmt(s);
+                }
+            }
+            Stmt::Expr(e) => self.expr(e),
+            Stmt::Empty => {}
+        }
+    }
+
+    // checks that `value` can be implicitly converted to `to`, as it is in
+    // assignments, returns and argument passing
+    fn convertible(&mut self, to: Type, value: &Expr, what: &str) {
+        let from = value.ty;
+        let line = value.line;
+        if (to.is_ptr() && from.is_float()) || (to.is_float() && from.is_ptr()) {
+            self.error(line, format!("incompatible types in {} ('{}' from '{}')", what, to, from));
+        } else if to.is_ptr() && is_integer(from) {
+            if !value.is_null_constant() {
+                self.warn(line, format!("{} makes pointer from integer without a cast", what));
+            }
+        } else if is_integer(to) && from.is_ptr() {
+            self.warn(line, format!("{} makes integer from pointer without a cast", what));
+        } else if to.is_ptr() && from.is_ptr() {
+            if to != from && !is_generic_ptr(to) && !is_generic_ptr(from) {
+                self.warn(line, format!("incompatible pointer types in {} ('{}' from '{}')", what, to, from));
+            }
+        } else if self.opts.wall && is_integer(to) && (from.is_float() || narrows(to, value)) {
+            self.warn(line, format!("conversion from '{}' to '{}' may change value", from, to));
+        }
+    }
+
+    fn expr(&mut self, e: &Expr) {
+        match &e.kind {
+            ExprKind::Num(_) | ExprKind::Float(_) | ExprKind::Str(_) | ExprKind::Global(..) | ExprKind::Sizeof(_) => {}
+            ExprKind::Local(name, _) => {
+                self.used.insert(name.clone());
+            }
+            ExprKind::Call { name, sys, args } => {
+                for a in args {
+                    self.expr(a);
+                }
+                match sys {
+                    Some(op) => {
+                        let bad = match sys_arity(*op) {
+                            Some(n) => args.len() != n,
+                            None => args.is_empty(),
+                        };
+                        if bad {
+                            self.error(e.line, format!("wrong number of arguments to '{}'", name));
+                        }
+                        // printf's format string is the only argument with a fixed type
+                        if *op == PRTF && !args.is_empty() {
+                            self.convertible(Type::CHAR.ptr_to(), &args[0], "passing argument 1 of 'printf'");
+                        }
+                    }
+                    None => {
+                        let params = self.functions.get(name).cloned().unwrap_or_default();
+                        if params.len() != args.len() {
+                            self.error(
+                                e.line,
+                                format!("'{}' takes {} but {} given", name, count(params.len(), "argument"), were(args.len())),
+                            );
+                        }
+                        for (i, (p, a)) in params.iter().zip(args).enumerate() {
+                            self.convertible(*p, a, &format!("passing argument {} of '{}'", i + 1, name));
+                        }
+                    }
+                }
+            }
+            ExprKind::Unary(op, inner) => {
+                self.expr(inner);
+                let bad = match op {
+                    UnOp::Plus | UnOp::Neg => inner.ty.is_ptr(),
+                    UnOp::BitNot => !is_integer(inner.ty),
+                    UnOp::Not => false,
+                };
+                if bad {
+                    self.error(e.line, format!("wrong type argument to unary operator ('{}')", inner.ty));
+                }
+            }
+            ExprKind::Binary(op, lhs, rhs) => {
+                self.expr(lhs);
+                self.expr(rhs);
+                self.binary(e.line, *op, lhs, rhs);
+            }
+            ExprKind::Assign(lhs, rhs) => {
+                self.expr(lhs);
+                self.expr(rhs);
+                self.convertible(lhs.ty, rhs, "assignment");
+            }
+            ExprKind::Cond(cond, then, els) => {
+                self.expr(cond);
+                self.expr(then);
+                self.expr(els);
+                let mixed = (then.ty.is_ptr() && is_integer(els.ty) && !els.is_null_constant())
+                    || (els.ty.is_ptr() && is_integer(then.ty) && !then.is_null_constant());
+                if mixed {
+                    self.warn(e.line, "pointer/integer type mismatch in conditional expression".to_string());
+                }
+            }
+            ExprKind::Cast(inner) => {
+                self.expr(inner);
+                if (e.ty.is_ptr() && inner.ty.is_float()) || (e.ty.is_float() && inner.ty.is_ptr()) {
+                    self.error(e.line, format!("cannot cast '{}' to '{}'", inner.ty, e.ty));
+                }
+            }
+            ExprKind::Deref(inner) | ExprKind::AddrOf(inner) | ExprKind::IncDec(_, inner) => self.expr(inner),
+            ExprKind::Index(base, index) => {
+                self.expr(base);
+                self.expr(index);
+                if !is_integer(index.ty) {
+                    self.error(e.line, "array subscript is not an integer".to_string());
+                }
+            }
+        }
+    }
+
+    fn binary(&mut self, line: i32, op: BinOp, lhs: &Expr, rhs: &Expr) {
+        let (l, r) = (lhs.ty, rhs.ty);
+        match op {
+            BinOp::LogOr | BinOp::LogAnd => {}
+            BinOp::Add => {
+                if (l.is_ptr() && !is_integer(r)) || (r.is_ptr() && !is_integer(l)) {
+                    self.error(line, format!("invalid operands to binary + ('{}' and '{}')", l, r));
+                }
+            }
+            BinOp::Sub => {
+                let ok = if l.is_ptr() { is_integer(r) || l == r } else { !r.is_ptr() };
+                if !ok {
+                    self.error(line, format!("invalid operands to binary - ('{}' and '{}')", l, r));
+                }
+            }
+            BinOp::Mul | BinOp::Div => {
+                if l.is_ptr() || r.is_ptr() {
+                    self.error(line, format!("invalid operands to binary {} ('{}' and '{}')", op_name(op), l, r));
+                }
+            }
+            BinOp::Or | BinOp::Xor | BinOp::And | BinOp::Shl | BinOp::Shr | BinOp::Mod => {
+                if !is_integer(l) || !is_integer(r) {
+                    self.error(line, format!("invalid operands to binary {} ('{}' and '{}')", op_name(op), l, r));
+                }
+            }
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge => {
+                self.comparison(line, lhs, rhs);
+            }
+        }
+    }
+
+    fn comparison(&mut self, line: i32, lhs: &Expr, rhs: &Expr) {
+        let (l, r) = (lhs.ty, rhs.ty);
+        if (l.is_ptr() && r.is_float()) || (l.is_float() && r.is_ptr()) {
+            self.error(line, format!("invalid comparison between '{}' and '{}'", l, r));
+        } else if (l.is_ptr() && is_integer(r) && !rhs.is_null_constant())
+            || (r.is_ptr() && is_integer(l) && !lhs.is_null_constant())
+        {
+            self.warn(line, "comparison between pointer and integer".to_string());
+        } else if l.is_ptr() && r.is_ptr() && l != r && !is_generic_ptr(l) && !is_generic_ptr(r) {
+            self.warn(line, format!("comparison of distinct pointer types ('{}' and '{}')", l, r));
+        } else if self.opts.wall && is_integer(l) && is_integer(r) {
+            // only a mix that actually ends up compared as unsigned is suspicious,
+            // and a non-negative constant on the signed side is always fine
+            let signed_side = |e: &Expr| !e.ty.promote().is_unsigned() && !matches!(e.kind, ExprKind::Num(n) if n >= 0);
+            let unsigned = Type::common(l, r).is_unsigned();
+            if unsigned && (signed_side(lhs) || signed_side(rhs)) && l.promote().is_unsigned() != r.promote().is_unsigned() {
+                self.warn(line, "comparison of integer expressions of different signedness".to_string());
+            }
+        }
+    }
+}
@@ -1,1404 +1,857 @@
-// this is the full implementation of the c4 compiler, not just the vm-- 
-// the vm can be found int he main functtion below.
-// Constants for tokens and opcodes- add more
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, Read}; // Import Read trait
-use std::ptr;
-
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
-enum Token {
-    None, 
-    Num(i32),
-    Id(String),
-    Char(char),
-    Str(String),
-    Else, Enum, If, Int, Return, Sizeof, While, Do,
-    Assign, Cond, Lor, Lan, Or, Xor, And, Eq, Lt, Shl, Add, Mul, Inc,
-    Ne, Le, Gt, Ge, Shr, Sub, Div, Mod, Dec,
-    Brak,
-    LParen, RParen, RBrace, LBrace, Comma, Colon, Semicolon, Not, BitNot,
+use std::io::{Read, Write};
+
+use crate::codegen::{bad_operand, line_of, operands, MNEMONICS};
+use crate::coverage::Coverage;
+use crate::fs::{FileSystem, Handle, HostFs};
+use crate::leaks::Leaks;
+use crate::memcheck::MemCheck;
+use crate::profile::Profiler;
+use crate::sandbox::{Limit, Limits};
+use crate::{ADJ, DIV, ENT, FADD, FGE, ITFS, JSR, LC, LEV, LI, MOD, OR, PSH, SC, SI, UDIV, ULT, UMOD, UTFS};
+
+// why the VM stopped a program before it exited
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    Fault(String),        // something it can't be allowed to do: a wild pointer, a division by zero
+    LimitExceeded(Limit), // one of the --max-* limits tripped
 }
 
-impl Token {
-    fn precedence(&self) -> Option<i32> {
-        use Token::*;
-        Some(match self {
-            Assign => 1,
-            Cond   => 2,
-            Lor    => 3,
-            Lan    => 4,
-            Or     => 5,
-            Xor    => 6,
-            And    => 7,
-            Eq     => 8,
-            Lt     => 9,
-            Shl    => 10,
-            Add    => 11,
-            Mul    => 12,
-            Inc    => 13,
-            _ => return Some(0),
-        })
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RuntimeError::Fault(why) => write!(f, "{}", why),
+            RuntimeError::LimitExceeded(limit) => write!(f, "{}", limit),
+        }
     }
 }
 
-#[derive(Debug, Hash, Clone, PartialEq)]
-enum Class {
-    Sys,  // System function
-    Fun,  // User-defined function
-    Num,  // Immediate number
-    Loc,  // Local variable
-    Glo,  // Global variable
-}
-
-#[derive(Debug, Hash, Clone)]
-struct Symbol {
-    class: Class,   // Class of symbol (Sys, Fun, Num, Loc, Glo)
-    val: i32,       // Address or value
-    typ: i32,       // Type (e.g., INT, CHAR, PTR, etc.)
-}
-
-const Num: i32 = 128;
-const Id: i32 = 129;
-
-// these are opcode constants the vm can execute
-const LEA: i32 = 0; // load effective address
-const IMM: i32 = 1; // load immediate value
-const JMP: i32 = 2; // unconditional jump
-const JSR: i32 = 3; // jump to subroutine (function call)
-const BZ: i32 = 4; // branch if zero
-const BNZ: i32 = 5; // branch if not zero
-const ENT: i32 = 6; // enter function (setup stack frame)
-const ADJ: i32 = 7; // adjust stack
-const LEV: i32 = 8; // leave function
-const LI: i32 = 9; // load integer from memory
-const LC: i32 = 10; // load character from memory
-const SI: i32 = 11; // store integer to memory
-const SC: i32 = 12; // store character to memory
-const PSH: i32 = 13; // push value onto stack
-
-// the rest below are arithmetic and logical operations
-const OR: i32 = 14;
-const XOR: i32 = 15;
-const AND: i32 = 16;
-const EQ: i32 = 17;
-const NE: i32 = 18;
-const LT: i32 = 19;
-const GT: i32 = 20;
-const LE: i32 = 21;
-const GE: i32 = 22;
-const SHL: i32 = 23;
-const SHR: i32 = 24;
-const ADD: i32 = 25;
-const SUB: i32 = 26;
-const MUL: i32 = 27;
-const DIV: i32 = 28;
-const MOD: i32 = 29;
-
-// below are system calls
-const OPEN: i32 = 30;
-const READ: i32 = 31;
-const CLOS: i32 = 32;
-const PRTF: i32 = 33;
-const MALC: i32 = 34;
-const FREE: i32 = 35;
-const MSET: i32 = 36;
-const MCMP: i32 = 37;
-const EXIT: i32 = 38;
-
-// data types
-const CHAR: i32 = 0;
-const INT: i32 = 1;
-const PTR: i32 = 2;
-
-
-// FROM C NEXT() TO RUST LEXER CLASS LOGIC EXPLAINED 
-// in the original c4 compiler the next function used a manual character 
-// pointer to iterate over the source code and classify tokens...
-
-// since pointers are considered unsafe in rust, other data structures like 
-// string slices (&str), indexes and options can track the source code.
-
-struct Lexer<'a> {
-    source: &'a str, // the full input source code to tokenize
-    position: usize, // current index in the source string
-    line: usize, // current line number- for debugging like c4
-    current_char: Option<char>, // currently read character 
-    keywords: HashMap<&'a str, Token>, // hashmap that maps strings like "if" and "return" to token types
+// the return address call() gives the function, past any text there can be
+const RETURNED: usize = usize::MAX;
+
+pub struct VM {
+    pub pc: usize,        // program counter - points to the current instruction in the text
+    pub sp: usize,        // stack pointer - points to the top of the stack
+    pub bp: usize,        // base pointer - used to manage stack frames for function calls
+    pub ax: i64,          // accumulator - used to hold intermediate values/results
+    pub stack: Vec<i64>,  // stack memory - used for storing variables and return addresses
+    pub text: Vec<i64>,   // bytecode - holds instructions and their operands
+    pub data: Vec<i64>,   // global/static variables memory
+    pub running: bool,    // execution flag - indicates whether VM should continue running
+    pub debug: bool,      // trace every instruction like c4 -d
+    pub heap: usize,      // next free cell for malloc, right after the data segment
+    pub fs: Box<dyn FileSystem>, // where open looks paths up, the host's files by default
+    files: HashMap<i64, Box<dyn Handle>>, // descriptors handed out by open
+    pub stdin: Box<dyn Read>,   // what read(0, ...) reads, the process's stdin by default
+    pub stdout: Box<dyn Write>, // where printf, write(1, ...), the exit value and the -d trace go
+    pub stderr: Box<dyn Write>, // where write(2, ...), faults and the reports at exit go
+    pub cycle: u64,       // instructions executed so far
+    pub error: Option<RuntimeError>, // why the program was stopped, if the VM had to
+    pub exit: Option<i64>, // what main returned or exit got, once the program has exited
+    pub limits: Limits,   // none by default
+    pub depth: usize,     // calls active below main
+    heap_used: usize,     // bytes malloc handed out
+    written: usize,       // bytes printf and write put on stdout and stderr
+    pub lines: Vec<(i32, i32)>, // the code's line table, to report faults by source line
+    pub memcheck: Option<MemCheck>, // the --check shadow memory, only the classic loop consults it
+    pub leaks: Option<Leaks>,       // the blocks --leaks reports at exit
+    pub profile: Option<Profiler>,  // --profile's counts, kept by the classic loop
+    pub coverage: Option<Coverage>, // --coverage's counts, likewise
 }
 
-// SInce structs in Rust can act like classes, we can define the 
-// following constructors and methods to build this lexer
-
-// new(): constructor to initialize the lexer - setting position, line, first char, populate keywords
-
-impl<'a> Lexer<'a> {
-    fn new(source: &'a str) -> Self { // constructor that initializes a new lexer instance
-
-        // default constructor --= default values
-        let mut lexer = Lexer {
-            source, // full source code
-            position: 0, // start reading from pos 0 = the beginning
-            line: 1, // error tracking from line 1 = the first line
-            current_char: None,
-            keywords: HashMap::new(), // empty keyword map for now
-        };
+impl VM {
+    // Constructor for VM: Initializes registers and allocates memory for stack and data
+    pub fn new(text: Vec<i64>, data_size: usize, stack_size: usize) -> Self {
+        Self {
+            pc: 0,                         // start execution at beginning of text
+            sp: stack_size,               // stack pointer starts at top of allocated stack
+            bp: stack_size,               // base pointer also starts at top
+            ax: 0,                        // accumulator starts with 0
+            stack: vec![0; stack_size],   // preallocated stack space
+            text,                         // program instructions
+            data: vec![0; data_size],     // preallocated data section
+            running: true,                // set VM as running
+            debug: false,
+            heap: 0,
+            fs: Box::new(HostFs),
+            files: HashMap::new(),
+            stdin: Box::new(std::io::stdin()),
+            stdout: Box::new(std::io::stdout()),
+            stderr: Box::new(std::io::stderr()),
+            cycle: 0,
+            error: None,
+            exit: None,
+            limits: Limits::default(),
+            depth: 0,
+            heap_used: 0,
+            written: 0,
+            lines: Vec::new(),
+            memcheck: None,
+            leaks: None,
+            profile: None,
+            coverage: None,
+        }
+    }
 
-        // we need to populate the keyword map with reserved words
-        Token::Else;
-        lexer.keywords.insert("enum", Token::Enum);
-        lexer.keywords.insert("if", Token::If);
-        lexer.keywords.insert("int", Token::Int);
-        lexer.keywords.insert("return", Token::Return);
-        lexer.keywords.insert("sizeof", Token::Sizeof);
-        lexer.keywords.insert("while", Token::While);
-
-        // advance to the first character of the source code
-        // this mimics how c4 manually reads the first char into a variable
-        lexer.advance();
-        lexer
+    // stops the program on something it can't be allowed to do, reporting
+    // it like c4 reports errors instead of panicking
+    pub(crate) fn fault(&mut self, why: String) {
+        self.stop(RuntimeError::Fault(why));
     }
 
-    // here this function moves to the next character in the source code
-    fn advance(&mut self) {
-        // if we're not at the end of the code, get the next byte and convert it to a char
-        self.current_char = if self.position < self.source.len() {
-            Some(self.source.as_bytes()[self.position] as char)
-        } else { // we reached the end of the source code
-            None
+    pub(crate) fn stop(&mut self, error: RuntimeError) {
+        let what = match error {
+            RuntimeError::Fault(_) => "fault",
+            RuntimeError::LimitExceeded(_) => "limit exceeded",
+        };
+        let _ = match line_of(&self.lines, self.pc) {
+            Some(line) => writeln!(self.stderr, "{} at pc {} (line {}): {}", what, self.pc, line, error),
+            None => writeln!(self.stderr, "{} at pc {}: {}", what, self.pc, error),
         };
-        self.position += 1; // move the reading position to the next 
+        self.error = Some(error);
+        self.running = false;
     }
 
-    // this function allows us to look at the next character in the source code 
-    // without actually advancing the current reading position (used for lookahead logic)
-    fn peek(&self) -> Option<char> { // sampe implementation as advance() method 
-        if self.position < self.source.len() {
-            Some(self.source.as_bytes()[self.position] as char)
-        } else {
-            None
+    // what would go wrong executing op with the registers as they are: a
+    // cell outside memory, a push into the heap, a pop past the top of
+    // memory, a division by zero or an operand op can't take
+    fn check(&self, op: i64) -> Option<String> {
+        let len = self.stack.len();
+        let outside = |addr: i64| addr < 0 || addr >= len as i64;
+        let op = i32::try_from(op).unwrap_or(-1);
+        if let Some(why) = self.text.get(self.pc).and_then(|&n| bad_operand(op, n)) {
+            return Some(why);
         }
-    }
-
-    // this function gets the next token from the source code
-    fn next_token(&mut self) -> Option<Token> {
-        while let Some(c) = self.current_char { // loop while there is a current character to process
-            match c {
-                ' ' | '\t' | '\r' => self.advance(), // skip whitespace characters
-                '\n' => { // a newline is found?
-                    self.line += 1; // then increment line number 
-                    self.advance();
-                }
-
-                // handle single-line comments and hash comments
-                '/' => {
-                    if self.peek() == Some('/') {
-                        while self.current_char != Some('\n') && self.current_char.is_some() {
-                            self.advance();
-                        }
-                    } else {
-                        self.advance();
-                        return Some(Token::Div);
-                    }
-                }
-                '#' => {
-                    while self.current_char != Some('\n') && self.current_char.is_some() {
-                        self.advance();
-                    }
-                }
-                // handle string literal
-                '"' => {
-                    self.advance();
-                    let mut string = String::new();
-                    while let Some(ch) = self.current_char {
-                        if ch == '"' {
-                            break;
-                        }
-                        string.push(ch);
-                        self.advance();
-                    }
-                    self.advance(); // skip the closing "
-                    return Some(Token::Str(string));
-                }
-                // handle character literal
-                '\'' => {
-                    self.advance();
-                    let ch = self.current_char?;
-                    self.advance();
-                    self.advance(); // skip closing '
-                    return Some(Token::Char(ch));
-                }
-                // handle operators
-                '=' => {
-                    self.advance();
-                    if self.current_char == Some('=') { // if it's == then the token is Eq
-                        self.advance();
-                        return Some(Token::Eq);
-                    }
-                    return Some(Token::Assign); // else its an assignment 
-                }
-                '!' => {
-                    self.advance();
-                    if self.current_char == Some('=') {
-                        self.advance();
-                        return Some(Token::Ne);
-                    }
-                }
-                '<' => {
-                    self.advance();
-                    if self.current_char == Some('=') {
-                        self.advance();
-                        return Some(Token::Le);
-                    }
-                    return Some(Token::Lt);
-                }
-                '>' => {
-                    self.advance();
-                    if self.current_char == Some('=') {
-                        self.advance();
-                        return Some(Token::Ge);
-                    }
-                    return Some(Token::Gt);
-                }
-                '+' => {
-                    self.advance();
-                    if self.current_char == Some('+') {
-                        self.advance();
-                        return Some(Token::Inc);
-                    }
-                    return Some(Token::Add);
-                }
-                '-' => {
-                    self.advance();
-                    if self.current_char == Some('-') {
-                        self.advance();
-                        return Some(Token::Dec);
-                    }
-                    return Some(Token::Sub);
-                }
-                '*' => {
-                    self.advance();
-                    return Some(Token::Mul);
-                }
-                '%' => {
-                    self.advance();
-                    return Some(Token::Mod);
-                }
-                '(' => {
-                    self.advance();
-                    return Some(Token::LParen);
-                }
-                ')' => {
-                    self.advance();
-                    return Some(Token::RParen);
-                }
-                '{' => {
-                    self.advance();
-                    return Some(Token::LBrace);
-                }
-                '}' => {
-                    self.advance();
-                    return Some(Token::RBrace);
-                }
-                ';' => {
-                    self.advance();
-                    return Some(Token::Semicolon);
-                }
-                ':' => {
-                    self.advance();
-                    return Some(Token::Colon);
-                }
-                ',' => {
-                    self.advance();
-                    return Some(Token::Comma);
-                }
-
-                '0'..='9' => return Some(self.lex_number()), // if a digit is found, parse a number token
-
-                // if a letter or underscore is found, parse an identifier or keyword
-                'a'..='z' | 'A'..='Z' | '_' => return Some(self.lex_identifier()), 
-
-                '"' => return Some(self.lex_string()), // detect string literals
-
-                _ => { // if an unknown character is found, just skip it
-                    self.advance(); // skip
-                }
-            }
+        match op {
+            _ if self.pc + operands(op) > self.text.len() => Some("the program ends inside an instruction".to_string()),
+            JSR | PSH if self.sp <= self.heap => Some("stack overflow".to_string()),
+            ENT if self.sp <= self.heap.saturating_add(self.text[self.pc] as usize + 1) => Some("stack overflow".to_string()),
+            ADJ if self.text[self.pc] as usize > len.saturating_sub(self.sp) => Some("stack underflow".to_string()),
+            LEV if self.bp >= len - 1 => Some("stack underflow".to_string()),
+            LI | LC if outside(self.ax) => Some(format!("read from {}, outside memory", self.ax)),
+            SI | SC | OR..=MOD | ULT..=UMOD | FADD..=FGE | ITFS | UTFS if self.sp >= len => Some("stack underflow".to_string()),
+            SI | SC if outside(self.stack[self.sp]) => Some(format!("write to {}, outside memory", self.stack[self.sp])),
+            DIV | MOD | UDIV | UMOD if self.ax == 0 => Some("division by zero".to_string()),
+            _ => None,
         }
-        None 
     }
 
-    // parses a sequence of digits into a number token
-    fn lex_number(&mut self) -> Token {
-        let mut value: i32 = 0;
-        while let Some(c) = self.current_char {
-            // if the character is a digit, convert it to a number and build the full value
-            if c.is_digit(10) {
-                value = value * 10 + c.to_digit(10).unwrap() as i32;
-                self.advance();
-            } else {
-                break; // stop reading if it's not a digit
-            }
+    // runs the optional checks and counters for op, false if one of them
+    // stopped the program
+    fn instrument(&mut self, op: i64) -> bool {
+        if let Some(why) = self.memcheck(op) {
+            self.pc -= 1;
+            self.fault(why);
+            return false;
+        }
+        if let Some(limit) = self.exceeded(op) {
+            self.pc -= 1;
+            self.stop(RuntimeError::LimitExceeded(limit));
+            return false;
         }
-        Token::Num(value) // return the number as a token
+        if let Some(p) = &mut self.profile {
+            p.step(op, self.pc - 1, self.text.get(self.pc).copied().unwrap_or(0));
+        }
+        if let Some(c) = &mut self.coverage {
+            c.step(self.pc - 1);
+        }
+        true
     }
 
-    // this method parses an identifier or a reserved keyword from c
-    fn lex_identifier(&mut self) -> Token {
-        let start = self.position - 1; // this si the satrting pos of the identifier
-
-        while let Some(c) = self.current_char { // keep reading letters, digits, or underscores
-            if c.is_alphanumeric() || c == '_' {
-                self.advance();
-            } else {
-                break;
-            }
+    // the limit executing op would go past, if any
+    fn exceeded(&self, op: i64) -> Option<Limit> {
+        match self.limits {
+            Limits { cycles: Some(max), .. } if self.cycle > max => Some(Limit::Cycles(max)),
+            Limits { depth: Some(max), .. } if op == 3 && self.depth >= max => Some(Limit::Depth(max)),
+            _ => None,
         }
+    }
 
-        let identifier = &self.source[start..self.position - 1]; // get identifier from source code
+    // what --check has against executing op, see memcheck.rs
+    fn memcheck(&mut self, op: i64) -> Option<String> {
+        let mut m = self.memcheck.take()?;
+        let operand = self.text.get(self.pc).copied().unwrap_or(0);
+        let why = m.step(op, operand, self);
+        self.memcheck = Some(m);
+        why
+    }
 
-        if let Some(keyword) = self.keywords.get(identifier) { // check if the keyword is known 
-            keyword.clone() // if known, return the keyword token
-        } else {
-            // otherwise, return it as a regular identifier
-            Token::Id(identifier.to_string())
-        }
+    // the same for a system call touching the n cells at from
+    fn memcheck_range(&mut self, from: usize, n: usize, write: bool) -> Option<String> {
+        let mut m = self.memcheck.take()?;
+        let why = m.range(from, n, write, self);
+        self.memcheck = Some(m);
+        why
     }
 
-    // handlling when printf is printing full string
-    fn lex_string(&mut self) -> Token {
-        let start = self.position; // mark the start of the string
-        self.advance(); // skip the opening quote
-        // Read characters until we find the closing quote or end of input
-        while let Some(c) = self.current_char {
-            if c == '"' {
-                break; // found the closing quote
-            }
-            self.advance();
+    // copies the data segment to the bottom of memory, one byte per cell,
+    // and starts the heap right after it
+    pub fn load_data(&mut self, data: &[u8]) {
+        for (i, &b) in data.iter().enumerate() {
+            self.stack[i] = b as i64;
         }
-        let string = &self.source[start..self.position - 1]; // extract the string content
-        self.advance(); // skip the closing quote
-        Token::Str(string.to_string()) // return the string token
+        self.heap = (data.len().max(1) + 7) & !7; // malloc never hands out address 0, which is NULL
     }
-}
-    
-///////////////////////// Parser Implementation Begins ////////////////////////
-#[derive(Debug)]
-struct Parser {
-    e: Vec<i32>, // Emitted code
-    tk: Token,     // Current token
-    ival: i32,   // Current token value
-    ty: i32,     // Current expression type
-    loc: i32,    // Local variable offset
-    line: i32,   // Current line number
-    data: Vec<u8>, // <--- memory area to simulate global string storage
-    pos: i32, 
-    symbols: HashMap<String, Symbol>,
-}
 
-impl Parser {
-    fn new() -> Self {
-        Self {
-            e: Vec::new(),
-            tk: Token::None, // defining anything for now
-            ival: 0,
-            ty: 0,
-            loc: 0,
-            line: 1,
-            data: Vec::new(),
-            pos: 0,
-            symbols: HashMap::new(),
+    // copies the arguments to the top of the stack as NUL terminated strings
+    // with a NULL terminated char* array after them, and returns that array's
+    // address; sp ends up below it
+    pub fn load_args(&mut self, args: &[String]) -> i64 {
+        let mut addrs = Vec::new();
+        for arg in args {
+            self.sp -= arg.len() + 1;
+            addrs.push(self.sp as i64);
+            for (i, b) in arg.bytes().enumerate() {
+                self.stack[self.sp + i] = b as i64;
+            }
+            self.stack[self.sp + arg.len()] = 0;
+        }
+        addrs.push(0);
+        // a char* is sizeof(int) wide, like every pointer
+        self.sp = (self.sp - addrs.len() * 4) & !7;
+        for (i, &addr) in addrs.iter().enumerate() {
+            self.stack[self.sp + i * 4] = addr;
         }
+        self.sp as i64
     }
 
-    fn next(&mut self) {
-        // here, given the tokens generated from the parser, 
-        // implement a function that advances through each token
-        // Logic to get the next token
-        // if self.pos < self.tokens.len() {
-        //     self.tk = self.tokens[self.pos].clone();
-        //     self.pos += 1;
-        // } else {
-        //     self.tk = Token::None;
-        // }
+    // runs the function at entry on an empty stack until it returns, which
+    // leaves its value in ax; the REPL runs each thing it compiles this way
+    pub fn call(&mut self, entry: usize) {
+        self.sp = self.stack.len() - 1;
+        self.bp = self.sp;
+        self.stack[self.sp] = RETURNED as i64;
+        self.pc = entry;
+        self.depth = 0;
+        self.error = None;
+        self.running = true;
+        self.run();
     }
 
-    fn expr(&mut self, lev: i32) {
-        let token = self.tk.clone();
-
-        match token {
-            Token::None => {
-                eprintln!("{}: unexpected eof in expression", self.line);
-                std::process::exit(-1);
-            }
-            Token::Num(val) => {
-                self.e.push(IMM);
-                self.e.push(val);
-                self.next();
-                self.ty = INT;
+    // Main execution loop for the VM
+    pub fn run(&mut self) {
+        // the checker, the limits, the profiler and coverage cost a branch each
+        // per instruction, so a plain run skips all of them at once
+        let instrumented = self.memcheck.is_some()
+            || self.limits.cycles.is_some()
+            || self.limits.depth.is_some()
+            || self.profile.is_some()
+            || self.coverage.is_some();
+        while self.running {
+            let op = match self.text.get(self.pc) { // fetch instruction
+                Some(&op) => op,
+                None if self.pc == RETURNED => break,
+                None => {
+                    self.fault(format!("jump to {}, outside the program", self.pc));
+                    break;
+                }
+            };
+            self.pc += 1;                 // advance to next bytecode
+            self.cycle += 1;
+            if self.debug {
+                let name = MNEMONICS.get(op as usize).copied().unwrap_or("???");
+                let args: Vec<String> = (0..operands(op as i32)).filter_map(|i| self.text.get(self.pc + i)).map(|a| a.to_string()).collect();
+                let _ = writeln!(self.stdout, "{}> {:4} {}", self.cycle, name, args.join(" "));
+            }
+
+            if let Some(why) = self.check(op) {
+                self.pc -= 1;
+                self.fault(why);
+                break;
             }
-            Token::Str(s) => {
-                self.e.push(IMM);
-                let addr = self.store_string(&s); // Now no conflict with borrowing `self`
-                self.e.push(addr);
-                self.next();
-                self.ty = INT; // Or PTR, depending on your needs
+            if instrumented && !self.instrument(op) {
+                break;
             }
 
-            Token::Sizeof => {
-                self.next();
-                if self.tk != Token::LParen {
-                    eprintln!("{}: open paren expected in sizeof", self.line);
-                    std::process::exit(-1);
+            match op {
+                0 => { // LEA: Load effective address
+                    let addr = (self.bp as i64).wrapping_add(self.text[self.pc]) as usize; // calculate address relative to base pointer, locals are below it
+                    self.pc += 1;
+                    self.ax = addr as i64; // store in accumulator
                 }
-                self.next();
-            
-                self.ty = INT;
-                if self.tk == Token::Int {
-                    self.next();
-                } else if matches!(self.tk, Token::Char(_)) {
-                    self.next();
-                    self.ty = CHAR;
-                } else {
-                    eprintln!("{}: type name expected in sizeof", self.line);
-                    std::process::exit(-1);
+
+                1 => { // IMM: Load immediate value
+                    self.ax = self.text[self.pc]; // load immediate value into accumulator
+                    self.pc += 1;
                 }
-                while self.tk == Token::Mul {
-                    self.next();
-                    self.ty += PTR;
+
+                2 => { // JMP: Unconditional jump
+                    self.pc = self.text[self.pc] as usize; // set program counter to new location
                 }
-                if self.tk != Token::RParen {
-                    eprintln!("{}: close paren expected in sizeof", self.line);
-                    std::process::exit(-1);
+
+                3 => { // JSR: Jump to subroutine
+                    self.sp -= 1;                         // make space on stack
+                    self.stack[self.sp] = self.pc as i64 + 1; // save return address, past the operand
+                    self.pc = self.text[self.pc] as usize; // jump to subroutine
+                    self.depth += 1;
                 }
-                self.next();
-            
-                self.e.push(IMM);
-                self.e.push(if self.ty == CHAR { 1 } else { 4 }); // assuming sizeof(char) = 1, sizeof(int) = 4
-                self.ty = INT;
-            }         
-
-            Token::Id(ref name) => {
-                // Lookup the symbol table entry by identifier name
-                // let d = self.symbols.get(name).cloned().unwrap_or_else(|| {
-                //     eprintln!("{}: undefined identifier '{}'", self.line, name);
-                //     std::process::exit(-1);
-                // });
-                // self.next();
-
-                if let Some(d) = self.symbols.get(name.as_str()).cloned() {
-                    self.next(); // consume identifier
-
-                    if self.tk == Token::LParen {
-                        self.next();
-                        let mut t = 0;
-                        while self.tk != Token::RParen {
-                            self.expr(Token::Assign.precedence().unwrap());
-                            self.e.push(PSH);
-                            t += 1;
-                            if self.tk == Token::Comma {
-                                self.next();
-                            }
-                        }
-                        self.next();
-                        match d.class {
-                            Class::Sys => self.e.push(d.val),
-                            Class::Fun => {
-                                self.e.push(JSR);
-                                self.e.push(d.val);
-                            }
-                            _ => {
-                                eprintln!("{}: bad function call", self.line);
-                                std::process::exit(-1);
-                            }
-                        }
-                        if t > 0 {
-                            self.e.push(ADJ);
-                            self.e.push(t);
-                        }
-                        self.ty = d.typ;
-                    } else if d.class == Class::Num {
-                        self.e.push(IMM);
-                        self.e.push(d.val);
-                        self.ty = INT;
-                    } else {
-                        match d.class {
-                            Class::Loc => {
-                                self.e.push(LEA);
-                                self.e.push(self.loc - d.val);
-                            }
-                            Class::Glo => {
-                                self.e.push(IMM);
-                                self.e.push(d.val);
-                            }
-                            _ => {
-                                eprintln!("{}: undefined variable '{}'", self.line, name);
-                                std::process::exit(-1);
-                            }
-                        }
-                        self.ty = d.typ;
-                        self.e.push(if self.ty == CHAR { LC } else { LI });
+
+                4 => { // BZ: Branch if zero
+                    let target = self.text[self.pc] as usize;
+                    self.pc += 1;
+                    if self.ax == 0 {
+                        self.pc = target; // jump if accumulator is zero
                     }
-                } else {
-                    println!("{}: undefined variable {}", self.line, name);
-                    std::process::exit(-1);
-                }
-                
-            }            
-            
-            Token::Mul => {
-                self.next();
-                self.expr(Token::Inc.precedence().unwrap());
-                if (self.ty == INT){
-                    self.ty = self.ty - PTR;
-                } else {
-                    eprintln!("{}: close paren expected in sizeof", self.line);
-                    std::process::exit(-1);
                 }
-                self.e.push(if self.ty == CHAR { LC } else { LI })
-            }
 
-            Token::And => {
-                self.next();
-                self.expr(Token::Inc.precedence().unwrap());
-                
-                if let Some(&last) = self.e.last() {
-                    if last == LC || last == LI {
-                        self.e.pop();
-                    } else {
-                        eprintln!("{}: bad address-of", self.line);
-                        std::process::exit(-1);
+                5 => { // BNZ: Branch if not zero
+                    let target = self.text[self.pc] as usize;
+                    self.pc += 1;
+                    if self.ax != 0 {
+                        self.pc = target; // jump if accumulator is not zero
                     }
-                } else {
-                    eprintln!("{}: close paren expected in sizeof", self.line);
-                    std::process::exit(-1);
                 }
-                self.ty = self.ty + PTR;
-            }
 
-            Token::LParen => {
-                self.next(); // consume '('
-                match self.tk {
-                    Token::Int => {
-                        // Handle cast for 'Int'
-                        let mut t = INT;  // Initialize the type as INT
-                        self.next(); // consume 'Int'
-            
-                        // Check for pointer dereferencing (*)
-                        while let Token::Mul = self.tk {
-                            self.next(); // consume '*'
-                            t = t + PTR; // Adjust type for pointers
-                        }
-            
-                        // Ensure we have a closing parenthesis ')'
-                        if let Token::RParen = self.tk {
-                            self.next(); // consume ')'
-                        } else {
-                            panic!("{}: bad cast", self.line); // Handle bad cast
-                        }
-            
-                        // Handle the casted expression
-                        self.expr(Token::Inc.precedence().unwrap());
-                        self.ty = t; // Set the type for the expression
-                    }
-                    Token::Char(_) => {
-                        // Handle cast for 'Char'
-                        let mut t = CHAR;  // Initialize the type as CHAR
-                        self.next(); // consume 'Char'
-            
-                        // Check for pointer dereferencing (*)
-                        while let Token::Mul = self.tk {
-                            self.next(); // consume '*'
-                            t = t + PTR; // Adjust type for pointers
-                        }
-            
-                        // Ensure we have a closing parenthesis ')'
-                        if let Token::RParen = self.tk {
-                            self.next(); // consume ')'
-                        } else {
-                            panic!("{}: bad cast", self.line); // Handle bad cast
-                        }
-            
-                        // Handle the casted expression
-                        self.expr(Token::Inc.precedence().unwrap());
-                        self.ty = t; // Set the type for the expression
-                    }
-                    _ => {
-                        // Regular parenthesis group
-                        self.expr(Token::Assign.precedence().unwrap());
-            
-                        // Ensure we have a closing parenthesis ')'
-                        if let Token::RParen = self.tk {
-                            self.next(); // consume ')'
-                        } else {
-                            panic!("{}: close paren expected", self.line); // Handle missing closing parenthesis
-                        }
-                    }
+                6 => { // ENT: Enter subroutine (setup stack frame)
+                    self.sp -= 1;
+                    self.stack[self.sp] = self.bp as i64;          // save current base pointer
+                    self.bp = self.sp;                             // set new base pointer
+                    self.sp -= self.text[self.pc] as usize;        // allocate space for local variables
+                    self.pc += 1;
                 }
-            }            
-
-            
-            Token::Not => {
-                self.next();
-                self.expr(Token::Inc.precedence().unwrap()); // Inc is the precedence level
-                self.e.push(PSH);
-                self.e.push(IMM);
-                self.e.push(0);
-                self.e.push(EQ);
-                self.ty = INT;
-            }
-            Token::BitNot => {
-                self.next();
-                self.expr(Token::Inc.precedence().unwrap());
-                self.e.push(PSH);
-                self.e.push(IMM);
-                self.e.push(-1);
-                self.e.push(XOR);
-                self.ty = INT;
-            }
-            Token::Add => {
-                self.next();
-                self.expr(Token::Inc.precedence().unwrap());
-                self.ty = INT;
-            }
-            
-            Token::Sub => {
-                self.next();
-                self.e.push(IMM);
-                match self.tk.clone() {
-                    Token::Num(val) => {
-                        self.e.push(-val);
-                        self.next();
-                    }
-                    _ => {
-                        self.e.push(-1);
-                        self.e.push(PSH);
-                        self.expr(Token::Inc.precedence().unwrap());
-                        self.e.push(MUL);
-                    }
+
+                7 => { // ADJ: Adjust stack
+                    self.sp += self.text[self.pc] as usize; // deallocate local variables
+                    self.pc += 1;
+                }
+
+                8 => { // LEV: Leave subroutine (restore frame)
+                    self.sp = self.bp;                             // reset stack pointer to base
+                    self.bp = self.stack[self.sp] as usize;        // restore old base pointer
+                    self.sp += 1;
+                    self.pc = self.stack[self.sp] as usize;        // return to saved return address
+                    self.sp += 1;
+                    self.depth = self.depth.saturating_sub(1);     // main's LEV goes to the exit stub
+                }
+
+                9 => { // LI: Load integer from stack address in ax
+                    self.ax = self.stack[self.ax as usize];
                 }
-                self.ty = INT;
-            }
 
-            Token::Inc | Token::Dec => {
-                let t = self.tk.clone(); // Clone the current token (Inc or Dec)
-                self.next(); // Consume the token (either Inc or Dec)
-                
-                // Evaluate the expression for the operand
-                self.expr(Token::Inc.precedence().unwrap());
-            
-                // Handle the left operand type (either LC or LI)
-                match self.e.last() {
-                    Some(&LC) => {
-                        self.e.push(PSH); // Push the operand to the stack
-                        self.e.push(LC); // Load the left value
-                    }
-                    Some(&LI) => {
-                        self.e.push(PSH); // Push the operand to the stack
-                        self.e.push(LI); // Load the left integer
-                    }
-                    _ => {
-                        panic!("{}: bad lvalue in pre-increment", self.line); // Handle bad lvalue
-                    }
+                10 => { // LC: Load character (8 bits)
+                    self.ax = self.stack[self.ax as usize] & 0xFF;
                 }
-            
-                // Push the size of the type onto the stack
-                self.e.push(PSH); // Push
-                let size = if self.ty > PTR {
-                    IMM // Immediate value
-                } else {
-                    IMM // Immediate value for char
-                };
-                self.e.push(size);
-                self.e.push(if t == Token::Inc { ADD } else { SUB }); // ADD for Inc, SUB for Dec
-            
-                // Store the result (either as a character or an integer)
-                self.e.push(if self.ty == CHAR { SC } else { SI });
-            }
-            
-            _ => {
-                eprintln!("{}: bad expression", self.line);
-                std::process::exit(-1);
-            }
-        }
-    
-        // precedence climbing would go here
-        let mut t = self.ty; // Store the current type
-    let mut d: usize; // Placeholder for jump addresses
-
-    while let Some(precedence) = self.tk.precedence() {
-        if precedence < lev {
-            break; // Exit if the current token's precedence is less than the level
-        }
 
-        match self.tk {
-            Token::Assign => {
-                self.next();
-                if self.e.last() == Some(&LC) || self.e.last() == Some(&LI) {
-                    self.e.push(PSH);
-                } else {
-                    eprintln!("{}: bad lvalue in assignment", self.line);
-                    std::process::exit(-1);
+                11 => { // SI: Store integer to address on stack
+                    let addr = self.stack[self.sp] as usize;
+                    self.sp += 1;
+                    self.stack[addr] = self.ax;
                 }
-                self.expr(Token::Assign.precedence().unwrap());
-                self.e.push(if t == CHAR { SC } else { SI });
-            }
-            Token::Cond => {
-                self.next();
-                self.e.push(BZ);
-                d = self.e.len(); // Save the current position for the jump
-                self.expr(Token::Assign.precedence().unwrap());
-                if let Token::Colon = self.tk {
-                    self.next();
-                } else {
-                    eprintln!("{}: conditional missing colon", self.line);
-                    std::process::exit(-1);
+
+                12 => { // SC: Store character (lower 8 bits of ax)
+                    let addr = self.stack[self.sp] as usize;
+                    self.sp += 1;
+                    self.stack[addr] = self.ax & 0xFF;
                 }
-                self.e.push(JMP);
-                let jump_pos = self.e.len();
-                self.e.push(0); // Placeholder for the jump
-                self.expr(Token::Cond.precedence().unwrap());
-                self.e[jump_pos - 1] = (self.e.len() + 1) as i32; // Fill in the jump address
-            }
-            Token::Lor => {
-                self.next();
-                self.e.push(BNZ);
-                d = self.e.len();
-                self.expr(Token::Lan.precedence().unwrap());
-                self.e.push((self.e.len() + 1) as i32); // Fill in the jump address
-                self.ty = INT;
-            }
-            Token::Lan => {
-                self.next();
-                self.e.push(BZ);
-                d = self.e.len();
-                self.expr(Token::Or.precedence().unwrap());
-                self.e.push((self.e.len() + 1) as i32); // Fill in the jump address
-                self.ty = INT;
-            }
-            Token::Or => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Xor.precedence().unwrap());
-                self.e.push(OR);
-                self.ty = INT;
-            }
-            Token::Xor => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::And.precedence().unwrap());
-                self.e.push(XOR);
-                self.ty = INT;
-            }
-            Token::And => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Eq.precedence().unwrap());
-                self.e.push(AND);
-                self.ty = INT;
-            }
-            Token::Eq => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Lt.precedence().unwrap());
-                self.e.push(EQ);
-                self.ty = INT;
-            }
-            Token::Ne => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Lt.precedence().unwrap());
-                self.e.push(NE);
-                self.ty = INT;
-            }
-            Token::Lt => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Shl.precedence().unwrap());
-                self.e.push(LT);
-                self.ty = INT;
-            }
-            Token::Gt => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Shl.precedence().unwrap());
-                self.e.push(GT);
-                self.ty = INT;
-            }
-            Token::Le => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Shl.precedence().unwrap());
-                self.e.push(LE);
-                self.ty = INT;
-            }
-            Token::Ge => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Shl.precedence().unwrap());
-                self.e.push(GE);
-                self.ty = INT;
-            }
-            Token::Shl => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Add.precedence().unwrap());
-                self.e.push(SHL);
-                self.ty = INT;
-            }
-            Token::Shr => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Add.precedence().unwrap());
-                self.e.push(SHR);
-                self.ty = INT;
-            }
-            Token::Add => {
-                self.next();
-                self.e .push(PSH);
-                self.expr(Token::Mul.precedence().unwrap());
-                if (t > PTR) {
-                    self.e.push(PSH);
-                    self.e.push(IMM);
-                    self.e.push(std::mem::size_of::<i32>() as i32);
-                    self.e.push(MUL);
+
+                13 => { // PSH: Push accumulator onto stack
+                    self.sp -= 1;
+                    self.stack[self.sp] = self.ax;
                 }
-                self.e.push(ADD);
-            }
-            Token::Sub => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Mul.precedence().unwrap());
-                if t > PTR && t == self.ty {
-                    self.e.push(SUB);
-                    self.e.push(PSH);
-                    self.e.push(IMM);
-                    self.e.push(std::mem::size_of::<i32>() as i32);
-                    self.e.push(DIV);
-                    self.ty = INT;
-                } else if (self.ty > PTR) {
-                    self.e.push(PSH);
-                    self.e.push(IMM);
-                    self.e.push(std::mem::size_of::<i32>() as i32);
-                    self.e.push(MUL);
-                    self.e.push(SUB);
-                } else {
-                    self.e.push(SUB);
+
+                // Binary operations: perform op with top of stack and ax
+                14 => self.ax |= self.stack[self.sp],   // OR
+                15 => self.ax ^= self.stack[self.sp],   // XOR
+                16 => self.ax &= self.stack[self.sp],   // AND
+                17 => self.ax = (self.stack[self.sp] == self.ax) as i64, // EQ (equal)
+                18 => self.ax = (self.stack[self.sp] != self.ax) as i64, // NE (not equal)
+                19 => self.ax = (self.stack[self.sp] < self.ax) as i64,  // LT (less than)
+                20 => self.ax = (self.stack[self.sp] > self.ax) as i64,  // GT (greater than)
+                21 => self.ax = (self.stack[self.sp] <= self.ax) as i64, // LE (less or equal)
+                22 => self.ax = (self.stack[self.sp] >= self.ax) as i64, // GE (greater or equal)
+                23 => self.ax = self.stack[self.sp].wrapping_shl(self.ax as u32), // SHL (shift left), the count taken mod 64
+                24 => self.ax = self.stack[self.sp].wrapping_shr(self.ax as u32), // SHR (shift right)
+                25 => self.ax = self.stack[self.sp].wrapping_add(self.ax),  // ADD, wrapping like the hardware
+                26 => self.ax = self.stack[self.sp].wrapping_sub(self.ax),  // SUB
+                27 => self.ax = self.stack[self.sp].wrapping_mul(self.ax),  // MUL
+                28 => self.ax = self.stack[self.sp].wrapping_div(self.ax),  // DIV
+                29 => self.ax = self.stack[self.sp].wrapping_rem(self.ax),  // MOD
+
+                // Unsigned variants: compare, shift and divide the cells as u64
+                39 => self.ax = ((self.stack[self.sp] as u64) < (self.ax as u64)) as i64,  // ULT
+                40 => self.ax = ((self.stack[self.sp] as u64) > (self.ax as u64)) as i64,  // UGT
+                41 => self.ax = ((self.stack[self.sp] as u64) <= (self.ax as u64)) as i64, // ULE
+                42 => self.ax = ((self.stack[self.sp] as u64) >= (self.ax as u64)) as i64, // UGE
+                43 => self.ax = (self.stack[self.sp] as u64).wrapping_shr(self.ax as u32) as i64, // USHR
+                44 => self.ax = ((self.stack[self.sp] as u64) / (self.ax as u64)) as i64, // UDIV
+                45 => self.ax = ((self.stack[self.sp] as u64) % (self.ax as u64)) as i64, // UMOD
+
+                46 => { // SXT: keep the low n bits of ax and sign extend them
+                    let shift = 64 - self.text[self.pc];
+                    self.pc += 1;
+                    self.ax = (self.ax << shift) >> shift;
                 }
-            }
-            Token::Mul => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Inc.precedence().unwrap());
-                self.e.push(MUL);
-                self.ty = INT;
-            }
-            Token::Div => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Inc.precedence().unwrap());
-                self.e.push(DIV);
-                self.ty = INT;
-            }
-            Token::Mod => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Inc.precedence().unwrap());
-                self.e.push(MOD);
-                self.ty = INT;
-            }
-            Token::Inc | Token::Dec => {
-                if self.e.last() == Some(&LC) {
-                    self.e.push(PSH);
-                    self.e.push(LC);
-                } else if self.e.last() == Some(&LI) {
-                    self.e.push(PSH);
-                    self.e.push(LI);
-                } else {
-                    eprintln!("{}: bad lvalue in post-increment", self.line);
-                    std::process::exit(-1);
+
+                47 => { // ZXT: keep the low n bits of ax and zero extend them
+                    let shift = 64 - self.text[self.pc];
+                    self.pc += 1;
+                    self.ax = ((self.ax as u64) << shift >> shift) as i64;
                 }
-                self.e.push(PSH);
-                self.e.push(IMM);
-                self.e.push(if self.ty > PTR { std::mem::size_of::<i32>() as i32 } else { std::mem::size_of::<char>() as i32 });
-                self.e.push(if self.tk == Token::Inc { ADD } else { SUB });
-                self.e.push(if self.ty == CHAR { SC } else { SI });
-                self.e.push(PSH);
-                self.e.push(IMM);
-                self.e.push(if self.ty > PTR { std::mem::size_of::<i32>() as i32 } else { std::mem::size_of::<char>() as i32 });
-                self.e.push(if self.tk == Token::Inc { SUB } else { ADD });
-                self.next();
-            }
-            Token::Brak => {
-                self.next();
-                self.e.push(PSH);
-                self.expr(Token::Assign.precedence().unwrap());
-                if let Token::RParen = self.tk {
-                    self.next();
-                } else {
-                    eprintln!("{}: close bracket expected", self.line);
-                    std::process::exit(-1);
+
+                48 => { // FIMM: Load a double from its low and high 32 bits
+                    let lo = self.text[self.pc] as u32 as u64;
+                    let hi = self.text[self.pc + 1] as u32 as u64;
+                    self.pc += 2;
+                    self.ax = (hi << 32 | lo) as i64;
                 }
-                if t > PTR {
-                    self.e.push(PSH);
-                    self.e.push(IMM);
-                    self.e.push(std::mem::size_of::<i32>() as i32);
-                    self.e.push(MUL);
-                } else if t < PTR {
-                    eprintln!("{}: pointer type expected", self.line);
-                    std::process::exit(-1);
+
+                // Floating point operations: the cells hold f64 bit patterns
+                49 => self.ax = to_cell(to_f64(self.stack[self.sp]) + to_f64(self.ax)), // FADD
+                50 => self.ax = to_cell(to_f64(self.stack[self.sp]) - to_f64(self.ax)), // FSUB
+                51 => self.ax = to_cell(to_f64(self.stack[self.sp]) * to_f64(self.ax)), // FMUL
+                52 => self.ax = to_cell(to_f64(self.stack[self.sp]) / to_f64(self.ax)), // FDIV
+                53 => self.ax = (to_f64(self.stack[self.sp]) == to_f64(self.ax)) as i64, // FEQ
+                54 => self.ax = (to_f64(self.stack[self.sp]) != to_f64(self.ax)) as i64, // FNE
+                55 => self.ax = (to_f64(self.stack[self.sp]) < to_f64(self.ax)) as i64,  // FLT
+                56 => self.ax = (to_f64(self.stack[self.sp]) > to_f64(self.ax)) as i64,  // FGT
+                57 => self.ax = (to_f64(self.stack[self.sp]) <= to_f64(self.ax)) as i64, // FLE
+                58 => self.ax = (to_f64(self.stack[self.sp]) >= to_f64(self.ax)) as i64, // FGE
+
+                // Conversions between integers and doubles
+                59 => self.ax = to_cell(self.ax as f64),          // ITF
+                60 => self.ax = to_cell(self.ax as u64 as f64),   // UTF
+                61 => self.ax = to_f64(self.ax) as i64,           // FTI
+                62 => self.ax = to_f64(self.ax) as u64 as i64,    // FTU
+                63 => self.ax = to_cell(to_f64(self.ax) as f32 as f64), // FRND
+                64 => self.stack[self.sp] = to_cell(self.stack[self.sp] as f64),        // ITFS
+                65 => self.stack[self.sp] = to_cell(self.stack[self.sp] as u64 as f64), // UTFS
+
+                30..=38 | 66 => { // system calls; printf takes its argument count from the ADJ that follows
+                    let argc = if op == 33 && self.text.get(self.pc) == Some(&7) { self.text.get(self.pc + 1).copied().unwrap_or(0) as usize } else { 0 };
+                    self.syscall(op, argc);
                 }
-                self.e.push(ADD);
 
-                self.ty = t - PTR; // Assign the new type
-                self.e.push(if self.ty == CHAR { LC } else { LI });
+                _ => { // unknown opcodes stop the program like any other fault
+                    self.pc -= 1;
+                    self.fault(format!("unknown instruction {}", op));
+                    break;
+                }
             }
-            _ => {
-                eprintln!("{}: compiler error tk={:?}", self.line, self.tk);
-                std::process::exit(-1);
+
+            // Most binary operations consume one stack value
+            if matches!(op, 14..=29 | 39..=45 | 49..=58) {
+                self.sp += 1;
             }
         }
     }
-    
-    }
 
-    fn store_string(&mut self, s: &str) -> i32 {
-        // Align to 4 bytes (simulate C4's `sizeof(int) & -sizeof(int)`)
-        while self.data.len() % 4 != 0 {
-            self.data.push(0);
+    // writes bytes to stdout, or stderr for fd 2, unless that goes over the
+    // output limit, which both count against, in which case what fits goes
+    // out and the program stops; false if it stopped
+    fn print(&mut self, fd: i64, bytes: &[u8]) -> bool {
+        let over = self.limits.output.filter(|&max| self.written + bytes.len() > max);
+        // what fits still goes out, so the output ends where the limit is
+        let fits = over.map_or(bytes.len(), |max| max - self.written);
+        self.written += fits;
+        let out = if fd == 2 { &mut self.stderr } else { &mut self.stdout };
+        let _ = out.write_all(&bytes[..fits]);
+        let _ = out.flush();
+        if let Some(max) = over {
+            self.stop(RuntimeError::LimitExceeded(Limit::Output(max)));
+            return false;
         }
+        true
+    }
 
-        let address = self.data.len() as i32; // get the current offset (address)
-
-        // Store the string bytes
-        self.data.extend_from_slice(s.as_bytes());
-        self.data.push(0); // null-terminator
-
-        // Optional: align after string for next storage
-        while self.data.len() % 4 != 0 {
-            self.data.push(0);
+    // the system calls, shared by both dispatch loops. The arguments are on
+    // the stack like for any call; argc is only needed by printf.
+    pub(crate) fn syscall(&mut self, op: i64, argc: usize) {
+        // the argument cells have to be there before anything reads them
+        let cells = match op {
+            33 => argc.max(1),
+            30 => 2,
+            31 | 36 | 37 | 66 => 3,
+            32 | 34 => 1,
+            _ => 0,
+        };
+        if cells > self.stack.len().saturating_sub(self.sp) {
+            self.fault("stack underflow".to_string());
+            return;
         }
+        // and the memory a call reads or writes, [from, from + n)
+        let range = |from: i64, n: i64| from >= 0 && n >= 0 && from.checked_add(n).is_some_and(|end| end <= self.stack.len() as i64);
+        match op {
+            33 => { // PRTF: printf(fmt, ...) with argc arguments
+                let t = self.sp + argc;
+                let fmt = self.stack[t - 1] as usize;
+                let args: Vec<i64> = (2..=argc).map(|i| self.stack[t - i]).collect();
+                let out = self.format(fmt, &args);
+                if self.print(1, out.as_bytes()) {
+                    self.ax = out.len() as i64;
+                }
+            }
+
+            30 => { // OPEN: open(path, flags), for writing if flags isn't 0
+                let path = String::from_utf8_lossy(&self.string_at(self.stack[self.sp + 1] as usize)).into_owned();
+                let write = self.stack[self.sp] != 0;
+                let Some(path) = self.limits.open.resolve(&path) else {
+                    self.ax = -1; // refused like a file that isn't there
+                    return;
+                };
+                self.ax = match self.fs.open(&path, write) {
+                    Ok(f) => {
+                        let fd = self.files.keys().max().map_or(3, |&fd| fd + 1);
+                        self.files.insert(fd, f);
+                        fd
+                    }
+                    Err(_) => -1,
+                };
+            }
 
-        address
-    }
+            31 => { // READ: read(fd, buf, n), fd 0 is stdin
+                let (fd, buf, n) = (self.stack[self.sp + 2], self.stack[self.sp + 1], self.stack[self.sp]);
+                if !range(buf, n) {
+                    self.fault(format!("read of {} bytes into {}, outside memory", n, buf));
+                    return;
+                }
+                let (buf, n) = (buf as usize, n as usize);
+                if let Some(why) = self.memcheck_range(buf, n, true) {
+                    self.fault(why);
+                    return;
+                }
+                let mut bytes = vec![0u8; n];
+                let got = match fd {
+                    0 => self.stdin.read(&mut bytes),
+                    _ => match self.files.get_mut(&fd) {
+                        Some(f) => f.read(&mut bytes),
+                        None => Err(std::io::ErrorKind::NotFound.into()),
+                    },
+                };
+                self.ax = match got {
+                    Ok(got) => {
+                        for (i, &b) in bytes[..got].iter().enumerate() {
+                            self.stack[buf + i] = b as i64;
+                        }
+                        got as i64
+                    }
+                    Err(_) => -1,
+                };
+            }
 
-    fn stmt(&mut self) {
-        let mut a: usize;
-        let mut b: usize;
-  
-        match self.tk {
-            Token::If => {
-                self.next();
-                if let Token::LParen = self.tk {
-                    self.next();
-                } else {
-                    eprintln!("{}: open paren expected", self.line);
-                    std::process::exit(-1);
-                }
-                self.expr(Token::Assign.precedence().unwrap());
-                if let Token::RParen = self.tk {
-                    self.next();
-                } else {
-                    eprintln!("{}: close paren expected", self.line);
-                    std::process::exit(-1);
+            66 => { // WRIT: write(fd, buf, n), fd 1 is stdout and 2 stderr
+                let (fd, buf, n) = (self.stack[self.sp + 2], self.stack[self.sp + 1], self.stack[self.sp]);
+                if !range(buf, n) {
+                    self.fault(format!("write of {} bytes from {}, outside memory", n, buf));
+                    return;
                 }
-                self.e.push(BZ);
-                b = self.e.len();
-                self.e.push(0);
-                self.stmt();
-                if let Token::Else = self.tk {
-                    self.e[b] = (self.e.len() + 1) as i32;
-                    self.e.push(JMP);
-                    b = self.e.len();
-                    self.next();
-                    self.stmt();
+                let (buf, n) = (buf as usize, n as usize);
+                if let Some(why) = self.memcheck_range(buf, n, false) {
+                    self.fault(why);
+                    return;
                 }
-                self.e[b] = (self.e.len() + 1) as i32;
+                let bytes: Vec<u8> = self.stack[buf..buf + n].iter().map(|&c| c as u8).collect();
+                self.ax = match fd {
+                    1 | 2 => {
+                        if !self.print(fd, &bytes) {
+                            return;
+                        }
+                        n as i64
+                    }
+                    _ => match self.files.get_mut(&fd).map(|f| f.write_all(&bytes)) {
+                        Some(Ok(())) => n as i64,
+                        _ => -1,
+                    },
+                };
             }
-            Token::While => {
-                self.next();
-                a = self.e.len() + 1;
-                if let Token::LParen = self.tk {
-                    self.next();
-                } else {
-                    eprintln!("{}: open paren expected", self.line);
-                    std::process::exit(-1);
-                }
-                self.expr(Token::Assign.precedence().unwrap());
-                if let Token::RParen = self.tk {
-                    self.next();
-                } else {
-                    eprintln!("{}: close paren expected", self.line);
-                    std::process::exit(-1);
-                }
-                self.e.push(BZ);
-                b = self.e.len();
-                self.e.push(0);
-                self.stmt();
-                self.e.push(JMP);
-                self.e.push(a as i32);
-                self.e[b] = (self.e.len() + 1) as i32;
+
+            32 => { // CLOS: close(fd)
+                self.ax = if self.files.remove(&self.stack[self.sp]).is_some() { 0 } else { -1 };
             }
-            Token::Do => {
-                self.next();
-                a = self.e.len() + 1; // Beginning of do-while body
-                self.stmt();
-                if let Token::While = self.tk {
-                    self.next();
-                    if let Token::LParen = self.tk {
-                        self.next();
-                    } else {
-                        eprintln!("{}: open paren expected after while in do-while", self.line);
-                        std::process::exit(-1);
-                    }
-                    self.expr(Token::Assign.precedence().unwrap());
-                    if let Token::RParen = self.tk {
-                        self.next();
-                    } else {
-                        eprintln!("{}: close paren expected after while in do-while", self.line);
-                        std::process::exit(-1);
+
+            34 => { // MALC: malloc(n), a bump allocator growing towards the stack
+                let n = self.stack[self.sp].max(0) as usize;
+                if let Some(max) = self.limits.heap.filter(|&max| self.heap_used + n > max) {
+                    self.stop(RuntimeError::LimitExceeded(Limit::Heap(max)));
+                    return;
+                }
+                if self.heap + n < self.sp {
+                    self.heap_used += n;
+                    self.ax = self.heap as i64;
+                    if let Some(m) = &mut self.memcheck {
+                        m.malloc(self.heap, n);
                     }
-                    self.e.push(BNZ);
-                    self.e.push(a as i32);
-                    if let Token::Semicolon = self.tk {
-                        self.next();
-                    } else {
-                        eprintln!("{}: semicolon expected after do-while", self.line);
-                        std::process::exit(-1);
+                    if let Some(l) = &mut self.leaks {
+                        l.malloc(self.heap, n, self.pc - 1); // pc is past the MALC during a system call
                     }
+                    self.heap = (self.heap + n + 7) & !7;
                 } else {
-                    eprintln!("{}: while expected after do statement", self.line);
-                    std::process::exit(-1);
+                    self.ax = 0;
                 }
             }
-            Token::Return => {
-                self.next();
-                if self.tk != Token::Semicolon {
-                    self.expr(Token::Assign.precedence().unwrap());
+
+            35 => { // FREE: c4 never gives memory back either, but --check and --leaks remember
+                if let Some(why) = self.memcheck.as_mut().and_then(|m| m.free(self.stack[self.sp])) {
+                    self.fault(why);
+                    return;
                 }
-                self.e.push(LEV);
-                if let Token::Semicolon = self.tk {
-                    self.next();
-                } else {
-                    eprintln!("{}: semicolon expected", self.line);
-                    std::process::exit(-1);
+                if let Some(l) = &mut self.leaks {
+                    l.free(self.stack[self.sp] as usize);
                 }
             }
-            Token::LBrace => {
-                self.next();
-                while self.tk != Token::RBrace {
-                    self.stmt();
+
+            36 => { // MSET: memset(p, c, n)
+                let (p, c, n) = (self.stack[self.sp + 2], self.stack[self.sp + 1] & 0xFF, self.stack[self.sp]);
+                if !range(p, n) {
+                    self.fault(format!("memset of {} bytes at {}, outside memory", n, p));
+                    return;
+                }
+                let (p, n) = (p as usize, n as usize);
+                if let Some(why) = self.memcheck_range(p, n, true) {
+                    self.fault(why);
+                    return;
                 }
-                self.next();
+                self.stack[p..p + n].fill(c);
+                self.ax = p as i64;
             }
-            Token::Semicolon => {
-                self.next();
+
+            37 => { // MCMP: memcmp(p, q, n)
+                let (p, q, n) = (self.stack[self.sp + 2], self.stack[self.sp + 1], self.stack[self.sp]);
+                if !range(p, n) || !range(q, n) {
+                    self.fault(format!("memcmp of {} bytes at {} and {}, outside memory", n, p, q));
+                    return;
+                }
+                let (p, q, n) = (p as usize, q as usize, n as usize);
+                if let Some(why) = self.memcheck_range(p, n, false).or_else(|| self.memcheck_range(q, n, false)) {
+                    self.fault(why);
+                    return;
+                }
+                self.ax = (0..n)
+                    .map(|i| (self.stack[p + i] & 0xFF) - (self.stack[q + i] & 0xFF))
+                    .find(|&d| d != 0)
+                    .unwrap_or(0);
             }
-            _ => {
-                self.expr(Token::Assign.precedence().unwrap());
-                if let Token::Semicolon = self.tk {
-                    self.next();
-                } else {
-                    eprintln!("{}: semicolon expected", self.line);
-                    std::process::exit(-1);
+
+            38 => { // EXIT: End program
+                self.exit = Some(self.ax);
+                let _ = writeln!(self.stdout, "Program exited with value: {}", self.ax); // print exit value
+                let _ = self.stdout.flush();
+                let reports = [
+                    self.leaks.as_ref().map(|l| l.report(&self.lines)),
+                    self.profile.as_ref().map(|p| p.report(&self.lines)),
+                    self.coverage.as_ref().map(|c| c.report(&self.lines)),
+                ];
+                for report in reports.into_iter().flatten() {
+                    let _ = self.stderr.write_all(report.as_bytes());
                 }
+                let _ = self.stderr.flush();
+                self.running = false; // stop execution
             }
+            _ => unreachable!("not a system call: {}", op),
         }
     }
-}
 
-
-// fn main() {
-//     let mut state = Parser::new();
-    
-//     // Test with a number
-//     state.tk = Token::Num(42);
-//     state.expr(0);
-//     println!("Emitted code (number): {:?}", state.e);
-
-//     // Reset state
-//     state.e.clear();
-
-//     // Test with a string
-//     state.tk = Token::Str("hello".to_string());
-//     state.expr(0);
-//     println!("Emitted code (string): {:?}", state.e);
-//     println!("Data section (string): {:?}", state.data);
-// }
-
-const POOL_SIZE: usize = 256 * 1024; // Define POOL_SIZE
-
-fn main() {
-    let mut src = false;
-    let mut debug = false;
-    let args: Vec<String> = std::env::args().collect();
-    
-    let mut argc = args.len() - 1; // Exclude the program name
-    let mut argv = &args[1..];
-
-    if argc > 0 && argv[0] == "-s" {
-        src = true;
-        argc -= 1;
-        argv = &argv[1..];
-    }
-    if argc > 0 && argv[0] == "-d" {
-        debug = true;
-        argc -= 1;
-        argv = &argv[1..];
+    // reads the NUL terminated string starting at addr
+    pub(crate) fn string_at(&self, addr: usize) -> Vec<u8> {
+        self.stack.get(addr..).unwrap_or_default().iter().take_while(|&&c| c != 0).map(|&c| c as u8).collect()
     }
-    if argc < 1 {
-        eprintln!("usage: c4 [-s] [-d] file ...");
-        return;
-    }
-
-    let file_path = &argv[0];
-    let mut file = match File::open(file_path) {
-        Ok(f) => f,
-        Err(_) => {
-            eprintln!("could not open({})", file_path);
-            return;
-        }
-    };
 
-    // Allocate memory pools
-    let sym = vec![0; POOL_SIZE]; // Symbol area
-    let e = vec![0; POOL_SIZE]; // Text area
-    let data = vec![0; POOL_SIZE]; // Data area
-    let mut sp = vec![0; POOL_SIZE]; // Stack area
-
-    // Read the source file
-    let mut source = String::new();
-    file.read_to_string(&mut source).unwrap();
-
-    // Initialize the lexer
-    let mut lexer = Lexer::new(&source);
-    
-    // Parse declarations
-    let mut line = 1;
-    let mut tk = lexer.next_token(); // Get the first token
-    let mut ival = 0; // Define ival to hold enum initializer values
-    //let mut idmain = None;
-    let mut idmain: Option<Parser> = None; // Define idmain to hold the main function reference
-
-
-    while tk != Some(Token::None) {
-        let mut bt = INT; // Base type
-        match tk {
-            Some(Token::Int) => {
-                tk = lexer.next_token();
-            }
-            Some(Token::Char(_)) => {
-                tk = lexer.next_token();
-                bt = CHAR;
+    // the formatting behind the PRTF syscall: handles the flags, width and
+    // precision of C's printf for %d %i %u %x %X %o %c %s %p %f %e %g and %%
+    fn format(&self, fmt: usize, args: &[i64]) -> String {
+        let fmt = self.string_at(fmt);
+        let mut out = String::new();
+        let mut args = args.iter().copied();
+        let mut i = 0;
+        while i < fmt.len() {
+            if fmt[i] != b'%' {
+                out.push(fmt[i] as char);
+                i += 1;
+                continue;
+            }
+            i += 1;
+            let mut spec = Spec::default();
+            while i < fmt.len() && b"-+ 0#".contains(&fmt[i]) {
+                match fmt[i] {
+                    b'-' => spec.left = true,
+                    b'+' => spec.plus = true,
+                    b' ' => spec.space = true,
+                    b'0' => spec.zero = true,
+                    _ => spec.alt = true,
+                }
+                i += 1;
+            }
+            // a '*' width or precision comes from the next argument
+            if i < fmt.len() && fmt[i] == b'*' {
+                let width = args.next().unwrap_or(0);
+                spec.left |= width < 0;
+                spec.width = width.unsigned_abs() as usize;
+                i += 1;
+            }
+            while i < fmt.len() && fmt[i].is_ascii_digit() {
+                spec.width = spec.width.saturating_mul(10).saturating_add((fmt[i] - b'0') as usize);
+                i += 1;
+            }
+            if i < fmt.len() && fmt[i] == b'.' {
+                i += 1;
+                let mut prec = 0;
+                if i < fmt.len() && fmt[i] == b'*' {
+                    prec = args.next().unwrap_or(0);
+                    i += 1;
+                }
+                while i < fmt.len() && fmt[i].is_ascii_digit() {
+                    prec = prec.saturating_mul(10).saturating_add((fmt[i] - b'0') as i64);
+                    i += 1;
+                }
+                // a negative precision is taken as if it were left out
+                spec.prec = usize::try_from(prec).ok();
+            }
+            // length modifiers don't matter, every argument is a full cell
+            while i < fmt.len() && b"hlLqjzt".contains(&fmt[i]) {
+                i += 1;
+            }
+            if i == fmt.len() {
+                break;
             }
-            Some(Token::Enum) => {
-                tk = lexer.next_token();
-                if tk == Some(Token::LBrace) {
-                    tk = lexer.next_token();
-                    let mut i = 0;
-                    while tk != Some(Token::RBrace) {
-                        if let Some(Token::Id(_)) = tk {
-                            eprintln!("{}: bad enum identifier {:?}", line, tk);
-                            return;
-                        }
-                        tk = lexer.next_token();
-                        if tk == Some(Token::Assign) {
-                            tk = lexer.next_token();
-                            if let Some(Token::Num(_)) = tk {
-                                eprintln!("{}: bad enum initializer", line);
-                                return;
-                            }
-                            // Assuming you have a way to get the value of the number
-                            // For example, if you have a method to get the value of the number token
-                            ival = get_number_value(tk); // Define this function to extract the value
-                            tk = lexer.next_token();
-                        }
-                        // Add to symbol table
-                        // Assuming you have a way to add to the symbol table
-                        // id[Class] = Num; id[Type] = INT; id[Val] = i++;
-                        if tk == Some(Token::Comma) {
-                            tk = lexer.next_token();
-                        }
+            let conv = fmt[i];
+            i += 1;
+            if conv == b'%' {
+                out.push('%');
+                continue;
+            }
+            let arg = args.next().unwrap_or(0);
+            let (sign, body) = match conv {
+                b'd' | b'i' => {
+                    let digits = with_precision(arg.unsigned_abs().to_string(), spec.prec);
+                    (sign_of(arg < 0, &spec), digits)
+                }
+                b'u' => (String::new(), with_precision((arg as u64).to_string(), spec.prec)),
+                b'x' => (String::new(), alt_prefix("0x", with_precision(format!("{:x}", arg as u64), spec.prec), &spec, arg)),
+                b'X' => (String::new(), alt_prefix("0X", with_precision(format!("{:X}", arg as u64), spec.prec), &spec, arg)),
+                b'o' => (String::new(), alt_prefix("0", with_precision(format!("{:o}", arg as u64), spec.prec), &spec, arg)),
+                b'p' => (String::new(), format!("0x{:x}", arg as u64)),
+                b'c' => (String::new(), ((arg as u8) as char).to_string()),
+                b's' => {
+                    let mut s = String::from_utf8_lossy(&self.string_at(arg as usize)).into_owned();
+                    if let Some(p) = spec.prec {
+                        s.truncate(p);
                     }
-                    tk = lexer.next_token();
+                    (String::new(), s)
                 }
-            }
-            _ => {
-                eprintln!("{}: unexpected token {:?}", line, tk);
-                return;
-            }
-        }
-
-        // Handle global declarations
-        while tk != Some(Token::Semicolon) && tk != Some(Token::RBrace) {
-            let mut ty = bt;
-            while tk == Some(Token::Mul) {
-                tk = lexer.next_token();
-                ty += PTR; // Assuming PTR is defined
-            }
-            if let Some(Token::Id(_)) = tk {
-                eprintln!("{}: bad global declaration {:?}", line, tk);
-                return;
-            }
-            // Check for duplicate definitions
-            // Assuming you have a way to check for duplicates
-            tk = lexer.next_token();
-            // id[Type] = ty; // Assuming you have a way to set the type
-
-            if tk == Some(Token::LParen) { // Function
-                // Handle function declaration
-                // Similar to the C code, handle parameters and local declarations
+                b'f' | b'F' | b'e' | b'E' | b'g' | b'G' => {
+                    let v = to_f64(arg);
+                    (sign_of(v.is_sign_negative() && !v.is_nan(), &spec), format_float(v.abs(), conv, &spec))
+                }
+                _ => {
+                    // unknown conversion, print it back verbatim like most libcs
+                    out.push('%');
+                    out.push(conv as char);
+                    continue;
+                }
+            };
+            let len = sign.len() + body.len();
+            let pad = spec.width.saturating_sub(len);
+            let numeric = !matches!(conv, b'c' | b's');
+            let zero_ok = numeric && (spec.prec.is_none() || matches!(conv, b'f' | b'F' | b'e' | b'E' | b'g' | b'G'));
+            if spec.left {
+                out.push_str(&sign);
+                out.push_str(&body);
+                out.push_str(&" ".repeat(pad));
+            } else if spec.zero && zero_ok {
+                out.push_str(&sign);
+                out.push_str(&"0".repeat(pad));
+                out.push_str(&body);
             } else {
-                // Handle global variable declaration
-                // id[Class] = Glo; id[Val] = (int)data; // Assuming you have a way to set these
-                // data += std::mem::size_of::<i32>(); // Assuming you have a way to manage data
-            }
-            if tk == Some(Token::Comma) {
-                tk = lexer.next_token();
+                out.push_str(&" ".repeat(pad));
+                out.push_str(&sign);
+                out.push_str(&body);
             }
         }
-        tk = lexer.next_token();
+        out
     }
+}
 
-    // Assuming idmain is defined and points to the main function
-    if let Some(main_func) = idmain {
-        // let pc = main_func.ival as *mut i32; // Get the program counter from the main function
-        let mut pc = main_func.ival as *mut i32;  // Declare pc as mutable
-        if src {
-            return;
-        }
-
-        // Setup stack
-        let mut bp = unsafe{sp.as_mut_ptr().add(POOL_SIZE)};
-        let mut sp = bp;
-        let mut t = 0; // Define t as needed
-        unsafe {
-            *sp = EXIT; // Call exit if main returns
-            sp = sp.sub(1);
-            *sp = PSH; // Push the return address
-            sp = sp.sub(1);
-            *sp = argc as i32; // Push argc
-            sp = sp.sub(1);
-            *sp = argv.as_ptr() as i32; // Push argv
-            sp = sp.sub(1);
-            *sp = t as i32; // Push the temporary pointer
-        }
+// a double in a cell is stored as its bit pattern
+fn to_f64(cell: i64) -> f64 {
+    f64::from_bits(cell as u64)
+}
 
-        // Run the virtual machine
-        let mut cycle = 0;
-        loop {
-            let i = unsafe { *pc }; // Fetch the instruction
-            pc = unsafe{pc.add(1)}; // Move to the next instruction
-            cycle += 1;
+fn to_cell(v: f64) -> i64 {
+    v.to_bits() as i64
+}
 
-            if debug {
-                println!("{}> {:?}", cycle, i); // Print debug information
-            }
+// one parsed printf conversion specification
+#[derive(Default)]
+struct Spec {
+    left: bool,          // '-': pad on the right
+    plus: bool,          // '+': always print a sign
+    space: bool,         // ' ': a space where a plus would go
+    zero: bool,          // '0': pad numbers with zeros
+    alt: bool,           // '#': alternate form
+    width: usize,        // minimum field width
+    prec: Option<usize>, // precision after the '.'
+}
 
-            match i {
-                LEA => {
-                    let a = unsafe { *(bp.add((*pc).try_into().unwrap())) }; // Load local address
-                    pc = unsafe{pc.add(1)};
-                }
-                IMM => {
-                    let a = unsafe { *pc }; // Load immediate value
-                    pc = unsafe{pc.add(1)};
-                }
-                JMP => {
-                    pc = unsafe { *(pc as *mut *mut i32) }; // Jump
-                }
-                JSR => {
-                    unsafe {
-                        sp = sp.sub(1);
-                        *sp = (pc as i32) + 1; // Save return address
-                    }
-                    pc = unsafe { *(pc as *mut *mut i32) }; // Jump to subroutine
-                }
-                BZ => {
-                    let a = unsafe { *sp }; // Fetch value from stack
-                    if a == 0 {
-                        pc = unsafe { *(pc as *mut *mut i32) }; // Branch if zero
-                    } else {
-                        pc = unsafe{pc.add(1)};
-                    }
-                }
-                BNZ => {
-                    let a = unsafe { *sp }; // Fetch value from stack
-                    if a != 0 {
-                        pc = unsafe { *(pc as *mut *mut i32) }; // Branch if not zero
-                    } else {
-                        pc =unsafe{ pc.add(1)};
-                    }
-                }
-                ENT => {
-                    unsafe {
-                        sp = sp.sub(1);
-                        *sp = bp as i32; // Enter subroutine
-                        sp = sp.sub((*pc as usize));  // Adjust stack pointer
-                    }
-                    bp = sp;
-                    pc = unsafe{pc.add(1)};
-                }
-                ADJ => {
-                    // sp = unsafe {sp.add(*pc)}; // Stack adjust
-                    sp = unsafe {sp.add((*pc).try_into().unwrap())}; // Stack adjust
-                    pc = unsafe {pc.add(1)};
-                }
-                LEV => {
-                    sp = bp; // Leave subroutine
-                    bp = unsafe { *sp as *mut i32 };
-                    pc = unsafe { *sp.add(1) as *mut i32 };
-                }
-                LI => {
-                    let a = unsafe { *(sp as *mut i32) }; // Load integer from stack
-                }
-                LC => {
-                    let a = unsafe { *(sp as *mut u8) }; // Load character from stack
-                }
-                SI => {
-                    let a = unsafe { *sp }; // Fetch integer from stack
-                    unsafe {
-                        *(sp as *mut i32) = a; // Store integer
-                    }
-                    sp = unsafe{sp.add(1)};
-                }
-                SC => {
-                    let a = unsafe { *sp }; // Fetch character from stack
-                    unsafe {
-                        *(sp as *mut u8) = a as u8; // Store character
-                    }
-                    sp = unsafe{sp.add(1)};
-                }
-                PSH => {
-                    let a = unsafe { *sp }; // Fetch value to push onto stack
-                    unsafe {
-                        sp = sp.sub(1);
-                        *sp = a; // Push value
-                    }
-                }
-                EXIT => {
-                    unsafe {
-                        println!("exit({}) cycle = {}", *sp, cycle);
-                    }
-                    return; // Exit the program
-                }
-                _ => {
-                    eprintln!("unknown instruction = {}! cycle = {}", i, cycle);
-                    return; // Handle unknown instruction
-                }
-            }
-            
-            
-        }
+fn sign_of(negative: bool, spec: &Spec) -> String {
+    if negative {
+        "-".to_string()
+    } else if spec.plus {
+        "+".to_string()
+    } else if spec.space {
+        " ".to_string()
     } else {
-        eprintln!("Main function not defined.");
+        String::new()
     }
 }
 
-// Define a function to extract the value from a number token
-fn get_number_value(token: Option<Token>) -> i32 {
-    match token {
-        Some(Token::Num(value)) => value,
-        _ => 0, // Default value if not a number
+// the precision of an integer conversion is its minimum number of digits
+fn with_precision(digits: String, prec: Option<usize>) -> String {
+    match prec {
+        Some(0) if digits == "0" => String::new(),
+        Some(p) if digits.len() < p => "0".repeat(p - digits.len()) + &digits,
+        _ => digits,
     }
 }
+
+fn alt_prefix(prefix: &str, digits: String, spec: &Spec, arg: i64) -> String {
+    if spec.alt && arg != 0 && !digits.starts_with('0') {
+        format!("{}{}", prefix, digits)
+    } else {
+        digits
+    }
+}
+
+// formats a non-negative double for %f, %e and %g the way C does
+fn format_float(v: f64, conv: u8, spec: &Spec) -> String {
+    let upper = conv.is_ascii_uppercase();
+    let s = if v.is_nan() {
+        "nan".to_string()
+    } else if v.is_infinite() {
+        "inf".to_string()
+    } else {
+        let prec = spec.prec.unwrap_or(6);
+        match conv.to_ascii_lowercase() {
+            b'f' => format!("{:.*}", prec, v),
+            b'e' => exp_form(v, prec),
+            _ => {
+                // %g picks %e or %f depending on the exponent, then drops trailing zeros
+                let p = if prec == 0 { 1 } else { prec };
+                let exp = if v == 0.0 { 0 } else { exp_form(v, p - 1).split('e').nth(1).unwrap().parse::<i32>().unwrap() };
+                let s = if exp < -4 || exp >= p as i32 {
+                    exp_form(v, p - 1)
+                } else {
+                    format!("{:.*}", (p as i32 - 1 - exp) as usize, v)
+                };
+                if spec.alt { s } else { strip_zeros(&s) }
+            }
+        }
+    };
+    if upper { s.to_uppercase() } else { s }
+}
+
+// d.ddde+xx with prec digits after the point and at least two exponent digits
+fn exp_form(v: f64, prec: usize) -> String {
+    let s = format!("{:.*e}", prec, v);
+    let (mantissa, exp) = s.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    format!("{}e{}{:02}", mantissa, if exp < 0 { '-' } else { '+' }, exp.abs())
+}
+
+// removes trailing zeros (and a trailing point) from the fraction of a %g result
+fn strip_zeros(s: &str) -> String {
+    let (num, exp) = match s.find('e') {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let num = if num.contains('.') { num.trim_end_matches('0').trim_end_matches('.') } else { num };
+    format!("{}{}", num, exp)
+}
@@ -0,0 +1,561 @@
+// The WebAssembly backend: --emit=wasm writes a binary module, --emit=wat the
+// same module as text.
+//
+// Memory is laid out like the VM's: one i64 cell per address, so cell n lives
+// at byte 8n, with the data segment at the bottom, the heap after it and the
+// stack at the top of POOL_SIZE cells. ax, sp and bp are globals, every c4
+// function becomes a wasm function (JSR is a call, LEV a return, after
+// keeping the stack exactly as the VM does) and jumps inside a function go
+// through a loop around a br_table over its basic blocks. The system calls
+// are imported from the "c4" module; src/runtime.mjs provides them for node.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::codegen::operands;
+
+// imported system calls, by opcode from OPEN to EXIT and then WRIT. Each
+// takes (sp, argc, ax) and returns the new ax; exit doesn't return.
+const IMPORTS: [&str; 10] = ["open", "read", "close", "printf", "malloc", "free", "memset", "memcmp", "exit", "write"];
+
+fn import(op: i64) -> usize {
+    if op == 66 { 9 } else { op as usize - 30 }
+}
+
+const GLOBAL_AX: u32 = 0;
+const GLOBAL_SP: u32 = 1;
+const GLOBAL_BP: u32 = 2;
+const LOCAL_PC: u32 = 0;
+const LOCAL_A: u32 = 1;
+
+// one wasm instruction, as text and as its binary encoding
+struct Ins {
+    text: String,
+    bytes: Vec<u8>,
+}
+
+fn uleb(mut v: u64, out: &mut Vec<u8>) {
+    loop {
+        let b = (v & 0x7F) as u8;
+        v >>= 7;
+        if v == 0 {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+fn sleb(mut v: i64, out: &mut Vec<u8>) {
+    loop {
+        let b = (v & 0x7F) as u8;
+        v >>= 7;
+        if (v == 0 && b & 0x40 == 0) || (v == -1 && b & 0x40 != 0) {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+struct Body {
+    code: Vec<Ins>,
+}
+
+impl Body {
+    fn op(&mut self, text: &str, bytes: &[u8]) {
+        self.code.push(Ins { text: text.to_string(), bytes: bytes.to_vec() });
+    }
+
+    fn local(&mut self, text: &str, code: u8, index: u32) {
+        self.op(&format!("{} {}", text, ["$pc", "$a"][index as usize]), &[code, index as u8]);
+    }
+
+    fn call(&mut self, index: u32, name: &str) {
+        let mut bytes = vec![0x10];
+        uleb(index as u64, &mut bytes);
+        self.op(&format!("call ${}", name), &bytes);
+    }
+
+    fn i64_const(&mut self, v: i64) {
+        let mut bytes = vec![0x42];
+        sleb(v, &mut bytes);
+        self.op(&format!("i64.const {}", v), &bytes);
+    }
+
+    fn global_get(&mut self, g: u32) {
+        let name = ["$ax", "$sp", "$bp"][g as usize];
+        self.op(&format!("global.get {}", name), &[0x23, g as u8]);
+    }
+
+    fn global_set(&mut self, g: u32) {
+        let name = ["$ax", "$sp", "$bp"][g as usize];
+        self.op(&format!("global.set {}", name), &[0x24, g as u8]);
+    }
+
+    // turns the cell address on the wasm stack into a byte address
+    fn cell(&mut self) {
+        self.i64_const(3);
+        self.op("i64.shl", &[0x86]);
+        self.op("i32.wrap_i64", &[0xA7]);
+    }
+
+    fn load(&mut self) {
+        self.cell();
+        self.op("i64.load", &[0x29, 0x03, 0x00]);
+    }
+
+    // sp += n
+    fn adjust_sp(&mut self, n: i64) {
+        self.global_get(GLOBAL_SP);
+        self.i64_const(n);
+        self.op("i64.add", &[0x7C]);
+        self.global_set(GLOBAL_SP);
+    }
+
+    // pushes the value `value` leaves on the wasm stack
+    fn push(&mut self, value: impl FnOnce(&mut Body)) {
+        self.adjust_sp(-1);
+        self.global_get(GLOBAL_SP);
+        self.cell();
+        value(self);
+        self.op("i64.store", &[0x37, 0x03, 0x00]);
+    }
+
+    // pops the left operand of a binary operator into $a
+    fn pop_a(&mut self) {
+        self.global_get(GLOBAL_SP);
+        self.load();
+        self.local("local.set", 0x21, LOCAL_A);
+        self.adjust_sp(1);
+    }
+
+    // ax = a <op> ax, for operators on integers
+    fn binary(&mut self, text: &str, code: u8, compare: bool) {
+        self.pop_a();
+        self.local("local.get", 0x20, LOCAL_A);
+        self.global_get(GLOBAL_AX);
+        self.op(text, &[code]);
+        if compare {
+            self.op("i64.extend_i32_u", &[0xAD]);
+        }
+        self.global_set(GLOBAL_AX);
+    }
+
+    // the same for doubles, which live in cells as their bits
+    fn float_binary(&mut self, text: &str, code: u8, compare: bool) {
+        self.pop_a();
+        self.local("local.get", 0x20, LOCAL_A);
+        self.op("f64.reinterpret_i64", &[0xBF]);
+        self.global_get(GLOBAL_AX);
+        self.op("f64.reinterpret_i64", &[0xBF]);
+        self.op(text, &[code]);
+        if compare {
+            self.op("i64.extend_i32_u", &[0xAD]);
+        } else {
+            self.op("i64.reinterpret_f64", &[0xBD]);
+        }
+        self.global_set(GLOBAL_AX);
+    }
+
+    // ax = f(ax)
+    fn unary(&mut self, ops: &[(&str, &[u8])]) {
+        self.global_get(GLOBAL_AX);
+        for (text, bytes) in ops {
+            self.op(text, bytes);
+        }
+        self.global_set(GLOBAL_AX);
+    }
+
+    // a jump to basic block `to` from code `depth` blocks inside the dispatch loop
+    fn jump(&mut self, to: usize, depth: usize) {
+        let mut bytes = vec![0x41];
+        sleb(to as i64, &mut bytes);
+        self.op(&format!("i32.const {}", to), &bytes);
+        self.local("local.set", 0x21, LOCAL_PC);
+        let mut bytes = vec![0x0C];
+        uleb(depth as u64, &mut bytes);
+        self.op(&format!("br {}", depth), &bytes);
+    }
+}
+
+struct Translator<'a> {
+    text: &'a [i64],
+    funcs: &'a BTreeMap<usize, (u32, String)>, // text address -> function index and name
+}
+
+impl Translator<'_> {
+    // one instruction; jumps go to the basic block of their target, `depth`
+    // blocks out from where the code is
+    fn translate(&self, b: &mut Body, pc: usize, blocks: &BTreeMap<usize, usize>, depth: usize) -> Result<(), String> {
+        let text = self.text;
+        let arg = |k: usize| text.get(pc + k).copied().unwrap_or(0);
+        let block = |addr: i64| match blocks.get(&(addr as usize)) {
+            Some(&i) => Ok(i),
+            None => Err(format!("jump to {}, outside the function", addr)),
+        };
+        match text[pc] {
+            0 => {
+                b.global_get(GLOBAL_BP);
+                b.i64_const(arg(1));
+                b.op("i64.add", &[0x7C]);
+                b.global_set(GLOBAL_AX);
+            }
+            1 => {
+                b.i64_const(arg(1));
+                b.global_set(GLOBAL_AX);
+            }
+            2 => b.jump(block(arg(1))?, depth),
+            3 => {
+                let (f, name) = match self.funcs.get(&(arg(1) as usize)) {
+                    Some(f) => f,
+                    None => return Err(format!("call to {}, which is not a function", arg(1))),
+                };
+                // the return address stays on the stack like the VM's, so frames line up
+                b.push(|b| b.i64_const(pc as i64 + 2));
+                b.call(*f, name);
+            }
+            op @ (4 | 5) => {
+                b.global_get(GLOBAL_AX);
+                if op == 4 {
+                    b.op("i64.eqz", &[0x50]);
+                } else {
+                    b.i64_const(0);
+                    b.op("i64.ne", &[0x52]);
+                }
+                b.op("if", &[0x04, 0x40]);
+                b.jump(block(arg(1))?, depth + 1);
+                b.op("end", &[0x0B]);
+            }
+            6 => {
+                b.push(|b| b.global_get(GLOBAL_BP));
+                b.global_get(GLOBAL_SP);
+                b.global_set(GLOBAL_BP);
+                b.adjust_sp(-arg(1));
+            }
+            7 => b.adjust_sp(arg(1)),
+            8 => {
+                b.global_get(GLOBAL_BP);
+                b.global_set(GLOBAL_SP);
+                b.global_get(GLOBAL_SP);
+                b.load();
+                b.global_set(GLOBAL_BP);
+                b.adjust_sp(2);
+                b.op("return", &[0x0F]);
+            }
+            op @ (9 | 10) => {
+                b.global_get(GLOBAL_AX);
+                b.load();
+                if op == 10 {
+                    b.i64_const(0xFF);
+                    b.op("i64.and", &[0x83]);
+                }
+                b.global_set(GLOBAL_AX);
+            }
+            op @ (11 | 12) => {
+                b.pop_a();
+                b.local("local.get", 0x20, LOCAL_A);
+                b.cell();
+                b.global_get(GLOBAL_AX);
+                if op == 12 {
+                    b.i64_const(0xFF);
+                    b.op("i64.and", &[0x83]);
+                }
+                b.op("i64.store", &[0x37, 0x03, 0x00]);
+            }
+            13 => b.push(|b| b.global_get(GLOBAL_AX)),
+            14 => b.binary("i64.or", 0x84, false),
+            15 => b.binary("i64.xor", 0x85, false),
+            16 => b.binary("i64.and", 0x83, false),
+            17 => b.binary("i64.eq", 0x51, true),
+            18 => b.binary("i64.ne", 0x52, true),
+            19 => b.binary("i64.lt_s", 0x53, true),
+            20 => b.binary("i64.gt_s", 0x55, true),
+            21 => b.binary("i64.le_s", 0x57, true),
+            22 => b.binary("i64.ge_s", 0x59, true),
+            23 => b.binary("i64.shl", 0x86, false),
+            24 => b.binary("i64.shr_s", 0x87, false),
+            25 => b.binary("i64.add", 0x7C, false),
+            26 => b.binary("i64.sub", 0x7D, false),
+            27 => b.binary("i64.mul", 0x7E, false),
+            28 => b.binary("i64.div_s", 0x7F, false),
+            29 => b.binary("i64.rem_s", 0x81, false),
+            op @ (30..=38 | 66) => {
+                let argc = if op == 33 && text.get(pc + 1) == Some(&7) { arg(2) } else { 0 };
+                b.global_get(GLOBAL_SP);
+                b.i64_const(argc);
+                b.global_get(GLOBAL_AX);
+                b.call(import(op) as u32, IMPORTS[import(op)]);
+                b.global_set(GLOBAL_AX);
+                if op == 38 {
+                    b.op("unreachable", &[0x00]);
+                }
+            }
+            39 => b.binary("i64.lt_u", 0x54, true),
+            40 => b.binary("i64.gt_u", 0x56, true),
+            41 => b.binary("i64.le_u", 0x58, true),
+            42 => b.binary("i64.ge_u", 0x5A, true),
+            43 => b.binary("i64.shr_u", 0x88, false),
+            44 => b.binary("i64.div_u", 0x80, false),
+            45 => b.binary("i64.rem_u", 0x82, false),
+            op @ (46 | 47) => {
+                let shift = 64 - arg(1);
+                b.global_get(GLOBAL_AX);
+                b.i64_const(shift);
+                b.op("i64.shl", &[0x86]);
+                b.i64_const(shift);
+                if op == 46 {
+                    b.op("i64.shr_s", &[0x87]);
+                } else {
+                    b.op("i64.shr_u", &[0x88]);
+                }
+                b.global_set(GLOBAL_AX);
+            }
+            48 => {
+                b.i64_const(((arg(2) as u32 as u64) << 32 | arg(1) as u32 as u64) as i64);
+                b.global_set(GLOBAL_AX);
+            }
+            49 => b.float_binary("f64.add", 0xA0, false),
+            50 => b.float_binary("f64.sub", 0xA1, false),
+            51 => b.float_binary("f64.mul", 0xA2, false),
+            52 => b.float_binary("f64.div", 0xA3, false),
+            53 => b.float_binary("f64.eq", 0x61, true),
+            54 => b.float_binary("f64.ne", 0x62, true),
+            55 => b.float_binary("f64.lt", 0x63, true),
+            56 => b.float_binary("f64.gt", 0x64, true),
+            57 => b.float_binary("f64.le", 0x65, true),
+            58 => b.float_binary("f64.ge", 0x66, true),
+            59 => b.unary(&[("f64.convert_i64_s", &[0xB9]), ("i64.reinterpret_f64", &[0xBD])]),
+            60 => b.unary(&[("f64.convert_i64_u", &[0xBA]), ("i64.reinterpret_f64", &[0xBD])]),
+            // the saturating truncations convert like Rust's `as`
+            61 => b.unary(&[("f64.reinterpret_i64", &[0xBF]), ("i64.trunc_sat_f64_s", &[0xFC, 0x06])]),
+            62 => b.unary(&[("f64.reinterpret_i64", &[0xBF]), ("i64.trunc_sat_f64_u", &[0xFC, 0x07])]),
+            63 => b.unary(&[
+                ("f64.reinterpret_i64", &[0xBF]),
+                ("f32.demote_f64", &[0xB6]),
+                ("f64.promote_f32", &[0xBB]),
+                ("i64.reinterpret_f64", &[0xBD]),
+            ]),
+            op @ (64 | 65) => {
+                // the conversion of the left operand, on top of the stack
+                b.global_get(GLOBAL_SP);
+                b.cell();
+                b.global_get(GLOBAL_SP);
+                b.load();
+                if op == 64 {
+                    b.op("f64.convert_i64_s", &[0xB9]);
+                } else {
+                    b.op("f64.convert_i64_u", &[0xBA]);
+                }
+                b.op("i64.reinterpret_f64", &[0xBD]);
+                b.op("i64.store", &[0x37, 0x03, 0x00]);
+            }
+            op => return Err(format!("unknown instruction {}", op)),
+        }
+        Ok(())
+    }
+
+    // the instructions in [from, to) as a function body
+    fn body(&self, from: usize, to: usize) -> Result<Body, String> {
+        let text = self.text;
+        let mut pcs = Vec::new();
+        let mut leaders = BTreeSet::from([from]);
+        let mut pc = from;
+        while pc < to {
+            pcs.push(pc);
+            let next = pc + 1 + operands(text[pc] as i32);
+            if matches!(text[pc], 2 | 4 | 5) {
+                leaders.insert(text[pc + 1] as usize);
+                leaders.insert(next);
+            }
+            pc = next;
+        }
+        leaders.retain(|&l| l < to);
+        let blocks: BTreeMap<usize, usize> = leaders.iter().enumerate().map(|(i, &l)| (l, i)).collect();
+        let n = blocks.len();
+
+        let mut b = Body { code: Vec::new() };
+        if n == 1 {
+            for &pc in &pcs {
+                self.translate(&mut b, pc, &blocks, 0)?;
+            }
+            return Ok(b);
+        }
+        // loop { block*n { br_table } code0 } code1 } ... }: the code of
+        // block i sits after the end of the i-th innermost block
+        b.op("loop", &[0x03, 0x40]);
+        for _ in 0..n {
+            b.op("block", &[0x02, 0x40]);
+        }
+        b.local("local.get", 0x20, LOCAL_PC);
+        let mut bytes = vec![0x0E];
+        uleb(n as u64 - 1, &mut bytes);
+        for i in 0..n as u64 {
+            uleb(i, &mut bytes);
+        }
+        let targets: Vec<String> = (0..n).map(|i| i.to_string()).collect();
+        b.op(&format!("br_table {}", targets.join(" ")), &bytes);
+        let mut current = 0;
+        for &pc in &pcs {
+            if let Some(&i) = blocks.get(&pc) {
+                b.op(&format!("end ;; L{}", pc), &[0x0B]);
+                current = i;
+            }
+            // inside the blocks of the later basic blocks, then the loop
+            self.translate(&mut b, pc, &blocks, n - 1 - current)?;
+        }
+        b.op("end", &[0x0B]);
+        Ok(b)
+    }
+}
+
+fn section(id: u8, contents: Vec<u8>, out: &mut Vec<u8>) {
+    out.push(id);
+    uleb(contents.len() as u64, out);
+    out.extend(contents);
+}
+
+fn name(s: &str, out: &mut Vec<u8>) {
+    uleb(s.len() as u64, out);
+    out.extend(s.as_bytes());
+}
+
+pub struct Module {
+    funcs: Vec<(String, Body)>, // the c4 functions in text order, then run
+    data: Vec<u8>,
+    pages: u64,
+}
+
+// the whole program: one function per c4 function, and `run(sp, argc, argv)`
+// which sets up main's stack below sp like the Rust driver and runs the exit
+// stub after main; the runtime copies the arguments in above sp first
+pub fn build(text: &[i64], functions: &HashMap<String, i32>, entry: usize, stub: usize, data: &[u8], cells: usize) -> Result<Module, String> {
+    let names: BTreeMap<usize, &str> = functions.iter().map(|(n, &pc)| (pc as usize, n.as_str())).collect();
+    let funcs: BTreeMap<usize, (u32, String)> =
+        names.iter().enumerate().map(|(i, (&pc, name))| (pc, ((IMPORTS.len() + i) as u32, format!("f_{}", name)))).collect();
+    let t = Translator { text, funcs: &funcs };
+    let starts: Vec<usize> = names.keys().copied().chain([stub]).collect();
+    let mut out: Vec<(String, Body)> =
+        starts.windows(2).zip(names.values()).map(|(w, name)| Ok((name.to_string(), t.body(w[0], w[1])?))).collect::<Result<_, String>>()?;
+
+    let mut run = Body { code: Vec::new() };
+    run.op("local.get $sp", &[0x20, 0]);
+    run.global_set(GLOBAL_SP);
+    run.push(|b| b.op("local.get $argc", &[0x20, 1]));
+    run.push(|b| b.op("local.get $argv", &[0x20, 2]));
+    run.push(|b| b.i64_const(stub as i64));
+    run.global_get(GLOBAL_SP);
+    run.global_set(GLOBAL_BP);
+    let (main, name) = funcs.get(&entry).ok_or("main is not a function")?;
+    run.call(*main, name);
+    let tail = t.body(stub, text.len())?;
+    run.code.extend(tail.code);
+    out.push(("run".to_string(), run));
+    Ok(Module { funcs: out, data: data.to_vec(), pages: ((cells * 8) as u64).div_ceil(65536) })
+}
+
+impl Module {
+    pub fn wat(&self) -> String {
+        let mut out = String::from("(module\n");
+        for name in IMPORTS {
+            out.push_str(&format!("  (import \"c4\" \"{0}\" (func ${0} (param i64 i64 i64) (result i64)))\n", name));
+        }
+        out.push_str(&format!("  (memory (export \"memory\") {})\n", self.pages));
+        out.push_str("  (global $ax (mut i64) (i64.const 0))\n  (global $sp (mut i64) (i64.const 0))\n  (global $bp (mut i64) (i64.const 0))\n");
+        out.push_str(&format!("  (global (export \"data_len\") i64 (i64.const {}))\n", self.data.len()));
+        for (i, (name, body)) in self.funcs.iter().enumerate() {
+            if i == self.funcs.len() - 1 {
+                out.push_str("  (func $run (export \"run\") (param $sp i64) (param $argc i64) (param $argv i64)\n");
+            } else {
+                out.push_str(&format!("  (func $f_{} (local $pc i32) (local $a i64)\n", name));
+            }
+            let mut indent = 2;
+            for ins in &body.code {
+                if ins.text.starts_with("end") {
+                    indent -= 1;
+                }
+                out.push_str(&format!("{}{}\n", "  ".repeat(indent), ins.text));
+                if matches!(ins.text.as_str(), "loop" | "block" | "if") {
+                    indent += 1;
+                }
+            }
+            out.push_str("  )\n");
+        }
+        // each byte of data is a cell of its own
+        if !self.data.is_empty() {
+            let bytes: String = self.data.iter().map(|&b| format!("\\{:02x}\\00\\00\\00\\00\\00\\00\\00", b)).collect();
+            out.push_str(&format!("  (data (i32.const 0) \"{}\")\n", bytes));
+        }
+        out.push_str(")\n");
+        out
+    }
+
+    pub fn binary(&self) -> Vec<u8> {
+        let mut out = b"\0asm\x01\0\0\0".to_vec();
+        // types: the system calls, the c4 functions and run
+        section(1, vec![3, 0x60, 3, 0x7E, 0x7E, 0x7E, 1, 0x7E, 0x60, 0, 0, 0x60, 3, 0x7E, 0x7E, 0x7E, 0], &mut out);
+
+        let mut imports = Vec::new();
+        uleb(IMPORTS.len() as u64, &mut imports);
+        for n in IMPORTS {
+            name("c4", &mut imports);
+            name(n, &mut imports);
+            imports.extend([0x00, 0x00]);
+        }
+        section(2, imports, &mut out);
+
+        let mut funcs = Vec::new();
+        uleb(self.funcs.len() as u64, &mut funcs);
+        for i in 0..self.funcs.len() {
+            funcs.push(if i == self.funcs.len() - 1 { 2 } else { 1 });
+        }
+        section(3, funcs, &mut out);
+
+        let mut memory = vec![1, 0];
+        uleb(self.pages, &mut memory);
+        section(5, memory, &mut out);
+
+        let mut globals = vec![4];
+        for _ in 0..3 {
+            globals.extend([0x7E, 0x01, 0x42, 0x00, 0x0B]);
+        }
+        globals.extend([0x7E, 0x00, 0x42]);
+        sleb(self.data.len() as i64, &mut globals);
+        globals.push(0x0B);
+        section(6, globals, &mut out);
+
+        let run = (IMPORTS.len() + self.funcs.len() - 1) as u64;
+        let mut exports = vec![3];
+        name("memory", &mut exports);
+        exports.extend([0x02, 0x00]);
+        name("run", &mut exports);
+        exports.push(0x00);
+        uleb(run, &mut exports);
+        name("data_len", &mut exports);
+        exports.extend([0x03, 0x03]);
+        section(7, exports, &mut out);
+
+        let mut code = Vec::new();
+        uleb(self.funcs.len() as u64, &mut code);
+        for (i, (_, body)) in self.funcs.iter().enumerate() {
+            // locals: $pc and $a, run only has its parameters
+            let mut f = if i == self.funcs.len() - 1 { vec![0] } else { vec![2, 1, 0x7F, 1, 0x7E] };
+            for ins in &body.code {
+                f.extend(&ins.bytes);
+            }
+            f.push(0x0B);
+            uleb(f.len() as u64, &mut code);
+            code.extend(f);
+        }
+        section(10, code, &mut out);
+
+        if !self.data.is_empty() {
+            let mut data = vec![1, 0, 0x41, 0, 0x0B];
+            uleb(self.data.len() as u64 * 8, &mut data);
+            for &b in &self.data {
+                data.extend([b, 0, 0, 0, 0, 0, 0, 0]);
+            }
+            section(11, data, &mut out);
+        }
+        out
+    }
+}
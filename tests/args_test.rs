@@ -0,0 +1,46 @@
+// tests/args_test.rs
+
+mod common;
+use common::run_c;
+
+// prints its arguments after the program's name, and returns how many there were
+const ARGS: &str = r#"
+int main(int argc, char **argv)
+{
+  int i;
+  i = 1;
+  while (i < argc) { printf("%d %s %d\n", i, argv[i], argv[i][0]); i++; }
+  if (argv[argc]) return -1;
+  return argc - 1;
+}
+"#;
+
+#[test]
+fn test_main_gets_the_arguments_after_the_source_file() {
+    for flags in [&["--dispatch=classic"][..], &["--dispatch=decoded"], &["--jit"], &["-O2"]] {
+        let (out, _, code) = run_c("args", ARGS, flags, &["one", "", "thr ee"]);
+        assert_eq!(out, "1 one 111\n2  0\n3 thr ee 116\nProgram exited with value: 3\n", "{:?}", flags);
+        assert_eq!(code, 3, "{:?}", flags);
+    }
+    // argv[0] is the source file, which the program can open like c4.c does
+    let (out, _, _) = run_c("self", "int main(int argc, char **argv) { printf(\"%d\\n\", open(argv[0], 0) > 0); return 0; }", &[], &[]);
+    assert_eq!(out, "1\nProgram exited with value: 0\n");
+}
+
+// what main returns or exit gets is the exit code, modulo 256 like any process's
+#[test]
+fn test_exit_code_is_the_return_value() {
+    let cases = [
+        ("int main() { return 0; }", 0),
+        ("int main() { return 42; }", 42),
+        ("int main() { return 300; }", 44),
+        ("int main() { return -1; }", 255),
+        ("int f() { exit(7); return 1; }\nint main() { f(); return 2; }", 7),
+    ];
+    for (source, expected) in cases {
+        for flags in [&[][..], &["--jit"]] {
+            let (_, _, code) = run_c("exit", source, flags, &[]);
+            assert_eq!(code, expected, "{} {:?}", source, flags);
+        }
+    }
+}
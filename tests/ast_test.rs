@@ -0,0 +1,57 @@
+// tests/ast_test.rs
+
+mod common;
+use common::run_c;
+
+const PROGRAM: &str = r#"int g;
+int main()
+{
+  int i; char *s;
+  s = "hi";
+  i = 2 + 3 * g;
+  if (i > 1) return -i;
+  return sizeof(int);
+}
+"#;
+
+// one node per line with its type and line, and nothing is run
+#[test]
+fn test_dump_ast() {
+    let (out, err, code) = run_c("dump", PROGRAM, &["--dump-ast"], &[]);
+    assert_eq!(err, "");
+    assert_eq!(code, 0);
+    assert_eq!(
+        out,
+        "Global g 'int' @1
+Function main 'int' @2
+  Local i 'int' @4
+  Local s 'char *' @4
+  Assign 'char *' @5
+    Local s [bp-2] 'char *' @5
+    Str &4 'char *' @5
+  Assign 'int' @6
+    Local i [bp-1] 'int' @6
+    Binary Add 'int' @6
+      Num 2 'int' @6
+      Binary Mul 'int' @6
+        Num 3 'int' @6
+        Global g &0 'int' @6
+  If
+    Binary Gt 'int' @7
+      Local i [bp-1] 'int' @7
+      Num 1 'int' @7
+    Return @7
+      Unary Neg 'int' @7
+        Local i [bp-1] 'int' @7
+  Return @8
+    Sizeof 'int' 'int' @8
+"
+    );
+}
+
+// -O folds the tree before it is dumped
+#[test]
+fn test_dump_ast_after_folding() {
+    let (out, _, _) = run_c("folded", "int main() { return 2 + 3 * 4; }", &["-O", "--dump-ast"], &[]);
+    assert!(out.contains("  Return @1\n    Num 14 'int' @1\n"), "{}", out);
+}
@@ -0,0 +1,110 @@
+// tests/check_test.rs
+
+mod common;
+use common::run_c;
+
+// a program that only touches memory it owns runs the same with --check
+#[test]
+fn test_check_passes_correct_programs() {
+    let source = r#"
+int sum(int *a, int n) { int s; s = 0; while (n > 0) { n--; s = s + a[n]; } return s; }
+int bump(int x) { int *p; p = &x; *p = *p + 5; return x; }
+int main()
+{
+  int *a; char *s; int i;
+  a = malloc(10 * sizeof(int));
+  s = malloc(4);
+  memset(s, 'x', 3); s[3] = 0;
+  i = 0;
+  while (i < 10) { a[i] = i; i++; }
+  printf("%s %d %d\n", s, sum(a, 10), bump(2));
+  free(s);
+  free(a);
+  free(0);
+  return 0;
+}
+"#;
+    let (plain, _, _) = run_c("correct", source, &[], &[]);
+    let (checked, err, code) = run_c("correct", source, &["--check"], &[]);
+    assert_eq!(plain, "xxx 45 7\nProgram exited with value: 0\n");
+    assert_eq!(checked, plain, "stderr was: {}", err);
+    assert_eq!(code, 0);
+}
+
+// every kind of error is reported with the source line, and stops the program;
+// stack addresses depend on how long the arguments are, so those cases leave
+// them out
+#[test]
+fn test_check_reports_memory_errors() {
+    let cases = [
+        (
+            "overflow",
+            "int main()\n{\n  int *a; int i;\n  a = malloc(4 * sizeof(int));\n  i = 0;\n  while (i <= 4) { a[i] = i; i++; }\n  return 0;\n}\n",
+            "(line 6): write to 24, 0 bytes after the 16 byte block at 8",
+        ),
+        (
+            "use_after_free",
+            "int main()\n{\n  char *p;\n  p = malloc(8);\n  free(p);\n  return *p;\n}\n",
+            "(line 6): read from 8, in the 8 byte block at 8 after it was freed",
+        ),
+        ("double_free", "int main()\n{\n  char *p;\n  p = malloc(8);\n  free(p);\n  free(p);\n  return 0;\n}\n", "(line 6): double free of the 8 byte block at 8"),
+        ("bad_free", "int main()\n{\n  char *p;\n  p = malloc(8);\n  free(p + 1);\n  return 0;\n}\n", "(line 5): free of 9, which malloc did not return"),
+        (
+            "uninitialized",
+            "int f(int n)\n{\n  int x;\n  if (n > 0) x = n;\n  return x;\n}\nint main() { return f(0); }\n",
+            ", an uninitialized stack slot",
+        ),
+        (
+            "returned_frame",
+            "int *f() { int x; x = 5; return &x; }\nint main()\n{\n  int *p;\n  p = f();\n  return *p;\n}\n",
+            ", in a stack frame that has returned",
+        ),
+        (
+            "before_block",
+            "int main()\n{\n  int *p;\n  p = malloc(16);\n  p[-1] = 3;\n  return 0;\n}\n",
+            "(line 5): write to 4, 4 bytes before the 16 byte block at 8",
+        ),
+        (
+            "past_locals",
+            "int main()\n{\n  int a, *p;\n  p = &a;\n  p[1] = 5;\n  p[3] = 9;\n  return 0;\n}\n",
+            ", outside the locals and arguments of every stack frame",
+        ),
+        (
+            "return_address",
+            "int f()\n{\n  int a; char *p;\n  p = (char *)&a + 2;\n  *p = 0;\n  return 0;\n}\nint main() { return f(); }\n",
+            ", the return address of the stack frame at ",
+        ),
+        (
+            "past_arguments",
+            "int f(int a)\n{\n  int *p;\n  p = &a;\n  return p[5];\n}\nint main() { int x; x = 3; return f(x); }\n",
+            ", outside the arguments of the stack frame at ",
+        ),
+        ("data_segment", "int g;\nint main()\n{\n  int *p;\n  p = &g + 10;\n  return *p;\n}\n", "(line 6): read from 40, 36 bytes after the data segment"),
+        (
+            "memset",
+            "int main()\n{\n  char *p;\n  p = malloc(8);\n  memset(p, 0, 9);\n  return 0;\n}\n",
+            "(line 5): write to 16, 0 bytes after the 8 byte block at 8",
+        ),
+    ];
+    for (name, source, expected) in cases {
+        let (out, err, code) = run_c(name, source, &["--check"], &[]);
+        assert!(err.contains(expected), "{}: expected {:?} in stderr, got {:?}", name, expected, err);
+        assert!(!out.contains("Program exited"), "{}: the program went on running: {}", name, out);
+        assert_eq!(code, 255, "{}: exit code", name);
+    }
+}
+
+// -O2 goes through the SSA IR, which keeps the source lines for the reports
+#[test]
+fn test_check_keeps_lines_under_o2() {
+    let source = "int main()\n{\n  int *a; int i;\n  a = malloc(4 * sizeof(int));\n  i = 0;\n  while (i <= 4) { a[i] = i; i++; }\n  return 0;\n}\n";
+    let (_, err, _) = run_c("o2_lines", source, &["--check", "-O2"], &[]);
+    assert!(err.ends_with("(line 6): write to 24, 0 bytes after the 16 byte block at 8\n"), "{}", err);
+}
+
+// without --check the same programs run to the end, like they would on c4
+#[test]
+fn test_check_is_opt_in() {
+    let (out, err, _) = run_c("opt_in", "int main()\n{\n  char *p;\n  p = malloc(8);\n  free(p);\n  free(p);\n  return *p;\n}\n", &[], &[]);
+    assert!(out.contains("Program exited with value: 0"), "stdout: {} stderr: {}", out, err);
+}
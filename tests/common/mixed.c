@@ -0,0 +1,14 @@
+// strings, unsigned, short and double arithmetic and printf's formats, on
+// top of fib.c
+int main()
+{
+  char *s; unsigned u; double d; short h; int i, *p;
+  s = malloc(16); memset(s, 'x', 15); s[15] = 0;
+  p = malloc(4 * sizeof(int)); p[3] = 7;
+  u = 7; d = 1.5; h = 40000; i = 0;
+  while (i < 5) { d = d * 2.0; i++; }
+  printf("%s %d %u %d %f %d %d\n", s, fib(15), u / 2, h, d, memcmp(s, "xxx", 3), p[3]);
+  printf("[%5d|%-5d|%05d|%+d|%x|%#o|%8.3f|%e|%g|%.2s|%c]\n", 42, 42, 42, 42, 255, 8, 3.14159, 12345.678, 0.0001, "hello", 65);
+  printf("[%-8.1f|%.0f|%.2f|%.1e|%.2g]\n", -1.25, 2.5, 0.125, 1.25, 0.125);
+  return (u > -1) + fib(10);
+}
@@ -0,0 +1,105 @@
+// tests/common/mod.rs
+//
+// Helpers the integration tests share, pulled in with `mod common;`. Not
+// every test file uses all of them.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// the compiler binary, ready for arguments
+pub fn compiler() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_c4_rust_mleiha"))
+}
+
+// a path in the temp directory no other test, here or in another test
+// binary running at the same time, uses
+pub fn temp_path(name: &str, ext: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("c4_{}_{}_{}{}", name, std::process::id(), n, ext))
+}
+
+pub fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+// fib.c's fib and mixed.c's main, which go through strings, unsigned, short
+// and double arithmetic and printf's formats, and the first line it prints
+pub const MIXED: &str = concat!(include_str!("fib.c"), include_str!("mixed.c"));
+pub const MIXED_PRINTS: &str = "xxxxxxxxxxxxxxx 610 3 -25536 48.000000 0 7";
+
+// writes a C program to a temp file of its own, for tests that pass its path
+// on themselves
+pub fn source_file(name: &str, source: &str) -> PathBuf {
+    let path = temp_path(name, ".c");
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+// writes a C program to a temp file and runs the compiler on it, with the
+// flags before the file and the program's arguments after it
+pub fn run_source(name: &str, source: &str, flags: &[&str], args: &[&str]) -> Output {
+    let path = source_file(name, source);
+    let out = compiler().args(flags).arg(&path).args(args).output().unwrap();
+    std::fs::remove_file(&path).ok();
+    out
+}
+
+// the same, returning (stdout, stderr, exit code)
+pub fn run_c(name: &str, source: &str, flags: &[&str], args: &[&str]) -> (String, String, i32) {
+    let out = run_source(name, source, flags, args);
+    (text(&out.stdout), text(&out.stderr), out.status.code().unwrap_or(-1))
+}
+
+// runs the compiler from the crate's root on the arguments as they are,
+// returning (stdout, stderr, exit code)
+pub fn run_args(args: &[&str]) -> (String, String, i32) {
+    let out = compiler().current_dir(env!("CARGO_MANIFEST_DIR")).args(args).output().unwrap();
+    (text(&out.stdout), text(&out.stderr), out.status.code().unwrap_or(-1))
+}
+
+// the instructions the VM executed, from what --stats put on stderr
+pub fn stats_cycles(stderr: &str) -> u64 {
+    let line = stderr.lines().find(|l| l.contains("cycles in")).expect("no --stats line");
+    line.split(' ').next().unwrap().parse().unwrap()
+}
+
+// runs a C program with --stats on top of the flags, for the instructions
+// the VM executed
+pub fn cycles(name: &str, source: &str, flags: &[&str]) -> u64 {
+    let (_, err, _) = run_c(name, source, &[flags, &["--stats"]].concat(), &[]);
+    stats_cycles(&err)
+}
+
+// writes the (name, source) files to a directory of their own and runs the
+// compiler there with the given arguments, so messages name the files the
+// way they were given. The directory goes away with the files unless the
+// compiler wrote something there, for a later run in it to use
+pub fn run_files(name: &str, files: &[(&str, &str)], args: &[&str]) -> (String, String, i32) {
+    let dir = std::env::temp_dir().join(format!("c4_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (file, source) in files {
+        std::fs::write(dir.join(file), source).unwrap();
+    }
+    let out = compiler().current_dir(&dir).args(args).output().unwrap();
+    for (file, _) in files {
+        std::fs::remove_file(dir.join(file)).ok();
+    }
+    std::fs::remove_dir(&dir).ok();
+    (text(&out.stdout), text(&out.stderr), out.status.code().unwrap_or(-1))
+}
+
+// src/runtime.c and the like, which emitted code builds against
+pub fn runtime(file: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("src").join(file)
+}
+
+// runs an external tool the backend tests need (cc, llc, node). Those tests
+// are #[ignore]d, so one that is asked for fails here if the tool is missing
+// instead of passing without having checked anything
+pub fn tool(cmd: &mut Command) -> Output {
+    let name = cmd.get_program().to_string_lossy().into_owned();
+    cmd.output().unwrap_or_else(|e| panic!("this test needs {}: {}", name, e))
+}
@@ -0,0 +1,59 @@
+// tests/coverage_test.rs
+
+mod common;
+use common::{run_args, source_file, temp_path};
+
+const PROGRAM: &str = r#"int abs(int x)
+{
+  if (x < 0)
+    return -x;
+  return x;
+}
+int unused(int x)
+{
+  return x + 1;
+}
+int main()
+{
+  int i; int s;
+  i = 0; s = 0;
+  while (i < 5) {
+    s = s + abs(i);
+    i++;
+  }
+  printf("%d\n", s);
+  return 0;
+}
+"#;
+
+#[test]
+fn test_coverage_summary() {
+    let path = source_file("coverage_summary", PROGRAM);
+    let (out, err, _) = run_args(&["--coverage", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).ok();
+    assert_eq!(out, "10\nProgram exited with value: 0\n");
+    let lines: Vec<&str> = err.lines().collect();
+    assert_eq!(lines.len(), 4, "stderr was:\n{}", err);
+    assert!(lines[0].starts_with("coverage: 76.9% of lines (10/13), "), "{}", lines[0]);
+    // abs never gets a negative number, unused is never called
+    assert!(lines[1].starts_with("  abs ") && lines[1].contains("lines   3/4 ") && lines[1].ends_with("not run: 4"), "{}", lines[1]);
+    assert!(lines[2].starts_with("  unused ") && lines[2].contains("instructions    0/8 ") && lines[2].ends_with("not run: 7, 9"), "{}", lines[2]);
+    assert!(lines[3].starts_with("  main ") && lines[3].contains("lines   7/7   100.0%"), "{}", lines[3]);
+}
+
+#[test]
+fn test_coverage_lcov() {
+    let path = source_file("coverage_lcov", PROGRAM);
+    let info = temp_path("coverage", ".info");
+    let (_, err, _) = run_args(&[&format!("--coverage={}", info.display()), path.to_str().unwrap()]);
+    let lcov = std::fs::read_to_string(&info).unwrap_or_else(|_| panic!("no tracefile, stderr was:\n{}", err));
+    let expected = format!(
+        "TN:\nSF:{}\nFN:1,abs\nFN:7,unused\nFN:11,main\nFNDA:5,abs\nFNDA:0,unused\nFNDA:1,main\nFNF:3\nFNH:2\n\
+         DA:1,5\nDA:3,5\nDA:4,0\nDA:5,5\nDA:7,0\nDA:9,0\nDA:11,1\nDA:14,1\nDA:15,6\nDA:16,5\nDA:17,5\nDA:19,1\nDA:20,1\n\
+         LF:13\nLH:10\nend_of_record\n",
+        std::fs::canonicalize(&path).unwrap().display()
+    );
+    std::fs::remove_file(&path).ok();
+    std::fs::remove_file(&info).ok();
+    assert_eq!(lcov, expected);
+}
@@ -0,0 +1,20 @@
+// tests/dispatch_test.rs
+
+mod common;
+use common::{cycles, run_c, MIXED, MIXED_PRINTS};
+
+#[test]
+fn test_decoded_dispatch_matches_classic() {
+    let (classic, _, _) = run_c("classic", MIXED, &["--dispatch=classic"], &[]);
+    let (decoded, _, _) = run_c("decoded", MIXED, &["--dispatch=decoded"], &[]);
+    assert!(classic.contains(MIXED_PRINTS), "unexpected output: {}", classic);
+    assert!(classic.contains("Program exited with value: 55"), "unexpected output: {}", classic);
+    assert_eq!(classic, decoded);
+}
+
+#[test]
+fn test_stats_report_the_same_cycles() {
+    let classic = cycles("classic", MIXED, &["--dispatch=classic"]);
+    assert!(classic > 0);
+    assert_eq!(classic, cycles("decoded", MIXED, &["--dispatch=decoded"]));
+}
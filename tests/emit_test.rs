@@ -0,0 +1,95 @@
+// tests/emit_test.rs
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+mod common;
+use common::{run_c, runtime, temp_path, text, tool};
+
+// builds the emitted files with the system C compiler and runs the result
+fn build_and_run(name: &str, files: &[&Path]) -> Output {
+    let exe = temp_path(&format!("emit_{}", name), "");
+    let built = tool(Command::new("cc").arg("-o").arg(&exe).args(files));
+    assert!(built.status.success(), "cc failed: {}", text(&built.stderr));
+    let out = Command::new(&exe).output().unwrap();
+    std::fs::remove_file(&exe).ok();
+    out
+}
+
+const PROGRAM: &str = concat!(
+    include_str!("common/fib.c"),
+    r#"
+int main()
+{
+  int *p, i; unsigned u; double d, z; float f; char *s;
+  p = malloc(10 * sizeof(int)); i = 0;
+  while (i < 10) { p[i] = i * i; i++; }
+  u = 2000000000; u = u * 2; d = 1.5; z = 0.0; f = 1.1; s = "hello";
+  printf("%d %d %s %c %5.2s|\n", p[9], fib(20), s, s[1], s);
+  printf("%u %u %d %d %d\n", u / 3, u % 7, u > 5, -7 / 2, -9 % 4);
+  printf("%f %d %d %d %f\n", d * 3.0, (int)(d * 5.0), d / z > 1.0, (z / z) != (z / z), f);
+  printf("%d %u %d %-4x| %08.3f\n", -1 >> 1, u >> 3, 1 << 40, 255, -d);
+  return fib(10);
+}
+"#
+);
+
+fn round_trip(kind: &str, flags: &[&str]) {
+    let (expected, _, _) = run_c(kind, PROGRAM, flags, &[]);
+    assert!(expected.contains("81 6765 hello e    he|"), "unexpected output: {}", expected);
+    let (emitted, _, _) = run_c(kind, PROGRAM, &[flags, &[&format!("--emit={}", kind)]].concat(), &[]);
+    let path = temp_path(&format!("emit_out_{}", kind), if kind == "asm" { ".s" } else { ".c" });
+    std::fs::write(&path, &emitted).unwrap();
+    let runtime: PathBuf = runtime("runtime.c");
+    let files: Vec<&Path> = if kind == "asm" { vec![&path, &runtime] } else { vec![&path] };
+    let got = build_and_run(kind, &files);
+    std::fs::remove_file(&path).ok();
+    assert_eq!(expected, text(&got.stdout), "--emit={} {:?} behaves differently", kind, flags);
+}
+
+#[test]
+#[ignore = "needs cc"]
+fn test_emit_asm_round_trip() {
+    round_trip("asm", &[]);
+    round_trip("asm", &["-O2"]);
+}
+
+// the checks the JIT makes come along into the assembly
+#[test]
+#[ignore = "needs cc"]
+fn test_emit_asm_faults() {
+    let programs = [
+        ("wild_store", "int main() { int *p; p = (int*)-4000000; *p = 1; return 0; }", "write to -4000000, outside memory"),
+        ("divide", "int main() { int a, b; a = 7; b = 0; return a / b; }", "division by zero"),
+        (
+            "bad_return",
+            "int f() { int a, *p; p = (int*)((char*)&a + 2); *p = 100000000; return 0; }\nint main() { f(); return 0; }",
+            "return to 100000000, which is not the start of an instruction",
+        ),
+    ];
+    for (name, source, why) in programs {
+        let (emitted, _, _) = run_c(name, source, &["--emit=asm"], &[]);
+        let path = temp_path(&format!("emit_fault_{}", name), ".s");
+        std::fs::write(&path, &emitted).unwrap();
+        let got = build_and_run(name, &[&path, &runtime("runtime.c")]);
+        std::fs::remove_file(&path).ok();
+        let err = text(&got.stderr);
+        assert!(err.starts_with("fault at pc ") && err.ends_with(&format!(": {}\n", why)), "{}: {}", name, err);
+        assert_eq!(got.status.code(), Some(255), "{}", name);
+    }
+}
+
+#[test]
+#[ignore = "needs cc"]
+fn test_emit_c_round_trip() {
+    round_trip("c", &[]);
+    round_trip("c", &["-O2"]);
+}
+
+#[test]
+fn test_emit_c_is_switch_free() {
+    let (c, _, _) = run_c("shape", PROGRAM, &["--emit=c"], &[]);
+    assert!(c.contains("static void f_fib(void)\n{\n") && c.contains("static void f_main(void)\n{\n"), "c was:\n{}", c);
+    let generated = &c[c.find("#define M c4_mem").unwrap()..];
+    assert!(!generated.contains("switch"), "c was:\n{}", generated);
+}
@@ -0,0 +1,42 @@
+// tests/float_test.rs
+
+mod common;
+use common::run_c;
+
+// what cc prints for the same program, float rounding included
+const PROGRAM: &str = r#"
+int main()
+{
+  double d, z; float f; int i;
+  d = 1.5; f = 0.1; z = 0.0;
+  i = d * 3;
+  printf("%f %d %f\n", d * 2 + 1, i, (double)7 / 2);
+  printf("%e %g %g %.3f %8.2f|\n", 12345.678, 0.0001, 100000000.0, 3.14159, -2.5);
+  printf("%d %d %d %d\n", d > 1, d == 1.5, f == 0.1, 1e3 == 1000);
+  printf("%f %f %d\n", f, -d, (int)-2.7);
+  printf("%f %d\n", 1 / z, z / z != z / z);
+  return d;
+}
+"#;
+
+#[test]
+fn test_float_arithmetic_and_printf() {
+    for flags in [&[][..], &["-O"], &["-O2"], &["--jit"], &["--dispatch=classic"]] {
+        let (out, err, _) = run_c("floats", PROGRAM, flags, &[]);
+        assert_eq!(
+            out,
+            "4.000000 4 3.500000\n1.234568e+04 0.0001 1e+08 3.142    -2.50|\n1 1 0 1\n0.100000 -1.500000 -2\ninf 1\nProgram exited with value: 1\n",
+            "{:?}: {}",
+            flags,
+            err
+        );
+    }
+}
+
+// a double passed to an int parameter converts, the way an assignment does
+#[test]
+fn test_conversions_at_calls() {
+    let source = "int half(int n) { return n / 2; }\ndouble third(double x) { return x / 3; }\nint main() { printf(\"%d %f\\n\", half(9.9), third(1)); return 0; }\n";
+    let (out, _, _) = run_c("calls", source, &[], &[]);
+    assert_eq!(out, "4 0.333333\nProgram exited with value: 0\n");
+}
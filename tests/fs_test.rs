@@ -0,0 +1,95 @@
+// tests/fs_test.rs
+
+use std::path::{Path, PathBuf};
+
+mod common;
+use common::run_c;
+
+// a fresh directory under the temp dir
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("c4_fs_{}_{}", name, std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// upper-cases in/words.txt into out.txt, reporting on stdout and stderr
+const SHOUT: &str = r#"
+int main()
+{
+  int in, out, n, i; char *buf;
+  buf = malloc(64);
+  in = open("in/words.txt", 0);
+  if (in < 0) { write(2, "no input\n", 9); return 1; }
+  out = open("out.txt", 1);
+  while ((n = read(in, buf, 64)) > 0) {
+    i = 0;
+    while (i < n) { if (buf[i] >= 'a' && buf[i] <= 'z') buf[i] = buf[i] - 32; i++; }
+    write(out, buf, n);
+  }
+  close(in); close(out);
+  printf("copied ");
+  write(1, "ok\n", 3);
+  return write(7, buf, 1);
+}
+"#;
+
+// with --vfs the program reads a snapshot of the directory and its writes
+// stay in memory until --vfs-out saves them, in every dispatch loop
+#[test]
+fn test_vfs_reads_snapshot_and_captures_writes() {
+    let input = temp_dir("input");
+    std::fs::create_dir_all(input.join("in")).unwrap();
+    std::fs::write(input.join("in/words.txt"), "hello, files\n").unwrap();
+    for flags in [&["--dispatch=classic"][..], &["--dispatch=decoded"], &["--jit"], &["-O2"]] {
+        let output = temp_dir("output");
+        let vfs = [format!("--vfs={}", input.display()), format!("--vfs-out={}", output.display())];
+        let args: Vec<&str> = flags.iter().copied().chain(vfs.iter().map(String::as_str)).collect();
+        let (out, err, code) = run_c("shout", SHOUT, &args, &[]);
+        assert_eq!((out.as_str(), code), ("copied ok\nProgram exited with value: -1\n", 255), "{:?}", flags);
+        assert!(!err.contains("no input"), "{:?}: {}", flags, err);
+        assert_eq!(std::fs::read_to_string(output.join("out.txt")).unwrap(), "HELLO, FILES\n", "{:?}", flags);
+        // the input is saved with the output, and nothing was written next to it
+        assert_eq!(std::fs::read_to_string(output.join("in/words.txt")).unwrap(), "hello, files\n");
+        assert!(!input.join("out.txt").exists() && !Path::new("out.txt").exists());
+        std::fs::remove_dir_all(&output).ok();
+    }
+    std::fs::remove_dir_all(&input).ok();
+
+    // an empty file system has nothing to read
+    let output = temp_dir("empty");
+    let (_, err, _) = run_c("empty", SHOUT, &[&format!("--vfs-out={}", output.display())], &[]);
+    assert_eq!(err, "no input\n");
+    std::fs::remove_dir_all(&output).ok();
+}
+
+// the open policy still decides which paths the program sees
+#[test]
+fn test_vfs_keeps_open_policy() {
+    let input = temp_dir("policy");
+    std::fs::create_dir_all(input.join("in")).unwrap();
+    std::fs::write(input.join("in/words.txt"), "x").unwrap();
+    let (_, err, _) = run_c("policy", SHOUT, &[&format!("--vfs={}", input.display()), "--no-open"], &[]);
+    assert_eq!(err, "no input\n");
+    std::fs::remove_dir_all(&input).ok();
+}
+
+// write on the host file system, and checked like read under --check
+#[test]
+fn test_write_to_host_files() {
+    let dir = temp_dir("host");
+    let file = dir.join("log.txt");
+    let source = format!(
+        "int main() {{ int fd; fd = open(\"{}\", 1); write(fd, \"line\\n\", 5); close(fd); return 0; }}",
+        file.display()
+    );
+    let (out, _, _) = run_c("host", &source, &[], &[]);
+    assert_eq!(out, "Program exited with value: 0\n");
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "line\n");
+    std::fs::remove_dir_all(&dir).ok();
+
+    let source = "int main() { char *p; p = malloc(4); write(1, p, 8); return 0; }";
+    let (_, err, code) = run_c("check", source, &["--check"], &[]);
+    assert!(err.starts_with("fault at pc ") && err.ends_with("read from 12, 0 bytes after the 4 byte block at 8\n"), "{}", err);
+    assert_eq!(code, 255);
+}
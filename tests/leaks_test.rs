@@ -0,0 +1,55 @@
+// tests/leaks_test.rs
+
+mod common;
+use common::run_c;
+
+const LEAKY: &str = r#"
+char *copy(char *s)
+{
+  char *p; int n;
+  n = 0;
+  while (s[n]) n++;
+  p = malloc(n + 1);
+  n = 0;
+  while (s[n]) { p[n] = s[n]; n++; }
+  p[n] = 0;
+  return p;
+}
+int main()
+{
+  int i; char *keep;
+  i = 0;
+  while (i < 3) { keep = copy("hello"); i++; }
+  free(keep);
+  keep = malloc(100);
+  return 0;
+}
+"#;
+
+// the blocks left at exit are grouped by the line that allocated them, the
+// biggest first; freed ones don't count
+#[test]
+fn test_leaks_grouped_by_site() {
+    let (out, err, _) = run_c("leaky", LEAKY, &["--leaks"], &[]);
+    assert_eq!(out, "Program exited with value: 0\n");
+    let lines: Vec<&str> = err.lines().collect();
+    assert_eq!(lines.len(), 3, "stderr was:\n{}", err);
+    assert_eq!(lines[0], "leaks: 112 bytes in 3 of 4 blocks were never freed");
+    assert!(lines[1].starts_with("  100 bytes in 1 block allocated at pc ") && lines[1].ends_with("(line 19)"), "{}", lines[1]);
+    assert!(lines[2].starts_with("  12 bytes in 2 blocks allocated at pc ") && lines[2].ends_with("(line 7)"), "{}", lines[2]);
+
+    // the decoded loop reports the same sites as the classic one
+    let (_, classic, _) = run_c("leaky_classic", LEAKY, &["--leaks", "--dispatch=classic"], &[]);
+    assert_eq!(classic, err);
+}
+
+// exit() reports just like returning from main, and nothing without the flag
+#[test]
+fn test_leaks_on_exit_and_opt_in() {
+    let source = "int main()\n{\n  char *p;\n  p = malloc(8);\n  free(p);\n  exit(3);\n}\n";
+    let (out, err, _) = run_c("exit", source, &["--leaks"], &[]);
+    assert_eq!(out, "Program exited with value: 3\n");
+    assert_eq!(err, "leaks: none, all 1 block was freed\n");
+    let (_, quiet, _) = run_c("quiet", LEAKY, &[], &[]);
+    assert_eq!(quiet, "");
+}
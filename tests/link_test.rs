@@ -0,0 +1,86 @@
+// tests/link_test.rs
+
+mod common;
+use common::run_files;
+
+// Helper: compile the (name, source) files separately in a directory of their
+// own, link them and run the program with the given flags and arguments,
+// returning (stdout, stderr, exit code)
+fn run_linked(name: &str, files: &[(&str, &str)], flags: &[&str], args: &[&str]) -> (String, String, i32) {
+    let names: Vec<&str> = files.iter().map(|(file, _)| *file).collect();
+    run_files(&format!("link_{}", name), files, &[flags, &names, &["--"], args].concat())
+}
+
+// main.c uses a function and a variable from lib.c, which uses one of main.c's
+const MAIN: &str = r#"
+int counter;
+extern char *greeting;
+int add(int a, int b);
+int bump();
+int main(int argc, char **argv)
+{
+  counter = 40;
+  add(1, 2);
+  printf("%s %d %d\n", greeting, add(1, 2), bump());
+  printf("%s %s %d\n", "main", argv[1], counter);
+  return counter;
+}
+"#;
+
+const LIB: &str = r#"
+extern int counter;
+char *greeting;
+int twice(int x) { return x + x; }
+int add(int a, int b) { if (!greeting) greeting = "hi from lib"; return twice(a) + b - a; }
+int bump() { counter = counter + 2; return counter; }
+"#;
+
+#[test]
+fn test_files_link_into_one_program() {
+    for flags in [&[][..], &["-O"], &["-O2"], &["--jit"], &["--dispatch=classic"], &["--check"]] {
+        let (out, _, code) = run_linked("linked", &[("main.c", MAIN), ("lib.c", LIB)], flags, &["arg"]);
+        assert_eq!(out, "hi from lib 3 42\nmain arg 42\nProgram exited with value: 42\n", "{:?}", flags);
+        assert_eq!(code, 42, "{:?}", flags);
+    }
+    // the order of the files doesn't matter, only that main is in one of them
+    let (out, _, _) = run_linked("order", &[("lib.c", LIB), ("main.c", MAIN)], &[], &[]);
+    assert!(out.starts_with("hi from lib 3 42\n"), "{}", out);
+}
+
+// a single file can declare what it defines further down
+#[test]
+fn test_prototypes_and_externs_in_one_file() {
+    let source = "int twice(int a);\nextern int g;\nint main() { g = twice(3); return g; }\nint twice(int a) { return a * 2; }\nint g;\n";
+    for flags in [&[][..], &["-O2"]] {
+        let (out, _, code) = run_linked("one", &[("one.c", source)], flags, &[]);
+        assert_eq!((out.as_str(), code), ("Program exited with value: 6\n", 6), "{:?}", flags);
+    }
+    let (_, err, _) = run_linked("conflict", &[("conflict.c", "int f(int a);\nint f(char *a) { return 0; }\nint main() { return 0; }")], &[], &[]);
+    assert_eq!(err, "2: error: conflicting types for 'f'\n");
+}
+
+#[test]
+fn test_link_errors() {
+    let other = "int nothing();\nextern int gone;\nint twice(int x) { return x; }\nint add(int a, int b) { return nothing() + gone + nothing(); }\n";
+    let (out, err, code) = run_linked("errors", &[("main.c", MAIN), ("lib.c", LIB), ("other.c", other)], &[], &[]);
+    assert_eq!(out, "");
+    assert_eq!(
+        err,
+        "duplicate symbol 'add' in lib.c and other.c\nduplicate symbol 'twice' in lib.c and other.c\n\
+         undefined symbol 'nothing' in other.c\nundefined symbol 'gone' in other.c\n"
+    );
+    assert_eq!(code, 255);
+
+    let (_, err, _) = run_linked("kinds", &[("var.c", "extern int f;\nint main() { return f; }"), ("fun.c", "int f() { return 1; }")], &[], &[]);
+    assert_eq!(err, "'f' used in var.c is a function in fun.c\n");
+    let (_, err, _) = run_linked("nomain", &[("a.c", "int f() { return 1; }"), ("b.c", "int g() { return 2; }")], &[], &[]);
+    assert_eq!(err, "main() not defined\n");
+}
+
+// the library links the same way
+#[test]
+fn test_compile_files() {
+    assert!(c4_rust_mleiha::compile_files(&[("main.c", MAIN), ("lib.c", LIB)]).is_ok());
+    let errors = c4_rust_mleiha::compile_files(&[("a.c", "int f();\nint main() { return f(); }")]).err().unwrap();
+    assert_eq!(errors, ["undefined symbol 'f' in a.c"]);
+}
@@ -0,0 +1,7 @@
+int fib(int n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+
+int main()
+{
+  printf("fib(20) = %d\n", fib(20));
+  return fib(10);
+}
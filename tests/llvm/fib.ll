@@ -0,0 +1,83 @@
+; ModuleID = 'tests/llvm/fib.c'
+source_filename = "tests/llvm/fib.c"
+
+@c4.data = private unnamed_addr constant [16 x i64] [i64 102, i64 105, i64 98, i64 40, i64 50, i64 48, i64 41, i64 32, i64 61, i64 32, i64 37, i64 100, i64 10, i64 0, i64 0, i64 0], align 8
+@c4.memory = global [2097152 x i64] zeroinitializer, align 8
+@c4.sp = internal global i64 0
+
+@.str.0 = private alias i64, i64* getelementptr inbounds ([2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 0) ; "fib(20) = %d\n"
+
+declare i64 @c4_open(i64, i64, i64)
+declare i64 @c4_read(i64, i64, i64)
+declare i64 @c4_close(i64, i64, i64)
+declare i64 @c4_printf(i64, i64, i64)
+declare i64 @c4_malloc(i64, i64, i64)
+declare i64 @c4_free(i64, i64, i64)
+declare i64 @c4_memset(i64, i64, i64)
+declare i64 @c4_memcmp(i64, i64, i64)
+declare i64 @c4_exit(i64, i64, i64)
+declare i64 @c4_write(i64, i64, i64)
+declare void @c4_init(i64*, i64)
+declare i64 @c4_args(i32, i8**, i64*)
+declare void @llvm.memcpy.p0i8.p0i8.i64(i8*, i8*, i64, i1)
+declare i64 @llvm.fptosi.sat.i64.f64(double)
+declare i64 @llvm.fptoui.sat.i64.f64(double)
+
+define internal i64 @c4.fib(i64 %p1) {
+entry:
+  br label %b0
+b0:
+  %t1 = icmp slt i64 %p1, 2
+  %v2 = zext i1 %t1 to i64
+  %t2 = icmp ne i64 %v2, 0
+  br i1 %t2, label %b1, label %b2
+b1:
+  ret i64 %p1
+b2:
+  %v3 = phi i64 [ %p1, %b0 ], [ 0, %b3 ]
+  %v6 = sub i64 %v3, 1
+  %v7 = call i64 @c4.fib(i64 %v6)
+  %v9 = sub i64 %v3, 2
+  %v10 = call i64 @c4.fib(i64 %v9)
+  %v11 = add i64 %v7, %v10
+  ret i64 %v11
+b3:
+  br label %b2
+b4:
+  ret i64 0
+}
+
+define internal i64 @c4.main() {
+entry:
+  br label %b0
+b0:
+  %v2 = call i64 @c4.fib(i64 20)
+  %t1 = load i64, i64* @c4.sp
+  %t2 = sub i64 %t1, 1
+  %t3 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %t2
+  store i64 0, i64* %t3
+  %t4 = sub i64 %t1, 2
+  %t5 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %t4
+  store i64 %v2, i64* %t5
+  %t6 = sub i64 %t1, 2
+  %v3 = call i64 @c4_printf(i64 %t6, i64 2, i64 %v2)
+  %v5 = call i64 @c4.fib(i64 10)
+  ret i64 %v5
+b1:
+  ret i64 0
+}
+
+define i32 @main(i32 %0, i8** %1) {
+entry:
+  %argc = sext i32 %0 to i64
+  %mem = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 0
+  %to = bitcast i64* %mem to i8*
+  %from = bitcast [16 x i64]* @c4.data to i8*
+  call void @llvm.memcpy.p0i8.p0i8.i64(i8* %to, i8* %from, i64 128, i1 false)
+  call void @c4_init(i64* %mem, i64 16)
+  store i64 2097152, i64* @c4.sp
+  %argv = call i64 @c4_args(i32 %0, i8** %1, i64* @c4.sp)
+  %ret = call i64 @c4.main()
+  %exit = call i64 @c4_exit(i64 2097152, i64 0, i64 %ret)
+  ret i32 0
+}
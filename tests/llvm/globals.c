@@ -0,0 +1,18 @@
+int count;
+char *greeting;
+double scale;
+
+void swap(int *a, int *b) { int t; t = *a; *a = *b; *b = t; }
+
+int main()
+{
+  int x, y; char *p;
+  greeting = "hi there";
+  scale = 2.5;
+  x = 3; y = 4;
+  swap(&x, &y);
+  p = greeting;
+  while (*p) { if (*p == 'e') count++; p++; }
+  printf("%d %d %s %d %f\n", x, y, greeting, count, scale * x);
+  return count;
+}
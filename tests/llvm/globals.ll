@@ -0,0 +1,153 @@
+; ModuleID = 'tests/llvm/globals.c'
+source_filename = "tests/llvm/globals.c"
+
+@c4.data = private unnamed_addr constant [40 x i64] [i64 0, i64 0, i64 0, i64 0, i64 0, i64 0, i64 0, i64 0, i64 0, i64 0, i64 0, i64 0, i64 104, i64 105, i64 32, i64 116, i64 104, i64 101, i64 114, i64 101, i64 0, i64 0, i64 0, i64 0, i64 37, i64 100, i64 32, i64 37, i64 100, i64 32, i64 37, i64 115, i64 32, i64 37, i64 100, i64 32, i64 37, i64 102, i64 10, i64 0], align 8
+@c4.memory = global [2097152 x i64] zeroinitializer, align 8
+@c4.sp = internal global i64 0
+
+@.str.0 = private alias i64, i64* getelementptr inbounds ([2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 12) ; "hi there"
+@.str.1 = private alias i64, i64* getelementptr inbounds ([2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 24) ; "%d %d %s %d %f\n"
+@count = alias i64, i64* getelementptr inbounds ([2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 0)
+@greeting = alias i64, i64* getelementptr inbounds ([2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 4)
+@scale = alias i64, i64* getelementptr inbounds ([2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 8)
+
+declare i64 @c4_open(i64, i64, i64)
+declare i64 @c4_read(i64, i64, i64)
+declare i64 @c4_close(i64, i64, i64)
+declare i64 @c4_printf(i64, i64, i64)
+declare i64 @c4_malloc(i64, i64, i64)
+declare i64 @c4_free(i64, i64, i64)
+declare i64 @c4_memset(i64, i64, i64)
+declare i64 @c4_memcmp(i64, i64, i64)
+declare i64 @c4_exit(i64, i64, i64)
+declare i64 @c4_write(i64, i64, i64)
+declare void @c4_init(i64*, i64)
+declare i64 @c4_args(i32, i8**, i64*)
+declare void @llvm.memcpy.p0i8.p0i8.i64(i8*, i8*, i64, i1)
+declare i64 @llvm.fptosi.sat.i64.f64(double)
+declare i64 @llvm.fptoui.sat.i64.f64(double)
+
+define internal i64 @c4.swap(i64 %p1, i64 %p2) {
+entry:
+  br label %b0
+b0:
+  %t1 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %p1
+  %v2 = load i64, i64* %t1
+  %t2 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %p2
+  %v3 = load i64, i64* %t2
+  %t3 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %p1
+  store i64 %v3, i64* %t3
+  %t4 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %p2
+  store i64 %v2, i64* %t4
+  ret i64 0
+}
+
+define internal i64 @c4.main() {
+entry:
+  %sp = load i64, i64* @c4.sp
+  %bp = sub i64 %sp, 2
+  %frame = add i64 %bp, -2
+  store i64 %frame, i64* @c4.sp
+  %v6 = add i64 %bp, -1
+  %v9 = add i64 %bp, -2
+  %v12 = add i64 %bp, -1
+  %v13 = add i64 %bp, -2
+  %v35 = add i64 %bp, -1
+  %v37 = add i64 %bp, -2
+  %v45 = add i64 %bp, -1
+  br label %b0
+b0:
+  store i64 12, i64* @greeting
+  store i64 4612811918334230528, i64* @scale
+  %t1 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %v6
+  store i64 3, i64* %t1
+  %t2 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %v9
+  store i64 4, i64* %t2
+  %v14 = call i64 @c4.swap(i64 %v12, i64 %v13)
+  %v16 = load i64, i64* @greeting
+  br label %b1
+b1:
+  %v17 = phi i64 [ %v16, %b0 ], [ %v31, %b5 ]
+  %t3 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %v17
+  %t4 = load i64, i64* %t3
+  %v18 = and i64 %t4, 255
+  %t5 = icmp ne i64 %v18, 0
+  br i1 %t5, label %b2, label %b3
+b2:
+  %t6 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %v17
+  %t7 = load i64, i64* %t6
+  %v19 = and i64 %t7, 255
+  %t8 = icmp eq i64 %v19, 101
+  %v21 = zext i1 %t8 to i64
+  %t9 = icmp ne i64 %v21, 0
+  br i1 %t9, label %b4, label %b5
+b3:
+  %t10 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %v35
+  %v36 = load i64, i64* %t10
+  %t11 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %v37
+  %v38 = load i64, i64* %t11
+  %v40 = load i64, i64* @greeting
+  %v42 = load i64, i64* @count
+  %v44 = load i64, i64* @scale
+  %t12 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %v45
+  %v46 = load i64, i64* %t12
+  %t13 = sitofp i64 %v46 to double
+  %v47 = bitcast double %t13 to i64
+  %t14 = bitcast i64 %v44 to double
+  %t15 = bitcast i64 %v47 to double
+  %t16 = fmul double %t14, %t15
+  %v48 = bitcast double %t16 to i64
+  %t17 = load i64, i64* @c4.sp
+  %t18 = sub i64 %t17, 1
+  %t19 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %t18
+  store i64 24, i64* %t19
+  %t20 = sub i64 %t17, 2
+  %t21 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %t20
+  store i64 %v36, i64* %t21
+  %t22 = sub i64 %t17, 3
+  %t23 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %t22
+  store i64 %v38, i64* %t23
+  %t24 = sub i64 %t17, 4
+  %t25 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %t24
+  store i64 %v40, i64* %t25
+  %t26 = sub i64 %t17, 5
+  %t27 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %t26
+  store i64 %v42, i64* %t27
+  %t28 = sub i64 %t17, 6
+  %t29 = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 %t28
+  store i64 %v48, i64* %t29
+  %t30 = sub i64 %t17, 6
+  %v49 = call i64 @c4_printf(i64 %t30, i64 6, i64 %v48)
+  %v51 = load i64, i64* @count
+  store i64 %sp, i64* @c4.sp
+  ret i64 %v51
+b4:
+  %v23 = load i64, i64* @count
+  %v25 = add i64 %v23, 1
+  store i64 %v25, i64* @count
+  %v28 = sub i64 %v25, 1
+  br label %b5
+b5:
+  %v29 = phi i64 [ %v17, %b2 ], [ %v17, %b4 ]
+  %v31 = add i64 %v29, 1
+  %v33 = sub i64 %v31, 1
+  br label %b1
+b6:
+  store i64 %sp, i64* @c4.sp
+  ret i64 0
+}
+
+define i32 @main(i32 %0, i8** %1) {
+entry:
+  %argc = sext i32 %0 to i64
+  %mem = getelementptr inbounds [2097152 x i64], [2097152 x i64]* @c4.memory, i64 0, i64 0
+  %to = bitcast i64* %mem to i8*
+  %from = bitcast [40 x i64]* @c4.data to i8*
+  call void @llvm.memcpy.p0i8.p0i8.i64(i8* %to, i8* %from, i64 320, i1 false)
+  call void @c4_init(i64* %mem, i64 40)
+  store i64 2097152, i64* @c4.sp
+  %argv = call i64 @c4_args(i32 %0, i8** %1, i64* @c4.sp)
+  %ret = call i64 @c4.main()
+  %exit = call i64 @c4_exit(i64 2097152, i64 0, i64 %ret)
+  ret i32 0
+}
@@ -0,0 +1,11 @@
+#include <string.h>
+#include <stdlib.h>
+
+int unused(int x);
+extern int nowhere;
+
+int main()
+{
+  printf("%d\n", 6 * 7);
+  return 0;
+}
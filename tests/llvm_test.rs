@@ -0,0 +1,96 @@
+// tests/llvm_test.rs
+
+use std::path::Path;
+use std::process::Command;
+
+mod common;
+use common::{compiler, run_c, runtime, temp_path, text, tool};
+
+// the --emit=llvm output for a program under tests/llvm, named relative to
+// the crate so the module header is the same wherever the tests run
+fn emit(name: &str, flags: &[&str]) -> String {
+    let out = compiler()
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(flags)
+        .arg("--emit=llvm")
+        .arg(format!("tests/llvm/{}.c", name))
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "--emit=llvm failed: {}", text(&out.stderr));
+    text(&out.stdout)
+}
+
+fn interpret(name: &str, flags: &[&str]) -> String {
+    let out = compiler()
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(flags)
+        .arg(format!("tests/llvm/{}.c", name))
+        .output()
+        .unwrap();
+    text(&out.stdout)
+}
+
+// compares against tests/llvm/<name>.ll; C4_BLESS=1 rewrites the golden file
+fn check_golden(name: &str) {
+    let got = emit(name, &[]);
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("tests/llvm/{}.ll", name));
+    if std::env::var_os("C4_BLESS").is_some() {
+        std::fs::write(&golden, &got).unwrap();
+    }
+    let expected = std::fs::read_to_string(&golden).unwrap();
+    assert_eq!(expected, got, "--emit=llvm output for {}.c changed, rerun with C4_BLESS=1 if intended", name);
+}
+
+// builds the IR with llc against src/runtime.c and runs it
+fn build_and_run(name: &str, ir: &str) -> String {
+    let base = temp_path(&format!("llvm_{}", name), "");
+    let (ll, asm, exe) = (base.with_extension("ll"), base.with_extension("s"), base);
+    std::fs::write(&ll, ir).unwrap();
+    let llc = tool(Command::new("llc").arg("-relocation-model=pic").arg(&ll).arg("-o").arg(&asm));
+    assert!(llc.status.success(), "llc rejected the IR: {}", text(&llc.stderr));
+    let built = tool(Command::new("cc").arg("-DC4_NO_MAIN").arg("-o").arg(&exe).arg(&asm).arg(runtime("runtime.c")));
+    assert!(built.status.success(), "cc failed: {}", text(&built.stderr));
+    let out = Command::new(&exe).output().unwrap();
+    for path in [&ll, &asm, &exe] {
+        std::fs::remove_file(path).ok();
+    }
+    text(&out.stdout)
+}
+
+#[test]
+fn test_llvm_golden_files() {
+    check_golden("fib");
+    check_golden("globals");
+}
+
+#[test]
+fn test_llvm_declares_symbols() {
+    let ir = emit("globals", &[]);
+    assert!(ir.contains("define internal i64 @c4.swap(i64 %p1, i64 %p2)"), "missing swap: {}", ir);
+    assert!(ir.contains("define i32 @main(i32 %0, i8** %1)"));
+    assert!(ir.contains("@count = alias i64"));
+    assert!(ir.contains("; \"hi there\""));
+    assert!(ir.contains("declare i64 @c4_printf(i64, i64, i64)"));
+}
+
+// the module isn't linked with anything, so what is declared, by a bundled
+// header or otherwise, can't be used, but may be there
+#[test]
+fn test_llvm_unused_declarations() {
+    assert!(emit("header", &[]).contains("define i32 @main(i32 %0, i8** %1)"));
+    let (_, err, code) = run_c("llvm_strlen", "#include <string.h>\nint main() { return strlen(\"ab\"); }\n", &["--emit=llvm"], &[]);
+    assert!(err.starts_with("--emit=llvm doesn't link: 'strlen' isn't defined in "), "{}", err);
+    assert_eq!(code, 255);
+}
+
+#[test]
+#[ignore = "needs llc and cc"]
+fn test_llvm_matches_interpreter() {
+    for name in ["fib", "globals", "header"] {
+        for flags in [&[][..], &["-O2"][..]] {
+            let expected = interpret(name, flags);
+            let got = build_and_run(name, &emit(name, flags));
+            assert_eq!(expected, got, "{}.c {:?} behaves differently when built with llc", name, flags);
+        }
+    }
+}
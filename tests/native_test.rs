@@ -0,0 +1,68 @@
+// tests/native_test.rs
+
+mod common;
+use common::run_c;
+
+const PROGRAM: &str = concat!(
+    include_str!("common/fib.c"),
+    r#"
+int main()
+{
+  int *p, i; unsigned u; double d, z; float f; char *s;
+  p = malloc(10 * sizeof(int)); i = 0;
+  while (i < 10) { p[i] = i * i; i++; }
+  u = 2000000000; u = u * 2; d = 1.5; z = 0.0; f = 1.1; s = "hello";
+  printf("%d %d %s %c\n", p[9], fib(20), s, s[1]);
+  printf("%u %u %d %d %d\n", u / 3, u % 7, u > 5, -7 / 2, -9 % 4);
+  printf("%f %d %d %d %f\n", d * 3.0, (int)(d * 5.0), d / z > 1.0, (z / z) != (z / z), f);
+  printf("%d %u %d\n", -1 >> 1, u >> 3, 1 << 40);
+  free(p);
+  return fib(10);
+}
+"#
+);
+
+#[test]
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+fn test_jit_matches_interpreter() {
+    for flags in [&[][..], &["-O2"][..]] {
+        let (interpreted, _, _) = run_c("vm", PROGRAM, flags, &[]);
+        let (native, _, _) = run_c("jit", PROGRAM, &[flags, &["--jit"]].concat(), &[]);
+        assert!(interpreted.contains("81 6765 hello e"), "unexpected output: {}", interpreted);
+        assert!(interpreted.contains("Program exited with value: 55"), "unexpected output: {}", interpreted);
+        assert_eq!(interpreted, native);
+    }
+}
+
+#[test]
+fn test_jit_listing() {
+    let (listing, _, _) = run_c("listing", PROGRAM, &["-s", "--jit"], &[]);
+    assert!(listing.starts_with(".L0:\n"), "listing was:\n{}", listing);
+    // returns go through the table of text addresses
+    assert!(listing.contains("jmp *(%r14,%rcx,8)"), "listing was:\n{}", listing);
+    assert!(listing.contains("call c4_syscall"), "listing was:\n{}", listing);
+}
+
+// what the interpreter stops with a fault, the native code stops with the
+// same fault, rather than crashing the process
+#[test]
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+fn test_jit_faults_like_interpreter() {
+    let programs = [
+        ("wild_store", "int main() { int *p; p = (int*)-4000000; *p = 1; return 0; }"),
+        ("wild_load", "int main() { int *p; p = (int*)-4000000; return *p; }"),
+        ("bad_return", "int f() { int a, *p; p = (int*)((char*)&a + 2); *p = 100000000; return 0; }\nint main() { f(); return 0; }"),
+        ("return_inside", "int f() { int a, *p; p = (int*)((char*)&a + 2); *p = 1; return 0; }\nint main() { f(); return 0; }"),
+        ("past_top", "int g() { int a, *p; p = (int*)((char*)&a + 1); *p = 2097150; return 0; }\nint f() { g(); return 0; }\nint h(int a, int b, int c, int d, int e, int f, int g, int h) { return 0; }\nint main() { int *top; top = (int*)2097151; *top = 80; f(); h(1, 2, 3, 4, 5, 6, 7, 8); printf(\"%d %d %d %d %d %d %d %d %d\\n\", 1, 2, 3, 4, 5, 6, 7, 8, 9); return 0; }"),
+        ("divide", "int main() { int a, b; a = 7; b = 0; printf(\"before\\n\"); return a / b; }"),
+        ("modulo", "int main() { unsigned a, b; a = 7; b = 0; return a % b; }"),
+        ("recursion", "int f(int n) { return f(n + 1) + 1; }\nint main() { return f(0); }"),
+    ];
+    for (name, source) in programs {
+        let interpreted = run_c(name, source, &[], &[]);
+        let native = run_c(name, source, &["--jit"], &[]);
+        assert!(interpreted.1.starts_with("fault at pc "), "{}: {:?}", name, interpreted);
+        assert_eq!(interpreted.2, 255, "{}", name);
+        assert_eq!(interpreted, native, "{}", name);
+    }
+}
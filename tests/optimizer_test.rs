@@ -0,0 +1,90 @@
+// tests/optimizer_test.rs
+
+mod common;
+use common::{cycles, run_args, run_c, stats_cycles};
+
+const PROGRAM: &str = concat!(
+    include_str!("common/fib.c"),
+    r#"
+int main()
+{
+  int i, *p, s;
+  p = malloc(8 * sizeof(int));
+  i = 0; s = 0;
+  while (1) {
+    if (i >= 8) return s + fib(10) + (2 + 3 * 4) - (1 << 3);
+    p[i] = i * (10 / 5);
+    s = s + p[i];
+    i++;
+  }
+  return 0;
+}
+"#
+);
+
+#[test]
+fn test_optimized_output_matches() {
+    let (plain, _, _) = run_c("same", PROGRAM, &[], &[]);
+    let (optimized, _, _) = run_c("same_o", PROGRAM, &["-O"], &[]);
+    assert!(plain.contains("Program exited with value: 117"), "unexpected output: {}", plain);
+    assert_eq!(plain, optimized, "-O must not change what the program does");
+}
+
+#[test]
+fn test_report_counts_saved_instructions() {
+    let (_, report, _) = run_c("report", PROGRAM, &["-O"], &[]);
+    let line = report.lines().find(|l| l.starts_with("-O:")).expect("no -O report");
+    let saved: usize = line.split('(').nth(1).unwrap().split(' ').next().unwrap().parse().unwrap();
+    assert!(saved > 0, "nothing was saved: {}", line);
+}
+
+#[test]
+fn test_constants_are_folded() {
+    let (listing, _, _) = run_c("fold", "int main() { return (2 + 3) * sizeof(int) - 1; }", &["-O", "-s"], &[]);
+    // everything collapses into a single immediate
+    assert!(listing.contains("IMM  19"), "listing was:\n{}", listing);
+    assert!(!listing.contains("MUL"), "listing was:\n{}", listing);
+}
+
+// the loop-heavy program of `cargo bench`, and one with invariant work that
+// only -O2 moves out of the loop
+const LOOPS: &str = "int main() { int i, j, s; s = 0; i = 0;\n  while (i < 300) { j = 0; while (j < 300) { s = s + (i ^ j) % 13; j++; } i++; }\n  return s & 255; }\n";
+const INVARIANT: &str = "int main() { int i, j, s, n; n = 50; s = 0; i = 0;\n  while (i < n) { j = 0; while (j < n) { s = s + (i * n + j) % 7 + n * 2; j++; } i++; }\n  return s & 255; }\n";
+
+#[test]
+fn test_optimizers_run_fewer_instructions() {
+    let plain = cycles("bench_loops", LOOPS, &[]);
+    for flags in [&["-O"][..], &["-O2"]] {
+        let optimized = cycles("bench_loops_o", LOOPS, flags);
+        assert!(optimized * 100 <= plain * 87, "{:?} ran {} instructions, unoptimized {}", flags, optimized, plain);
+    }
+    let plain = cycles("bench_invariant", INVARIANT, &[]);
+    let o1 = cycles("bench_invariant_o", INVARIANT, &["-O"]);
+    let o2 = cycles("bench_invariant_o2", INVARIANT, &["-O2"]);
+    assert!(o1 * 100 <= plain * 92, "-O ran {} instructions, unoptimized {}", o1, plain);
+    assert!(o2 * 100 <= plain * 75, "-O2 ran {} instructions, unoptimized {}", o2, plain);
+}
+
+// -O2 runs no more instructions than -O on any of the programs
+// tests/programs.rs checks
+#[test]
+fn test_o2_runs_no_more_than_o() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs");
+    let mut programs: Vec<_> =
+        std::fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).filter(|p| p.extension().is_some_and(|e| e == "c")).collect();
+    programs.sort();
+    assert!(!programs.is_empty());
+    for path in programs {
+        let run = |flag: &str| stats_cycles(&run_args(&[flag, "--stats", path.to_str().unwrap()]).1);
+        let (o1, o2) = (run("-O"), run("-O2"));
+        assert!(o2 <= o1, "{}: -O2 ran {} instructions, -O {}", path.display(), o2, o1);
+    }
+}
+
+// the loop test moves to the bottom, where it branches back into the body
+#[test]
+fn test_loops_are_rotated() {
+    let (listing, _, _) = run_c("rotate", LOOPS, &["-O", "-s"], &[]);
+    assert_eq!(listing.matches("JMP").count(), 0, "listing was:\n{}", listing);
+    assert_eq!(listing.matches("BNZ").count(), 2, "listing was:\n{}", listing);
+}
@@ -24,7 +24,8 @@ impl VM {
         }
     }
 
-    // Main execution loop for the VM
+    // Main execution loop for the VM; the operators read stack op ax like c4
+    #[allow(clippy::assign_op_pattern)]
     pub fn run(&mut self) {
         while self.running {
             let op = self.text[self.pc];  // fetch instruction
@@ -0,0 +1,68 @@
+// tests/profile_test.rs
+
+mod common;
+use common::run_c;
+
+const PROGRAM: &str = r#"int fib(int n)
+{
+  if (n < 2) return n;
+  return fib(n - 1) + fib(n - 2);
+}
+int square(int x) { return x * x; }
+int sum(int n)
+{
+  int s;
+  s = 0;
+  while (n > 0) { s = s + square(n); n--; }
+  return s;
+}
+int main()
+{
+  printf("%d %d\n", fib(10), sum(100));
+  return 0;
+}
+"#;
+
+// the columns of the report line for a function: calls, self, self%, total, total%
+fn row<'a>(report: &'a str, name: &str) -> Vec<&'a str> {
+    let line = report.lines().find(|l| l.split_whitespace().next() == Some(name)).unwrap_or_else(|| panic!("no {} in\n{}", name, report));
+    line.split_whitespace().skip(1).collect()
+}
+
+#[test]
+fn test_profile_functions_and_lines() {
+    let (out, report, _) = run_c("report", PROGRAM, &["--profile"], &[]);
+    assert_eq!(out, "55 338350\nProgram exited with value: 0\n");
+    let (_, stats, _) = run_c("stats", PROGRAM, &["--stats", "--dispatch=classic"], &[]);
+    let cycles = stats.split_whitespace().next().unwrap();
+    assert!(report.starts_with(&format!("profile: {} instructions\n", cycles)), "report was:\n{}", report);
+
+    // fib(10) makes 177 calls, all of it in fib itself
+    assert_eq!(row(&report, "fib")[0], "177");
+    assert_eq!(row(&report, "fib")[1], row(&report, "fib")[3]);
+    // sum's total is its own cycles and those of the 100 calls to square
+    let (sum, square) = (row(&report, "sum"), row(&report, "square"));
+    assert_eq!(square[0], "100");
+    let own: u64 = sum[1].parse().unwrap();
+    let squares: u64 = square[3].parse().unwrap();
+    assert_eq!(sum[3].parse::<u64>().unwrap(), own + squares);
+    assert_eq!(row(&report, "main")[4], "100.0%");
+    // the loop in sum is the hottest line
+    let hot = report.lines().skip_while(|l| !l.contains("hottest lines")).nth(1).unwrap();
+    assert_eq!(hot.split_whitespace().next(), Some("11"), "report was:\n{}", report);
+}
+
+// --profile=FILE writes one line per call path, which add up to every
+// instruction but the two after main returns
+#[test]
+fn test_profile_collapsed_stacks() {
+    let folded = common::temp_path("profile", ".folded");
+    let (_, report, _) = run_c("folded", PROGRAM, &[&format!("--profile={}", folded.display())], &[]);
+    let stacks = std::fs::read_to_string(&folded).unwrap();
+    std::fs::remove_file(&folded).ok();
+    assert!(stacks.contains("\nmain;sum;square 800\n"), "stacks were:\n{}", stacks);
+    assert!(stacks.contains("\nmain;fib;fib;fib 104\n"), "stacks were:\n{}", stacks);
+    let counted: u64 = stacks.lines().map(|l| l.rsplit(' ').next().unwrap().parse::<u64>().unwrap()).sum();
+    let total: u64 = report.split_whitespace().nth(1).unwrap().parse().unwrap();
+    assert_eq!(counted + 2, total);
+}
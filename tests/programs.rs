@@ -0,0 +1,146 @@
+// tests/programs.rs
+//
+// Runs every program under tests/programs/ on the VM and compares what it
+// prints and its exit code with the golden files next to it: <name>.out holds
+// the expected stdout, <name>.exit the value main returned (or exit() got).
+//
+// The golden files come from the original c4: `cargo test --test programs --
+// --bless` builds c4.c with the system C compiler (with int as long long, like
+// c4 on a 64 bit host), runs each program on it and rewrites them. Other
+// arguments filter the programs by name.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs");
+
+// the flags every program is checked under
+const MODES: [&[&str]; 2] = [&[], &["-O2"]];
+
+// what a run printed, split from the line reporting the exit code
+struct Outcome {
+    stdout: String,
+    exit: i64,
+}
+
+// the programs to run, sorted by name
+fn programs(filters: &[String]) -> Vec<PathBuf> {
+    let mut found: Vec<PathBuf> = std::fs::read_dir(DIR)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "c"))
+        .filter(|p| filters.is_empty() || filters.iter().any(|f| p.to_string_lossy().contains(f.as_str())))
+        .collect();
+    found.sort();
+    found
+}
+
+// splits off the last line when it reports the exit code the way `exit`
+// recognises it, from the rest of stdout
+fn split_exit(stdout: &str, exit: impl Fn(&str) -> Option<i64>) -> Option<Outcome> {
+    let body = stdout.strip_suffix('\n')?;
+    let (rest, last) = match body.rfind('\n') {
+        Some(i) => (&stdout[..i + 1], &body[i + 1..]),
+        None => ("", body),
+    };
+    Some(Outcome { stdout: rest.to_string(), exit: exit(last)? })
+}
+
+// runs the program on this compiler's VM
+fn run(path: &Path, flags: &[&str]) -> Option<Outcome> {
+    let out = Command::new(env!("CARGO_BIN_EXE_c4_rust_mleiha"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(flags)
+        .arg(path)
+        .output()
+        .unwrap();
+    split_exit(&String::from_utf8_lossy(&out.stdout), |line| {
+        line.strip_prefix("Program exited with value: ")?.parse().ok()
+    })
+}
+
+// builds the original c4 from c4.c to produce the golden files with
+fn build_reference() -> PathBuf {
+    let dir = std::env::temp_dir();
+    let source = dir.join(format!("c4_reference_{}.c", std::process::id()));
+    let exe = dir.join(format!("c4_reference_{}", std::process::id()));
+    let c4 = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/c4.c")).unwrap();
+    let prelude = "#include <stdio.h>\n#include <stdlib.h>\n#include <string.h>\n#include <fcntl.h>\n#include <unistd.h>\n#define int long long\n";
+    std::fs::write(&source, format!("{}{}", prelude, c4)).unwrap();
+    let built = Command::new("cc").arg("-w").arg("-o").arg(&exe).arg(&source).output();
+    std::fs::remove_file(&source).ok();
+    match built {
+        Ok(out) if out.status.success() => exe,
+        Ok(out) => panic!("could not build c4.c: {}", String::from_utf8_lossy(&out.stderr)),
+        Err(e) => panic!("--bless needs a C compiler to build c4.c with: {}", e),
+    }
+}
+
+// runs the program on the original c4, which ends with "exit(N) cycle = M"
+fn run_reference(c4: &Path, path: &Path) -> Outcome {
+    let out = Command::new(c4).current_dir(env!("CARGO_MANIFEST_DIR")).arg(path).output().unwrap();
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    split_exit(&stdout, |line| line.strip_prefix("exit(")?.split(')').next()?.parse().ok())
+        .unwrap_or_else(|| panic!("{} did not exit on c4:\n{}", path.display(), stdout))
+}
+
+fn bless(programs: &[PathBuf]) {
+    let c4 = build_reference();
+    for path in programs {
+        let outcome = run_reference(&c4, path);
+        std::fs::write(path.with_extension("out"), &outcome.stdout).unwrap();
+        std::fs::write(path.with_extension("exit"), format!("{}\n", outcome.exit)).unwrap();
+        println!("blessed {}", path.file_name().unwrap().to_string_lossy());
+    }
+    std::fs::remove_file(&c4).ok();
+}
+
+// checks one program in every mode, returning what went wrong
+fn check(path: &Path) -> Vec<String> {
+    let golden = |ext: &str| {
+        std::fs::read_to_string(path.with_extension(ext))
+            .unwrap_or_else(|_| panic!("{} has no .{} file, run with --bless", path.display(), ext))
+    };
+    let stdout = golden("out");
+    let exit: i64 = golden("exit").trim().parse().unwrap();
+    let mut failures = Vec::new();
+    for flags in MODES {
+        match run(path, flags) {
+            None => failures.push(format!("{:?}: the program did not exit", flags)),
+            Some(got) if got.stdout != stdout => {
+                failures.push(format!("{:?}: stdout differs\n--- expected\n{}--- got\n{}", flags, stdout, got.stdout))
+            }
+            Some(got) if got.exit != exit => failures.push(format!("{:?}: exited with {}, expected {}", flags, got.exit, exit)),
+            Some(_) => {}
+        }
+    }
+    failures
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let filters: Vec<String> = args.iter().filter(|a| !a.starts_with('-')).cloned().collect();
+    let programs = programs(&filters);
+    if args.iter().any(|a| a == "--bless") {
+        bless(&programs);
+        return;
+    }
+
+    println!("\nrunning {} programs", programs.len());
+    let mut failed = Vec::new();
+    for path in &programs {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let failures = check(path);
+        println!("program {} ... {}", name, if failures.is_empty() { "ok" } else { "FAILED" });
+        if !failures.is_empty() {
+            failed.push((name, failures));
+        }
+    }
+    for (name, failures) in &failed {
+        println!("\n---- {} ----\n{}", name, failures.join("\n"));
+    }
+    println!("\nprogram result: {}. {} passed; {} failed\n", if failed.is_empty() { "ok" } else { "FAILED" }, programs.len() - failed.len(), failed.len());
+    if !failed.is_empty() {
+        std::process::exit(1);
+    }
+}
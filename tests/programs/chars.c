@@ -0,0 +1,14 @@
+// char variables keep the low byte of what is stored in them
+int main()
+{
+  char c, *p;
+  int i;
+  c = 300;
+  i = c;
+  printf("%d %d\n", i, c + 1);
+  p = malloc(4);
+  *p = 65 + 256;
+  p[1] = 'z' + 512;
+  printf("%c %d %c\n", *p, *p, p[1]);
+  return c;
+}
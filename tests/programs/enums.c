@@ -0,0 +1,25 @@
+// enums, globals and nested control flow
+enum { RED, GREEN = 5, BLUE };
+enum color { CYAN = 10, MAGENTA };
+
+int calls;
+char *names;
+
+int classify(int n)
+{
+  calls++;
+  if (n < 0) return -1;
+  else if (n == 0) return 0;
+  else return 1;
+}
+
+int main()
+{
+  int i;
+  names = "rgb";
+  printf("%d %d %d %d %d\n", RED, GREEN, BLUE, CYAN, MAGENTA);
+  i = -2;
+  while (i <= 2) { printf("%d:%d ", i, classify(i)); i++; }
+  printf("\n%c%c%c %d\n", names[0], names[1], names[2], calls);
+  return BLUE + calls;
+}
@@ -0,0 +1,13 @@
+// exit() ends the program from inside a call with its own code
+void check(int n)
+{
+  if (n > 3) { printf("giving up at %d\n", n); exit(n * 10); }
+}
+
+int main()
+{
+  int i;
+  i = 0;
+  while (1) { check(i); i++; }
+  return 0;
+}
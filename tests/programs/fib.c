@@ -0,0 +1,17 @@
+// recursion and the return value as the exit code
+int fib(int n)
+{
+  if (n < 2) return n;
+  return fib(n - 1) + fib(n - 2);
+}
+
+int main()
+{
+  int i;
+  i = 0;
+  while (i <= 15) {
+    printf("fib(%d) = %d\n", i, fib(i));
+    i++;
+  }
+  return fib(12) % 256;
+}
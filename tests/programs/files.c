@@ -0,0 +1,18 @@
+// open, read and close on this file
+int main()
+{
+  int fd, n, lines;
+  char *buf, *p;
+  fd = open("tests/programs/files.c", 0);
+  if (fd < 0) { printf("could not open\n"); return 1; }
+  buf = malloc(4096);
+  n = read(fd, buf, 4095);
+  buf[n] = 0;
+  close(fd);
+  lines = 0;
+  p = buf;
+  while (*p) { if (*p == '\n') lines++; p++; }
+  printf("%d bytes, %d lines, starts with %.16s\n", n, lines, buf);
+  printf("missing: %d\n", open("tests/programs/no-such-file", 0));
+  return lines;
+}
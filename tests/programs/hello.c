@@ -0,0 +1,6 @@
+// the smallest program: one printf and a zero exit
+int main()
+{
+  printf("hello, world\n");
+  return 0;
+}
@@ -0,0 +1,19 @@
+// arithmetic, bitwise, comparison and logical operators at full int width
+int main()
+{
+  int a, b;
+  a = 1000; b = -7;
+  printf("%d %d %d %d %d\n", a + b, a - b, a * b, a / b, a % b);
+  printf("%d %d %d %d\n", -17 / 5, -17 % 5, 17 / -5, 17 % -5);
+  printf("%d %d %d %d %d\n", a & 0xF0, a | 5, a ^ 0xFF, ~a, -a);
+  printf("%d %d %d %d\n", 1 << 20, a >> 3, b >> 1, 010 + 0x10);
+  printf("%d %d %d\n", a < b, a > b, a <= 1000);
+  printf("%d %d %d\n", b >= -7, a == 1000, a != b);
+  if (a && b) printf("and ");
+  if (a && 0) printf("never ");
+  if (0 || b) printf("or ");
+  if (!a) printf("never");
+  printf("\n%d %d\n", a > 0 ? 1 : 2, b > 0 ? 1 : b < -5 ? 3 : 4);
+  printf("%x %X %o %5d|%-5d|\n", 255, 48879, 8, 42, 42);
+  return (a ^ b) & 127;
+}
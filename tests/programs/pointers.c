@@ -0,0 +1,33 @@
+// pointer arithmetic, pointers to pointers and locals taken by address
+int *table;
+
+void fill(int *p, int n, int step)
+{
+  int i;
+  i = 0;
+  while (i < n) { *p++ = i * step; i++; }
+}
+
+void swap(int *x, int *y)
+{
+  int t;
+  t = *x; *x = *y; *y = t;
+}
+
+int main()
+{
+  int **rows, i, x, y, *end;
+  table = malloc(10 * sizeof(int));
+  fill(table, 10, 3);
+  end = table + 10;
+  printf("%d %d %d\n", table[4], *(table + 9), end - table);
+  rows = malloc(2 * sizeof(int *));
+  rows[0] = table; rows[1] = table + 5;
+  printf("%d %d\n", rows[1][2], **rows);
+  x = 1; y = 2;
+  swap(&x, &y);
+  printf("%d %d %d\n", x, y, sizeof(int) == sizeof(int *));
+  i = 0;
+  while (table + i < end) i++;
+  return i;
+}
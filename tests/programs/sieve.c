@@ -0,0 +1,23 @@
+// malloc, memset and char arrays indexed through pointers
+int main()
+{
+  char *composite;
+  int n, i, j, count;
+  n = 200;
+  composite = malloc(n);
+  memset(composite, 0, n);
+  count = 0;
+  i = 2;
+  while (i < n) {
+    if (!composite[i]) {
+      count++;
+      printf("%d ", i);
+      j = i * i;
+      while (j < n) { composite[j] = 1; j = j + i; }
+    }
+    i++;
+  }
+  printf("\n%d primes below %d\n", count, n);
+  free(composite);
+  return count;
+}
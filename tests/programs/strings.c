@@ -0,0 +1,36 @@
+// string handling with char pointers
+int length(char *s)
+{
+  char *p;
+  p = s;
+  while (*p) p++;
+  return p - s;
+}
+
+void reverse(char *s)
+{
+  char *e, t;
+  e = s + length(s) - 1;
+  while (s < e) { t = *s; *s++ = *e; *e-- = t; }
+}
+
+char *copy(char *s)
+{
+  char *d, *p;
+  d = p = malloc(length(s) + 1);
+  while (*p++ = *s++) ;
+  return d;
+}
+
+int main()
+{
+  char *s;
+  s = copy("stressed desserts");
+  printf("%s has %d chars\n", s, length(s));
+  reverse(s);
+  printf("reversed: %s\n", s);
+  printf("[%c%c%c]", s[0], s[1], *(s + 2));
+  printf(" [%10s] [%-6s] [%.3s]\n", "right", "left", s);
+  printf("memcmp: %d %d\n", memcmp("abc", "abd", 3) < 0, memcmp(s, s, 5));
+  return length(s);
+}
@@ -0,0 +1,116 @@
+// tests/repl_test.rs
+
+use std::io::Write;
+use std::process::Stdio;
+
+use c4_rust_mleiha::repl::Repl;
+use c4_rust_mleiha::stdio::Capture;
+
+mod common;
+use common::{compiler, text};
+
+// Helper: type the lines into `c4 repl` and return (stdout, stderr, exit code)
+fn run_repl(lines: &[&str]) -> (String, String, i32) {
+    let mut child = compiler()
+        .arg("repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let input: String = lines.iter().map(|l| format!("{}\n", l)).collect();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let out = child.wait_with_output().unwrap();
+    (text(&out.stdout), text(&out.stderr), out.status.code().unwrap_or(-1))
+}
+
+#[test]
+fn test_session_keeps_its_state() {
+    let (out, err, code) = run_repl(&[
+        "1 + 2",
+        "int x;",
+        "x = 20;", // a statement: nothing to print
+        "x * 2 + 2",
+        "int fact(int n) {",
+        "  if (n < 2) return 1;",
+        "  return n * fact(n - 1);",
+        "}",
+        "fact(5)",
+        "#include <string.h>",
+        "char *s;",
+        "s = strdup(\"abc\"); x = x + strlen(s);",
+        "s",
+        "s[1]",
+        "printf(\"%d %s\\n\", x, s);",
+        "double d; unsigned u;",
+        "d = 1.25; u = -1;",
+        "d * 2",
+        "u",
+        "while (x > 20) {",
+        "  x--;",
+        "}",
+        "x",
+        // declarations and statements in one entry
+        "int y; y = 3; y + 1",
+        "int twice(int a) { return 2 * a; } y = twice(y); int z; z = y + 1; z",
+        // braces in block comments don't count, and a comment goes on over lines
+        "/* { */ y",
+        "/* y + 1;",
+        "   } */ y + 2",
+    ]);
+    assert_eq!(err, "");
+    assert_eq!(out, "3\n42\n120\n\"abc\"\n'b'\n23 abc\n2.5\n4294967295\n20\n4\n7\n6\n8\n");
+    assert_eq!(code, 0);
+}
+
+// a bad entry is reported and forgotten, and the session goes on
+#[test]
+fn test_errors_leave_the_session_running() {
+    let (out, err, code) = run_repl(&[
+        "int x; x +* 2",
+        "int x;",
+        "x = 5 +;",
+        "int later(int a);",
+        "later(1)",
+        "int later(int a) { return a + x; }",
+        "later(1, 2)",
+        "later(1)",
+        "1 / x",
+        "x = 2;",
+        "exit(later(x));",
+        "x",
+    ]);
+    assert_eq!(out, "1\nProgram exited with value: 4\n");
+    let err: Vec<&str> = err.lines().collect();
+    assert_eq!(err[..4], ["1: bad dereference", "1: bad expression", "undefined symbol 'later'", "1: error: 'later' takes 1 argument but 2 were given"]);
+    assert!(err[4].starts_with("fault at pc ") && err[4].ends_with("division by zero"), "{}", err[4]);
+    assert_eq!(err.len(), 5);
+    // exit ends the session with its code
+    assert_eq!(code, 4);
+}
+
+// the same through the library, with the output captured
+#[test]
+fn test_repl_api() {
+    let mut repl = Repl::new();
+    let (out, err) = (Capture::new(), Capture::new());
+    repl.vm.stdout = Box::new(out.clone());
+    repl.vm.stderr = Box::new(err.clone());
+    assert_eq!(repl.eval("int twice(int a) { return a * 2; }"), Ok(()));
+    assert_eq!(repl.eval("int missing();\nint g() { return missing(); }"), Err(vec!["undefined symbol 'missing'".to_string()]));
+    assert_eq!(repl.eval("twice(1 +)"), Err(vec!["1: bad expression".to_string()]));
+    assert_eq!(repl.eval("int *p;"), Ok(()));
+    assert_eq!(repl.eval("p = 5;"), Ok(()));
+    assert_eq!(repl.eval("printf(\"%d\\n\", twice(21)); twice(4)"), Ok(()));
+    assert_eq!(repl.exited(), None);
+    assert_eq!(out.text(), "42\n8\n");
+    assert_eq!(err.text(), "1: warning: assignment makes pointer from integer without a cast\n");
+
+    // run reports errors the same way
+    let mut repl = Repl::new();
+    let err = Capture::new();
+    repl.vm.stderr = Box::new(err.clone());
+    repl.vm.stdout = Box::new(Capture::new());
+    assert_eq!(repl.run(&b"1 +;\nexit(3);\n"[..], false), 3);
+    assert_eq!(err.text(), "1: bad expression\n");
+}
@@ -0,0 +1,86 @@
+// tests/sandbox_test.rs
+
+mod common;
+use common::run_c;
+
+// each limit stops a program that would otherwise run away, in both dispatch
+// loops and with --jit, which leaves limited programs to the interpreter
+#[test]
+fn test_limits_stop_runaway_programs() {
+    let cases = [
+        ("cycles", "int main() { while (1) {} return 0; }", "--max-cycles=1000", "ran more than 1000 cycles"),
+        ("depth", "int f(int n) { return f(n + 1); }\nint main() { return f(0); }", "--max-depth=50", "nested calls more than 50 deep"),
+        ("heap", "int main() { while (1) malloc(1000); return 0; }", "--max-heap=10000", "allocated more than 10000 bytes"),
+        ("output", "int main() { while (1) printf(\"hello\\n\"); return 0; }", "--max-output=15", "printed more than 15 bytes"),
+    ];
+    for (name, source, flag, why) in cases {
+        for dispatch in ["--dispatch=classic", "--dispatch=decoded", "--jit"] {
+            let (out, err, code) = run_c(name, source, &[flag, dispatch], &[]);
+            assert!(err.starts_with("limit exceeded at pc ") && err.ends_with(&format!(": {}\n", why)), "{} {}: {}", name, dispatch, err);
+            assert_eq!(code, 255, "{} {}", name, dispatch);
+            assert!(!out.contains("Program exited"), "{} {}: {}", name, dispatch, out);
+        }
+    }
+    // output stops exactly at the limit
+    let (out, _, _) = run_c("exact", "int main() { while (1) printf(\"hello\\n\"); return 0; }", &["--max-output=15"], &[]);
+    assert_eq!(out, "hello\nhello\nhel");
+    // and writes to stderr count against the same limit
+    let source = "int main() { printf(\"out\\n\"); while (1) write(2, \"err\\n\", 4); return 0; }";
+    for dispatch in ["--dispatch=classic", "--dispatch=decoded", "--jit"] {
+        let (out, err, code) = run_c("stderr", source, &["--max-output=10", dispatch], &[]);
+        assert_eq!(out, "out\n", "{}", dispatch);
+        assert!(err.starts_with("err\nerlimit exceeded at pc ") && err.ends_with(": printed more than 10 bytes\n"), "{}: {}", dispatch, err);
+        assert_eq!(code, 255, "{}", dispatch);
+    }
+}
+
+// programs within their limits run as before
+#[test]
+fn test_limits_leave_programs_alone() {
+    let source = concat!(include_str!("common/fib.c"), "int main() { printf(\"%d\\n\", fib(10)); return 0; }");
+    let flags = ["--max-cycles=100000", "--max-depth=10", "--max-heap=0", "--max-output=3"];
+    let (out, err, code) = run_c("fits", source, &flags, &[]);
+    assert_eq!((out.as_str(), err.as_str(), code), ("55\nProgram exited with value: 0\n", "", 0));
+}
+
+const CAT: &str = r#"
+int main(int argc, char **argv)
+{
+  int fd; char *buf; int n;
+  buf = malloc(64);
+  fd = open(argv[1], 0);
+  if (fd < 0) { printf("cannot open %s\n", argv[1]); return 1; }
+  n = read(fd, buf, 63); buf[n] = 0;
+  printf("%s", buf);
+  return 0;
+}
+"#;
+
+#[test]
+fn test_open_policies() {
+    let dir = std::env::temp_dir().join(format!("c4_sandbox_root_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("sub/f.txt"), "inside\n").unwrap();
+    let file = dir.join("sub/f.txt");
+    let (file, dir_s) = (file.to_str().unwrap(), dir.to_str().unwrap());
+    let sub = format!("{}/sub", dir_s);
+    let escape = format!("{}/sub/../../etc/passwd", dir_s);
+
+    let runs: [(&[&str], &str, &str); 7] = [
+        (&[], file, "inside\n"),
+        (&["--no-open"], file, "cannot open "),
+        (&[&format!("--root={}", dir_s)], "/sub/f.txt", "inside\n"),
+        (&[&format!("--root={}", dir_s)], "sub/../../sub/f.txt", "cannot open "),
+        (&[&format!("--allow-open={}", sub)], file, "inside\n"),
+        (&[&format!("--allow-open={}", sub)], &escape, "cannot open "),
+        (&[&format!("--allow-open={}", "/nowhere"), &format!("--allow-open={}", sub)], file, "inside\n"),
+    ];
+    for (flags, path, expected) in runs {
+        for jit in [&[][..], &["--jit"]] {
+            let flags = [flags, jit].concat();
+            let (out, err, _) = run_c("open", CAT, &flags, &[path]);
+            assert!(out.starts_with(expected), "{:?} {}: {} {}", flags, path, out, err);
+        }
+    }
+    std::fs::remove_dir_all(&dir).ok();
+}
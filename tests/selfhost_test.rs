@@ -0,0 +1,45 @@
+// tests/selfhost_test.rs
+
+mod common;
+use common::{run_args, source_file};
+
+const HELLO: &str = r#"
+int main()
+{
+  int i;
+  char *s;
+  s = "hello, world";
+  i = 0;
+  while (s[i]) i++;
+  printf("%s (%d chars)\n", s, i);
+  return i - 5;
+}
+"#;
+
+// c4.c compiled by this compiler, running on the VM, compiles and runs a
+// program the same way this compiler does
+#[test]
+fn test_c4_compiles_hello_world() {
+    let path = source_file("selfhost_hello", HELLO);
+    let hello = path.to_str().unwrap();
+
+    let direct = run_args(&[hello]).0;
+    assert_eq!(direct, "hello, world (12 chars)\nProgram exited with value: 7\n");
+    for flags in [&[][..], &["-O2"][..], &["--dispatch=classic"][..]] {
+        let nested = run_args(&[flags, &["c4.c", hello]].concat()).0;
+        // the nested c4 reports its own exit, then returns the same value; the
+        // cycle count is what c4.c built natively with cc reports as well
+        assert_eq!(
+            nested,
+            "hello, world (12 chars)\nexit(7) cycle = 263\nProgram exited with value: 7\n",
+            "c4.c {:?} ran hello world differently",
+            flags
+        );
+    }
+
+    // -s makes the nested c4 list the source and its code instead
+    let listing = run_args(&["c4.c", "-s", hello]).0;
+    assert!(listing.contains("9:   printf(\"%s (%d chars)\\n\", s, i);\n"), "listing was:\n{}", listing);
+    assert!(listing.contains("    PRTF\n    ADJ  3\n"), "listing was:\n{}", listing);
+    std::fs::remove_file(&path).ok();
+}
@@ -0,0 +1,53 @@
+// tests/ssa_test.rs
+
+mod common;
+use common::{cycles, run_c};
+
+const PROGRAM: &str = r#"
+int swap(int n) { int a, b, t; a = 1; b = 2; while (n > 0) { t = a; a = b; b = t; n--; } return a * 10 + b; }
+int bump(int x) { int *p; p = &x; *p = *p + 5; return x; }
+int main()
+{
+  char c; signed char s; double d; int i, k;
+  c = 300; s = 200; d = 0.0; i = 0; k = 0;
+  do { d = d + i * 0.5; k = k + (i > 4 ? i : -i); i++; } while (i < 10);
+  printf("%d %d %d %d %f %d\n", swap(3), swap(4), bump(7), c + s, d, k);
+  return (c && s) + (i || 0) * 2;
+}
+"#;
+
+const LOOPS: &str = r#"
+int main()
+{
+  int i, j, s, n;
+  n = 50; s = 0; i = 0;
+  while (i < n) { j = 0; while (j < n) { s = s + (i * n + j) % 7 + n * 2; j++; } i++; }
+  return s & 255;
+}
+"#;
+
+#[test]
+fn test_ssa_output_matches() {
+    let (plain, _, _) = run_c("same", PROGRAM, &[], &[]);
+    let (optimized, _, _) = run_c("same_o2", PROGRAM, &["-O2"], &[]);
+    assert!(plain.contains("21 12 12 -12 22.500000 25"), "unexpected output: {}", plain);
+    assert_eq!(plain, optimized, "-O2 must not change what the program does");
+}
+
+#[test]
+fn test_ssa_runs_fewer_instructions() {
+    let plain = cycles("loops", LOOPS, &[]);
+    let optimized = cycles("loops_o2", LOOPS, &["-O2"]);
+    assert!(optimized * 10 < plain * 9, "-O2 ran {} instructions, unoptimized {}", optimized, plain);
+}
+
+#[test]
+fn test_dump_ir_shows_phis_and_hoisting() {
+    let (ir, _, _) = run_c("dump", LOOPS, &["-O2", "--dump-ir"], &[]);
+    assert!(ir.contains("= phi ["), "ir was:\n{}", ir);
+    // n is the constant 50, so n * 2 folds away and i * n moves out of the inner loop
+    assert!(!ir.contains("mul 50, 2") && ir.contains("add v"), "ir was:\n{}", ir);
+    let outer = ir.lines().position(|l| l.contains("mul v")).expect("no i * n left");
+    let inner_phi = ir.lines().enumerate().filter(|(_, l)| l.contains("= phi [")).map(|(i, _)| i).nth(2).unwrap();
+    assert!(outer < inner_phi, "i * n is still inside the inner loop:\n{}", ir);
+}
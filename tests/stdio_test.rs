@@ -0,0 +1,77 @@
+// tests/stdio_test.rs
+
+use c4_rust_mleiha::stdio::Capture;
+use c4_rust_mleiha::{compile, dispatch};
+
+// Helper: compile and run a C program in this process on the given stdin,
+// returning (stdout, stderr) as the program wrote them
+fn run_c(source: &str, stdin: &'static [u8], decoded: bool) -> (String, String) {
+    let image = compile(source).unwrap_or_else(|errors| panic!("{}", errors.join("\n")));
+    let mut vm = image.vm(&["prog.c", "arg"]);
+    let (out, err) = (Capture::new(), Capture::new());
+    vm.stdin = Box::new(stdin);
+    vm.stdout = Box::new(out.clone());
+    vm.stderr = Box::new(err.clone());
+    if decoded {
+        let code = dispatch::decode(&vm.text).unwrap();
+        vm.run_decoded(&code);
+    } else {
+        vm.run();
+    }
+    (out.text(), err.text())
+}
+
+// echoes stdin back, one read at a time, to stdout and stderr
+const ECHO: &str = r#"
+int main(int argc, char **argv)
+{
+  char *buf; int n;
+  buf = malloc(4);
+  printf("%d %s\n", argc, argv[1]);
+  while ((n = read(0, buf, 4)) > 0) { write(1, buf, n); write(2, buf, 1); }
+  return 7;
+}
+"#;
+
+#[test]
+fn test_output_goes_to_the_sinks() {
+    for decoded in [false, true] {
+        let (out, err) = run_c(ECHO, b"hello, sinks\n", decoded);
+        assert_eq!(out, "2 arg\nhello, sinks\nProgram exited with value: 7\n");
+        assert_eq!(err, "hoi\n");
+    }
+}
+
+// faults and limits are reported on the stderr sink, and vm.error says why
+#[test]
+fn test_faults_go_to_stderr_sink() {
+    let (out, err) = run_c("int main(int argc, char **argv) { printf(\"before\\n\"); return 1 / (argc - 2); }", b"", false);
+    assert_eq!(out, "before\n");
+    assert!(err.starts_with("fault at pc ") && err.ends_with("division by zero\n"), "{}", err);
+
+    let image = compile("int main() { while (1) printf(\"spam\"); return 0; }").unwrap();
+    let mut vm = image.vm(&["spam.c"]);
+    let (out, err) = (Capture::new(), Capture::new());
+    vm.stdout = Box::new(out.clone());
+    vm.stderr = Box::new(err.clone());
+    vm.limits.output = Some(10);
+    vm.run();
+    assert_eq!(out.text(), "spamspamsp");
+    assert!(err.text().ends_with(": printed more than 10 bytes\n"), "{}", err.text());
+    assert!(vm.error.is_some());
+}
+
+// compile reports syntax and type errors and a missing main instead of
+// running anything, or ending the process
+#[test]
+fn test_compile_errors() {
+    let errors = compile("int f(int a) { return a; }\nint main() { return f(1, 2); }").err().unwrap();
+    assert_eq!(errors, ["2: error: 'f' takes 1 argument but 2 were given"]);
+    assert_eq!(compile("int f() { return 0; }").err().unwrap(), ["main() not defined"]);
+    assert_eq!(compile("int main() {\n  return 1\n}").err().unwrap(), ["3: semicolon expected"]);
+    assert_eq!(compile("int main() { return 1e; }").err().unwrap(), ["1: bad floating literal 1e"]);
+    assert_eq!(compile("int main() { return y; }").err().unwrap(), ["1: undefined variable 'y'"]);
+    let errors = c4_rust_mleiha::compile_files(&[("a.c", "int main() { return 0; }"), ("b.c", "int f( {")]).err().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].starts_with("b.c:1: "), "{:?}", errors);
+}
@@ -0,0 +1,162 @@
+// tests/stdlib_test.rs
+
+mod common;
+use common::run_files;
+
+// every function of the library at least once
+const USES_ALL: &str = r#"
+#include <stdio.h>
+#include <string.h>
+#include <ctype.h>
+#include <stdlib.h>
+
+int main()
+{
+  char *s, *t, *end;
+  int i;
+  s = strdup("Hello, ");
+  t = malloc(64);
+  strcpy(t, s);
+  strcat(t, "world");
+  printf("%s %d %d %d %d\n", t, strlen(t), strcmp("abc", "abd") < 0, strncmp("abc", "abd", 2), strcmp("b", "a") > 0);
+  printf("%s|%s|%s|%d\n", strchr(t, 'o'), strrchr(t, 'o'), strstr(t, "wor"), strstr(t, "xyz") == 0);
+  strncpy(s, "xyz", 2);
+  memcpy(s + 2, "!!", 2);
+  printf("%s\n", s);
+  printf("%d %d %d %d %d %d\n", atoi("  -42x"), abs(-7), strtol("0x1F", &end, 0), strtol("777", 0, 8), strtol("z", 0, 36), *end == 0);
+  i = 0;
+  while (t[i]) { t[i] = toupper(t[i]); i++; }
+  printf("%s %c\n", t, tolower('Q'));
+  printf("%d%d%d%d%d%d%d%d%d\n", isdigit('5'), isalpha('x'), isalnum('_'), isspace('\n'), isupper('a'), islower('a'), isprint('\t'), ispunct('!'), isxdigit('g'));
+  srand(1);
+  i = rand();
+  srand(1);
+  printf("%d %d\n", i == rand(), rand() <= 32767);
+  end = calloc(4, 4);
+  return end[15];
+}
+"#;
+
+#[test]
+fn test_library_functions() {
+    let expected = "Hello, world 12 1 0 1\no, world|orld|world|1\nxy!!o, \n-42 7 31 511 35 1\nHELLO, WORLD q\n110101010\n1 1\nProgram exited with value: 0\n";
+    for flags in [&["--dispatch=classic"][..], &["--dispatch=decoded"], &["--jit"], &["-O2"], &["--check"]] {
+        let (out, _, code) = run_files("stdlib_all", &[("all.c", USES_ALL)], &[flags, &["all.c"]].concat());
+        assert_eq!((out.as_str(), code), (expected, 0), "{:?}", flags);
+    }
+}
+
+// a program's own definitions win, and only what it uses is linked
+#[test]
+fn test_program_overrides_library() {
+    let source = "#include <string.h>\nint strlen(char *s) { return 42; }\nint main() { char *b; b = malloc(8); strcpy(b, \"abc\"); printf(\"%d %s\\n\", strlen(b), b); return 0; }";
+    let (out, err, _) = run_files("stdlib_own", &[("own.c", source)], &["own.c"]);
+    assert_eq!((out.as_str(), err.as_str()), ("42 abc\nProgram exited with value: 0\n", ""));
+    let (_, err, code) = run_files("stdlib_nostdlib", &[("own.c", source)], &["-nostdlib", "own.c"]);
+    assert_eq!((err.as_str(), code), ("undefined symbol 'strcpy' in own.c\n", 255));
+    // headers the library doesn't have are skipped, like c4 does
+    let (out, _, _) = run_files("stdlib_unknown", &[("u.c", "#include <unistd.h>\n#include \"mine.h\"\nint main() { return 3; }")], &["u.c"]);
+    assert_eq!(out, "Program exited with value: 3\n");
+}
+
+// --archive saves objects that --lib links like the bundled library
+#[test]
+fn test_archive_round_trip() {
+    let shout = "#include <string.h>\n#include <ctype.h>\nint shout(char *s) { int i; i = 0; while (s[i]) { s[i] = toupper(s[i]); i++; } return strlen(s); }\n";
+    let unused = "int twice(int x) { return 2 * x; }\n";
+    let main = "#include <string.h>\nint shout(char *s);\nint main() { char *s; s = strdup(\"hey\"); printf(\"%d %s\\n\", shout(s), s); return 0; }\n";
+    let (out, err, code) = run_files(
+        "stdlib_archive",
+        &[("shout.c", shout), ("twice.c", unused), ("main.c", main)],
+        &["--archive=libshout.c4a", "shout.c", "twice.c", "--"],
+    );
+    assert_eq!((out.as_str(), err.as_str(), code), ("", "", 0));
+    let dir = std::env::temp_dir().join(format!("c4_stdlib_archive_{}", std::process::id()));
+    let (out, err, _) = run_files("stdlib_archive", &[("main.c", main)], &["--lib=libshout.c4a", "main.c"]);
+    assert_eq!((out.as_str(), err.as_str()), ("3 HEY\nProgram exited with value: 0\n", ""));
+    std::fs::remove_dir_all(&dir).ok();
+
+    let (_, err, code) = run_files("stdlib_bad", &[("main.c", main)], &["--lib=main.c", "main.c"]);
+    assert_eq!((err.as_str(), code), ("could not read main.c: not a c4 archive\n", 255));
+}
+
+// a damaged archive is refused rather than linked into something that panics
+#[test]
+fn test_corrupt_archive() {
+    let twice = "int twice(int x) { while (x > 100) x = x / 2; return 2 * x; }\n";
+    let main = "int twice(int x);\nint main() { return twice(4); }\n";
+    let (_, err, _) = run_files("stdlib_corrupt", &[("twice.c", twice)], &["--archive=libtwice.c4a", "twice.c", "--"]);
+    assert_eq!(err, "");
+    let dir = std::env::temp_dir().join(format!("c4_stdlib_corrupt_{}", std::process::id()));
+    let lib = dir.join("libtwice.c4a");
+    let bytes = std::fs::read(&lib).unwrap();
+    let run = |bytes: &[u8]| {
+        std::fs::write(&lib, bytes).unwrap();
+        run_files("stdlib_corrupt", &[("main.c", main)], &["--max-cycles=100000", "--lib=libtwice.c4a", "main.c"])
+    };
+    assert_eq!(run(&bytes).0, "Program exited with value: 8\n");
+
+    // the first instruction of twice.c's text, after the magic, the member
+    // count, the name and the text length
+    let mut bad = bytes.clone();
+    bad[4 + 4 + 4 + "twice.c".len() + 4] = 99;
+    assert_eq!(run(&bad).1, "could not read libtwice.c4a: corrupt archive\n");
+    for i in 0..bytes.len() {
+        let mut bad = bytes.clone();
+        bad[i] ^= 0x41;
+        let (_, err, _) = run(&bad);
+        assert!(!err.contains("panicked"), "byte {}: {}", i, err);
+    }
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+// an archive of one member, name.c, with the given text and one function f
+// at 0, written the way Archive::to_bytes lays it out
+fn archive(text: &[i32]) -> Vec<u8> {
+    let mut out = b"c4ar".to_vec();
+    let words = |out: &mut Vec<u8>, ws: &[i32]| ws.iter().for_each(|w| out.extend(w.to_le_bytes()));
+    words(&mut out, &[1, 6]);
+    out.extend(b"evil.c");
+    words(&mut out, &[text.len() as i32]);
+    words(&mut out, text);
+    words(&mut out, &[1, 1]);
+    out.push(b'f');
+    // f at 0, then no globals, lines, relocations or data
+    words(&mut out, &[0, 0, 0, 0, 0]);
+    out
+}
+
+// operands no engine can run are refused, and those that only go wrong at
+// run time fault there; calls into the middle of a function can't be emitted
+#[test]
+fn test_archive_operands() {
+    const ENT: i32 = 6;
+    const ADJ: i32 = 7;
+    const LEV: i32 = 8;
+    const JSR: i32 = 3;
+    const IMM: i32 = 1;
+    const PSH: i32 = 13;
+    let main = "int f();\nint main() { return f(); }\n";
+    let dir = std::env::temp_dir().join(format!("c4_stdlib_operands_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let run = |text: &[i32], flags: &[&str]| {
+        std::fs::write(dir.join("libevil.c4a"), archive(text)).unwrap();
+        run_files("stdlib_operands", &[("main.c", main)], &[flags, &["--lib=libevil.c4a", "main.c"]].concat())
+    };
+    for text in [&[ENT, -1, LEV][..], &[ENT, 0, ADJ, -2, LEV], &[ENT, 0, ADJ, 1 << 30, LEV], &[ENT, 0, 46, 0, LEV], &[ENT, 0, 47, 65, LEV]] {
+        let (_, err, code) = run(text, &[]);
+        assert_eq!((err.as_str(), code), ("could not read libevil.c4a: corrupt archive\n", 255), "{:?}", text);
+    }
+    // an ADJ past the top of memory, after a frame that was overwritten
+    for flags in [&[][..], &["--dispatch=classic"], &["--jit"]] {
+        let (_, err, code) = run(&[ENT, 0, ADJ, 100000, IMM, 65, PSH, LEV], flags);
+        assert!(err.ends_with(": stack underflow\n"), "{:?}: {}", flags, err);
+        assert_eq!(code, 255);
+    }
+    for emit in ["--emit=c", "--emit=wasm", "--emit=wat"] {
+        let (_, err, code) = run(&[ENT, 0, JSR, 5, LEV, IMM, 1, LEV], &[emit]);
+        assert!(err.starts_with(&format!("{}: call to ", emit)) && err.ends_with(", which is not a function\n"), "{}", err);
+        assert_eq!(code, 255);
+    }
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,86 @@
+// tests/typeck_test.rs
+
+mod common;
+use common::run_c;
+
+// mixups a C compiler warns about, and one it refuses
+const MISMATCHES: &str = r#"
+int twice(int a) { return a * 2; }
+int main()
+{
+  int i, *p; char *s; double d;
+  s = "x";
+  i = s;
+  p = 5;
+  p = s;
+  p = 0;
+  if (p == 1) i = 0;
+  return twice(i, 2);
+}
+"#;
+
+#[test]
+fn test_mismatches_are_reported() {
+    let (_, err, code) = run_c("mismatches", MISMATCHES, &[], &[]);
+    assert_eq!(
+        err.lines().collect::<Vec<_>>(),
+        [
+            "7: warning: assignment makes integer from pointer without a cast",
+            "8: warning: assignment makes pointer from integer without a cast",
+            "11: warning: comparison between pointer and integer",
+            "12: error: 'twice' takes 1 argument but 2 were given",
+        ]
+    );
+    assert_eq!(code, 255);
+
+    // -fpermissive is c4: no checks, the program just runs
+    let (out, err, _) = run_c("permissive", MISMATCHES, &["-fpermissive"], &[]);
+    assert_eq!(err, "");
+    assert!(out.contains("Program exited with value:"), "{}", out);
+
+    let (_, err, code) = run_c("float_ptr", "int main() { int *p; double d; d = 1.5; p = d; return 0; }", &[], &[]);
+    assert_eq!(err, "1: error: incompatible types in assignment ('int *' from 'double')\n");
+    assert_eq!(code, 255);
+}
+
+#[test]
+fn test_argument_counts_pluralise() {
+    let source = "int none() { return 0; }\nint two(int a, int b) { return a + b; }\nint main() { none(1); two(1); return 0; }\n";
+    let (_, err, _) = run_c("plural", source, &[], &[]);
+    assert_eq!(err, "3: error: 'none' takes 0 arguments but 1 was given\n3: error: 'two' takes 2 arguments but 1 was given\n");
+}
+
+// -Wall adds the pedantic warnings, among them conversions that can drop bits
+#[test]
+fn test_wall_warns_about_narrowing() {
+    let source = r#"
+int main()
+{
+  int i, unused; char c; short s; long l; double d;
+  i = 300; l = 5; d = 1.5;
+  c = 'a'; s = 1000; i = -1;
+  c = i;
+  s = l;
+  i = d;
+  c = c + 1;
+  return 0;
+}
+"#;
+    let (_, err, _) = run_c("narrowing", source, &[], &[]);
+    assert_eq!(err, "");
+    let (_, err, code) = run_c("narrowing_wall", source, &["-Wall"], &[]);
+    assert_eq!(
+        err.lines().collect::<Vec<_>>(),
+        [
+            "4: warning: unused variable 'unused'",
+            "7: warning: conversion from 'int' to 'char' may change value",
+            "8: warning: conversion from 'long' to 'short' may change value",
+            "9: warning: conversion from 'double' to 'int' may change value",
+            "10: warning: conversion from 'int' to 'char' may change value",
+        ]
+    );
+    assert_eq!(code, 0);
+    // -Werror turns them into failures
+    let (out, _, code) = run_c("narrowing_werror", source, &["-Wall", "-Werror"], &[]);
+    assert_eq!((out.as_str(), code), ("", 255));
+}
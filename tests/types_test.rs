@@ -0,0 +1,60 @@
+// tests/types_test.rs
+
+mod common;
+use common::run_c;
+
+// unsigned int arithmetic wraps at 32 bits, the way C's does, whatever the
+// cells the VM computes in hold
+const UNSIGNED: &str = r#"
+int main()
+{
+  unsigned u, v; int i;
+  printf("%d\n", (unsigned)-1 % 7);
+  u = 0 - 1;
+  printf("%d %u\n", u / 2 == 2147483647, u);
+  u = 1;
+  printf("%d\n", u << 32);
+  u = 3000000000;
+  printf("%d %d\n", u / 3, u > 0);
+  u = 0; u--; v = u++;
+  printf("%u %u %u\n", u, v, ~u);
+  i = -1;
+  printf("%d %d\n", u < i, -(unsigned)1 == 4294967295);
+  u = 4000000000; u = u + u;
+  printf("%u %u\n", u, u * 3);
+  return 0;
+}
+"#;
+
+#[test]
+fn test_unsigned_int_wraps_at_32_bits() {
+    // the same through every back end that runs on the VM
+    for flags in [&[][..], &["-O"], &["-O2"], &["--jit"], &["--dispatch=classic"]] {
+        let (out, err, _) = run_c("unsigned", UNSIGNED, flags, &[]);
+        assert_eq!(
+            out,
+            "3\n1 4294967295\n0\n1000000000 1\n0 4294967295 4294967295\n1 1\n3705032704 2525163520\nProgram exited with value: 0\n",
+            "{:?}: {}",
+            flags,
+            err
+        );
+    }
+}
+
+// a literal too wide for int is a long, and keeps its value
+#[test]
+fn test_wide_literals() {
+    let source = r#"
+int main()
+{
+  long big;
+  big = 10000000000;
+  printf("%d %d %d\n", big / 10, -2147483648 < 0, 0x100000000 == 4294967296);
+  return 4294967296 / 65536 / 4096;
+}
+"#;
+    for flags in [&[][..], &["-O2"]] {
+        let (out, err, _) = run_c("literals", source, flags, &[]);
+        assert_eq!(out, "1000000000 1 1\nProgram exited with value: 16\n", "{:?}: {}", flags, err);
+    }
+}
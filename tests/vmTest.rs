@@ -1,5 +1,14 @@
 // tests/vm_test.rs
+
+use c4_rust_mleiha::dispatch;
+use c4_rust_mleiha::stdio::Capture;
+use c4_rust_mleiha::vm::{RuntimeError, VM};
+
 const IMM: i64 = 1;
+const JMP: i64 = 2;
+const BZ: i64 = 4;
+const ENT: i64 = 6;
+const ADJ: i64 = 7;
 const PSH: i64 = 13;
 const ADD_OP: i64 = 25; 
 const EXIT: i64 = 38;
@@ -26,7 +35,7 @@ fn test_vm_imm_and_psh() {
     assert_eq!(vm.sp, stack_size - 2, "Stack pointer incorrect");
     assert_eq!(vm.stack[stack_size - 1], 42, "First pushed value (42) incorrect");
     assert_eq!(vm.stack[stack_size - 2], 10, "Second pushed value (10) incorrect");
-    assert_eq!(vm.running, false, "VM should not be running after EXIT");
+    assert!(!vm.running, "VM should not be running after EXIT");
 }
 
 #[test]
@@ -45,7 +54,7 @@ fn test_vm_add_operation() {
     // ADD pops one value, sp should be back to where it was before the first PSH if stack was empty
     // After PSH, sp = stack_size - 1. After ADD, sp = stack_size.
     assert_eq!(vm.sp, 100, "Stack pointer after ADD incorrect");
-    assert_eq!(vm.running, false, "VM should not be running after EXIT");
+    assert!(!vm.running, "VM should not be running after EXIT");
 }
 
 #[test]
@@ -94,3 +103,55 @@ fn test_vm_bz_branch_not_taken() {
     assert_eq!(vm.stack[99], 20, "Value 20 should be on stack");
 }
 
+#[test]
+fn test_vm_unknown_instruction_faults() {
+    let bytecode = vec![IMM, 1, 99, EXIT];
+    let mut vm = VM::new(bytecode.clone(), 0, 100);
+    let err = Capture::new();
+    vm.stderr = Box::new(err.clone());
+    vm.run();
+    assert_eq!(vm.error, Some(RuntimeError::Fault("unknown instruction 99".to_string())));
+    assert_eq!(err.text(), "fault at pc 2: unknown instruction 99\n");
+    // the decoded loop won't start such a program at all
+    assert_eq!(dispatch::decode(&bytecode).err(), Some((2, "unknown instruction 99".to_string())));
+}
+
+#[test]
+fn test_vm_jump_inside_instruction_is_refused() {
+    let bytecode = vec![IMM, 1, JMP, 1, EXIT];
+    assert_eq!(dispatch::decode(&bytecode).err(), Some((2, "jump to 1, which is not the start of an instruction".to_string())));
+}
+
+// operands and pops that would take the stack pointer out of memory fault
+// on both loops, at the instruction's pc
+#[test]
+fn test_vm_bad_operands_fault() {
+    let cases: [(&[i64], &str); 6] = [
+        (&[IMM, 1, ADJ, 100000, PSH], "fault at pc 2: stack underflow\n"),
+        (&[ENT, -1, PSH], "fault at pc 0: ENT -1, a negative number of cells\n"),
+        (&[ADJ, -3], "fault at pc 0: ADJ -3, a negative number of cells\n"),
+        (&[IMM, 1, 46, 0], "fault at pc 2: SXT 0, not a width from 1 to 64 bits\n"),
+        (&[47, 65], "fault at pc 0: ZXT 65, not a width from 1 to 64 bits\n"),
+        (&[IMM, 1, PSH], "fault at pc 3: jump to 3, outside the program\n"),
+    ];
+    for (bytecode, expected) in cases {
+        let mut vm = VM::new(bytecode.to_vec(), 0, 100);
+        let err = Capture::new();
+        vm.stderr = Box::new(err.clone());
+        vm.run();
+        assert_eq!(err.text(), expected, "{:?}", bytecode);
+        assert!(!vm.running);
+
+        let mut vm = VM::new(bytecode.to_vec(), 0, 100);
+        let err = Capture::new();
+        vm.stderr = Box::new(err.clone());
+        match dispatch::decode(bytecode) {
+            Ok(code) => {
+                vm.run_decoded(&code);
+                assert_eq!(err.text(), expected, "{:?} decoded", bytecode);
+            }
+            // the decoded loop refuses bad operands before it starts
+            Err((pc, why)) => assert_eq!(format!("fault at pc {}: {}\n", pc, why), expected, "{:?} decoded", bytecode),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+// tests/wasm_test.rs
+
+use std::process::Command;
+
+mod common;
+use common::{run_c, run_source, runtime, temp_path, text, tool, MIXED, MIXED_PRINTS};
+
+// the output the module has to reproduce
+fn expected(flags: &[&str]) -> String {
+    let (out, _, _) = run_c("vm", MIXED, flags, &[]);
+    assert!(out.contains(MIXED_PRINTS), "unexpected output: {}", out);
+    out
+}
+
+fn module(flags: &[&str]) -> Vec<u8> {
+    let module = run_source("wasm", MIXED, &[flags, &["--emit=wasm"]].concat(), &[]).stdout;
+    assert!(module.starts_with(b"\0asm\x01\0\0\0"));
+    module
+}
+
+#[test]
+fn test_wasm_module() {
+    for flags in [&[][..], &["-O2"][..]] {
+        expected(flags);
+        module(flags);
+    }
+}
+
+#[test]
+#[ignore = "needs node"]
+fn test_wasm_matches_interpreter() {
+    for flags in [&[][..], &["-O2"][..]] {
+        let path = temp_path("wasm", ".wasm");
+        std::fs::write(&path, module(flags)).unwrap();
+        let out = tool(Command::new("node").arg(runtime("runtime.mjs")).arg(&path));
+        std::fs::remove_file(&path).ok();
+        // main returns fib(10), which is the exit code as well
+        assert_eq!(out.status.code(), Some(55), "node failed: {}", text(&out.stderr));
+        assert_eq!(expected(flags), text(&out.stdout));
+    }
+}
+
+#[test]
+fn test_wat_shows_the_module() {
+    let (wat, _, _) = run_c("wat", MIXED, &["--emit=wat"], &[]);
+    for import in ["printf", "open", "read", "malloc"] {
+        assert!(wat.contains(&format!("(import \"c4\" \"{}\"", import)), "wat was:\n{}", wat);
+    }
+    assert!(wat.contains("(memory (export \"memory\") 256)"), "wat was:\n{}", wat);
+    assert!(wat.contains("(func $f_fib") && wat.contains("call $f_fib"), "wat was:\n{}", wat);
+    // the loop in main dispatches over its basic blocks
+    assert!(wat.contains("br_table"), "wat was:\n{}", wat);
+    // the string pool sits in the data segment, a cell per byte
+    assert!(wat.contains("(data (i32.const 0) \"\\25\\00\\00\\00\\00\\00\\00\\00"), "wat was:\n{}", wat);
+}